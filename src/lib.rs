@@ -0,0 +1,2 @@
+pub mod snes_cpu;
+pub mod zeal;
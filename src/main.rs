@@ -1,45 +1,160 @@
 extern crate clap;
+extern crate zealc;
 
-mod zeal;
-mod snes_cpu;
+use clap::{App, AppSettings, Arg, ArgMatches, ErrorKind, SubCommand};
 
-use clap::{App, Arg};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::io::{Read, Write};
+use std::fs;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 
-use std::path::Path;
-use std::io::Read;
-use std::fs::File;
-use std::error::Error;
+use zealc::snes_cpu::*;
 
-use snes_cpu::*;
+use zealc::zeal::assembler::*;
+use zealc::zeal::ast_json::*;
+use zealc::zeal::config_file::*;
+use zealc::zeal::debug_file::*;
+use zealc::zeal::disassembler::Disassembler;
+use zealc::zeal::expression::Expr;
+use zealc::zeal::formatter::format_source;
+use zealc::zeal::hex_format::*;
+use zealc::zeal::hex_style::*;
+use zealc::zeal::lexer::{is_line_terminator, strip_byte_order_mark, SourceFileError};
+use zealc::zeal::output_writer::*;
+use zealc::zeal::parser::*;
+use zealc::zeal::pass::has_error_severity;
+use zealc::zeal::rom_usage::*;
+use zealc::zeal::self_check::self_check;
+use zealc::zeal::symbol_file::*;
+use zealc::zeal::symbol_table::*;
+use zealc::zeal::system_definition::*;
+use zealc::zeal::xref::*;
 
-use zeal::collect_label_pass::*;
-use zeal::instruction_statement_pass::*;
-use zeal::output_writer::*;
-use zeal::parser::*;
-use zeal::pass::*;
-use zeal::resolve_label_pass::*;
-use zeal::symbol_table::*;
-use zeal::system_definition::SystemDefinition;
+// Set when the output is written to stdout with -o -, messages then go to stderr to keep the output intact
+static MESSAGES_TO_STDERR: AtomicBool = AtomicBool::new(false);
+
+macro_rules! message {
+    ($($argument:tt)*) => {
+        if MESSAGES_TO_STDERR.load(Ordering::Relaxed) {
+            eprint!($($argument)*)
+        } else {
+            print!($($argument)*)
+        }
+    };
+}
+
+macro_rules! messageln {
+    ($($argument:tt)*) => {
+        if MESSAGES_TO_STDERR.load(Ordering::Relaxed) {
+            eprintln!($($argument)*)
+        } else {
+            println!($($argument)*)
+        }
+    };
+}
+
+// Read from the current directory when --config isn't given
+const CONFIG_FILE_NAME: &str = ".zealrc";
+
+// Flags undoing each other, one given on the command line replaces the other given by the config file
+const OPPOSITE_FLAGS: &[(&str, &str)] = &[("--stats", "--no-stats"), ("--hex-upper", "--hex-lower")];
+
+// Arguments adding the subcommands, the help lists them too
+const SUBCOMMAND_ARGUMENTS: &[&str] = &["fmt", "strip-header", "help", "-h", "--help"];
 
 static SUPPORTED_SYSTEMS: &'static [&'static SystemDefinition] = &[&SNES_CPU];
 
-fn find_system(cpu_name: &str) -> &'static SystemDefinition {
+// Names are matched without case, like SNES or 65816
+fn find_system(cpu_name: &str) -> Option<&'static SystemDefinition> {
     for system in SUPPORTED_SYSTEMS.iter() {
-        if system.short_name == cpu_name {
-            return system;
+        if system.short_name.eq_ignore_ascii_case(cpu_name)
+            || system.aliases.iter().any(|alias| alias.eq_ignore_ascii_case(cpu_name))
+        {
+            return Some(system);
         }
     }
 
-    &SNES_CPU
+    None
 }
 
-fn print_error_message(error_message: &ErrorMessage) {
+// Short name followed by the aliases, like snes-cpu (65816, snes, sfc)
+fn system_names(system: &SystemDefinition) -> String {
+    if system.aliases.is_empty() {
+        system.short_name.to_owned()
+    } else {
+        format!("{} ({})", system.short_name, system.aliases.join(", "))
+    }
+}
+
+fn instruction_arguments_to_string(instruction: &InstructionInfo) -> String {
+    let mut arguments = Vec::new();
+
+    for argument in instruction.arguments.iter() {
+        match argument {
+            &InstructionArgument::Number(size) => {
+                arguments.push(format!("{}-bit", argument_size_to_bit_size(size)));
+            }
+            &InstructionArgument::Numbers(sizes) => {
+                let bit_sizes: Vec<String> = sizes
+                    .iter()
+                    .map(|size| argument_size_to_bit_size(*size).to_string())
+                    .collect();
+                arguments.push(format!("{}-bit", bit_sizes.join("/")));
+            }
+            &InstructionArgument::Register(register_name) => {
+                arguments.push(register_name.to_string());
+            }
+            &InstructionArgument::NotStaticRegister(ref register_name) => {
+                arguments.push(register_name.to_owned());
+            }
+        }
+    }
+
+    arguments.join(", ")
+}
+
+fn list_opcodes(system: &SystemDefinition) {
+    let mut instructions: Vec<&InstructionInfo> = system.instructions.iter().collect();
+    instructions.sort_by(|a, b| a.name.cmp(b.name).then(a.opcode.cmp(&b.opcode)));
+
+    println!("Opcodes for {}:", system.name);
+    println!("{:<8} {:<32} {:<8} {}", "Name", "Addressing mode", "Opcode", "Arguments");
+
+    for instruction in instructions {
+        println!(
+            "{:<8} {:<32} ${:<7} {}",
+            instruction.name,
+            instruction_addressing_to_string(system, instruction),
+            format!("{:02X}", instruction.opcode),
+            instruction_arguments_to_string(instruction)
+        );
+    }
+}
+
+// Name given to the source read from stdin when the input file is -
+static STDIN_SOURCE_NAME: &'static str = "<stdin>";
+// Output path writing the output to stdout
+static STDOUT_OUTPUT_NAME: &'static str = "-";
+
+// None when the file can't be read, like an input file that doesn't exist.
+// Token positions don't count the byte order mark so it is removed like the lexer does.
+fn read_source(source_file: &str) -> Option<String> {
+    match fs::read(source_file) {
+        Err(_) => None,
+        Ok(file_bytes) => Some(strip_byte_order_mark(&String::from_utf8_lossy(&file_bytes)).to_owned()),
+    }
+}
+
+// The source read from stdin is kept in `stdin_content` since it can't be read again
+fn print_error_message(error_message: &ErrorMessage, stdin_content: &Option<String>) {
     let severity_string = match error_message.severity {
         ErrorSeverity::Error => "error",
         ErrorSeverity::Warning => "warning",
     };
 
-    println!(
+    messageln!(
         "{}({},{}): {}: {}",
         error_message.token.source_file,
         error_message.token.line,
@@ -48,78 +163,585 @@ fn print_error_message(error_message: &ErrorMessage) {
         error_message.message
     );
 
-    let mut file = match File::open(&error_message.token.source_file) {
-        Err(why) => panic!(
-            "Couldn't open {}: {}",
-            error_message.token.source_file,
-            why.description()
-        ),
-        Ok(file) => file,
-    };
-
-    let mut string_file_content = String::new();
-    match file.read_to_string(&mut string_file_content) {
-        Err(why) => panic!(
-            "Couldn't read {}: {}",
-            error_message.token.source_file,
-            why.description()
-        ),
-        Ok(result) => result,
-    };
-
-    for context_char in string_file_content
-        .chars()
-        .skip(error_message.token.context_start)
-    {
-        if context_char == '\n' {
-            break;
-        } else {
-            print!("{}", context_char);
+    let string_file_content = match stdin_content {
+        &Some(ref content) if error_message.token.source_file.as_str() == STDIN_SOURCE_NAME => {
+            Some(strip_byte_order_mark(content).to_owned())
+        }
+        _ => read_source(&error_message.token.source_file),
+    };
+
+    // Without the source there is no line to show. Like the lexer, positions count characters and not bytes.
+    if let Some(string_file_content) = string_file_content {
+        let context_line: Vec<char> = string_file_content
+            .chars()
+            .skip(error_message.token.context_start)
+            .take_while(|&context_char| !is_line_terminator(context_char))
+            .collect();
+        messageln!("{}", context_line.iter().collect::<String>());
+
+        // Tabs are kept so the caret lines up with the text above it
+        for index in 0..(error_message.token.start_column - 1) as usize {
+            match context_line.get(index) {
+                Some(&'\t') => message!("\t"),
+                _ => message!(" "),
+            }
+        }
+
+        for _ in error_message.token.start_column..error_message.token.end_column {
+            message!("^");
         }
+
+        messageln!("");
     }
-    println!("");
 
-    for _ in 0..(error_message.token.start_column - 1) {
-        print!(" ");
+    for include_location in error_message.token.include_chain.iter().rev() {
+        messageln!(
+            "    included from {}({})",
+            include_location.source_file, include_location.line
+        );
     }
+}
 
-    for _ in error_message.token.start_column..error_message.token.end_column {
-        print!("^");
+struct AssemblyStats {
+    instructions: usize,
+    bytes_emitted: u64,
+    labels: usize,
+    files_included: usize,
+    source_lines: u32,
+    // Size of the output file, None when nothing was written
+    rom_size: Option<u64>,
+    elapsed: Duration,
+    // Time taken by parsing, each pass and writing the output, in order
+    phase_timings: Vec<(&'static str, Duration)>,
+}
+
+// Printed to stderr so it doesn't get mixed with piped output
+fn print_stats(stats: &AssemblyStats) {
+    eprintln!("Assembly statistics:");
+    eprintln!("  {:<16}{:>10}", "Instructions", stats.instructions);
+    eprintln!("  {:<16}{:>10}", "Bytes emitted", stats.bytes_emitted);
+    eprintln!("  {:<16}{:>10}", "Labels", stats.labels);
+    eprintln!("  {:<16}{:>10}", "Files included", stats.files_included);
+    eprintln!("  {:<16}{:>10}", "Source lines", stats.source_lines);
+    if let Some(rom_size) = stats.rom_size {
+        eprintln!("  {:<16}{:>10}", "ROM size", rom_size);
     }
+    eprintln!("  {:<16}{:>7.2} ms", "Time", stats.elapsed.as_secs_f64() * 1000.0);
+}
 
-    println!("");
+fn print_timing(name: &str, duration: Duration) {
+    eprintln!("  {:<24}{:>9.2} ms", name, duration.as_secs_f64() * 1000.0);
 }
 
-fn process_errors(messages: &Vec<ErrorMessage>) {
-    for error_message in messages {
-        print_error_message(&error_message);
+fn stats_to_json(stats: &AssemblyStats) -> String {
+    let phases = stats
+        .phase_timings
+        .iter()
+        .map(|&(name, duration)| format!("{{\"name\":\"{}\",\"ms\":{:.3}}}", name, duration.as_secs_f64() * 1000.0))
+        .collect::<Vec<String>>();
+
+    format!(
+        "{{\"instructions\":{},\"bytes_emitted\":{},\"labels\":{},\"files_included\":{},\"source_lines\":{},\"rom_size\":{},\"ms\":{:.3},\"phases\":[{}]}}",
+        stats.instructions,
+        stats.bytes_emitted,
+        stats.labels,
+        stats.files_included,
+        stats.source_lines,
+        match stats.rom_size {
+            Some(rom_size) => rom_size.to_string(),
+            None => "null".to_owned(),
+        },
+        stats.elapsed.as_secs_f64() * 1000.0,
+        phases.join(",")
+    )
+}
+
+// Number given on the command line in hexadecimal ($FF or 0xFF) or decimal
+fn parse_number_value(text: &str) -> Option<u32> {
+    if text.starts_with('$') {
+        u32::from_str_radix(&text[1..], 16).ok()
+    } else if text.starts_with("0x") || text.starts_with("0X") {
+        u32::from_str_radix(&text[2..], 16).ok()
+    } else {
+        text.parse::<u32>().ok()
     }
+}
+
+fn parse_byte_value(text: &str) -> Option<u8> {
+    parse_number_value(text).and_then(|value| if value <= 0xFF { Some(value as u8) } else { None })
+}
 
+fn parse_number_option(cmd_matches: &ArgMatches, option_name: &str) -> u32 {
+    let text = cmd_matches.value_of(option_name).unwrap();
+    match parse_number_value(text) {
+        Some(value) => value,
+        None => {
+            messageln!("ERROR: Invalid --{} '{}', use $8000, 0x8000 or 32768.", option_name, text);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn process_errors(messages: &Vec<ErrorMessage>, stdin_content: &Option<String>) {
     for error_message in messages {
-        if error_message.severity == ErrorSeverity::Error {
+        print_error_message(&error_message, stdin_content);
+    }
+
+    if has_error_severity(messages) {
+        std::process::exit(1);
+    }
+}
+
+// Catch mistakes in the hand written instruction tables during development
+#[cfg(debug_assertions)]
+fn validate_systems() {
+    for system in SUPPORTED_SYSTEMS.iter() {
+        let issues = system.validate();
+        if !issues.is_empty() {
+            panic!("Invalid system definition for {}:\n{}", system.short_name, issues.join("\n"));
+        }
+    }
+}
+
+// NAME=VALUE where VALUE is an expression using the symbols imported or defined before it,
+// the symbol is added like an imported label
+fn add_define(system: &'static SystemDefinition, symbol_table: &mut SymbolTable, define: &str) -> Result<(), String> {
+    let (name, value_text) = match define.find('=') {
+        Some(index) if index > 0 => (&define[..index], &define[index + 1..]),
+        _ => return Err("Expected NAME=VALUE.".to_owned()),
+    };
+
+    // Lexed like a label so opcodes are rejected
+    if system.registers.contains(&name) {
+        return Err(format!("'{}' is a register name and can't be used as a label.", name));
+    }
+    match Expr::parse_str(system, name) {
+        Ok(Expr::Identifier(ref identifier)) if identifier == name => {}
+        Ok(_) => return Err(format!("'{}' isn't a valid label name.", name)),
+        Err(message) => return Err(message),
+    }
+
+    if symbol_table.has_label(name) {
+        return Err(format!("'{}' is already defined.", name));
+    }
+
+    let value_expression = Expr::parse_str(system, value_text)?;
+    if value_expression.uses_current_address() {
+        return Err("The current address '*' is only known in a statement.".to_owned());
+    }
+
+    let value = value_expression.evaluate(symbol_table, 0)?;
+    if value < 0 || value > u32::max_value() as i64 {
+        return Err(format!("Value {} doesn't fit in 32 bits.", value));
+    }
+
+    symbol_table.add_imported_label(name, value as u32);
+    Ok(())
+}
+
+fn strip_header(rom_path: &str, output_path: &Path, force: bool) {
+    let mut output_options = OutputWriterOptions::new();
+    output_options.force = force;
+
+    if let Err(message) = output_options.check_output_path(output_path) {
+        messageln!("ERROR: {}", message);
+        std::process::exit(1);
+    }
+
+    match strip_copier_header(Path::new(rom_path), output_path) {
+        Ok(true) => println!("Stripped the copier header of '{}'.", rom_path),
+        Ok(false) => println!("'{}' has no copier header, it was copied as is.", rom_path),
+        Err(why) => {
+            messageln!("ERROR: Couldn't strip the header of '{}': {}", rom_path, why);
+            std::process::exit(1);
+        }
+    }
+}
+
+// Print the formatted source to stdout, or write it back to the file with --write.
+// --check only tells if the file is already formatted, exiting with 1 when it isn't.
+fn format_file(system: &'static SystemDefinition, fmt_matches: &ArgMatches) {
+    let input_file = fmt_matches.value_of("INPUT").unwrap();
+
+    // Keep stdout for the formatted source
+    let is_stdout_output =
+        !fmt_matches.is_present("check") && (!fmt_matches.is_present("write") || input_file == "-");
+    MESSAGES_TO_STDERR.store(is_stdout_output, Ordering::Relaxed);
+
+    let stdin_content = if input_file == "-" {
+        let mut content = String::new();
+        if let Err(why) = std::io::stdin().read_to_string(&mut content) {
+            messageln!("ERROR: Couldn't read the source from stdin: {}", why);
+            std::process::exit(1);
+        }
+        Some(content)
+    } else {
+        None
+    };
+
+    // Unlike read_source() invalid UTF-8 is an error, the replacement characters would be written back
+    let (source_name, source) = match stdin_content {
+        Some(ref content) => (STDIN_SOURCE_NAME, content.clone()),
+        None => match fs::read(input_file).map_err(SourceFileError::Io).and_then(|file_bytes| {
+            String::from_utf8(file_bytes).map_err(|why| SourceFileError::InvalidUtf8(why.utf8_error().valid_up_to()))
+        }) {
+            Ok(source) => (input_file, source),
+            Err(why) => {
+                messageln!("ERROR: Couldn't read '{}': {}.", input_file, why);
+                std::process::exit(1);
+            }
+        },
+    };
+
+    let formatted = match format_source(system, source_name, strip_byte_order_mark(&source)) {
+        Ok(formatted) => formatted,
+        Err(error_message) => {
+            print_error_message(&error_message, &stdin_content);
+            std::process::exit(1);
+        }
+    };
+
+    if fmt_matches.is_present("check") {
+        if formatted != source {
+            println!("'{}' isn't formatted.", source_name);
+            std::process::exit(1);
+        }
+    } else if fmt_matches.is_present("write") && stdin_content.is_none() {
+        if formatted != source {
+            if let Err(why) = fs::write(input_file, formatted) {
+                messageln!("ERROR: Couldn't write '{}': {}", input_file, why);
+                std::process::exit(1);
+            }
+        }
+    } else {
+        print!("{}", formatted);
+    }
+}
+
+// Round trip the instructions of every supported CPU through the assembler and the disassembler,
+// exits with 1 when one of them doesn't come back unchanged
+fn self_check_systems() {
+    let mut has_failures = false;
+
+    for system in SUPPORTED_SYSTEMS.iter() {
+        let (checked_count, failures) = self_check(system);
+        for failure in failures.iter() {
+            println!("{}: {}", system.short_name, failure);
+        }
+
+        println!(
+            "{}: {} of {} instructions came back unchanged.",
+            system.short_name,
+            checked_count - failures.len(),
+            checked_count
+        );
+        has_failures = has_failures || !failures.is_empty();
+    }
+
+    std::process::exit(if has_failures { 1 } else { 0 });
+}
+
+// Write the disassembly of a ROM range to the output file or to stdout
+fn disassemble_rom(system: &'static SystemDefinition, cmd_matches: &ArgMatches) {
+    let rom_path = cmd_matches.value_of("disassemble").unwrap();
+
+    // Keep stdout for the disassembly
+    let is_stdout_output = cmd_matches.value_of("output").map_or(true, |output_path| output_path == STDOUT_OUTPUT_NAME);
+    MESSAGES_TO_STDERR.store(is_stdout_output, Ordering::Relaxed);
+
+    let origin = parse_number_option(cmd_matches, "origin");
+    let length = parse_number_option(cmd_matches, "length");
+
+    let snes_map = match cmd_matches.value_of("snesmap") {
+        None => None,
+        Some(map_name) => match SnesMap::from_name(map_name) {
+            Some(snes_map) => Some(snes_map),
+            None => {
+                messageln!("ERROR: Unknown --snesmap '{}', use lorom, hirom, hirom-fast, sa1rom or exhirom.", map_name);
+                std::process::exit(1);
+            }
+        },
+    };
+
+    let rom = match fs::read(rom_path) {
+        Ok(rom) => rom,
+        Err(why) => {
+            messageln!("ERROR: Couldn't read '{}': {}", rom_path, why);
             std::process::exit(1);
         }
+    };
+
+    let text = match Disassembler::new(system, snes_map).disassemble(&rom, origin, length) {
+        Ok(text) => text,
+        Err(message) => {
+            messageln!("ERROR: {}", message);
+            std::process::exit(1);
+        }
+    };
+
+    match cmd_matches.value_of("output") {
+        None => print!("{}", text),
+        Some(output_path) if output_path == STDOUT_OUTPUT_NAME => print!("{}", text),
+        Some(output_path) => {
+            let mut output_options = OutputWriterOptions::new();
+            output_options.force = cmd_matches.is_present("force");
+
+            if let Err(message) = output_options.check_output_path(Path::new(output_path)) {
+                messageln!("ERROR: {}", message);
+                std::process::exit(1);
+            }
+
+            if let Err(why) = fs::write(output_path, text) {
+                messageln!("ERROR: Couldn't write '{}': {}", output_path, why);
+                std::process::exit(1);
+            }
+        }
     }
 }
 
+// Value of --config FILE, found before clap runs since the config file adds arguments for it
+fn config_path_argument(args: &[OsString]) -> Option<String> {
+    let mut args = args.iter().skip(1).filter_map(|arg| arg.to_str());
+
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            break;
+        } else if arg == "--config" {
+            return args.next().map(|path| path.to_owned());
+        } else if arg.starts_with("--config=") {
+            return Some(arg["--config=".len()..].to_owned());
+        }
+    }
+
+    None
+}
+
+// Name of a NAME=VALUE definition
+fn define_name(definition: &str) -> &str {
+    definition.split('=').next().unwrap_or("").trim()
+}
+
+// Names given with -D or --define on the command line
+fn command_line_define_names(args: &[OsString]) -> Vec<String> {
+    let mut names = Vec::new();
+    let mut args = args.iter().skip(1).filter_map(|arg| arg.to_str());
+
+    while let Some(arg) = args.next() {
+        let definition = if arg == "--" {
+            break;
+        } else if arg == "-D" || arg == "--define" {
+            args.next()
+        } else if arg.starts_with("--define=") {
+            Some(&arg["--define=".len()..])
+        } else if arg.starts_with("-D") {
+            Some(&arg["-D".len()..])
+        } else {
+            None
+        };
+
+        if let Some(definition) = definition {
+            names.push(define_name(definition).to_owned());
+        }
+    }
+
+    names
+}
+
+// Whether an argument of the config file is replaced by the command line. Most options are replaced by
+// clap, but defines add up and flags like --no-stats can only be undone by their opposite.
+fn is_overridden_by_command_line(argument: &str, args: &[OsString], define_names: &[String]) -> bool {
+    if argument.starts_with("--define=") {
+        let name = define_name(&argument["--define=".len()..]);
+        return define_names.iter().any(|define_name| define_name == name);
+    }
+
+    OPPOSITE_FLAGS.iter().any(|&(flag, opposite)| {
+        let opposite_of_argument = if argument == flag {
+            opposite
+        } else if argument == opposite {
+            flag
+        } else {
+            return false;
+        };
+
+        args.iter().skip(1).any(|arg| arg == opposite_of_argument)
+    })
+}
+
+// First line of a clap error without the usage
+fn clap_error_text(error: &clap::Error) -> String {
+    error.message.lines().next().unwrap_or("").trim_start_matches("error: ").to_owned()
+}
+
+// Whether the long option like --gap-fill takes a value, an error for options that don't exist.
+// --list-cpu is given since clap reports a missing --output before a missing value.
+fn option_takes_value(app: &App, option_name: &str) -> Result<bool, String> {
+    let arguments = vec![String::from("zealc"), String::from("--list-cpu"), option_name.to_owned()];
+
+    match app.clone().setting(AppSettings::ColorNever).get_matches_from_safe(arguments) {
+        Err(ref error) if error.kind == ErrorKind::EmptyValue => Ok(true),
+        Err(ref error) if error.kind == ErrorKind::UnknownArgument => {
+            Err(format!("Unknown option '{}', the keys are the long options listed by --help.", option_name))
+        }
+        _ => Ok(false),
+    }
+}
+
+// Arguments given by the config file, they go before those of the command line which then take precedence
+fn config_file_arguments(app: &App, args: &[OsString]) -> Vec<OsString> {
+    if args.iter().skip(1).any(|arg| arg == "--no-config") {
+        return Vec::new();
+    }
+
+    let (config_path, is_explicit) = match config_path_argument(args) {
+        Some(config_path) => (config_path, true),
+        None => (CONFIG_FILE_NAME.to_owned(), false),
+    };
+
+    let config_text = match fs::read_to_string(&config_path) {
+        Ok(config_text) => config_text,
+        Err(ref why) if !is_explicit && why.kind() == std::io::ErrorKind::NotFound => return Vec::new(),
+        Err(why) => {
+            messageln!("ERROR: Couldn't read config file '{}': {}", config_path, why);
+            std::process::exit(1);
+        }
+    };
+
+    let entries = match parse_config(&config_text) {
+        Ok(entries) => entries,
+        Err(message) => {
+            messageln!("ERROR: Invalid config file '{}', {}", config_path, message);
+            std::process::exit(1);
+        }
+    };
+
+    let define_names = command_line_define_names(args);
+
+    let mut arguments = Vec::new();
+    for entry in entries.iter() {
+        if entry.option_name() == "--config" || entry.option_name() == "--no-config" {
+            messageln!("ERROR: Config file '{}' line {}: {} can only be given on the command line.", config_path, entry.line, entry.key);
+            std::process::exit(1);
+        }
+
+        // Each entry is checked alone so unknown options and invalid values point at the config file
+        let entry_error = match (&entry.value, option_takes_value(app, &entry.option_name())) {
+            (_, Err(message)) => Some(message),
+            (&ConfigValue::Flag(_), Ok(true)) => Some(format!("'{}' takes a value like {} = \"VALUE\".", entry.key, entry.key)),
+            (&ConfigValue::Flag(_), Ok(false)) => None,
+            (_, Ok(false)) => Some(format!("'{}' doesn't take a value, use {} = true or false.", entry.key, entry.key)),
+            (_, Ok(true)) => {
+                // A subcommand makes the required options optional
+                let mut entry_arguments = vec![String::from("zealc")];
+                entry_arguments.extend(entry.arguments());
+                entry_arguments.extend(vec![String::from("strip-header"), String::new(), String::new()]);
+                add_subcommands(app.clone())
+                    .setting(AppSettings::ColorNever)
+                    .get_matches_from_safe(entry_arguments)
+                    .err()
+                    .map(|error| clap_error_text(&error))
+            }
+        };
+
+        if let Some(message) = entry_error {
+            messageln!("ERROR: Config file '{}' line {}: {}", config_path, entry.line, message);
+            std::process::exit(1);
+        }
+
+        arguments.extend(
+            entry
+                .arguments()
+                .into_iter()
+                .filter(|argument| !is_overridden_by_command_line(argument, args, &define_names))
+                .map(OsString::from),
+        );
+    }
+
+    arguments
+}
+
+// Subcommands are only known when one of them is on the command line, otherwise clap takes an input
+// file like fmt.zc for a misspelled subcommand
+fn uses_subcommand(args: &[OsString]) -> bool {
+    args.iter()
+        .skip(1)
+        .any(|arg| SUBCOMMAND_ARGUMENTS.iter().any(|name| arg == name))
+}
+
+fn add_subcommands<'a, 'b>(app: App<'a, 'b>) -> App<'a, 'b> {
+    app
+        .subcommand(
+            SubCommand::with_name("strip-header")
+                .about("Copy a ROM without its 512 byte copier header, if it has one")
+                .arg(Arg::with_name("ROM").help("ROM file to read").required(true).index(1))
+                .arg(Arg::with_name("OUTPUT").help("File to write the ROM to").required(true).index(2))
+                .arg(
+                    Arg::with_name("force")
+                        .long("force")
+                        .help("Overwrite the output file if it already exists."),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("fmt")
+                .about("Print a source file formatted: labels at the start of the line, statements indented, comments aligned")
+                .arg(
+                    Arg::with_name("INPUT")
+                        .help("Source file to format, - reads it from stdin")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("write")
+                        .long("write")
+                        .help("Write the formatted source back to the file instead of stdout."),
+                )
+                .arg(
+                    Arg::with_name("check")
+                        .long("check")
+                        .help("Exit with 1 when the file isn't formatted, without printing it."),
+                ),
+        )
+}
+
 fn main() {
+    let start_time = Instant::now();
+
+    #[cfg(debug_assertions)]
+    validate_systems();
+
     let zeal_args_info = App::new("Zeal Compiler")
         .version("0.1.0")
         .author("Michaël Larouche <michael.larouche@gmail.com>")
         .about("Compiler/Assembler for SNES/SFC 65816 (for now)")
+        .setting(AppSettings::SubcommandsNegateReqs)
+        // Options of the config file come first and are replaced by those of the command line
+        .setting(AppSettings::AllArgsOverrideSelf)
         .arg(
             Arg::with_name("output")
                 .short("o")
                 .long("output")
                 .takes_value(true)
-                .required(true)
-                .help("Resultant ROM file or an existing rom file"),
+                .required_unless_one(&["listcpu", "listopcodes", "selfcheck", "verify", "dryrun", "check", "disassemble"])
+                .help("Resultant ROM file or an existing rom file, - writes the output to stdout"),
         )
         .arg(
             Arg::with_name("INPUT")
-                .help("Input assembler file")
+                .help("Input assembler file, - reads the source from stdin")
                 .index(1),
         )
+        .arg(
+            Arg::with_name("config")
+                .long("config")
+                .value_name("FILE")
+                .help("Read options from FILE instead of .zealrc in the current directory. Keys are long options like gap_fill = \"$FF\" or optimize = true, options given on the command line take precedence.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("noconfig")
+                .long("no-config")
+                .conflicts_with("config")
+                .help("Don't read .zealrc, to leave out the flags it sets."),
+        )
         .arg(
             Arg::with_name("cpu")
                 .short("c")
@@ -133,72 +755,720 @@ fn main() {
                 .long("patch")
                 .help("Put the compiler in patching mode. The compiler will only modifiy the relevant parts of the output.")
         )
+        .arg(
+            Arg::with_name("force")
+                .long("force")
+                .help("Overwrite the output file if it already exists.")
+                .conflicts_with("patch"),
+        )
+        .arg(
+            Arg::with_name("format")
+                .short("f")
+                .long("format")
+                .visible_alias("output-format")
+                .value_name("FORMAT")
+                .possible_values(&["raw", "ihex", "srec", "ips", "bps", "listing"])
+                .help("Format of the output file: raw binary, Intel HEX, Motorola S-record, IPS or BPS patch against --source-rom, or a text listing. (Default: raw)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("sourcerom")
+                .long("source-rom")
+                .value_name("PATH")
+                .help("ROM the ips and bps patches are made against.")
+                .required_ifs(&[("format", "ips"), ("format", "bps")])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("bytesperrecord")
+                .long("bytes-per-record")
+                .value_name("COUNT")
+                .help("Number of data bytes in each record of the ihex and srec formats. (Default: 16)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("gapfill")
+                .long("gap-fill")
+                .value_name("BYTE")
+                .help("Byte written between the emitted parts of a new output file, like $FF. (Default: $00)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("dryrun")
+                .long("dry-run")
+                .conflicts_with("verify")
+                .help("Assemble and report errors, warnings and statistics without writing any file."),
+        )
+        .arg(
+            Arg::with_name("check")
+                .long("check")
+                .conflicts_with_all(&["dryrun", "verify", "output"])
+                .help("Only check the input for errors, exits with 1 if any is found. No output file is needed."),
+        )
+        .arg(
+            Arg::with_name("verify")
+                .long("verify")
+                .value_name("ROM")
+                .help("Compare the assembled bytes with an existing ROM instead of writing the output.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("verifymax")
+                .long("verify-max-mismatches")
+                .value_name("COUNT")
+                .requires("verify")
+                .help("Number of mismatches reported by --verify. (Default: 16)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("smcheader")
+                .long("smc-header")
+                .help("The output starts with a 512 byte copier header, detected from the file size in patching mode.")
+                .conflicts_with("splitbanks"),
+        )
+        .arg(
+            Arg::with_name("splitbanks")
+                .long("split-banks")
+                .value_name("DIR")
+                .help("Also write each bank containing data to its own file in DIR, with a manifest.txt listing them.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("keepemptybanks")
+                .long("keep-empty-banks")
+                .requires("splitbanks")
+                .help("With --split-banks, write zero-filled files for the empty banks before the last used one."),
+        )
         .arg(
             Arg::with_name("listcpu")
                 .long("list-cpu")
                 .help("List available CPU types."),
+        )
+        .arg(
+            Arg::with_name("listopcodes")
+                .long("list-opcodes")
+                .help("List the opcodes of the selected CPU with their addressing modes."),
+        )
+        .arg(
+            Arg::with_name("selfcheck")
+                .long("self-check")
+                .help("Assemble and disassemble every instruction of every CPU and check that they come back unchanged."),
+        )
+        .arg(
+            Arg::with_name("disassemble")
+                .long("disassemble")
+                .value_name("ROM")
+                .requires_all(&["origin", "length"])
+                .conflicts_with_all(&["INPUT", "verify", "dryrun", "check", "patch"])
+                .help("Write the source of the bytes of a ROM to the output file, or to stdout without -o.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("origin")
+                .long("origin")
+                .value_name("ADDRESS")
+                .requires("disassemble")
+                .help("Address of the first byte to disassemble, like $008000.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("length")
+                .long("length")
+                .value_name("SIZE")
+                .requires("disassemble")
+                .help("Number of bytes to disassemble.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("snesmap")
+                .long("snesmap")
+                .value_name("MAP")
+                .requires("disassemble")
+                .help("Memory map of the disassembled ROM: lorom, hirom, hirom-fast, sa1rom or exhirom. (Default: addresses are file offsets)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("importsymbols")
+                .long("import-symbols")
+                .value_name("FILE")
+                .help("Load the labels of a WLA DX symbol file or of a file of 'NAME $ADDRESS' lines before assembling.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("define")
+                .long("define")
+                .short("D")
+                .value_name("NAME=VALUE")
+                .help("Define a symbol before assembling, VALUE is an expression like $7E0000+$200 that can use the symbols defined before it.")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("exportsymbols")
+                .long("export-symbols")
+                .value_name("FILE")
+                .help("Write the labels marked with export to a symbol file in the format given by --symbol-format.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("symbolformat")
+                .long("symbol-format")
+                .value_name("FORMAT")
+                .possible_values(&["wla", "plain"])
+                .help("Format of --export-symbols: WLA DX symbol file or 'NAME $ADDRESS' lines. (Default: wla)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("hexupper")
+                .long("hex-upper")
+                .overrides_with("hexlower")
+                .help("Write hexadecimal in uppercase in the listing, symbol files, cross reference and messages printing addresses. (Default: lowercase in WLA symbol files, uppercase elsewhere)"),
+        )
+        .arg(
+            Arg::with_name("hexlower")
+                .long("hex-lower")
+                .help("Write hexadecimal in lowercase, like --hex-upper."),
+        )
+        .arg(
+            Arg::with_name("hexaddresswidth")
+                .long("hex-address-width")
+                .value_name("DIGITS")
+                .help("Minimum number of hexadecimal digits of the addresses in the listing, plain symbol files, cross reference and messages. (Default: 6)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("emitast")
+                .long("emit-ast")
+                .value_name("FILE")
+                .help("Write the parse tree with the address of each statement as JSON.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("debugfile")
+                .long("debug-file")
+                .value_name("FILE")
+                .help("Write the source file, line and column of every emitted byte range.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("sourcemap")
+                .short("g")
+                .long("source-map")
+                .help("Write the source file and line of every byte range of the output file to OUTPUT.srcmap as CSV.")
+                .conflicts_with_all(&["verify", "dryrun", "check"]),
+        )
+        .arg(
+            Arg::with_name("xref")
+                .long("xref")
+                .value_name("FILE")
+                .help("Write every label with its address and the lines using it.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("romusage")
+                .long("rom-usage")
+                .help("Print the emitted ranges and the remaining space of each freespace region and bank."),
+        )
+        .arg(
+            Arg::with_name("romusagejson")
+                .long("rom-usage-json")
+                .help("Same as --rom-usage but printed as JSON."),
+        )
+        .arg(
+            Arg::with_name("optimize")
+                .long("optimize")
+                .help("Remove redundant instructions like a clc right before a sec, this moves the code after them."),
+        )
+        .arg(
+            Arg::with_name("strictorigin")
+                .long("strict-origin")
+                .help("Make origins going back without origin! and bytes written twice errors instead of warnings."),
+        )
+        .arg(
+            Arg::with_name("strict")
+                .long("strict")
+                .help("Make operands sized by the value of a decimal literal or by the size of labels errors, use lda.b, lda.w or lda.l to give their size."),
+        )
+        .arg(
+            Arg::with_name("nowarnjumptonext")
+                .long("no-warn-jump-to-next")
+                .help("Don't warn about branches and jumps to the next instruction."),
+        )
+        .arg(
+            Arg::with_name("nowarnunusedlabels")
+                .long("no-warn-unused-labels")
+                .help("Don't warn about labels that are never used."),
+        )
+        .arg(
+            Arg::with_name("nowarnrepflags")
+                .long("no-warn-rep-flags")
+                .help("Don't warn about 16-bit immediate values after a rep that didn't clear the M or X flag of their register."),
+        )
+        .arg(
+            Arg::with_name("stats")
+                .long("stats")
+                .help("Print assembly statistics to stderr after a successful assembly."),
+        )
+        .arg(
+            Arg::with_name("nostats")
+                .long("no-stats")
+                .help("Don't print assembly statistics, overrides --stats."),
+        )
+        .arg(
+            Arg::with_name("statsjson")
+                .long("stats-json")
+                .help("Print the assembly statistics and the time taken by each phase as JSON to stdout."),
+        )
+        .arg(
+            Arg::with_name("timereport")
+                .long("time-report")
+                .help("Print the time taken by parsing, each pass and writing the output to stderr."),
+        )
+        .arg(
+            Arg::with_name("verbose")
+                .long("verbose")
+                .short("v")
+                .help("Print the time taken by each phase and the assembly statistics to stderr."),
         );
 
-    let cmd_matches = zeal_args_info.get_matches();
+    let command_line: Vec<OsString> = std::env::args_os().collect();
+    let zeal_args_info = if uses_subcommand(&command_line) {
+        add_subcommands(zeal_args_info)
+    } else {
+        zeal_args_info
+    };
+
+    let mut arguments: Vec<OsString> = command_line.iter().take(1).cloned().collect();
+    arguments.extend(config_file_arguments(&zeal_args_info, &command_line));
+    arguments.extend(command_line.iter().skip(1).cloned());
+
+    let cmd_matches = zeal_args_info.get_matches_from(arguments);
+
+    // - writes the output to stdout
+    let is_stdout_output = cmd_matches.value_of("output") == Some(STDOUT_OUTPUT_NAME);
+    MESSAGES_TO_STDERR.store(is_stdout_output, Ordering::Relaxed);
+
+    if let Some(strip_matches) = cmd_matches.subcommand_matches("strip-header") {
+        strip_header(
+            strip_matches.value_of("ROM").unwrap(),
+            Path::new(strip_matches.value_of("OUTPUT").unwrap()),
+            strip_matches.is_present("force"),
+        );
+        std::process::exit(0);
+    }
+
+    if cmd_matches.is_present("selfcheck") {
+        self_check_systems();
+    }
 
     if cmd_matches.is_present("listcpu") {
         println!("Available CPU:");
 
         for system in SUPPORTED_SYSTEMS.iter() {
-            println!("* {}: {}", system.short_name, system.name);
+            println!("* {}: {}", system_names(system), system.name);
         }
         std::process::exit(0);
     }
 
+    let selected_cpu = match cmd_matches.value_of("cpu") {
+        None => &SNES_CPU,
+        Some(cpu_name) => match find_system(cpu_name) {
+            Some(system) => system,
+            None => {
+                let available_names = SUPPORTED_SYSTEMS
+                    .iter()
+                    .map(|system| system_names(system))
+                    .collect::<Vec<String>>();
+                messageln!("ERROR: Unknown CPU '{}', the available CPU types are: {}.", cpu_name, available_names.join(", "));
+                std::process::exit(1);
+            }
+        },
+    };
+
+    if let Some(fmt_matches) = cmd_matches.subcommand_matches("fmt") {
+        format_file(selected_cpu, fmt_matches);
+        std::process::exit(0);
+    }
+
+    if cmd_matches.is_present("listopcodes") {
+        list_opcodes(selected_cpu);
+        std::process::exit(0);
+    }
+
+    if cmd_matches.is_present("disassemble") {
+        disassemble_rom(selected_cpu, &cmd_matches);
+        std::process::exit(0);
+    }
+
     let input_file = match cmd_matches.value_of("INPUT") {
         None => {
-            println!("ERROR: No input file found!\n");
-            println!("{}", cmd_matches.usage());
+            messageln!("ERROR: No input file found!\n");
+            messageln!("{}", cmd_matches.usage());
             std::process::exit(1);
         }
         Some(result) => result,
     };
 
-    let output_path = match cmd_matches.value_of("output") {
-        None => {
-            println!("ERROR: No output file found!\n");
-            println!("{}", cmd_matches.usage());
-            std::process::exit(1);
-        }
-        Some(result) => Path::new(result),
+    let is_dry_run = cmd_matches.is_present("dryrun");
+    let is_verbose = cmd_matches.is_present("verbose");
+    let is_check = cmd_matches.is_present("check");
+    let verify_path = cmd_matches.value_of("verify").map(Path::new);
+
+    let output_path = cmd_matches.value_of("output").map(Path::new);
+
+    // Verifying compares the assembled bytes with the ROM, there is nothing to write
+    if output_path.is_none() && verify_path.is_none() && !is_dry_run && !is_check {
+        messageln!("ERROR: No output file found!\n");
+        messageln!("{}", cmd_matches.usage());
+        std::process::exit(1);
+    }
+
+    let max_mismatches = match cmd_matches.value_of("verifymax") {
+        None => 16,
+        Some(count) => match count.parse::<usize>() {
+            Ok(count) => count,
+            Err(_) => {
+                messageln!("ERROR: Invalid --verify-max-mismatches '{}'.", count);
+                std::process::exit(1);
+            }
+        },
     };
 
-    let selected_cpu = match cmd_matches.value_of("cpu") {
-        None => &SNES_CPU,
-        Some(cpu_name) => find_system(cpu_name),
+    let output_format = match cmd_matches.value_of("format") {
+        None => OutputFormat::Binary,
+        Some(format_name) => OutputFormat::from_name(format_name).unwrap(),
     };
 
+    let bytes_per_record = match cmd_matches.value_of("bytesperrecord") {
+        None => 16,
+        Some(count) => match count.parse::<usize>() {
+            Ok(count) if count > 0 && count <= output_format.max_bytes_per_record() => count,
+            _ => {
+                messageln!("ERROR: Invalid --bytes-per-record '{}'.", count);
+                std::process::exit(1);
+            }
+        },
+    };
+
+    let gap_fill = match cmd_matches.value_of("gapfill") {
+        None => 0x00,
+        Some(byte_text) => match parse_byte_value(byte_text) {
+            Some(byte) => byte,
+            None => {
+                messageln!("ERROR: Invalid --gap-fill '{}', expected a byte like $FF, 0xFF or 255.", byte_text);
+                std::process::exit(1);
+            }
+        },
+    };
+
+    let mut hex_style = HexStyle::new();
+    if cmd_matches.is_present("hexupper") {
+        hex_style.case = Some(HexCase::Upper);
+    } else if cmd_matches.is_present("hexlower") {
+        hex_style.case = Some(HexCase::Lower);
+    }
+
+    if let Some(width_text) = cmd_matches.value_of("hexaddresswidth") {
+        hex_style.address_width = match width_text.parse::<usize>() {
+            Ok(width) if width > 0 && width <= MAX_ADDRESS_WIDTH => width,
+            _ => {
+                messageln!("ERROR: Invalid --hex-address-width '{}', expected 1 to {} digits.", width_text, MAX_ADDRESS_WIDTH);
+                std::process::exit(1);
+            }
+        };
+    }
+
+    if output_format != OutputFormat::Binary && cmd_matches.is_present("patch") {
+        messageln!("ERROR: Patching mode only works with the raw output format.");
+        std::process::exit(1);
+    }
+
+    if is_stdout_output && cmd_matches.is_present("patch") {
+        messageln!("ERROR: Patching mode needs an existing output file, it can't write to stdout.");
+        std::process::exit(1);
+    }
+
+    if is_stdout_output && cmd_matches.is_present("sourcemap") {
+        messageln!("ERROR: The source map is written next to the output file, it can't be used when writing to stdout.");
+        std::process::exit(1);
+    }
+
+    let source_rom = cmd_matches.value_of("sourcerom").map(PathBuf::from);
+    if let Some(ref source_rom) = source_rom {
+        if !output_format.is_patch() {
+            messageln!("ERROR: --source-rom is only used by the ips and bps output formats.");
+            std::process::exit(1);
+        } else if !source_rom.is_file() {
+            messageln!("ERROR: Source ROM '{}' doesn't exist.", source_rom.display());
+            std::process::exit(1);
+        }
+    }
+
     let mut parser = Parser::new(selected_cpu);
-    parser.set_current_input_file(input_file);
+    parser.set_strict_sizes(cmd_matches.is_present("strict"));
 
+    // - reads the source from stdin, included files are then relative to the current directory
+    let stdin_content = if input_file == "-" {
+        let mut content = String::new();
+        if let Err(why) = std::io::stdin().read_to_string(&mut content) {
+            messageln!("ERROR: Couldn't read the source from stdin: {}", why);
+            std::process::exit(1);
+        }
+
+        parser.set_current_input_string(STDIN_SOURCE_NAME, &content);
+        Some(content)
+    } else {
+        parser.set_current_input_file(input_file);
+        None
+    };
+
+    let print_timings = is_verbose || cmd_matches.is_present("timereport");
+    let mut phase_timings = Vec::new();
+
+    let parse_start_time = Instant::now();
     let mut parse_tree = parser.parse_tree();
+    phase_timings.push(("parse", parse_start_time.elapsed()));
+    if print_timings {
+        eprintln!("Phase timings:");
+        print_timing("parse", parse_start_time.elapsed());
+    }
+
     if parser.has_errors() {
-        process_errors(&parser.error_messages);
+        process_errors(&parser.error_messages, &stdin_content);
     }
 
     let mut symbol_table = SymbolTable::new();
 
-    let mut passes: Vec<Box<TreePass>> = Vec::new();
+    if let Some(symbol_file) = cmd_matches.value_of("importsymbols") {
+        let symbol_path = Path::new(symbol_file);
 
-    passes.push(Box::new(CollectLabelPass::new(selected_cpu)));
-    passes.push(Box::new(ResolveLabelPass::new(selected_cpu)));
-    passes.push(Box::new(InstructionToStatementPass::new(selected_cpu)));
+        match is_wla_symbol_file(symbol_path) {
+            Ok(true) => match read_wla_symbols(symbol_path) {
+                Ok(symbols) => for (name, address) in symbols {
+                    symbol_table.add_imported_label(&name, address);
+                },
+                Err(message) => {
+                    messageln!("ERROR: {}", message);
+                    std::process::exit(1);
+                }
+            },
+            Ok(false) => if let Err(why) = symbol_table.import_from_file(symbol_path) {
+                messageln!("ERROR: Couldn't import symbol file '{}': {}", symbol_file, why);
+                std::process::exit(1);
+            },
+            Err(why) => {
+                messageln!("ERROR: Couldn't open '{}': {}", symbol_file, why);
+                std::process::exit(1);
+            }
+        }
+    }
 
-    for pass in passes.iter_mut() {
-        parse_tree = pass.do_pass(parse_tree, &mut symbol_table);
-        if pass.has_errors() {
-            process_errors(pass.get_error_messages());
+    // In the order given so a define can use the ones before it
+    if let Some(defines) = cmd_matches.values_of("define") {
+        for define in defines {
+            if let Err(message) = add_define(selected_cpu, &mut symbol_table, define) {
+                messageln!("ERROR: Invalid --define '{}': {}", define, message);
+                std::process::exit(1);
+            }
         }
     }
 
+    let mut assemble_options = AssembleOptions::new();
+    assemble_options.warn_jump_to_next = !cmd_matches.is_present("nowarnjumptonext");
+    assemble_options.warn_unused_labels = !cmd_matches.is_present("nowarnunusedlabels");
+    assemble_options.warn_rep_flags = !cmd_matches.is_present("nowarnrepflags");
+    assemble_options.optimize = cmd_matches.is_present("optimize");
+    assemble_options.strict_origin = cmd_matches.is_present("strictorigin");
+    assemble_options.strict_sizes = cmd_matches.is_present("strict");
+    assemble_options.hex_style = hex_style;
+
+    let mut pipeline = assembly_pipeline(selected_cpu, &assemble_options);
+
+    parse_tree = pipeline.run(parse_tree, &mut symbol_table);
+    phase_timings.extend(pipeline.timings.iter().cloned());
+    if print_timings {
+        for &(pass_name, duration) in pipeline.timings.iter() {
+            print_timing(pass_name, duration);
+        }
+    }
+
+    process_errors(&pipeline.error_messages(), &stdin_content);
+
+    // The errors were reported by the passes, nothing else is needed
+    if is_check {
+        std::process::exit(0);
+    }
+
     let mut output_options = OutputWriterOptions::new();
     output_options.create_new = !cmd_matches.is_present("patch");
+    output_options.force = cmd_matches.is_present("force");
+    output_options.format = output_format;
+    output_options.bytes_per_record = bytes_per_record;
+    output_options.split_banks_directory = cmd_matches.value_of("splitbanks").map(PathBuf::from);
+    output_options.keep_empty_banks = cmd_matches.is_present("keepemptybanks");
+    output_options.gap_fill = gap_fill;
+    output_options.track_nodes = verify_path.is_some() || cmd_matches.is_present("sourcemap");
+    output_options.smc_header = cmd_matches.is_present("smcheader");
+    output_options.source_rom = source_rom;
+    output_options.hex_style = hex_style;
+
+    // Nothing is written to the output path when verifying or doing a dry run
+    if let (Some(output_path), None, false, false) = (output_path, verify_path, is_dry_run, is_stdout_output) {
+        if let Err(message) = output_options.check_output_path(output_path) {
+            messageln!("ERROR: {}", message);
+            std::process::exit(1);
+        }
+    }
+
+    let base_directory = match Path::new(input_file).canonicalize() {
+        Ok(input_path) => input_path.parent().unwrap().to_path_buf(),
+        Err(_) => PathBuf::new(),
+    };
+
+    let output_start_time = Instant::now();
+    let (bytes_written, rom_usage, output_errors, io_error) = {
+        let mut output_writer = match output_path {
+            // Kept in memory since the output is written out of order, and when verifying so no file is touched
+            _ if verify_path.is_some() && !is_dry_run => OutputWriter::in_memory(selected_cpu, &output_options),
+            Some(_) if is_stdout_output && !is_dry_run => OutputWriter::in_memory(selected_cpu, &output_options),
+            Some(output_path) if !is_dry_run => match OutputWriter::new(selected_cpu, output_path, &output_options) {
+                Ok(output_writer) => output_writer,
+                Err(why) => {
+                    messageln!("ERROR: Couldn't open output file '{}': {}", output_path.display(), why);
+                    std::process::exit(1);
+                }
+            },
+            _ => OutputWriter::discarding(selected_cpu, &output_options),
+        };
+        output_writer.write(&parse_tree);
+
+        let mut io_error = None;
+        if is_dry_run {
+            // Only the layout of the output was needed
+        } else if let Some(golden_path) = verify_path {
+            if let Err(why) = output_writer.verify(golden_path, max_mismatches) {
+                io_error = Some(format!("Couldn't verify against '{}': {}", golden_path.display(), why));
+            }
+        } else if !has_error_severity(&output_writer.error_messages) {
+            if let Err(why) = output_writer.finalize() {
+                io_error = Some(format!("Couldn't write output file '{}': {}", output_path.unwrap().display(), why));
+            } else if is_stdout_output {
+                let stdout = std::io::stdout();
+                let mut stdout_lock = stdout.lock();
+                if let Err(why) = stdout_lock.write_all(&output_writer.take_bytes()).and_then(|_| stdout_lock.flush()) {
+                    io_error = Some(format!("Couldn't write the output to stdout: {}", why));
+                }
+            } else if cmd_matches.is_present("sourcemap") {
+                let mut source_map_path = output_path.unwrap().as_os_str().to_owned();
+                source_map_path.push(".srcmap");
+
+                let source_map = source_map_to_csv(output_writer.node_ranges(), &base_directory);
+                if let Err(why) = fs::write(&source_map_path, source_map) {
+                    io_error = Some(format!(
+                        "Couldn't write source map '{}': {}",
+                        Path::new(&source_map_path).display(),
+                        why
+                    ));
+                }
+            }
+        }
+
+        let rom_usage = RomUsage::new(&output_writer.emitted_ranges, &parse_tree, &output_writer.snes_map);
+
+        // The temporary output file is removed here if it wasn't finalized
+        (
+            output_writer.bytes_written,
+            rom_usage,
+            std::mem::replace(&mut output_writer.error_messages, Vec::new()),
+            io_error,
+        )
+    };
+
+    phase_timings.push(("output", output_start_time.elapsed()));
+    if print_timings {
+        print_timing("output", output_start_time.elapsed());
+    }
+
+    if let Some(message) = io_error {
+        messageln!("ERROR: {}", message);
+        std::process::exit(1);
+    }
+
+    if !output_errors.is_empty() {
+        process_errors(&output_errors, &stdin_content);
+    }
 
-    let mut output_writer = OutputWriter::new(selected_cpu, output_path, &output_options);
-    output_writer.write(&parse_tree);
+    if cmd_matches.is_present("romusage") {
+        message!("{}", rom_usage.to_text());
+    }
+
+    if cmd_matches.is_present("romusagejson") {
+        messageln!("{}", rom_usage.to_json());
+    }
+
+    if let (Some(symbol_file), false) = (cmd_matches.value_of("exportsymbols"), is_dry_run) {
+        let result = match cmd_matches.value_of("symbolformat") {
+            Some("plain") => symbol_table.export_to_file(Path::new(symbol_file), &hex_style),
+            _ => write_wla_symbols(Path::new(symbol_file), &symbol_table.exported_symbols(), &hex_style),
+        };
+
+        if let Err(why) = result {
+            messageln!("ERROR: Couldn't write symbol file '{}': {}", symbol_file, why);
+            std::process::exit(1);
+        }
+    }
+
+    if let (Some(ast_file), false) = (cmd_matches.value_of("emitast"), is_dry_run) {
+        if let Err(why) = fs::write(ast_file, parse_tree_to_json(selected_cpu, &parse_tree, &base_directory)) {
+            messageln!("ERROR: Couldn't write AST file '{}': {}", ast_file, why);
+            std::process::exit(1);
+        }
+    }
+
+    if let (Some(debug_file), false) = (cmd_matches.value_of("debugfile"), is_dry_run) {
+        if let Err(why) = fs::write(debug_file, debug_info_to_text(selected_cpu, &parse_tree, &base_directory)) {
+            messageln!("ERROR: Couldn't write debug file '{}': {}", debug_file, why);
+            std::process::exit(1);
+        }
+    }
+
+    if let (Some(xref_file), false) = (cmd_matches.value_of("xref"), is_dry_run) {
+        if let Err(why) = fs::write(xref_file, xref_to_text(&symbol_table, &base_directory, &hex_style)) {
+            messageln!("ERROR: Couldn't write cross reference file '{}': {}", xref_file, why);
+            std::process::exit(1);
+        }
+    }
+
+    let print_statistics = is_verbose || (cmd_matches.is_present("stats") && !cmd_matches.is_present("nostats"));
+    if print_statistics || cmd_matches.is_present("statsjson") {
+        let instruction_count = parse_tree
+            .iter()
+            .filter(|node| match node.expression {
+                ParseExpression::FinalInstruction(_) => true,
+                _ => false,
+            })
+            .count();
+
+        let stats = AssemblyStats {
+            instructions: instruction_count,
+            bytes_emitted: bytes_written,
+            labels: symbol_table.label_count(),
+            files_included: parser.file_count() - 1,
+            source_lines: parser.lines_processed,
+            rom_size: match (output_path, verify_path, is_dry_run) {
+                (Some(output_path), None, false) => fs::metadata(output_path).ok().map(|metadata| metadata.len()),
+                _ => None,
+            },
+            elapsed: start_time.elapsed(),
+            phase_timings: phase_timings,
+        };
+
+        if print_statistics {
+            print_stats(&stats);
+        }
+        if cmd_matches.is_present("statsjson") {
+            messageln!("{}", stats_to_json(&stats));
+        }
+    }
 }
@@ -9,13 +9,25 @@ fn snes_argument_size_to_addressing_mode(size: ArgumentSize) -> &'static str {
     }
 }
 
+fn snes_argument_size_to_operand_name(size: ArgumentSize) -> &'static str {
+    match size {
+        ArgumentSize::Word8 => "dp",
+        ArgumentSize::Word16 => "abs",
+        ArgumentSize::Word24 => "long",
+        ArgumentSize::Word32 => "invalid",
+    }
+}
+
 pub static SNES_CPU: SystemDefinition = SystemDefinition {
     short_name: "snes-cpu",
+    aliases: &["65816", "snes", "sfc"],
     name: "Super Nintendo/Super Famicom Ricoh 5A22 (65816 derivate)",
     is_big_endian: false,
     label_size: ArgumentSize::Word16,
+    max_argument_size: ArgumentSize::Word24,
     registers: &["x", "y", "s"],
     size_to_addressing_mode: snes_argument_size_to_addressing_mode,
+    size_to_operand_name: snes_argument_size_to_operand_name,
     instructions: &[
         // adc (dp,x)
         InstructionInfo {
@@ -427,6 +439,13 @@ pub static SNES_CPU: SystemDefinition = SystemDefinition {
             opcode: 0x00,
             arguments: &[],
         },
+        // brk const
+        InstructionInfo {
+            name: "brk",
+            addressing: AddressingMode::SingleArgument,
+            opcode: 0x00,
+            arguments: &[InstructionArgument::Number(ArgumentSize::Word8)],
+        },
         // brl label
         InstructionInfo {
             name: "brl",
@@ -1460,7 +1479,7 @@ pub static SNES_CPU: SystemDefinition = SystemDefinition {
         },
         // phy
         InstructionInfo {
-            name: "pha",
+            name: "phy",
             addressing: AddressingMode::Implied,
             opcode: 0x5A,
             arguments: &[],
@@ -1530,7 +1549,7 @@ pub static SNES_CPU: SystemDefinition = SystemDefinition {
         },
         // rol absolute
         InstructionInfo {
-            name: "lsr",
+            name: "rol",
             addressing: AddressingMode::SingleArgument,
             opcode: 0x2E,
             arguments: &[InstructionArgument::Number(ArgumentSize::Word16)],
@@ -2088,7 +2107,7 @@ pub static SNES_CPU: SystemDefinition = SystemDefinition {
         },
         // txy
         InstructionInfo {
-            name: "txa",
+            name: "txy",
             addressing: AddressingMode::Implied,
             opcode: 0x9B,
             arguments: &[],
@@ -2121,6 +2140,13 @@ pub static SNES_CPU: SystemDefinition = SystemDefinition {
             opcode: 0x42,
             arguments: &[],
         },
+        // wdm const
+        InstructionInfo {
+            name: "wdm",
+            addressing: AddressingMode::SingleArgument,
+            opcode: 0x42,
+            arguments: &[InstructionArgument::Number(ArgumentSize::Word8)],
+        },
         // xba
         InstructionInfo {
             name: "xba",
@@ -2136,4 +2162,6 @@ pub static SNES_CPU: SystemDefinition = SystemDefinition {
             arguments: &[],
         },
     ],
+    accumulator_immediates: &["adc", "and", "bit", "cmp", "eor", "lda", "ora", "sbc"],
+    index_immediates: &["cpx", "cpy", "ldx", "ldy"],
 };
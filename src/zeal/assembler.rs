@@ -0,0 +1,181 @@
+use std::path::Path;
+use zeal::bank_check_pass::*;
+use zeal::collect_label_pass::*;
+use zeal::freespace_pass::*;
+use zeal::hex_style::HexStyle;
+use zeal::instruction_statement_pass::*;
+use zeal::jump_to_next_pass::*;
+use zeal::output_writer::*;
+use zeal::parser::*;
+use zeal::pass::PassPipeline;
+use zeal::peephole_pass::*;
+use zeal::register_width_pass::*;
+use zeal::resolve_label_pass::*;
+use zeal::symbol_table::SymbolTable;
+use zeal::system_definition::*;
+use zeal::unused_label_pass::*;
+use zeal::version_check_pass::*;
+
+pub struct AssembleOptions {
+    pub warn_jump_to_next: bool,
+    pub warn_unused_labels: bool,
+    // Warn about a 16-bit immediate after a rep that didn't clear the flag of its register
+    pub warn_rep_flags: bool,
+    // Remove redundant instructions, this moves the code after them
+    pub optimize: bool,
+    // Origins going back without origin! and bytes written twice are errors instead of warnings
+    pub strict_origin: bool,
+    // Operand sizes taken from the value of a decimal literal or from the size of labels are errors
+    pub strict_sizes: bool,
+    // Byte used between the emitted ranges of the output
+    pub gap_fill: u8,
+    // Hexadecimal of the messages printing addresses
+    pub hex_style: HexStyle,
+}
+
+impl AssembleOptions {
+    pub fn new() -> Self {
+        AssembleOptions {
+            warn_jump_to_next: true,
+            warn_unused_labels: true,
+            warn_rep_flags: true,
+            optimize: false,
+            strict_origin: false,
+            strict_sizes: false,
+            gap_fill: 0x00,
+            hex_style: HexStyle::new(),
+        }
+    }
+}
+
+// Everything known after assembling a file, for tools that need more than the output file
+pub struct AssembleResult {
+    pub output: Vec<u8>,
+    // Errors and warnings of every stage, in the order they were found
+    pub error_messages: Vec<ErrorMessage>,
+    pub symbol_table: SymbolTable,
+}
+
+impl AssembleResult {
+    pub fn has_errors(&self) -> bool {
+        self.error_messages
+            .iter()
+            .any(|error_message| error_message.severity == ErrorSeverity::Error)
+    }
+}
+
+// Passes run on the parse tree, in order. Custom passes can be inserted in the returned pipeline
+// before giving it to assemble_file_with_pipeline().
+pub fn assembly_pipeline(system: &'static SystemDefinition, options: &AssembleOptions) -> PassPipeline {
+    let mut pipeline = PassPipeline::new();
+
+    pipeline.add_pass(Box::new(VersionCheckPass::new()));
+    if options.optimize {
+        pipeline.add_pass(Box::new(PeepholePass::new()));
+    }
+    pipeline.add_pass(Box::new(FreeSpacePass::new(system)));
+    let mut collect_label_pass = CollectLabelPass::new(system);
+    collect_label_pass.set_strict_origin(options.strict_origin);
+    collect_label_pass.set_hex_style(options.hex_style);
+    pipeline.add_pass(Box::new(collect_label_pass));
+    pipeline.add_pass(Box::new(ResolveLabelPass::new(system)));
+    let mut bank_check_pass = BankCheckPass::new(system);
+    bank_check_pass.set_hex_style(options.hex_style);
+    pipeline.add_pass(Box::new(bank_check_pass));
+    let mut register_width_pass = RegisterWidthPass::new(system);
+    register_width_pass.set_warn_rep_flags(options.warn_rep_flags);
+    pipeline.add_pass(Box::new(register_width_pass));
+    if options.warn_jump_to_next {
+        pipeline.add_pass(Box::new(JumpToNextPass::new(system)));
+    }
+    if options.warn_unused_labels {
+        pipeline.add_pass(Box::new(UnusedLabelPass::new()));
+    }
+    pipeline.add_pass(Box::new(InstructionToStatementPass::new(system)));
+
+    pipeline
+}
+
+fn take_error_messages(error_messages: &mut Vec<ErrorMessage>, result: &mut AssembleResult) -> bool {
+    result.error_messages.append(error_messages);
+    result.has_errors()
+}
+
+// Assemble a file in memory, assembly stops at the first stage reporting an error
+pub fn assemble_file(file_path: &Path, system: &'static SystemDefinition, options: &AssembleOptions) -> AssembleResult {
+    assemble_file_with_pipeline(file_path, system, options, assembly_pipeline(system, options))
+}
+
+// Same as assemble_file() with the passes of `pipeline`, the messages of every pass are in the result
+pub fn assemble_file_with_pipeline(
+    file_path: &Path,
+    system: &'static SystemDefinition,
+    options: &AssembleOptions,
+    pipeline: PassPipeline,
+) -> AssembleResult {
+    let mut parser = Parser::new(system);
+    parser.set_strict_sizes(options.strict_sizes);
+    parser.set_current_input_file(&file_path.to_string_lossy());
+
+    assemble_with_parser(parser, system, options, pipeline)
+}
+
+// Assemble source held in memory, includes are relative to the current directory
+pub fn assemble_string(source: &str, system: &'static SystemDefinition, options: &AssembleOptions) -> AssembleResult {
+    assemble_source("<input>", source, system, options)
+}
+
+// Same as assemble_string() with the source named `source_name`, includes are relative to its directory
+pub fn assemble_source(
+    source_name: &str,
+    source: &str,
+    system: &'static SystemDefinition,
+    options: &AssembleOptions,
+) -> AssembleResult {
+    let mut parser = Parser::new(system);
+    parser.set_strict_sizes(options.strict_sizes);
+    parser.set_current_input_string(source_name, source);
+
+    assemble_with_parser(parser, system, options, assembly_pipeline(system, options))
+}
+
+fn assemble_with_parser(
+    parser: Parser,
+    system: &'static SystemDefinition,
+    options: &AssembleOptions,
+    pipeline: PassPipeline,
+) -> AssembleResult {
+    let mut result = AssembleResult {
+        output: Vec::new(),
+        error_messages: Vec::new(),
+        symbol_table: SymbolTable::new(),
+    };
+
+    let mut parser = parser;
+    let mut parse_tree = parser.parse_tree();
+    if take_error_messages(&mut parser.error_messages, &mut result) {
+        return result;
+    }
+
+    let mut pipeline = pipeline;
+    parse_tree = pipeline.run(parse_tree, &mut result.symbol_table);
+    if take_error_messages(&mut pipeline.error_messages(), &mut result) {
+        return result;
+    }
+
+    let mut output_options = OutputWriterOptions::new();
+    output_options.gap_fill = options.gap_fill;
+    output_options.hex_style = options.hex_style;
+
+    let mut output_writer = OutputWriter::in_memory(system, &output_options);
+    output_writer.write(&parse_tree);
+    if take_error_messages(&mut output_writer.error_messages, &mut result) {
+        return result;
+    }
+
+    // Nothing can fail with the output in memory
+    output_writer.finalize().unwrap();
+    result.output = output_writer.take_bytes();
+
+    result
+}
@@ -0,0 +1,128 @@
+use std::fmt::Write;
+use std::path::Path;
+use zeal::lexer::{relative_source_path, Token};
+use zeal::parser::*;
+use zeal::system_definition::*;
+
+fn json_string(text: &str) -> String {
+    let mut result = String::from("\"");
+
+    for character in text.chars() {
+        match character {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\t' => result.push_str("\\t"),
+            character if (character as u32) < 0x20 => {
+                write!(result, "\\u{:04x}", character as u32).unwrap();
+            }
+            character => result.push(character),
+        }
+    }
+
+    result.push('"');
+    result
+}
+
+// Numbers are resolved at this point, registers are kept by name
+fn argument_to_json(argument: &ParseArgument) -> String {
+    match argument {
+        &ParseArgument::NumberLiteral(ref number) => number.number.to_string(),
+        &ParseArgument::Register(ref register_name) => json_string(register_name),
+        &ParseArgument::Identifier(ref identifier) => json_string(identifier),
+        &ParseArgument::Expression(_) => String::from("null"),
+    }
+}
+
+fn arguments_to_json(arguments: &[&ParseArgument]) -> String {
+    let values = arguments
+        .iter()
+        .map(|argument| argument_to_json(argument))
+        .collect::<Vec<String>>();
+
+    format!("[{}]", values.join(","))
+}
+
+fn source_to_json(token: &Token, base_directory: &Path) -> String {
+    format!(
+        "\"file\":{},\"line\":{},\"column\":{},\"end_column\":{}",
+        json_string(&relative_source_path(&token.source_file, base_directory)),
+        token.line,
+        token.start_column,
+        token.end_column
+    )
+}
+
+fn instruction_to_json(system: &SystemDefinition, instruction: &FinalInstruction) -> String {
+    let (info, arguments) = match instruction {
+        &FinalInstruction::ImpliedInstruction(info) => (info, vec![]),
+        &FinalInstruction::SingleArgumentInstruction(info, ref argument) => (info, vec![argument]),
+        &FinalInstruction::TwoArgumentInstruction(info, ref argument1, ref argument2) => {
+            (info, vec![argument1, argument2])
+        }
+    };
+
+    format!(
+        ",\"opcode\":{},\"opcode_byte\":{},\"addressing\":{},\"operands\":{}",
+        json_string(info.name),
+        info.opcode,
+        json_string(&instruction_addressing_to_string(system, info)),
+        arguments_to_json(&arguments)
+    )
+}
+
+// Fields specific to each kind of node
+fn expression_to_json(system: &SystemDefinition, expression: &ParseExpression) -> String {
+    match expression {
+        &ParseExpression::FinalInstruction(ref instruction) => instruction_to_json(system, instruction),
+        &ParseExpression::Label(ref name)
+        | &ParseExpression::ExportLabel(ref name)
+        | &ParseExpression::StructDefinition(ref name, _) => format!(",\"name\":{}", json_string(name)),
+        &ParseExpression::OriginStatement(ref number, allow_rewind) => {
+            format!(",\"value\":{},\"allow_rewind\":{}", number.number, allow_rewind)
+        }
+        &ParseExpression::DirectPageBase(value) | &ParseExpression::DataBank(value) => format!(",\"value\":{}", value),
+        &ParseExpression::SkipBytes(size) => format!(",\"size\":{}", size),
+        &ParseExpression::BankStatement(bank) => format!(",\"bank\":{}", bank),
+        &ParseExpression::SnesMapStatement(ref map_mode) => format!(
+            ",\"map\":{}",
+            json_string(map_mode.name())
+        ),
+        &ParseExpression::IncBinStatement(ref path, offset, size, _) => {
+            format!(",\"path\":{},\"offset\":{},\"size\":{}", json_string(path), offset, size)
+        }
+        &ParseExpression::FreeSpace(FreeSpaceRegion::Range(start_address, end_address)) => {
+            format!(",\"start\":{},\"end\":{}", start_address, end_address)
+        }
+        &ParseExpression::DataBytes(ref elements)
+        | &ParseExpression::DataWords(ref elements)
+        | &ParseExpression::DataLongs(ref elements) => format!(
+            ",\"values\":{}",
+            arguments_to_json(&elements.iter().map(|element| &element.argument).collect::<Vec<&ParseArgument>>())
+        ),
+        _ => String::new(),
+    }
+}
+
+// The parse tree after the passes as JSON, one node per line with the keys always in the same order
+pub fn parse_tree_to_json(system: &SystemDefinition, parse_tree: &Vec<ParseNode>, base_directory: &Path) -> String {
+    let nodes = parse_tree
+        .iter()
+        .map(|node| {
+            format!(
+                "{{\"kind\":{},\"text\":{},{},\"address\":{}{}}}",
+                json_string(node.expression.kind_name()),
+                json_string(&node.expression.to_string()),
+                source_to_json(&node.start_token, base_directory),
+                match node.address {
+                    Some(address) => address.to_string(),
+                    None => String::from("null"),
+                },
+                expression_to_json(system, &node.expression)
+            )
+        })
+        .collect::<Vec<String>>();
+
+    format!("{{\"nodes\":[\n{}\n]}}\n", nodes.join(",\n"))
+}
@@ -0,0 +1,246 @@
+use zeal::hex_style::HexStyle;
+use zeal::lexer::NumberLiteral;
+use zeal::node_size::*;
+use zeal::parser::*;
+use zeal::pass::{DiagnosticSink, TreePass};
+use zeal::symbol_table::SymbolTable;
+use zeal::system_definition::*;
+
+// Banks where the low RAM and the I/O registers are visible below $8000
+fn is_system_bank(bank: u32) -> bool {
+    bank <= 0x3F || (bank >= 0x80 && bank <= 0xBF)
+}
+
+// Whether an absolute operand reaches the address with the data bank register set to `data_bank`
+fn is_reachable_from_bank(address: u32, data_bank: u32) -> bool {
+    let bank = address >> 16;
+    let offset = address & 0xFFFF;
+
+    if bank == data_bank {
+        return true;
+    }
+
+    if is_system_bank(data_bank) {
+        // Low RAM mirror of $7E0000-$7E1FFF and the I/O registers of the system banks
+        (bank == 0x7E && offset < 0x2000) || (is_system_bank(bank) && offset < 0x8000)
+    } else {
+        false
+    }
+}
+
+// Warn about operands whose address can't be reached with the direct page and data bank registers assumed by
+// dpbase and databank, and about jmp and jsr leaving the program bank. tcd after lda #constant and plb after phk,
+// pea or lda #constant then pha are followed.
+// Runs after ResolveLabelPass so labels are replaced by their full address.
+pub struct BankCheckPass {
+    system: &'static SystemDefinition,
+    diagnostics: DiagnosticSink,
+    // None once the register is set by code the pass can't follow
+    direct_page: Option<u32>,
+    data_bank: Option<u32>,
+    accumulator: Option<u32>,
+    // Byte on top of the stack
+    pushed_byte: Option<u32>,
+    hex_style: HexStyle,
+}
+
+impl BankCheckPass {
+    pub fn new(system: &'static SystemDefinition) -> Self {
+        BankCheckPass {
+            system: system,
+            diagnostics: DiagnosticSink::new(),
+            // D is $0000 after reset and the data bank is unknown until databank or plb
+            direct_page: Some(0),
+            data_bank: None,
+            accumulator: None,
+            pushed_byte: None,
+            hex_style: HexStyle::new(),
+        }
+    }
+
+    pub fn set_hex_style(&mut self, hex_style: HexStyle) {
+        self.hex_style = hex_style;
+    }
+
+    // Tracks the constants loaded in the accumulator and pushed to the stack from one instruction to the next
+    fn follow_registers(&mut self, node: &ParseNode) {
+        let accumulator = self.accumulator.take();
+        let pushed_byte = self.pushed_byte.take();
+
+        match node.expression {
+            ParseExpression::ImmediateInstruction(ref opcode_name, ParseArgument::NumberLiteral(ref number))
+                if opcode_name == "lda" =>
+            {
+                self.accumulator = Some(number.number);
+            }
+            ParseExpression::SingleArgumentInstruction(ref opcode_name, ParseArgument::NumberLiteral(ref number))
+                if opcode_name == "pea" =>
+            {
+                // The low byte is pushed last
+                self.pushed_byte = Some(number.number & 0xFF);
+            }
+            ParseExpression::ImpliedInstruction(ref opcode_name) => match opcode_name.as_str() {
+                "tcd" => {
+                    self.direct_page = accumulator.map(|value| value & 0xFFFF);
+                    self.accumulator = accumulator;
+                }
+                "pha" => {
+                    self.pushed_byte = accumulator.map(|value| value & 0xFF);
+                    self.accumulator = accumulator;
+                }
+                "phk" => {
+                    self.pushed_byte = node.address.map(|address| address >> 16);
+                    self.accumulator = accumulator;
+                }
+                "plb" => {
+                    self.data_bank = pushed_byte;
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+
+    // Absolute jmp and jsr, and their (abs,x) pointer, stay in the program bank of the instruction
+    fn check_jump(&mut self, node: &ParseNode, opcode_name: &str, number: &NumberLiteral) {
+        let program_bank = match node.address {
+            Some(address) => address >> 16,
+            None => return,
+        };
+        let bank = number.number >> 16;
+        let offset = number.number & 0xFFFF;
+
+        // Only full addresses are checked, the ROM at $8000-$FFFF of banks $00-$3F is mirrored in $80-$BF
+        if number.number <= 0xFFFF
+            || is_reachable_from_bank(number.number, program_bank)
+            || (bank ^ 0x80 == program_bank && is_system_bank(bank) && offset >= 0x8000)
+        {
+            return;
+        }
+
+        self.diagnostics.add_warning(
+            &format!(
+                "Address {} is in bank {} but '{}' stays in bank {}, use jml or jsl to change banks.",
+                self.hex_style.address(number.number),
+                self.hex_style.byte(bank),
+                opcode_name,
+                self.hex_style.byte(program_bank)
+            ),
+            node.start_token.clone(),
+        );
+    }
+
+    fn check_operand(&mut self, node: &ParseNode, opcode_name: &str, number: &NumberLiteral) {
+        if opcode_name == "jmp" || opcode_name == "jsr" {
+            match node.expression {
+                // The pointer of jmp (abs) is in bank $00
+                ParseExpression::SingleArgumentInstruction(..) | ParseExpression::IndexedIndirectInstruction(..) => {
+                    self.check_jump(node, opcode_name, number);
+                }
+                _ => {}
+            }
+            return;
+        }
+
+        // jml and jsl are long, pea pushes a value and branches are relative
+        if ["jml", "jsl", "pea"].contains(&opcode_name)
+            || find_instruction_argument_size(self.system, opcode_name, &[AddressingMode::Relative]).is_some()
+        {
+            return;
+        }
+
+        match (number.argument_size, self.direct_page, self.data_bank) {
+            // Numbers that fit the operand are offsets written by hand, only full addresses are checked
+            // The direct page is always in bank $00, low RAM is also reached through its mirrors
+            (ArgumentSize::Word8, Some(direct_page), _) if number.number > 0xFF => {
+                let offset = (number.number & 0xFFFF) as i64 - direct_page as i64;
+
+                if !is_reachable_from_bank(number.number, 0) {
+                    self.diagnostics.add_warning(
+                        &format!(
+                            "Address {} is in bank {} but the direct page is in bank $00.",
+                            self.hex_style.address(number.number),
+                            self.hex_style.byte(number.number >> 16)
+                        ),
+                        node.start_token.clone(),
+                    );
+                } else if offset < 0 || offset > 0xFF {
+                    self.diagnostics.add_warning(
+                        &format!(
+                            "Address {} can't be reached from the direct page at ${}.",
+                            self.hex_style.address(number.number),
+                            self.hex_style.digits(direct_page, 4)
+                        ),
+                        node.start_token.clone(),
+                    );
+                }
+            }
+            (ArgumentSize::Word16, _, Some(data_bank)) if number.number > 0xFFFF => {
+                if !is_reachable_from_bank(number.number, data_bank) {
+                    self.diagnostics.add_warning(
+                        &format!(
+                            "Address {} is in bank {} but the data bank is assumed to be {}.",
+                            self.hex_style.address(number.number),
+                            self.hex_style.byte(number.number >> 16),
+                            self.hex_style.byte(data_bank)
+                        ),
+                        node.start_token.clone(),
+                    );
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl TreePass for BankCheckPass {
+    fn name(&self) -> &'static str {
+        "bank-check"
+    }
+
+    fn do_pass(
+        &mut self,
+        parse_tree: Vec<ParseNode>,
+        _symbol_table: &mut SymbolTable,
+    ) -> (Vec<ParseNode>, Vec<ErrorMessage>) {
+        for node in parse_tree.iter() {
+            match node.expression {
+                ParseExpression::DirectPageBase(value) => {
+                    self.direct_page = Some(value);
+                    continue;
+                }
+                ParseExpression::DataBank(value) => {
+                    self.data_bank = Some(value);
+                    continue;
+                }
+                // Code can jump here from anywhere
+                ParseExpression::Label(_) | ParseExpression::OriginStatement(..) => {
+                    self.accumulator = None;
+                    self.pushed_byte = None;
+                    continue;
+                }
+                // Stack relative operands don't depend on D or B
+                ParseExpression::IndexedInstruction(_, _, ParseArgument::Register(ref register_name))
+                    if register_name == "s" => {}
+                ParseExpression::SingleArgumentInstruction(ref opcode_name, ParseArgument::NumberLiteral(ref number))
+                | ParseExpression::IndexedInstruction(ref opcode_name, ParseArgument::NumberLiteral(ref number), _)
+                | ParseExpression::IndirectInstruction(ref opcode_name, ParseArgument::NumberLiteral(ref number))
+                | ParseExpression::IndirectLongInstruction(ref opcode_name, ParseArgument::NumberLiteral(ref number))
+                | ParseExpression::IndexedIndirectInstruction(ref opcode_name, ParseArgument::NumberLiteral(ref number), _)
+                | ParseExpression::IndirectIndexedInstruction(ref opcode_name, ParseArgument::NumberLiteral(ref number), _)
+                | ParseExpression::IndirectIndexedLongInstruction(
+                    ref opcode_name,
+                    ParseArgument::NumberLiteral(ref number),
+                    _,
+                ) => {
+                    self.check_operand(node, opcode_name, number);
+                }
+                _ => {}
+            }
+
+            self.follow_registers(node);
+        }
+
+        return (parse_tree, self.diagnostics.take_messages());
+    }
+}
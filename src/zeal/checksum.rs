@@ -0,0 +1,255 @@
+use std::fmt;
+use std::io::{self, Read};
+
+#[derive(Clone, PartialEq)]
+pub enum Checksum {
+    Crc32(u32),
+    Md5([u8; 16]),
+}
+
+impl fmt::Display for Checksum {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &Checksum::Crc32(value) => write!(f, "CRC32 ${:08X}", value),
+            &Checksum::Md5(ref digest) => {
+                write!(f, "MD5 \"")?;
+                for byte in digest.iter() {
+                    write!(f, "{:02x}", byte)?;
+                }
+                write!(f, "\"")
+            }
+        }
+    }
+}
+
+// Parse a MD5 digest written as 32 hexadecimal characters
+pub fn parse_md5_digest(text: &str) -> Option<[u8; 16]> {
+    if text.len() != 32 || !text.is_ascii() {
+        return None;
+    }
+
+    let mut digest = [0u8; 16];
+    for index in 0..16 {
+        match u8::from_str_radix(&text[index * 2..index * 2 + 2], 16) {
+            Ok(byte) => digest[index] = byte,
+            Err(_) => return None,
+        }
+    }
+
+    Some(digest)
+}
+
+// Computes the same kind of checksum as the expected one
+pub enum ChecksumBuilder {
+    Crc32(Crc32),
+    Md5(Md5),
+}
+
+impl ChecksumBuilder {
+    pub fn new(expected: &Checksum) -> Self {
+        match expected {
+            &Checksum::Crc32(_) => ChecksumBuilder::Crc32(Crc32::new()),
+            &Checksum::Md5(_) => ChecksumBuilder::Md5(Md5::new()),
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            &mut ChecksumBuilder::Crc32(ref mut crc32) => crc32.update(data),
+            &mut ChecksumBuilder::Md5(ref mut md5) => md5.update(data),
+        }
+    }
+
+    pub fn finish(self) -> Checksum {
+        match self {
+            ChecksumBuilder::Crc32(crc32) => Checksum::Crc32(crc32.finish()),
+            ChecksumBuilder::Md5(md5) => Checksum::Md5(md5.finish()),
+        }
+    }
+}
+
+// Computes the checksum of everything read through it when a checksum is expected
+pub struct ChecksumReader<R> {
+    inner: R,
+    builder: Option<ChecksumBuilder>,
+}
+
+impl<R: Read> ChecksumReader<R> {
+    pub fn new(inner: R, expected: &Option<Checksum>) -> Self {
+        ChecksumReader {
+            inner: inner,
+            builder: match expected {
+                &Some(ref expected) => Some(ChecksumBuilder::new(expected)),
+                &None => None,
+            },
+        }
+    }
+
+    pub fn finish(self) -> Option<Checksum> {
+        match self.builder {
+            Some(builder) => Some(builder.finish()),
+            None => None,
+        }
+    }
+}
+
+impl<R: Read> Read for ChecksumReader<R> {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        let read_size = self.inner.read(buffer)?;
+        if let Some(ref mut builder) = self.builder {
+            builder.update(&buffer[..read_size]);
+        }
+
+        Ok(read_size)
+    }
+}
+
+// CRC-32 (IEEE 802.3), same as zlib and most ROM tools
+pub struct Crc32 {
+    table: [u32; 256],
+    value: u32,
+}
+
+impl Crc32 {
+    pub fn new() -> Self {
+        let mut table = [0u32; 256];
+        for index in 0..256 {
+            let mut value = index as u32;
+            for _ in 0..8 {
+                value = if value & 1 != 0 {
+                    0xEDB88320 ^ (value >> 1)
+                } else {
+                    value >> 1
+                };
+            }
+            table[index] = value;
+        }
+
+        Crc32 {
+            table: table,
+            value: 0xFFFFFFFF,
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        for byte in data.iter() {
+            let index = ((self.value ^ (*byte as u32)) & 0xFF) as usize;
+            self.value = self.table[index] ^ (self.value >> 8);
+        }
+    }
+
+    pub fn finish(self) -> u32 {
+        self.value ^ 0xFFFFFFFF
+    }
+}
+
+// MD5 as described in RFC 1321
+pub struct Md5 {
+    state: [u32; 4],
+    buffer: Vec<u8>,
+    length: u64,
+}
+
+static MD5_SHIFTS: [u32; 64] = [
+    7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22, 5, 9, 14, 20, 5, 9, 14, 20, 5, 9,
+    14, 20, 5, 9, 14, 20, 4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23, 6, 10, 15,
+    21, 6, 10, 15, 21, 6, 10, 15, 21, 6, 10, 15, 21,
+];
+
+static MD5_CONSTANTS: [u32; 64] = [
+    0xd76aa478, 0xe8c7b756, 0x242070db, 0xc1bdceee, 0xf57c0faf, 0x4787c62a, 0xa8304613, 0xfd469501,
+    0x698098d8, 0x8b44f7af, 0xffff5bb1, 0x895cd7be, 0x6b901122, 0xfd987193, 0xa679438e, 0x49b40821,
+    0xf61e2562, 0xc040b340, 0x265e5a51, 0xe9b6c7aa, 0xd62f105d, 0x02441453, 0xd8a1e681, 0xe7d3fbc8,
+    0x21e1cde6, 0xc33707d6, 0xf4d50d87, 0x455a14ed, 0xa9e3e905, 0xfcefa3f8, 0x676f02d9, 0x8d2a4c8a,
+    0xfffa3942, 0x8771f681, 0x6d9d6122, 0xfde5380c, 0xa4beea44, 0x4bdecfa9, 0xf6bb4b60, 0xbebfbc70,
+    0x289b7ec6, 0xeaa127fa, 0xd4ef3085, 0x04881d05, 0xd9d4d039, 0xe6db99e5, 0x1fa27cf8, 0xc4ac5665,
+    0xf4292244, 0x432aff97, 0xab9423a7, 0xfc93a039, 0x655b59c3, 0x8f0ccc92, 0xffeff47d, 0x85845dd1,
+    0x6fa87e4f, 0xfe2ce6e0, 0xa3014314, 0x4e0811a1, 0xf7537e82, 0xbd3af235, 0x2ad7d2bb, 0xeb86d391,
+];
+
+impl Md5 {
+    pub fn new() -> Self {
+        Md5 {
+            state: [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476],
+            buffer: Vec::with_capacity(64),
+            length: 0,
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.length = self.length.wrapping_add(data.len() as u64);
+
+        for byte in data.iter() {
+            self.buffer.push(*byte);
+            if self.buffer.len() == 64 {
+                let block = self.buffer.clone();
+                self.process_block(&block);
+                self.buffer.clear();
+            }
+        }
+    }
+
+    pub fn finish(mut self) -> [u8; 16] {
+        let bit_length = self.length.wrapping_mul(8);
+
+        let mut padding = vec![0x80u8];
+        while (self.buffer.len() + padding.len()) % 64 != 56 {
+            padding.push(0);
+        }
+        for index in 0..8 {
+            padding.push((bit_length >> (index * 8)) as u8);
+        }
+
+        let length = self.length;
+        self.update(&padding);
+        self.length = length;
+
+        let mut digest = [0u8; 16];
+        for (index, word) in self.state.iter().enumerate() {
+            for byte_index in 0..4 {
+                digest[index * 4 + byte_index] = (word >> (byte_index * 8)) as u8;
+            }
+        }
+
+        digest
+    }
+
+    fn process_block(&mut self, block: &[u8]) {
+        let mut words = [0u32; 16];
+        for index in 0..16 {
+            words[index] = (block[index * 4] as u32)
+                | ((block[index * 4 + 1] as u32) << 8)
+                | ((block[index * 4 + 2] as u32) << 16)
+                | ((block[index * 4 + 3] as u32) << 24);
+        }
+
+        let mut a = self.state[0];
+        let mut b = self.state[1];
+        let mut c = self.state[2];
+        let mut d = self.state[3];
+
+        for round in 0..64 {
+            let (f, word_index) = match round / 16 {
+                0 => ((b & c) | (!b & d), round),
+                1 => ((d & b) | (!d & c), (5 * round + 1) % 16),
+                2 => (b ^ c ^ d, (3 * round + 5) % 16),
+                _ => (c ^ (b | !d), (7 * round) % 16),
+            };
+
+            let rotated = a.wrapping_add(f)
+                .wrapping_add(MD5_CONSTANTS[round])
+                .wrapping_add(words[word_index])
+                .rotate_left(MD5_SHIFTS[round]);
+
+            a = d;
+            d = c;
+            c = b;
+            b = b.wrapping_add(rotated);
+        }
+
+        self.state[0] = self.state[0].wrapping_add(a);
+        self.state[1] = self.state[1].wrapping_add(b);
+        self.state[2] = self.state[2].wrapping_add(c);
+        self.state[3] = self.state[3].wrapping_add(d);
+    }
+}
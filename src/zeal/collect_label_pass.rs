@@ -1,329 +1,461 @@
+use zeal::hex_style::HexStyle;
+use zeal::lexer::{NumberLiteral, NumberLiteralKind, Token};
+use zeal::node_size::*;
+use zeal::output_writer::snes_map_function;
 use zeal::parser::*;
 use zeal::system_definition::*;
-use zeal::pass::TreePass;
+use zeal::pass::{DiagnosticSink, TreePass};
 use zeal::symbol_table::*;
 
+// File offsets written by statements following each other, starting at the statement of the token
+struct WrittenRange {
+    start_offset: u32,
+    end_offset: u32,
+    token: Token,
+}
+
+// Address of an origin using symbols. The addresses after an origin depend on it so its symbols
+// must be defined before it, by --define, an imported symbol file or a label above it.
+fn evaluate_origin(
+    origin_address: &OriginAddress,
+    symbol_table: &mut SymbolTable,
+    origin_token: &Token,
+    current_address: u32,
+) -> Result<u32, String> {
+    let mut identifiers = origin_address.address.identifiers();
+    if let Some(ref bank) = origin_address.bank {
+        identifiers.extend(bank.identifiers());
+    }
+
+    for identifier in identifiers.iter() {
+        if !symbol_table.has_label(identifier) {
+            return Err(format!(
+                "origin uses '{}' which isn't defined before it, symbols used by an origin must be defined above it.",
+                identifier
+            ));
+        }
+
+        symbol_table.add_reference(identifier, origin_token);
+    }
+
+    let bank = match origin_address.bank {
+        Some(ref bank) => Some(bank.evaluate(symbol_table, current_address)?),
+        None => None,
+    };
+
+    origin_address_value(bank, origin_address.address.evaluate(symbol_table, current_address)?)
+}
+
 pub struct CollectLabelPass {
     system: &'static SystemDefinition,
-    pub error_messages: Vec<ErrorMessage>,
+    diagnostics: DiagnosticSink,
+    // Start address, end address (exclusive) and token of each enum seen so far
+    enum_ranges: Vec<(u32, u32, Token)>,
+    snes_map: Option<SnesMap>,
+    written_ranges: Vec<WrittenRange>,
+    // Position in the output file, set by origin and kept by .bank which only changes the addresses
+    file_offset: u32,
+    // False after an origin, the next statement starts a new range
+    continues_range: bool,
+    // Report origins going back and bytes written twice as errors instead of warnings
+    strict_origin: bool,
+    hex_style: HexStyle,
 }
 
 impl CollectLabelPass {
     pub fn new(system: &'static SystemDefinition) -> Self {
         CollectLabelPass {
             system: system,
-            error_messages: Vec::new(),
+            diagnostics: DiagnosticSink::new(),
+            enum_ranges: Vec::new(),
+            snes_map: None,
+            written_ranges: Vec::new(),
+            file_offset: 0,
+            continues_range: false,
+            strict_origin: false,
+            hex_style: HexStyle::new(),
         }
     }
 
-    // fn add_error_message(&mut self, error_message: &str, offending_token: Token<'a>) {
-    //     let new_message = ErrorMessage {
-    //         message: error_message.to_owned(),
-    //         token: offending_token,
-    //         severity: ErrorSeverity::Error
-    //     };
-
-    //     self.error_messages.push(new_message);
-    // }
-
-    fn find_instruction_argument_size(
-        &self,
-        opcode_name: &str,
-        possible_addressings: &[AddressingMode],
-    ) -> Option<ArgumentSize> {
-        for instruction in self.system.instructions.iter() {
-            if instruction.name == opcode_name {
-                for addressing_mode in possible_addressings.iter() {
-                    if &instruction.addressing == addressing_mode {
-                        for argument in instruction.arguments {
-                            match argument {
-                                &InstructionArgument::Number(argument_size) => {
-                                    return Some(argument_size);
-                                }
-                                &InstructionArgument::Numbers(ref sizes) => if sizes.len() > 0 {
-                                    return Some(sizes[0]);
-                                },
-                                _ => {}
-                            };
-                        }
-                    }
+    pub fn set_strict_origin(&mut self, strict_origin: bool) {
+        self.strict_origin = strict_origin;
+    }
+
+    pub fn set_hex_style(&mut self, hex_style: HexStyle) {
+        self.hex_style = hex_style;
+    }
+
+    fn add_origin_message(&mut self, message: &str, token: Token) {
+        if self.strict_origin {
+            self.diagnostics.add_error(message, token);
+        } else {
+            self.diagnostics.add_warning(message, token);
+        }
+    }
+
+    // An origin going back in the file is either patching another region or a paste error overwriting earlier output.
+    // File offsets are compared since banks can be in a different order on the bus, like $C00000 before $808000 in HiROM.
+    fn check_origin(&mut self, node: &ParseNode, address: u32, allow_rewind: bool, current_address: u32) {
+        let origin_offset = snes_map_function(&self.snes_map)(address);
+        let is_going_back = match self.written_ranges.last() {
+            Some(range) => origin_offset < range.end_offset,
+            None => false,
+        };
+
+        if is_going_back && !allow_rewind {
+            self.add_origin_message(
+                &format!(
+                    "origin {} goes back before the current address {}, use origin! if this is intended.",
+                    self.hex_style.address(address),
+                    self.hex_style.address(current_address)
+                ),
+                node.start_token.clone(),
+            );
+        }
+
+        self.file_offset = origin_offset;
+        self.continues_range = false;
+    }
+
+    // Compare the file offsets written by the statement with those of the statements before it,
+    // mirrors like $808000 and $008000 in LoROM are the same bytes
+    fn check_written(&mut self, node: &ParseNode, address: u32, size: u32) {
+        if size == 0 {
+            return;
+        }
+
+        let start_offset = self.file_offset;
+        let end_offset = start_offset + size;
+
+        let overwritten_token = self.written_ranges
+            .iter()
+            .find(|range| start_offset < range.end_offset && range.start_offset < end_offset)
+            .map(|range| range.token.clone());
+
+        if let Some(overwritten_token) = overwritten_token {
+            self.add_origin_message(
+                &format!(
+                    "Statement at {} overwrites bytes already written by the code starting at {}({},{}).",
+                    self.hex_style.address(address),
+                    overwritten_token.source_file,
+                    overwritten_token.line,
+                    overwritten_token.start_column
+                ),
+                node.start_token.clone(),
+            );
+        }
+
+        match self.written_ranges.last_mut() {
+            Some(range) if self.continues_range && range.end_offset == start_offset => {
+                range.end_offset = end_offset;
+            }
+            _ => {
+                self.written_ranges.push(WrittenRange {
+                    start_offset: start_offset,
+                    end_offset: end_offset,
+                    token: node.start_token.clone(),
+                });
+            }
+        }
+
+        self.continues_range = true;
+    }
+
+    // Bytes past the end of a bank are written to the start of the next bank in the output file,
+    // which is past the ROM when that bank isn't mapped to it
+    fn check_bank_end(&mut self, node: &ParseNode, start_address: u32, size: u32) {
+        if size == 0 {
+            return;
+        }
+
+        // Past the last address, no ROM goes that far
+        let end_address = start_address.checked_add(size - 1).unwrap_or(u32::max_value());
+        if start_address >> 16 == end_address >> 16 {
+            return;
+        }
+
+        let is_past_rom = match self.snes_map {
+            Some(ref snes_map) => !snes_map.is_rom_address((end_address & 0xFF0000) | 0x8000),
+            None => end_address > 0xFFFFFF,
+        };
+
+        if is_past_rom {
+            self.diagnostics.add_error(
+                &format!(
+                    "Statement at {} ends at {} which is past the end of the ROM.",
+                    self.hex_style.address(start_address),
+                    self.hex_style.address(end_address)
+                ),
+                node.start_token.clone(),
+            );
+        } else {
+            self.diagnostics.add_warning(
+                &format!(
+                    "Statement at {} crosses the end of bank {} and ends at {}.",
+                    self.hex_style.address(start_address),
+                    self.hex_style.byte(start_address >> 16),
+                    self.hex_style.address(end_address)
+                ),
+                node.start_token.clone(),
+            );
+        }
+    }
+
+    fn field_size(&mut self, field: &StorageField, symbol_table: &mut SymbolTable, current_address: u32) -> u32 {
+        for identifier in field.size.identifiers() {
+            symbol_table.add_reference(identifier, &field.token);
+        }
+
+        match field.size.evaluate(symbol_table, current_address) {
+            Ok(size) if size >= 0 => size as u32,
+            Ok(size) => {
+                self.diagnostics.add_error(
+                    &format!("Size of field '{}' is negative ({}).", field.name, size),
+                    field.token.clone(),
+                );
+                0
+            }
+            Err(message) => {
+                self.diagnostics.add_error(&message, field.token.clone());
+                0
+            }
+        }
+    }
+
+    // Struct and enum fields define symbols like labels do, defining one again would silently change its value
+    fn define_field_symbol(&mut self, symbol_name: &str, value: u32, token: &Token, symbol_table: &mut SymbolTable) {
+        let qualified_name = symbol_table.qualified_name(symbol_name);
+        if symbol_table.is_imported(&qualified_name) {
+            self.diagnostics.add_error(
+                &format!("Symbol '{}' conflicts with a symbol of the same name from the imported symbol file or --define.", qualified_name),
+                token.clone(),
+            );
+        } else if symbol_table.has_label_in_namespace(symbol_name) {
+            self.diagnostics.add_error(&format!("Symbol '{}' is already defined.", qualified_name), token.clone());
+        } else {
+            symbol_table.add_or_update_label(symbol_name, value);
+        }
+    }
+
+    // Register StructName.field offset constants and StructName.size
+    fn collect_struct(
+        &mut self,
+        struct_token: &Token,
+        struct_name: &str,
+        fields: &[StorageField],
+        symbol_table: &mut SymbolTable,
+        current_address: u32,
+    ) {
+        let mut offset: u32 = 0;
+
+        for field in fields.iter() {
+            self.define_field_symbol(&format!("{}.{}", struct_name, field.name), offset, &field.token, symbol_table);
+            offset += self.field_size(field, symbol_table, current_address);
+        }
+
+        self.define_field_symbol(&format!("{}.size", struct_name), offset, struct_token, symbol_table);
+    }
+
+    // Assign consecutive addresses starting at the enum base to each field
+    fn collect_enum(
+        &mut self,
+        enum_token: &Token,
+        start_address: u32,
+        end_address: Option<u32>,
+        fields: &[StorageField],
+        symbol_table: &mut SymbolTable,
+        current_address: u32,
+    ) {
+        let mut enum_address = start_address;
+        let mut exceeded_end = false;
+
+        for field in fields.iter() {
+            self.define_field_symbol(&field.name, enum_address, &field.token, symbol_table);
+            // Saturates at $FFFFFFFF, an end address there can't be exceeded
+            let field_size = self.field_size(field, symbol_table, current_address);
+            enum_address = enum_address.saturating_add(field_size);
+
+            if let Some(end_address) = end_address {
+                let is_past_end = end_address.checked_add(1).map_or(false, |limit| enum_address > limit);
+                if is_past_end && !exceeded_end {
+                    self.diagnostics.add_warning(
+                        &format!(
+                            "Field '{}' ends at {} which is past the end address {} of the enum.",
+                            field.name,
+                            self.hex_style.value(enum_address - 1),
+                            self.hex_style.value(end_address)
+                        ),
+                        field.token.clone(),
+                    );
+                    exceeded_end = true;
                 }
             }
         }
 
-        return None;
+        if enum_address == start_address {
+            return;
+        }
+
+        let overlapping_tokens: Vec<Token> = self.enum_ranges
+            .iter()
+            .filter(|&&(other_start, other_end, _)| start_address < other_end && other_start < enum_address)
+            .map(|&(_, _, ref token)| token.clone())
+            .collect();
+
+        for other_token in overlapping_tokens {
+            self.diagnostics.add_warning(
+                &format!(
+                    "enum {}..{} overlaps the enum defined at {}({},{}).",
+                    self.hex_style.value(start_address),
+                    self.hex_style.value(enum_address - 1),
+                    other_token.source_file,
+                    other_token.line,
+                    other_token.start_column
+                ),
+                enum_token.clone(),
+            );
+        }
+
+        self.enum_ranges.push((start_address, enum_address, enum_token.clone()));
     }
 }
 
 impl TreePass for CollectLabelPass {
-    fn has_errors(&self) -> bool {
-        return !self.error_messages.is_empty();
-    }
-
-    fn get_error_messages(&self) -> &Vec<ErrorMessage> {
-        &self.error_messages
+    fn name(&self) -> &'static str {
+        "collect-labels"
     }
 
     fn do_pass(
         &mut self,
         parse_tree: Vec<ParseNode>,
         symbol_table: &mut SymbolTable,
-    ) -> Vec<ParseNode> {
+    ) -> (Vec<ParseNode>, Vec<ErrorMessage>) {
         let mut new_tree: Vec<ParseNode> = Vec::new();
 
         let mut current_address: u32 = 0;
+        let mut namespace_tokens: Vec<Token> = Vec::new();
+        let mut exported_labels: Vec<(String, Token)> = Vec::new();
 
-        for node in parse_tree.iter() {
+        for node in parse_tree.into_iter() {
             match node.expression {
-                ParseExpression::ImpliedInstruction(_) => {
-                    new_tree.push(node.clone());
-                    current_address += 1;
-                }
-                ParseExpression::ImmediateInstruction(_, ref argument) => {
-                    new_tree.push(node.clone());
-                    current_address += 1;
-
-                    match argument {
-                        &ParseArgument::NumberLiteral(ref number) => {
-                            current_address += argument_size_to_byte_size(number.argument_size);
-                        }
-                        &ParseArgument::Identifier(_) => {
-                            current_address += argument_size_to_byte_size(self.system.label_size);
-                        }
-                        _ => {}
-                    }
+                ParseExpression::OriginStatement(ref number, allow_rewind) => {
+                    self.check_origin(&node, number.number, allow_rewind, current_address);
+                    current_address = number.number;
+                    new_tree.push(node);
                 }
-                ParseExpression::SingleArgumentInstruction(ref opcode_name, ref argument) => {
-                    new_tree.push(node.clone());
-                    current_address += 1;
-
-                    match argument {
-                        &ParseArgument::NumberLiteral(ref number) => {
-                            current_address += argument_size_to_byte_size(number.argument_size);
-                        }
-                        &ParseArgument::Identifier(_) => {
-                            match self.find_instruction_argument_size(
-                                opcode_name,
-                                &[AddressingMode::Relative],
-                            ) {
-                                Some(size) => current_address += argument_size_to_byte_size(size),
-                                None => {
-                                    current_address +=
-                                        argument_size_to_byte_size(self.system.label_size);
-                                }
-                            };
-                        }
-                        _ => {}
+                ParseExpression::OriginExpression(ref origin_address, allow_rewind) => {
+                    match evaluate_origin(origin_address, symbol_table, &node.start_token, current_address) {
+                        Ok(address) => {
+                            self.check_origin(&node, address, allow_rewind, current_address);
+                            current_address = address;
+                            new_tree.push(ParseNode {
+                                start_token: node.start_token.clone(),
+                                address: None,
+                                expression: ParseExpression::OriginStatement(
+                                    NumberLiteral {
+                                        number: address,
+                                        argument_size: ArgumentSize::Word24,
+                                        kind: NumberLiteralKind::Hex,
+                                    },
+                                    allow_rewind,
+                                ),
+                            });
+                        }
+                        Err(message) => self.diagnostics.add_error(&message, node.start_token.clone()),
                     }
                 }
-                ParseExpression::IndexedInstruction(_, ref argument1, ref argument2) => {
-                    new_tree.push(node.clone());
-                    current_address += 1;
-
-                    match argument1 {
-                        &ParseArgument::NumberLiteral(ref number) => {
-                            current_address += argument_size_to_byte_size(number.argument_size);
-                        }
-                        &ParseArgument::Identifier(_) => {
-                            current_address += argument_size_to_byte_size(self.system.label_size);
-                        }
-                        _ => {}
-                    };
-
-                    match argument2 {
-                        &ParseArgument::NumberLiteral(ref number) => {
-                            current_address += argument_size_to_byte_size(number.argument_size);
-                        }
-                        &ParseArgument::Identifier(_) => {
-                            current_address += argument_size_to_byte_size(self.system.label_size);
-                        }
-                        _ => {}
-                    };
-                }
-                ParseExpression::IndirectInstruction(_, ref argument) => {
-                    new_tree.push(node.clone());
-                    current_address += 1;
-
-                    match argument {
-                        &ParseArgument::NumberLiteral(ref number) => {
-                            current_address += argument_size_to_byte_size(number.argument_size);
-                        }
-                        &ParseArgument::Identifier(_) => {
-                            current_address += argument_size_to_byte_size(self.system.label_size);
-                        }
-                        _ => {}
-                    }
+                ParseExpression::BankStatement(bank) => {
+                    current_address = address_in_bank(current_address, bank);
+                    new_tree.push(node);
                 }
-                ParseExpression::IndirectLongInstruction(_, ref argument) => {
-                    new_tree.push(node.clone());
-                    current_address += 1;
-
-                    match argument {
-                        &ParseArgument::NumberLiteral(ref number) => {
-                            current_address += argument_size_to_byte_size(number.argument_size);
-                        }
-                        &ParseArgument::Identifier(_) => {
-                            current_address += argument_size_to_byte_size(self.system.label_size);
-                        }
-                        _ => {}
+                ParseExpression::Label(ref label_name) => {
+                    let qualified_name = symbol_table.qualified_name(label_name);
+                    if symbol_table.is_imported(&qualified_name) {
+                        self.diagnostics.add_error(
+                            &format!("Label '{}' conflicts with a symbol of the same name from the imported symbol file or --define.", qualified_name),
+                            node.start_token.clone(),
+                        );
+                    } else if symbol_table.has_label_in_namespace(label_name) {
+                        self.diagnostics.add_error(
+                            &format!("Label '{}' is already defined.", qualified_name),
+                            node.start_token.clone(),
+                        );
+                    } else {
+                        symbol_table.add_label_definition(label_name, current_address, &node.start_token);
                     }
+                    // Kept for the passes that need to know where code can be jumped to
+                    new_tree.push(node);
                 }
-                ParseExpression::IndexedIndirectInstruction(_, ref argument1, ref argument2) => {
-                    new_tree.push(node.clone());
-                    current_address += 1;
-
-                    match argument1 {
-                        &ParseArgument::NumberLiteral(ref number) => {
-                            current_address += argument_size_to_byte_size(number.argument_size);
-                        }
-                        &ParseArgument::Identifier(_) => {
-                            current_address += argument_size_to_byte_size(self.system.label_size);
-                        }
-                        _ => {}
-                    };
-
-                    match argument2 {
-                        &ParseArgument::NumberLiteral(ref number) => {
-                            current_address += argument_size_to_byte_size(number.argument_size);
-                        }
-                        &ParseArgument::Identifier(_) => {
-                            current_address += argument_size_to_byte_size(self.system.label_size);
-                        }
-                        _ => {}
-                    };
-                }
-                ParseExpression::IndirectIndexedInstruction(_, ref argument1, ref argument2) => {
-                    new_tree.push(node.clone());
-                    current_address += 1;
-
-                    match argument1 {
-                        &ParseArgument::NumberLiteral(ref number) => {
-                            current_address += argument_size_to_byte_size(number.argument_size);
-                        }
-                        &ParseArgument::Identifier(_) => {
-                            current_address += argument_size_to_byte_size(self.system.label_size);
-                        }
-                        _ => {}
-                    };
-
-                    match argument2 {
-                        &ParseArgument::NumberLiteral(ref number) => {
-                            current_address += argument_size_to_byte_size(number.argument_size);
-                        }
-                        &ParseArgument::Identifier(_) => {
-                            current_address += argument_size_to_byte_size(self.system.label_size);
-                        }
-                        _ => {}
-                    };
+                ParseExpression::ExportLabel(ref label_name) => {
+                    exported_labels.push((symbol_table.qualified_name(label_name), node.start_token.clone()));
                 }
-                ParseExpression::IndirectIndexedLongInstruction(
-                    _,
-                    ref argument1,
-                    ref argument2,
-                ) => {
-                    new_tree.push(node.clone());
-                    current_address += 1;
-
-                    match argument1 {
-                        &ParseArgument::NumberLiteral(ref number) => {
-                            current_address += argument_size_to_byte_size(number.argument_size);
-                        }
-                        &ParseArgument::Identifier(_) => {
-                            current_address += argument_size_to_byte_size(self.system.label_size);
-                        }
-                        _ => {}
-                    };
-
-                    match argument2 {
-                        &ParseArgument::NumberLiteral(ref number) => {
-                            current_address += argument_size_to_byte_size(number.argument_size);
-                        }
-                        &ParseArgument::Identifier(_) => {
-                            current_address += argument_size_to_byte_size(self.system.label_size);
-                        }
-                        _ => {}
-                    };
+                ParseExpression::NamespaceStart(ref namespace) => {
+                    symbol_table.push_namespace(namespace);
+                    namespace_tokens.push(node.start_token.clone());
+                    new_tree.push(node);
                 }
-                ParseExpression::BlockMoveInstruction(_, ref argument1, ref argument2) => {
-                    new_tree.push(node.clone());
-                    current_address += 1;
-
-                    match argument1 {
-                        &ParseArgument::NumberLiteral(ref number) => {
-                            current_address += argument_size_to_byte_size(number.argument_size);
-                        }
-                        &ParseArgument::Identifier(_) => {
-                            current_address += argument_size_to_byte_size(self.system.label_size);
-                        }
-                        _ => {}
-                    };
-
-                    match argument2 {
-                        &ParseArgument::NumberLiteral(ref number) => {
-                            current_address += argument_size_to_byte_size(number.argument_size);
-                        }
-                        &ParseArgument::Identifier(_) => {
-                            current_address += argument_size_to_byte_size(self.system.label_size);
-                        }
-                        _ => {}
-                    };
+                ParseExpression::NamespaceEnd => {
+                    if symbol_table.pop_namespace().is_some() {
+                        namespace_tokens.pop();
+                        new_tree.push(node);
+                    } else {
+                        self.diagnostics.add_error(&"endnamespace found without a matching namespace.", node.start_token.clone());
+                    }
                 }
-                ParseExpression::StackRelativeIndirectIndexedInstruction(
-                    _,
-                    ref argument1,
-                    ref argument2,
-                    ref argument3,
-                ) => {
-                    new_tree.push(node.clone());
-                    current_address += 1;
-
-                    match argument1 {
-                        &ParseArgument::NumberLiteral(ref number) => {
-                            current_address += argument_size_to_byte_size(number.argument_size);
-                        }
-                        &ParseArgument::Identifier(_) => {
-                            current_address += argument_size_to_byte_size(self.system.label_size);
-                        }
-                        _ => {}
-                    };
-
-                    match argument2 {
-                        &ParseArgument::NumberLiteral(ref number) => {
-                            current_address += argument_size_to_byte_size(number.argument_size);
-                        }
-                        &ParseArgument::Identifier(_) => {
-                            current_address += argument_size_to_byte_size(self.system.label_size);
-                        }
-                        _ => {}
-                    };
-
-                    match argument3 {
-                        &ParseArgument::NumberLiteral(ref number) => {
-                            current_address += argument_size_to_byte_size(number.argument_size);
-                        }
-                        &ParseArgument::Identifier(_) => {
-                            current_address += argument_size_to_byte_size(self.system.label_size);
-                        }
-                        _ => {}
-                    };
+                ParseExpression::StructDefinition(ref struct_name, ref fields) => {
+                    self.collect_struct(&node.start_token, struct_name, fields, symbol_table, current_address);
                 }
-                ParseExpression::OriginStatement(ref number) => {
-                    current_address = number.number;
-                    new_tree.push(node.clone());
+                ParseExpression::EnumDefinition {
+                    start_address,
+                    end_address,
+                    ref fields,
+                } => {
+                    self.collect_enum(
+                        &node.start_token,
+                        start_address,
+                        end_address,
+                        fields,
+                        symbol_table,
+                        current_address,
+                    );
                 }
-                ParseExpression::IncBinStatement(_, file_size) => {
-                    current_address += file_size as u32;
-                    new_tree.push(node.clone());
+                ParseExpression::SnesMapStatement(ref map_mode) => {
+                    self.snes_map = Some(map_mode.clone());
+                    new_tree.push(node);
                 }
-                ParseExpression::Label(ref label_name) => {
-                    symbol_table.add_or_update_label(label_name, current_address);
+                // Skipped bytes are only filled outside of patch mode so they don't count as written
+                ParseExpression::SkipBytes(size) => {
+                    self.check_bank_end(&node, current_address, size);
+                    current_address += size;
+                    self.file_offset += size;
+                    self.continues_range = false;
+                    new_tree.push(node);
                 }
                 _ => {
-                    new_tree.push(node.clone());
+                    let size = node_size(self.system, &node);
+                    self.check_bank_end(&node, current_address, size);
+                    self.check_written(&node, current_address, size);
+                    current_address += size;
+                    self.file_offset += size;
+                    new_tree.push(node);
                 }
             }
         }
 
-        return new_tree;
+        for namespace_token in namespace_tokens {
+            self.diagnostics.add_error(&"namespace is missing its endnamespace.", namespace_token);
+            symbol_table.pop_namespace();
+        }
+
+        // Exported labels can be defined after the export statement
+        for (qualified_name, export_token) in exported_labels {
+            if symbol_table.has_label(&qualified_name) {
+                symbol_table.export_label(&qualified_name);
+            } else {
+                self.diagnostics.add_error(&format!("Exported label '{}' is not defined.", qualified_name), export_token);
+            }
+        }
+
+        return (new_tree, self.diagnostics.take_messages());
     }
 }
@@ -0,0 +1,166 @@
+// Value of a key of the config file
+pub enum ConfigValue {
+    // Option without a value like optimize = true, false leaves it out
+    Flag(bool),
+    Value(String),
+    // Option given once per value like define = ["A=1", "B=2"]
+    Values(Vec<String>),
+}
+
+pub struct ConfigEntry {
+    pub key: String,
+    pub value: ConfigValue,
+    pub line: usize,
+}
+
+impl ConfigEntry {
+    // Long command line option of the key, gap_fill is --gap-fill
+    pub fn option_name(&self) -> String {
+        format!("--{}", self.key.replace('_', "-"))
+    }
+
+    // Command line arguments giving the same option, like --gap-fill=$FF for gap_fill = "$FF".
+    // The value is attached to the option so a value given to a flag is an error instead of an input file.
+    pub fn arguments(&self) -> Vec<String> {
+        match self.value {
+            ConfigValue::Flag(true) => vec![self.option_name()],
+            ConfigValue::Flag(false) => vec![],
+            ConfigValue::Value(ref value) => vec![format!("{}={}", self.option_name(), value)],
+            ConfigValue::Values(ref values) => values
+                .iter()
+                .map(|value| format!("{}={}", self.option_name(), value))
+                .collect(),
+        }
+    }
+}
+
+fn is_key_char(key_char: char) -> bool {
+    key_char.is_ascii_alphanumeric() || key_char == '_' || key_char == '-'
+}
+
+// String between double quotes, returns it with the text after the closing quote
+fn parse_string(text: &str) -> Result<(String, &str), String> {
+    let mut value = String::new();
+    let mut chars = text[1..].char_indices();
+
+    while let Some((index, current_char)) = chars.next() {
+        match current_char {
+            '"' => return Ok((value, &text[index + 2..])),
+            '\\' => match chars.next() {
+                Some((_, '"')) => value.push('"'),
+                Some((_, '\\')) => value.push('\\'),
+                Some((_, 'n')) => value.push('\n'),
+                Some((_, 't')) => value.push('\t'),
+                _ => return Err(String::from("Unknown escape sequence in string, use \\\" or \\\\.")),
+            },
+            _ => value.push(current_char),
+        }
+    }
+
+    Err(String::from("String isn't closed, a \" is missing."))
+}
+
+// Text after a value must be empty or a comment
+fn check_end(rest: &str) -> Result<(), String> {
+    let rest = rest.trim();
+    if rest.is_empty() || rest.starts_with('#') {
+        Ok(())
+    } else {
+        Err(format!("Unexpected '{}' after the value.", rest))
+    }
+}
+
+fn parse_list(text: &str) -> Result<Vec<String>, String> {
+    let mut values = Vec::new();
+    let mut rest = text[1..].trim_start();
+
+    loop {
+        if rest.starts_with(']') {
+            check_end(&rest[1..])?;
+            return Ok(values);
+        }
+
+        if !rest.starts_with('"') {
+            return Err(String::from("Lists hold strings like [\"A=1\", \"B=2\"] on a single line."));
+        }
+
+        let (value, after_value) = parse_string(rest)?;
+        values.push(value);
+
+        rest = after_value.trim_start();
+        if rest.starts_with(',') {
+            rest = rest[1..].trim_start();
+        } else if !rest.starts_with(']') {
+            return Err(String::from("Expected a ',' or a ']' after the string of the list."));
+        }
+    }
+}
+
+fn parse_value(text: &str) -> Result<ConfigValue, String> {
+    if text.starts_with('"') {
+        let (value, rest) = parse_string(text)?;
+        check_end(rest)?;
+        return Ok(ConfigValue::Value(value));
+    }
+
+    if text.starts_with('[') {
+        return parse_list(text).map(ConfigValue::Values);
+    }
+
+    let value = match text.find('#') {
+        Some(comment_start) => text[..comment_start].trim(),
+        None => text,
+    };
+
+    match value {
+        "true" => Ok(ConfigValue::Flag(true)),
+        "false" => Ok(ConfigValue::Flag(false)),
+        _ if value.parse::<i64>().is_ok() => Ok(ConfigValue::Value(value.to_owned())),
+        _ => Err(format!(
+            "Invalid value '{}', use true, false, a number, a string like \"$FF\" or a list of strings.",
+            value
+        )),
+    }
+}
+
+// Config files like .zealrc are a subset of TOML: one `key = value` per line where the key is a long
+// command line option written with - or _, like gap_fill = "$FF", optimize = true or define = ["A=1"].
+// Comments start with #. Errors give the line they were found on.
+pub fn parse_config(text: &str) -> Result<Vec<ConfigEntry>, String> {
+    let mut entries = Vec::new();
+
+    for (index, line) in text.lines().enumerate() {
+        let line_number = index + 1;
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if line.starts_with('[') {
+            return Err(format!("line {}: Sections like {} aren't supported, options go at the top level.", line_number, line));
+        }
+
+        let (key, value) = match line.find('=') {
+            Some(equal_index) => (line[..equal_index].trim(), line[equal_index + 1..].trim()),
+            None => return Err(format!("line {}: Expected 'key = value' but found '{}'.", line_number, line)),
+        };
+
+        if key.is_empty() || !key.chars().all(is_key_char) {
+            return Err(format!("line {}: Invalid key '{}', keys are option names like gap_fill.", line_number, key));
+        }
+
+        let value = match parse_value(value) {
+            Ok(value) => value,
+            Err(message) => return Err(format!("line {}: {}", line_number, message)),
+        };
+
+        entries.push(ConfigEntry {
+            key: key.to_owned(),
+            value: value,
+            line: line_number,
+        });
+    }
+
+    Ok(entries)
+}
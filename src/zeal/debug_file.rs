@@ -0,0 +1,61 @@
+use std::fmt::Write;
+use std::path::Path;
+use zeal::lexer::relative_source_path;
+use zeal::node_size::node_size;
+use zeal::output_writer::NodeRange;
+use zeal::parser::ParseNode;
+use zeal::system_definition::SystemDefinition;
+
+// Source location of every emitted byte range, for emulators and debuggers stepping through the source.
+// One range per line in the order of the input: SNES address and size in hexadecimal then file:line:column,
+// like `C08000 0003 main.zc:12:5`. Data and incbin statements are mapped to the line of the directive.
+pub fn debug_info_to_text(system: &SystemDefinition, parse_tree: &Vec<ParseNode>, base_directory: &Path) -> String {
+    let mut text = String::from("# zealc debug info: address size file:line:column\n");
+
+    for node in parse_tree.iter() {
+        let size = node_size(system, node);
+
+        if let (Some(address), true) = (node.address, size > 0) {
+            writeln!(
+                text,
+                "{:06X} {:04X} {}:{}:{}",
+                address,
+                size,
+                relative_source_path(&node.start_token.source_file, base_directory),
+                node.start_token.line,
+                node.start_token.start_column
+            ).unwrap();
+        }
+    }
+
+    text
+}
+
+// Fields holding a comma or a quote are quoted, with their quotes doubled
+fn csv_field(field: &str) -> String {
+    if field.contains(',') || field.contains('"') {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+// Source line of every byte range of the output file, written with -g. Unlike debug_info_to_text() the ranges
+// are offsets in the output file, copier header included, so a debugger can map ROM bytes back to the source.
+// One CSV row per range with decimal offset and length, like `0,2,main.zc,12`.
+pub fn source_map_to_csv(node_ranges: &[NodeRange], base_directory: &Path) -> String {
+    let mut text = String::from("offset,length,file,line\n");
+
+    for node_range in node_ranges.iter() {
+        writeln!(
+            text,
+            "{},{},{},{}",
+            node_range.file_offset,
+            node_range.size,
+            csv_field(&relative_source_path(&node_range.token.source_file, base_directory)),
+            node_range.token.line
+        ).unwrap();
+    }
+
+    text
+}
@@ -0,0 +1,347 @@
+use std::collections::HashSet;
+use std::fmt::Write;
+use zeal::output_writer::{has_copier_header, snes_map_function, COPIER_HEADER_SIZE};
+use zeal::parser::{format_instruction, SnesMap};
+use zeal::system_definition::*;
+
+// Bits of the processor status register set by sep and cleared by rep
+const ACCUMULATOR_FLAG: u32 = 0x20;
+const INDEX_FLAG: u32 = 0x10;
+
+
+// Instruction decoded from the ROM
+struct DecodedInstruction {
+    address: u32,
+    bytes: Vec<u8>,
+    instruction: &'static InstructionInfo,
+    // Operand values in the order of the instruction arguments, registers are None
+    values: Vec<Option<u32>>,
+    // Target of a branch
+    branch_target: Option<u32>,
+    // Why the encoding could be read another way
+    note: Option<String>,
+}
+
+// An instruction, or bytes that aren't one along with why
+enum DisassemblyLine {
+    Instruction(DecodedInstruction),
+    Bytes(u32, Vec<u8>, &'static str),
+}
+
+fn read_value(bytes: &[u8], size: ArgumentSize, is_big_endian: bool) -> u32 {
+    let byte_size = argument_size_to_byte_size(size) as usize;
+
+    let mut value = 0;
+    for index in 0..byte_size {
+        let byte = if is_big_endian { bytes[index] } else { bytes[byte_size - 1 - index] };
+        value = (value << 8) | byte as u32;
+    }
+
+    value
+}
+
+// Hexadecimal literal as wide as its size so it assembles back to the same addressing mode
+fn format_value(value: u32, size: ArgumentSize) -> String {
+    format!("${:0width$X}", value, width = argument_size_to_byte_size(size) as usize * 2)
+}
+
+fn format_bytes(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02X}", byte)).collect::<Vec<String>>().join(" ")
+}
+
+fn label_name(address: u32) -> String {
+    format!("L{:06X}", address)
+}
+
+// Instructions like brk are listed without and with their signature byte, the CPU always skips it
+fn find_instruction(system: &'static SystemDefinition, opcode: u8) -> Option<&'static InstructionInfo> {
+    system
+        .instructions
+        .iter()
+        .filter(|instruction| instruction.opcode == opcode)
+        .max_by_key(|instruction| instruction.arguments.len())
+}
+
+// Turn the bytes of a ROM back into source, like `--disassemble game.sfc --origin $808000 --length $100`.
+// Immediate values of lda, ldx and the like are as wide as the last rep or sep seen before them,
+// they are 8-bit with a note when no rep or sep was found. Branches to an instruction of the range get a label.
+pub struct Disassembler {
+    system: &'static SystemDefinition,
+    snes_map: Option<SnesMap>,
+    // Width of the registers set by the last rep or sep, None when unknown
+    accumulator: Option<ArgumentSize>,
+    index: Option<ArgumentSize>,
+}
+
+impl Disassembler {
+    pub fn new(system: &'static SystemDefinition, snes_map: Option<SnesMap>) -> Self {
+        Disassembler {
+            system: system,
+            snes_map: snes_map,
+            accumulator: None,
+            index: None,
+        }
+    }
+
+    pub fn disassemble(&mut self, rom: &[u8], origin: u32, length: u32) -> Result<String, String> {
+        if let Some(ref snes_map) = self.snes_map {
+            if !snes_map.is_rom_address(origin) {
+                return Err(format!(
+                    "Origin ${:06X} isn't mapped to the ROM, {} ROMs start at {}.",
+                    origin,
+                    snes_map.name(),
+                    snes_map.example_address()
+                ));
+            }
+        }
+
+        let rom = if has_copier_header(rom.len() as u64) {
+            &rom[COPIER_HEADER_SIZE as usize..]
+        } else {
+            rom
+        };
+
+        let start_offset = snes_map_function(&self.snes_map)(origin) as usize;
+        let end_offset = start_offset + length as usize;
+        if length == 0 || end_offset > rom.len() {
+            return Err(format!(
+                "${:06X} + ${:X} ends at ROM offset ${:X} but the ROM is ${:X} bytes, without a copier header.",
+                origin,
+                length,
+                end_offset,
+                rom.len()
+            ));
+        }
+
+        let lines = self.decode(&rom[start_offset..end_offset], origin);
+        Ok(self.to_text(&lines, origin, length))
+    }
+
+    // Source of each line without the addresses and the bytes, branches are written relative to *.
+    // Used to check that the instructions come back as they were assembled.
+    pub fn disassemble_lines(&mut self, bytes: &[u8], origin: u32) -> Vec<String> {
+        let no_labels = HashSet::new();
+
+        self.decode(bytes, origin)
+            .iter()
+            .map(|line| match line {
+                &DisassemblyLine::Instruction(ref decoded) => self.instruction_text(decoded, &no_labels),
+                &DisassemblyLine::Bytes(_, ref bytes, _) => {
+                    let values = bytes.iter().map(|byte| format!("${:02X}", byte)).collect::<Vec<String>>();
+                    format!("db {}", values.join(", "))
+                }
+            })
+            .collect()
+    }
+
+    fn decode(&mut self, bytes: &[u8], origin: u32) -> Vec<DisassemblyLine> {
+        let mut lines = Vec::new();
+        let mut offset = 0;
+
+        while offset < bytes.len() {
+            let address = origin + offset as u32;
+            let instruction = match find_instruction(self.system, bytes[offset]) {
+                Some(instruction) => instruction,
+                None => {
+                    lines.push(DisassemblyLine::Bytes(address, vec![bytes[offset]], "unknown opcode"));
+                    offset += 1;
+                    continue;
+                }
+            };
+
+            let mut note = None;
+            let sizes = self.operand_sizes(instruction, &mut note);
+            let size = 1 + sizes
+                .iter()
+                .map(|size| size.map_or(0, |size| argument_size_to_byte_size(size) as usize))
+                .sum::<usize>();
+
+            if offset + size > bytes.len() {
+                lines.push(DisassemblyLine::Bytes(address, bytes[offset..].to_vec(), "cut by the end of the range"));
+                break;
+            }
+
+            let mut values = Vec::new();
+            let mut value_offset = offset + 1;
+            for size in sizes.iter() {
+                match size {
+                    &Some(size) => {
+                        values.push(Some(read_value(&bytes[value_offset..], size, self.system.is_big_endian)));
+                        value_offset += argument_size_to_byte_size(size) as usize;
+                    }
+                    &None => values.push(None),
+                }
+            }
+
+            let branch_target = match (&instruction.addressing, values.first(), sizes.first()) {
+                (&AddressingMode::Relative, Some(&Some(value)), Some(&Some(size))) => {
+                    let displacement = if size == ArgumentSize::Word8 {
+                        value as u8 as i8 as i64
+                    } else {
+                        value as u16 as i16 as i64
+                    };
+                    // Relative to the end of the instruction
+                    Some((address as i64 + 1 + argument_size_to_byte_size(size) as i64 + displacement) as u32)
+                }
+                _ => None,
+            };
+
+            self.track_widths(instruction, &values);
+
+            lines.push(DisassemblyLine::Instruction(DecodedInstruction {
+                address: address,
+                bytes: bytes[offset..offset + size].to_vec(),
+                instruction: instruction,
+                values: values,
+                branch_target: branch_target,
+                note: note,
+            }));
+            offset += size;
+        }
+
+        lines
+    }
+
+    // Size of each argument of the instruction, None for registers
+    fn operand_sizes(&self, instruction: &InstructionInfo, note: &mut Option<String>) -> Vec<Option<ArgumentSize>> {
+        instruction
+            .arguments
+            .iter()
+            .map(|argument| match argument {
+                &InstructionArgument::Number(size) => Some(size),
+                &InstructionArgument::Numbers(sizes) => Some(self.immediate_size(instruction.name, sizes, note)),
+                &InstructionArgument::Register(_) | &InstructionArgument::NotStaticRegister(_) => None,
+            })
+            .collect()
+    }
+
+    fn immediate_size(&self, opcode_name: &str, sizes: &[ArgumentSize], note: &mut Option<String>) -> ArgumentSize {
+        let (width, register_name) = match self.system.immediate_width_register(opcode_name) {
+            Some(WidthRegister::Accumulator) => (self.accumulator, "accumulator"),
+            Some(WidthRegister::Index) => (self.index, "index registers"),
+            None => return sizes[0],
+        };
+
+        match width {
+            Some(size) => size,
+            None => {
+                *note = Some(format!("8-bit {} assumed, no rep or sep before", register_name));
+                ArgumentSize::Word8
+            }
+        }
+    }
+
+    fn track_widths(&mut self, instruction: &InstructionInfo, values: &[Option<u32>]) {
+        let size = match instruction.name {
+            // Cleared flags make the registers 16-bit
+            "rep" => ArgumentSize::Word16,
+            "sep" => ArgumentSize::Word8,
+            // The status register comes from somewhere that isn't followed
+            "plp" | "xce" => {
+                self.accumulator = None;
+                self.index = None;
+                return;
+            }
+            _ => return,
+        };
+
+        let flags = values.first().cloned().unwrap_or(None).unwrap_or(0);
+        if flags & ACCUMULATOR_FLAG != 0 {
+            self.accumulator = Some(size);
+        }
+        if flags & INDEX_FLAG != 0 {
+            self.index = Some(size);
+        }
+    }
+
+    fn to_text(&self, lines: &[DisassemblyLine], origin: u32, length: u32) -> String {
+        let instruction_addresses: HashSet<u32> = lines
+            .iter()
+            .filter_map(|line| match line {
+                &DisassemblyLine::Instruction(ref decoded) => Some(decoded.address),
+                &DisassemblyLine::Bytes(..) => None,
+            })
+            .collect();
+
+        let labels: HashSet<u32> = lines
+            .iter()
+            .filter_map(|line| match line {
+                &DisassemblyLine::Instruction(ref decoded) => decoded.branch_target,
+                &DisassemblyLine::Bytes(..) => None,
+            })
+            .filter(|target| instruction_addresses.contains(target))
+            .collect();
+
+        let mut text = String::new();
+        writeln!(text, "// Disassembled by zealc, ${:06X} to ${:06X}", origin, origin + length - 1).unwrap();
+        if let Some(ref snes_map) = self.snes_map {
+            writeln!(text, "snesmap {}", snes_map.name()).unwrap();
+        }
+        writeln!(text, "origin ${:06X}", origin).unwrap();
+        writeln!(text).unwrap();
+
+        for line in lines.iter() {
+            match line {
+                &DisassemblyLine::Instruction(ref decoded) => {
+                    if labels.contains(&decoded.address) {
+                        writeln!(text, "{}:", label_name(decoded.address)).unwrap();
+                    }
+
+                    let mut comment = format!("{:06X}  {}", decoded.address, format_bytes(&decoded.bytes));
+                    if let Some(ref note) = decoded.note {
+                        comment.push_str(&format!("  {}", note));
+                    }
+
+                    writeln!(text, "    {:28} // {}", self.instruction_text(decoded, &labels), comment).unwrap();
+                }
+                &DisassemblyLine::Bytes(address, ref bytes, reason) => {
+                    let values = bytes.iter().map(|byte| format!("${:02X}", byte)).collect::<Vec<String>>();
+                    writeln!(
+                        text,
+                        "    {:28} // {:06X}  {}  {}",
+                        format!("db {}", values.join(", ")),
+                        address,
+                        format_bytes(bytes),
+                        reason
+                    ).unwrap();
+                }
+            }
+        }
+
+        text
+    }
+
+    fn instruction_text(&self, decoded: &DecodedInstruction, labels: &HashSet<u32>) -> String {
+        let instruction = decoded.instruction;
+
+        let mut operands = Vec::new();
+        for (argument, value) in instruction.arguments.iter().zip(decoded.values.iter()) {
+            let operand = match (argument, value, decoded.branch_target) {
+                // Branches outside the range are relative to the start of the instruction
+                (_, &Some(_), Some(target)) => if labels.contains(&target) {
+                    label_name(target)
+                } else if target >= decoded.address {
+                    format!("* + {}", target - decoded.address)
+                } else {
+                    format!("* - {}", decoded.address - target)
+                },
+                (&InstructionArgument::Number(size), &Some(value), None) => format_value(value, size),
+                (&InstructionArgument::Numbers(_), &Some(value), None) => {
+                    let size = if decoded.bytes.len() == 2 { ArgumentSize::Word8 } else { ArgumentSize::Word16 };
+                    format_value(value, size)
+                }
+                (&InstructionArgument::Register(name), _, _) => name.to_string(),
+                (&InstructionArgument::NotStaticRegister(ref name), _, _) => name.to_owned(),
+                _ => String::new(),
+            };
+            operands.push(operand);
+        }
+
+        // Block moves are encoded destination then source bank but written 'source, destination'
+        if instruction.addressing == AddressingMode::BlockMove {
+            operands.reverse();
+        }
+
+        format_instruction(instruction.name, &instruction.addressing, &operands)
+    }
+}
@@ -0,0 +1,328 @@
+use std::fmt;
+use zeal::lexer::{NumberLiteral, NumberLiteralKind};
+use zeal::parser::Parser;
+use zeal::symbol_table::SymbolTable;
+use zeal::system_definition::*;
+
+#[derive(PartialEq, Copy, Clone)]
+pub enum BinaryOperator {
+    Add,
+    Subtract,
+    Multiply,
+    ShiftLeft,
+    ShiftRight,
+    BitwiseAnd,
+    BitwiseOr,
+}
+
+#[derive(PartialEq, Copy, Clone)]
+pub enum BuiltinFunction {
+    Low,
+    High,
+    Bank,
+}
+
+impl BuiltinFunction {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "low" => Some(BuiltinFunction::Low),
+            "high" => Some(BuiltinFunction::High),
+            "bank" => Some(BuiltinFunction::Bank),
+            _ => None,
+        }
+    }
+
+    // Each function picks a byte of its argument
+    fn apply(&self, value: i64) -> i64 {
+        match self {
+            &BuiltinFunction::Low => value & 0xFF,
+            &BuiltinFunction::High => (value >> 8) & 0xFF,
+            &BuiltinFunction::Bank => (value >> 16) & 0xFF,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub enum Expr {
+    Number(NumberLiteral),
+    Identifier(String),
+    CurrentAddress,
+    Negate(Box<Expr>),
+    Binary(BinaryOperator, Box<Expr>, Box<Expr>),
+    Call(BuiltinFunction, Box<Expr>),
+    // Value given the width of a register by a .a16 or .i8 block
+    Sized(ArgumentSize, Box<Expr>),
+}
+
+// Narrowest of two sizes, None when neither is known
+fn narrower_size(left: Option<ArgumentSize>, right: Option<ArgumentSize>) -> Option<ArgumentSize> {
+    match (left, right) {
+        (Some(left), Some(right)) => if argument_size_to_byte_size(right) < argument_size_to_byte_size(left) {
+            Some(right)
+        } else {
+            Some(left)
+        },
+        (Some(size), None) | (None, Some(size)) => Some(size),
+        (None, None) => None,
+    }
+}
+
+// Widest of two sizes, None when neither is known
+fn wider_size(left: Option<ArgumentSize>, right: Option<ArgumentSize>) -> Option<ArgumentSize> {
+    match (left, right) {
+        (Some(left), Some(right)) => if argument_size_to_byte_size(right) > argument_size_to_byte_size(left) {
+            Some(right)
+        } else {
+            Some(left)
+        },
+        (Some(size), None) | (None, Some(size)) => Some(size),
+        (None, None) => None,
+    }
+}
+
+impl BinaryOperator {
+    fn symbol(&self) -> &'static str {
+        match self {
+            &BinaryOperator::Add => "+",
+            &BinaryOperator::Subtract => "-",
+            &BinaryOperator::Multiply => "*",
+            &BinaryOperator::ShiftLeft => "<<",
+            &BinaryOperator::ShiftRight => ">>",
+            &BinaryOperator::BitwiseAnd => "&",
+            &BinaryOperator::BitwiseOr => "|",
+        }
+    }
+
+    // Operators with a higher precedence are applied first, like * before +
+    fn precedence(&self) -> u32 {
+        match self {
+            &BinaryOperator::BitwiseOr => 1,
+            &BinaryOperator::BitwiseAnd => 2,
+            &BinaryOperator::ShiftLeft | &BinaryOperator::ShiftRight => 3,
+            &BinaryOperator::Add | &BinaryOperator::Subtract => 4,
+            &BinaryOperator::Multiply => 5,
+        }
+    }
+
+    // None when the result doesn't fit or a shift count is negative or too large
+    fn apply(&self, left: i64, right: i64) -> Option<i64> {
+        match self {
+            &BinaryOperator::Add => left.checked_add(right),
+            &BinaryOperator::Subtract => left.checked_sub(right),
+            &BinaryOperator::Multiply => left.checked_mul(right),
+            &BinaryOperator::ShiftLeft if right >= 0 && right < 64 => left.checked_mul(1 << right),
+            &BinaryOperator::ShiftRight if right >= 0 && right < 64 => Some(left >> right),
+            &BinaryOperator::ShiftLeft | &BinaryOperator::ShiftRight => None,
+            &BinaryOperator::BitwiseAnd => Some(left & right),
+            &BinaryOperator::BitwiseOr => Some(left | right),
+        }
+    }
+}
+
+impl fmt::Display for BuiltinFunction {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            &BuiltinFunction::Low => "low",
+            &BuiltinFunction::High => "high",
+            &BuiltinFunction::Bank => "bank",
+        };
+
+        write!(formatter, "{}", name)
+    }
+}
+
+// Operands applied after their operator, like an addition multiplied, are put in parentheses to keep
+// the order of evaluation visible
+// Operators are left associative, so a right operand of the same precedence needs parentheses too, like a - (b - c)
+fn fmt_operand(formatter: &mut fmt::Formatter, operand: &Expr, operator: BinaryOperator, is_right: bool) -> fmt::Result {
+    match operand {
+        &Expr::Binary(operand_operator, _, _)
+            if operand_operator.precedence() < operator.precedence()
+                || (is_right && operand_operator.precedence() == operator.precedence()) =>
+        {
+            write!(formatter, "({})", operand)
+        }
+        _ => write!(formatter, "{}", operand),
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &Expr::Number(ref number) => write!(formatter, "{}", number),
+            &Expr::Identifier(ref identifier) => write!(formatter, "{}", identifier),
+            &Expr::CurrentAddress => write!(formatter, "*"),
+            &Expr::Negate(ref operand) => match **operand {
+                Expr::Binary(..) => write!(formatter, "-({})", operand),
+                _ => write!(formatter, "-{}", operand),
+            },
+            &Expr::Binary(operator, ref left, ref right) => {
+                fmt_operand(formatter, left, operator, false)?;
+                write!(formatter, " {} ", operator.symbol())?;
+                fmt_operand(formatter, right, operator, true)
+            }
+            &Expr::Call(function, ref argument) => write!(formatter, "{}({})", function, argument),
+            &Expr::Sized(_, ref operand) => write!(formatter, "{}", operand),
+        }
+    }
+}
+
+impl Expr {
+    // Expression written outside of a source file, like --define BANK_BASE=$7E0000+$200
+    pub fn parse_str(system: &'static SystemDefinition, text: &str) -> Result<Expr, String> {
+        Parser::expression_from_str(system, text)
+    }
+
+    // Evaluate the expression, `current_address` is the address of the start of the statement
+    pub fn evaluate(&self, symbol_table: &SymbolTable, current_address: u32) -> Result<i64, String> {
+        match self {
+            &Expr::Number(ref number) => Ok(number.number as i64),
+            &Expr::Identifier(ref identifier) => {
+                if symbol_table.has_label(identifier) {
+                    Ok(symbol_table.address_for(identifier) as i64)
+                } else {
+                    Err(format!("Label '{}' not found.", identifier))
+                }
+            }
+            &Expr::CurrentAddress => Ok(current_address as i64),
+            &Expr::Negate(ref operand) => Ok(-operand.evaluate(symbol_table, current_address)?),
+            &Expr::Binary(operator, ref left, ref right) => {
+                let left_value = left.evaluate(symbol_table, current_address)?;
+                let right_value = right.evaluate(symbol_table, current_address)?;
+
+                match operator.apply(left_value, right_value) {
+                    Some(value) => Ok(value),
+                    None => Err(format!(
+                        "Can't compute {} {} {}, the result is too large or the shift count isn't 0 to 63.",
+                        left_value,
+                        operator.symbol(),
+                        right_value
+                    )),
+                }
+            }
+            &Expr::Call(function, ref argument) => Ok(function.apply(argument.evaluate(symbol_table, current_address)?)),
+            &Expr::Sized(_, ref operand) => operand.evaluate(symbol_table, current_address),
+        }
+    }
+
+    // Labels used by the expression
+    pub fn identifiers(&self) -> Vec<&str> {
+        match self {
+            &Expr::Identifier(ref identifier) => vec![identifier],
+            &Expr::Number(_) | &Expr::CurrentAddress => vec![],
+            &Expr::Negate(ref operand) | &Expr::Call(_, ref operand) | &Expr::Sized(_, ref operand) => {
+                operand.identifiers()
+            }
+            &Expr::Binary(_, ref left, ref right) => {
+                let mut identifiers = left.identifiers();
+                identifiers.extend(right.identifiers());
+                identifiers
+            }
+        }
+    }
+
+    // The current address only has a value in a statement
+    pub fn uses_current_address(&self) -> bool {
+        match self {
+            &Expr::CurrentAddress => true,
+            &Expr::Number(_) | &Expr::Identifier(_) => false,
+            &Expr::Negate(ref operand) | &Expr::Call(_, ref operand) | &Expr::Sized(_, ref operand) => {
+                operand.uses_current_address()
+            }
+            &Expr::Binary(_, ref left, ref right) => left.uses_current_address() || right.uses_current_address(),
+        }
+    }
+
+    // Size of the value before it is evaluated, only known for functions returning a byte, sized values
+    // and masks, like label >> 8 & $FF which is a byte
+    pub fn size_hint(&self) -> Option<ArgumentSize> {
+        match self {
+            &Expr::Call(_, _) => Some(ArgumentSize::Word8),
+            &Expr::Sized(size, _) => Some(size),
+            &Expr::Binary(BinaryOperator::BitwiseAnd, ref left, ref right) => {
+                narrower_size(left.mask_size(), right.mask_size())
+            }
+            _ => None,
+        }
+    }
+
+    // Size an operand of & limits the result to
+    fn mask_size(&self) -> Option<ArgumentSize> {
+        match self.fold_constant() {
+            Some(number) => Some(number.argument_size),
+            None => self.size_hint(),
+        }
+    }
+
+    // Value of an expression made only of number literals, None when it uses a label or *
+    pub fn constant_value(&self) -> Option<i64> {
+        self.fold_value().map(|(value, _, _)| value)
+    }
+
+    // Value of an expression made only of number literals along with its kind and the widest
+    // size among the hexadecimal and binary literals
+    fn fold_value(&self) -> Option<(i64, NumberLiteralKind, Option<ArgumentSize>)> {
+        match self {
+            &Expr::Number(number) => if number.kind.has_explicit_size() {
+                Some((number.number as i64, number.kind, Some(number.argument_size)))
+            } else {
+                Some((number.number as i64, number.kind, None))
+            },
+            &Expr::Identifier(_) | &Expr::CurrentAddress => None,
+            &Expr::Negate(ref operand) => {
+                let (value, kind, size) = operand.fold_value()?;
+                Some((-value, kind, size))
+            }
+            &Expr::Binary(operator, ref left, ref right) => {
+                let (left_value, left_kind, left_size) = left.fold_value()?;
+                let (right_value, right_kind, right_size) = right.fold_value()?;
+
+                let value = operator.apply(left_value, right_value)?;
+
+                let kind = if right_size.is_some() { right_kind } else { left_kind };
+
+                // A mask limits the size of the result, which is sized from its value unless both sides are sized
+                let size = match (operator, left_size, right_size) {
+                    (BinaryOperator::BitwiseAnd, Some(_), Some(_)) => narrower_size(left_size, right_size),
+                    (BinaryOperator::BitwiseAnd, _, _) => None,
+                    _ => wider_size(left_size, right_size),
+                };
+
+                Some((value, kind, size))
+            }
+            &Expr::Call(function, ref argument) => {
+                let (value, kind, _) = argument.fold_value()?;
+                Some((function.apply(value), kind, Some(ArgumentSize::Word8)))
+            }
+            // Left unfolded when the value doesn't fit so the error is reported when resolving
+            &Expr::Sized(size, ref operand) => {
+                let (value, kind, _) = operand.fold_value()?;
+                signed_value_to_argument_size(value, size)?;
+                Some((value, kind, Some(size)))
+            }
+        }
+    }
+
+    // Fold an expression made only of number literals into a single number literal.
+    // The resulting size is the widest of the hexadecimal and binary literals or the size needed by the result.
+    // Negative results are stored in two's complement, so -1 is $FF.
+    pub fn fold_constant(&self) -> Option<NumberLiteral> {
+        let (value, kind, explicit_size) = self.fold_value()?;
+
+        let value_size = if value < 0 {
+            negative_number_to_argument_size(value)?
+        } else if value > u32::max_value() as i64 {
+            return None;
+        } else {
+            number_to_argument_size(value as u32)
+        };
+
+        let argument_size = wider_size(Some(value_size), explicit_size).unwrap();
+
+        Some(NumberLiteral {
+            number: truncate_to_argument_size(value, argument_size),
+            argument_size: argument_size,
+            kind: kind,
+        })
+    }
+}
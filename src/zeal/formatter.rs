@@ -0,0 +1,301 @@
+use std::collections::HashMap;
+use zeal::lexer::*;
+use zeal::parser::{ErrorMessage, ErrorSeverity};
+use zeal::system_definition::SystemDefinition;
+
+const INDENT: &str = "    ";
+// Column of the comments following a statement, longer statements push them to the right
+const COMMENT_COLUMN: usize = 32;
+
+// Statements written at the start of the line, the others are indented
+fn is_layout_keyword(ttype: &TokenType) -> bool {
+    match ttype {
+        &TokenType::KeywordOrigin
+        | &TokenType::KeywordSnesMap
+        | &TokenType::KeywordBank
+        | &TokenType::KeywordFreeSpace
+        | &TokenType::KeywordAutoOrigin
+        | &TokenType::KeywordNamespace
+        | &TokenType::KeywordEndNamespace
+        | &TokenType::KeywordExport
+        | &TokenType::KeywordVersion
+        | &TokenType::KeywordStruct
+        | &TokenType::KeywordEndStruct
+        | &TokenType::KeywordEnum
+        | &TokenType::KeywordEndEnum
+        | &TokenType::KeywordVectors
+        | &TokenType::KeywordEndVectors => true,
+        _ => false,
+    }
+}
+
+// Blocks whose lines are fields, like 'x: dsb 2' in a struct, instead of labels
+fn is_block_start(ttype: &TokenType) -> bool {
+    match ttype {
+        &TokenType::KeywordStruct | &TokenType::KeywordEnum | &TokenType::KeywordVectors => true,
+        _ => false,
+    }
+}
+
+fn is_block_end(ttype: &TokenType) -> bool {
+    match ttype {
+        &TokenType::KeywordEndStruct | &TokenType::KeywordEndEnum | &TokenType::KeywordEndVectors => true,
+        _ => false,
+    }
+}
+
+// Tokens ending an operand, a + or - after them is a binary operator
+fn ends_operand(ttype: &TokenType) -> bool {
+    match ttype {
+        &TokenType::NumberLiteral(_)
+        | &TokenType::Identifier(_)
+        | &TokenType::StringLiteral(_)
+        | &TokenType::RightParen
+        | &TokenType::RightBracket
+        | &TokenType::CurrentAddress => true,
+        _ => false,
+    }
+}
+
+fn is_adjacent(previous: &Token, next: &Token) -> bool {
+    previous.line == next.line && previous.end_column == next.start_column
+}
+
+// Like hirom-fast, the minus is part of the name when it touches the identifiers around it
+fn is_name_dash(tokens: &[Token], index: usize) -> bool {
+    if index == 0 || index + 1 >= tokens.len() {
+        return false;
+    }
+
+    match (&tokens[index - 1].ttype, &tokens[index + 1].ttype) {
+        (&TokenType::Identifier(_), &TokenType::Identifier(_)) => {
+            is_adjacent(&tokens[index - 1], &tokens[index]) && is_adjacent(&tokens[index], &tokens[index + 1])
+        }
+        _ => false,
+    }
+}
+
+// A + or - between two operands, a * used as multiplication and the shift and bitwise operators
+fn is_binary_operator(tokens: &[Token], index: usize) -> bool {
+    match tokens[index].ttype {
+        TokenType::ShiftLeft | TokenType::ShiftRight | TokenType::BitwiseAnd | TokenType::BitwiseOr => true,
+        TokenType::Plus | TokenType::Minus | TokenType::CurrentAddress if index > 0 => {
+            ends_operand(&tokens[index - 1].ttype) && !is_name_dash(tokens, index)
+        }
+        _ => false,
+    }
+}
+
+// Text between the tokens at index - 1 and index of a statement
+fn separator(tokens: &[Token], index: usize, is_instruction: bool) -> &'static str {
+    let previous = &tokens[index - 1];
+    let next = &tokens[index];
+
+    match (&previous.ttype, &next.ttype) {
+        (_, &TokenType::Comma) | (_, &TokenType::RightParen) | (_, &TokenType::RightBracket) | (_, &TokenType::Colon) => "",
+        (_, &TokenType::Range) | (&TokenType::Range, _) | (_, &TokenType::Dot) | (&TokenType::Dot, _) => "",
+        // origin! is lexed as origin followed by an invalid '!'
+        (&TokenType::KeywordOrigin, &TokenType::Invalid('!')) => "",
+        (&TokenType::LeftParen, _) | (&TokenType::LeftBracket, _) | (&TokenType::Immediate, _) => "",
+        // Operands of instructions are written like lda ($10,x), data like db 1, 2
+        (&TokenType::Comma, _) => if is_instruction { "" } else { " " },
+        // Bank and address of an origin like origin CODE_BANK:$8000
+        (&TokenType::Colon, _) if tokens[0].ttype == TokenType::KeywordOrigin => "",
+        (&TokenType::Colon, _) => " ",
+        _ if is_binary_operator(tokens, index) || is_binary_operator(tokens, index - 1) => " ",
+        (&TokenType::Minus, _) if is_name_dash(tokens, index - 1) => "",
+        (_, &TokenType::Minus) if is_name_dash(tokens, index) => "",
+        // Unary minus like -1
+        (&TokenType::Minus, _) | (&TokenType::Plus, _) => "",
+        // Opcode or keyword followed by its first operand
+        _ if index == 1 => " ",
+        // Anything else is kept as written, like the parenthesis of low(label)
+        _ => if is_adjacent(previous, next) { "" } else { " " },
+    }
+}
+
+fn token_text(lexer: &Lexer, token: &Token) -> String {
+    match token.ttype {
+        TokenType::Opcode(ref name) | TokenType::Register(ref name) => name.to_lowercase(),
+        // Hexadecimal in uppercase padded to the size it was written with, which picks the addressing mode
+        TokenType::NumberLiteral(ref number) => number.to_string(),
+        _ => lexer.token_text(token),
+    }
+}
+
+fn format_statement(lexer: &Lexer, tokens: &[Token]) -> String {
+    let is_instruction = match tokens[0].ttype {
+        TokenType::Opcode(_) | TokenType::SizedOpcode(_, _) => true,
+        _ => false,
+    };
+
+    let mut text = token_text(lexer, &tokens[0]);
+    for index in 1..tokens.len() {
+        text.push_str(separator(tokens, index, is_instruction));
+        text.push_str(&token_text(lexer, &tokens[index]));
+    }
+
+    text
+}
+
+fn push_line(lines: &mut Vec<String>, code: String, comment: Option<&Comment>) {
+    let line = match comment {
+        Some(comment) => if code.len() < COMMENT_COLUMN {
+            format!("{:width$}{}", code, comment.text, width = COMMENT_COLUMN)
+        } else {
+            format!("{} {}", code, comment.text)
+        },
+        None => code,
+    };
+
+    lines.push(line.trim_end().to_owned());
+}
+
+// Source written back in the canonical style: labels and layout statements like origin at the start
+// of the line, everything else indented, one space after the opcode and comments after a statement
+// aligned. Each line of the source stays on its own line except a label followed by a statement,
+// blank lines are kept but never more than one in a row. Formatting twice gives the same text.
+// Files with invalid tokens are left alone, the first one is returned as an error.
+pub fn format_source(system: &'static SystemDefinition, source_name: &str, source: &str) -> Result<String, ErrorMessage> {
+    let mut lexer = Lexer::from_string(system, source_name, source);
+    lexer.keep_comments();
+
+    let mut tokens_by_line: HashMap<u32, Vec<Token>> = HashMap::new();
+    let mut previous_type = TokenType::EndOfFile;
+    loop {
+        let token = lexer.get_next_token();
+        let is_origin_rewind = previous_type == TokenType::KeywordOrigin && token.ttype == TokenType::Invalid('!');
+        previous_type = token.ttype.clone();
+
+        match token.ttype {
+            TokenType::EndOfFile => break,
+            TokenType::InvalidString(ref message) => {
+                return Err(ErrorMessage {
+                    message: message.clone(),
+                    token: token.clone(),
+                    severity: ErrorSeverity::Error,
+                });
+            }
+            TokenType::Invalid(invalid_char) if !is_origin_rewind => {
+                return Err(ErrorMessage {
+                    message: format!("Invalid token '{}' found.", invalid_char),
+                    token: token.clone(),
+                    severity: ErrorSeverity::Error,
+                });
+            }
+            _ => tokens_by_line.entry(token.line).or_insert_with(Vec::new).push(token),
+        }
+    }
+
+    let comments = lexer.take_comments();
+    let comments_by_line: HashMap<u32, &Comment> = comments.iter().map(|comment| (comment.line, comment)).collect();
+
+    let mut lines: Vec<String> = Vec::new();
+    let mut in_block = false;
+
+    for line in 1..lexer.line_count() + 1 {
+        let comment = comments_by_line.get(&line).cloned();
+        let tokens = match tokens_by_line.get(&line) {
+            Some(tokens) => tokens.as_slice(),
+            None => &[],
+        };
+
+        if tokens.is_empty() {
+            match comment {
+                // Comments on their own line are indented only if they were
+                Some(comment) => {
+                    let indent = if comment.column == 1 { "" } else { INDENT };
+                    lines.push(format!("{}{}", indent, comment.text.trim_end()));
+                }
+                None => if lines.last().map_or(false, |last_line| !last_line.is_empty()) {
+                    lines.push(String::new());
+                },
+            }
+            continue;
+        }
+
+        let mut statement = tokens;
+
+        // Label on its own line, the statement after it goes on the next line
+        let is_label = match (statement.get(0), statement.get(1)) {
+            (Some(&Token { ttype: TokenType::Identifier(_), .. }), Some(&Token { ttype: TokenType::Colon, .. })) => true,
+            _ => false,
+        };
+        if is_label && !in_block {
+            let label = format_statement(&lexer, &statement[..2]);
+            statement = &statement[2..];
+            push_line(&mut lines, label, if statement.is_empty() { comment } else { None });
+
+            if statement.is_empty() {
+                continue;
+            }
+        }
+
+        if is_block_end(&statement[0].ttype) {
+            in_block = false;
+        }
+
+        let indent = if is_layout_keyword(&statement[0].ttype) { "" } else { INDENT };
+        push_line(&mut lines, format!("{}{}", indent, format_statement(&lexer, statement)), comment);
+
+        if is_block_start(&statement[0].ttype) {
+            in_block = true;
+        }
+    }
+
+    while lines.last().map_or(false, |last_line| last_line.is_empty()) {
+        lines.pop();
+    }
+
+    let mut text = lines.join("\n");
+    text.push('\n');
+    Ok(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use snes_cpu::SNES_CPU;
+    use std::fs;
+    use std::path::Path;
+    use zeal::assembler::*;
+    use zeal::formatter::format_source;
+
+    // Every sample that can be formatted gives the same text when formatted again, and assembles
+    // to the same bytes as the original
+    #[test]
+    fn format_testfiles_is_idempotent() {
+        let testfiles = Path::new(env!("CARGO_MANIFEST_DIR")).join("testfiles").join("snescpu");
+        let mut formatted_count = 0;
+
+        for entry in fs::read_dir(&testfiles).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().map_or(true, |extension| extension != "zc") {
+                continue;
+            }
+
+            let source_name = path.to_string_lossy().into_owned();
+            // Samples of invalid UTF-8 and of invalid tokens can't be formatted
+            let source = match fs::read_to_string(&path) {
+                Ok(source) => source,
+                Err(_) => continue,
+            };
+            let formatted = match format_source(&SNES_CPU, &source_name, &source) {
+                Ok(formatted) => formatted,
+                Err(_) => continue,
+            };
+
+            let formatted_again = format_source(&SNES_CPU, &source_name, &formatted).ok();
+            assert!(formatted_again.as_ref() == Some(&formatted), "Formatting {} twice changes it", source_name);
+
+            let options = AssembleOptions::new();
+            let original = assemble_source(&source_name, &source, &SNES_CPU, &options);
+            let reformatted = assemble_source(&source_name, &formatted, &SNES_CPU, &options);
+            assert!(original.has_errors() == reformatted.has_errors(), "Formatting {} changes its errors", source_name);
+            assert!(original.output == reformatted.output, "Formatting {} changes its bytes", source_name);
+
+            formatted_count += 1;
+        }
+
+        assert!(formatted_count > 0, "No sample found in {}", testfiles.display());
+    }
+}
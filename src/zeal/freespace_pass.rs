@@ -0,0 +1,186 @@
+use zeal::lexer::{NumberLiteral, NumberLiteralKind};
+use zeal::node_size::*;
+use zeal::parser::*;
+use zeal::pass::{DiagnosticSink, TreePass};
+use zeal::symbol_table::SymbolTable;
+use zeal::system_definition::*;
+
+struct FreeRegion {
+    next_address: u32,
+    end_address: u32,
+}
+
+// Code following an autoorg, named by its first label in the messages
+struct AutoBlock {
+    size: u32,
+    name: Option<String>,
+}
+
+// Replace each autoorg with an origin in the first freespace region that has enough room
+pub struct FreeSpacePass {
+    system: &'static SystemDefinition,
+    diagnostics: DiagnosticSink,
+    regions: Vec<FreeRegion>,
+}
+
+impl FreeSpacePass {
+    pub fn new(system: &'static SystemDefinition) -> Self {
+        FreeSpacePass {
+            system: system,
+            diagnostics: DiagnosticSink::new(),
+            regions: Vec::new(),
+        }
+    }
+
+    // Regions can be declared after the blocks using them so they are all collected first.
+    // Returns each autoorg block, a block goes until the next origin or the end of the input.
+    fn scan(&mut self, parse_tree: &Vec<ParseNode>) -> Vec<AutoBlock> {
+        let mut snes_map: Option<SnesMap> = None;
+        let mut blocks: Vec<AutoBlock> = Vec::new();
+        let mut in_block = false;
+
+        for node in parse_tree.iter() {
+            match node.expression {
+                ParseExpression::SnesMapStatement(ref map_mode) => {
+                    snes_map = Some(map_mode.clone());
+                }
+                ParseExpression::FreeSpace(ref region) => {
+                    let (start_address, end_address) = match region {
+                        &FreeSpaceRegion::Bank(bank) => match snes_map {
+                            Some(SnesMap::LoRom) | Some(SnesMap::Sa1Rom) => ((bank << 16) | 0x8000, (bank << 16) | 0xFFFF),
+                            _ => (bank << 16, (bank << 16) | 0xFFFF),
+                        },
+                        &FreeSpaceRegion::Range(start_address, end_address) => (start_address, end_address),
+                    };
+
+                    self.regions.push(FreeRegion {
+                        next_address: start_address,
+                        end_address: end_address,
+                    });
+                }
+                ParseExpression::AutoOrigin(_) => {
+                    blocks.push(AutoBlock { size: 0, name: None });
+                    in_block = true;
+                }
+                ParseExpression::OriginStatement(..) | ParseExpression::OriginExpression(..) => {
+                    in_block = false;
+                }
+                ParseExpression::Label(ref label_name) if in_block => {
+                    if let Some(block) = blocks.last_mut() {
+                        if block.name.is_none() {
+                            block.name = Some(label_name.to_owned());
+                        }
+                    }
+                }
+                _ => if in_block {
+                    if let Some(block) = blocks.last_mut() {
+                        block.size += node_size(self.system, node);
+                    }
+                },
+            }
+        }
+
+        blocks
+    }
+
+    fn allocate(&mut self, size: u32) -> Option<u32> {
+        for region in self.regions.iter_mut() {
+            if region.next_address <= region.end_address && region.end_address - region.next_address + 1 >= size {
+                let address = region.next_address;
+                region.next_address += size;
+                return Some(address);
+            }
+        }
+
+        None
+    }
+}
+
+impl TreePass for FreeSpacePass {
+    fn name(&self) -> &'static str {
+        "freespace"
+    }
+
+    fn do_pass(
+        &mut self,
+        parse_tree: Vec<ParseNode>,
+        _symbol_table: &mut SymbolTable,
+    ) -> (Vec<ParseNode>, Vec<ErrorMessage>) {
+        let mut new_tree: Vec<ParseNode> = Vec::new();
+
+        let mut blocks = self.scan(&parse_tree).into_iter();
+
+        // Regions are kept as address ranges for the ROM usage report
+        let mut resolved_regions = self.regions
+            .iter()
+            .map(|region| FreeSpaceRegion::Range(region.next_address, region.end_address))
+            .collect::<Vec<FreeSpaceRegion>>()
+            .into_iter();
+
+        for node in parse_tree.into_iter() {
+            match node.expression {
+                ParseExpression::FreeSpace(_) => {
+                    if let Some(region) = resolved_regions.next() {
+                        new_tree.push(ParseNode {
+                            start_token: node.start_token,
+                            address: None,
+                            expression: ParseExpression::FreeSpace(region),
+                        });
+                    }
+                }
+                ParseExpression::AutoOrigin(reserved_size) => {
+                    let (block_size, block_name) = match blocks.next() {
+                        Some(block) => (block.size, block.name),
+                        None => (0, None),
+                    };
+
+                    let size = match reserved_size {
+                        Some(reserved_size) if reserved_size < block_size => {
+                            self.diagnostics.add_error(
+                                &format!(
+                                    "Block is {} bytes which is more than the {} bytes reserved by autoorg.",
+                                    block_size, reserved_size
+                                ),
+                                node.start_token.clone(),
+                            );
+                            continue;
+                        }
+                        Some(reserved_size) => reserved_size,
+                        None => block_size,
+                    };
+
+                    if self.regions.is_empty() {
+                        self.diagnostics.add_error(&"No freespace region was declared for this block.", node.start_token.clone());
+                        continue;
+                    }
+
+                    match self.allocate(size) {
+                        Some(address) => {
+                            new_tree.push(ParseNode {
+                                start_token: node.start_token.clone(),
+                                address: None,
+                                expression: ParseExpression::OriginStatement(NumberLiteral {
+                                    number: address,
+                                    argument_size: ArgumentSize::Word24,
+                                    kind: NumberLiteralKind::Hex,
+                                }, true),
+                            });
+                        }
+                        None => {
+                            let message = match block_name {
+                                Some(ref block_name) => format!("Not enough freespace left for block '{}' of {} bytes.", block_name, size),
+                                None => format!("Not enough freespace left for the block of {} bytes starting here.", size),
+                            };
+                            self.diagnostics.add_error(&message, node.start_token.clone());
+                        }
+                    }
+                }
+                _ => {
+                    new_tree.push(node);
+                }
+            }
+        }
+
+        return (new_tree, self.diagnostics.take_messages());
+    }
+}
@@ -0,0 +1,241 @@
+use std::io::{Result, Write};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    Binary,
+    IntelHex,
+    SRecord,
+    // Patches against the source ROM
+    Ips,
+    Bps,
+    // Text listing of the address and bytes of each statement
+    Listing,
+}
+
+impl OutputFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "raw" | "bin" => Some(OutputFormat::Binary),
+            "ihex" | "hex" => Some(OutputFormat::IntelHex),
+            "srec" => Some(OutputFormat::SRecord),
+            "ips" => Some(OutputFormat::Ips),
+            "bps" => Some(OutputFormat::Bps),
+            "listing" => Some(OutputFormat::Listing),
+            _ => None,
+        }
+    }
+
+    pub fn is_patch(&self) -> bool {
+        *self == OutputFormat::Ips || *self == OutputFormat::Bps
+    }
+
+    // The byte count of a record is 8-bit and also counts the address and checksum in S-records
+    pub fn max_bytes_per_record(&self) -> usize {
+        match self {
+            &OutputFormat::Binary | &OutputFormat::Ips | &OutputFormat::Bps | &OutputFormat::Listing => {
+                usize::max_value()
+            }
+            &OutputFormat::IntelHex => 255,
+            &OutputFormat::SRecord => 250,
+        }
+    }
+}
+
+// A run of bytes starting at an offset in the output image
+pub struct DataRun {
+    pub offset: u32,
+    pub bytes: Vec<u8>,
+}
+
+fn write_hex_bytes<W: Write>(output: &mut W, bytes: &[u8]) -> Result<()> {
+    for byte in bytes.iter() {
+        write!(output, "{:02X}", byte)?;
+    }
+
+    Ok(())
+}
+
+fn write_ihex_record<W: Write>(output: &mut W, address: u16, record_type: u8, data: &[u8]) -> Result<()> {
+    let mut record = vec![data.len() as u8, (address >> 8) as u8, address as u8, record_type];
+    record.extend_from_slice(data);
+
+    // Two's complement of the sum of every byte of the record
+    let checksum = record.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+    record.push(0u8.wrapping_sub(checksum));
+
+    write!(output, ":")?;
+    write_hex_bytes(output, &record)?;
+    writeln!(output, "")
+}
+
+// Intel HEX, with extended linear address records (type 04) for data above 64KB
+pub fn write_intel_hex<W: Write>(output: &mut W, runs: &[DataRun], bytes_per_record: usize) -> Result<()> {
+    let mut upper_address = 0;
+
+    for run in runs.iter() {
+        let mut position = 0;
+
+        while position < run.bytes.len() {
+            let address = run.offset + position as u32;
+
+            if address >> 16 != upper_address {
+                upper_address = address >> 16;
+                write_ihex_record(output, 0, 0x04, &[(upper_address >> 8) as u8, upper_address as u8])?;
+            }
+
+            // A record can't cross a 64KB boundary
+            let until_boundary = (0x10000 - (address & 0xFFFF)) as usize;
+            let count = bytes_per_record.min(run.bytes.len() - position).min(until_boundary);
+
+            write_ihex_record(output, address as u16, 0x00, &run.bytes[position..position + count])?;
+            position += count;
+        }
+    }
+
+    write_ihex_record(output, 0, 0x01, &[])
+}
+
+fn write_srecord_record<W: Write>(
+    output: &mut W,
+    record_type: u8,
+    address: u32,
+    address_size: usize,
+    data: &[u8],
+) -> Result<()> {
+    let mut record = vec![(address_size + data.len() + 1) as u8];
+    for index in (0..address_size).rev() {
+        record.push((address >> (index * 8)) as u8);
+    }
+    record.extend_from_slice(data);
+
+    // One's complement of the sum of the count, address and data bytes
+    let checksum = record.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+    record.push(!checksum);
+
+    write!(output, "S{}", record_type)?;
+    write_hex_bytes(output, &record)?;
+    writeln!(output, "")
+}
+
+// Motorola S-record, S1/S2/S3 data records are picked from the highest address of the image
+pub fn write_srecord<W: Write>(output: &mut W, header: &str, runs: &[DataRun], bytes_per_record: usize) -> Result<()> {
+    let end_address = runs.iter()
+        .map(|run| run.offset as u64 + run.bytes.len() as u64)
+        .max()
+        .unwrap_or(0);
+
+    let (data_type, termination_type, address_size) = if end_address <= 0x10000 {
+        (1, 9, 2)
+    } else if end_address <= 0x1000000 {
+        (2, 8, 3)
+    } else {
+        (3, 7, 4)
+    };
+
+    // The header is only informative, keep it short enough to fit in a record
+    let header = header.as_bytes();
+    write_srecord_record(output, 0, 0, 2, &header[..header.len().min(64)])?;
+
+    let mut record_count = 0;
+    for run in runs.iter() {
+        for (index, chunk) in run.bytes.chunks(bytes_per_record).enumerate() {
+            let address = run.offset + (index * bytes_per_record) as u32;
+            write_srecord_record(output, data_type, address, address_size, chunk)?;
+            record_count += 1;
+        }
+    }
+
+    if record_count <= 0xFFFF {
+        write_srecord_record(output, 5, record_count, 2, &[])?;
+    } else {
+        write_srecord_record(output, 6, record_count, 3, &[])?;
+    }
+
+    write_srecord_record(output, termination_type, 0, address_size, &[])
+}
+
+#[cfg(test)]
+mod tests {
+    use zeal::hex_format::*;
+
+    fn intel_hex_lines(runs: &[DataRun], bytes_per_record: usize) -> Vec<String> {
+        let mut output = Vec::new();
+        write_intel_hex(&mut output, runs, bytes_per_record).unwrap();
+        String::from_utf8(output).unwrap().lines().map(String::from).collect()
+    }
+
+    fn srecord_lines(runs: &[DataRun], bytes_per_record: usize) -> Vec<String> {
+        let mut output = Vec::new();
+        write_srecord(&mut output, "zealc", runs, bytes_per_record).unwrap();
+        String::from_utf8(output).unwrap().lines().map(String::from).collect()
+    }
+
+    #[test]
+    fn intel_hex_switches_the_upper_address_past_64kb() {
+        let runs = [DataRun { offset: 0xFFFE, bytes: vec![0x01, 0x02, 0x03, 0x04] }];
+
+        // The record stops at the 64KB boundary, then a type 04 record sets the upper address to $0001
+        assert_eq!(
+            intel_hex_lines(&runs, 16),
+            [":02FFFE000102FE", ":020000040001F9", ":020000000304F7", ":00000001FF"]
+        );
+    }
+
+    #[test]
+    fn intel_hex_splits_runs_by_bytes_per_record() {
+        let runs = [DataRun { offset: 0x8000, bytes: vec![0x10, 0x11, 0x12, 0x13, 0x14] }];
+
+        assert_eq!(
+            intel_hex_lines(&runs, 2),
+            [":0280000010115D", ":02800200121357", ":018004001467", ":00000001FF"]
+        );
+    }
+
+    #[test]
+    fn srecord_uses_s1_and_s9_below_64kb() {
+        let runs = [DataRun { offset: 0x8000, bytes: vec![0xAA, 0xBB] }];
+
+        assert_eq!(
+            srecord_lines(&runs, 16),
+            ["S00800007A65616C63E8", "S1058000AABB15", "S5030001FB", "S9030000FC"]
+        );
+    }
+
+    #[test]
+    fn srecord_uses_s2_and_s8_below_16mb() {
+        let runs = [DataRun { offset: 0x10000, bytes: vec![0x01] }];
+
+        assert_eq!(
+            srecord_lines(&runs, 16),
+            ["S00800007A65616C63E8", "S20501000001F8", "S5030001FB", "S804000000FB"]
+        );
+    }
+
+    #[test]
+    fn srecord_uses_s3_and_s7_from_16mb() {
+        let runs = [DataRun { offset: 0x1000000, bytes: vec![0x01] }];
+
+        assert_eq!(
+            srecord_lines(&runs, 16),
+            ["S00800007A65616C63E8", "S3060100000001F7", "S5030001FB", "S70500000000FA"]
+        );
+    }
+
+    #[test]
+    fn srecord_splits_runs_by_bytes_per_record() {
+        let runs = [DataRun { offset: 0x8000, bytes: vec![0x10, 0x11, 0x12, 0x13, 0x14] }];
+
+        // The S5 record counts the 3 data records
+        assert_eq!(
+            srecord_lines(&runs, 2),
+            [
+                "S00800007A65616C63E8",
+                "S1058000101159",
+                "S1058002121353",
+                "S10480041463",
+                "S5030003F9",
+                "S9030000FC",
+            ]
+        );
+    }
+}
@@ -0,0 +1,72 @@
+#[derive(Clone, Copy, PartialEq)]
+pub enum HexCase {
+    Upper,
+    Lower,
+}
+
+// How values are written in hexadecimal in the listing, the symbol files and the messages
+// printing addresses, set with --hex-upper, --hex-lower and --hex-address-width
+#[derive(Clone, Copy)]
+pub struct HexStyle {
+    // None keeps the usual case of each output, lowercase in WLA symbol files and uppercase elsewhere
+    pub case: Option<HexCase>,
+    // Minimum number of digits of an address, longer addresses aren't cut
+    pub address_width: usize,
+}
+
+pub const DEFAULT_ADDRESS_WIDTH: usize = 6;
+// An address has at most 32 bits
+pub const MAX_ADDRESS_WIDTH: usize = 8;
+
+impl HexStyle {
+    pub fn new() -> Self {
+        HexStyle {
+            case: None,
+            address_width: DEFAULT_ADDRESS_WIDTH,
+        }
+    }
+
+    // Same style with `case` used when no case was asked for
+    pub fn with_default_case(&self, case: HexCase) -> Self {
+        HexStyle {
+            case: Some(self.case.unwrap_or(case)),
+            address_width: self.address_width,
+        }
+    }
+
+    // Digits of the value padded with zeros to `width`, without the $
+    pub fn digits(&self, value: u32, width: usize) -> String {
+        match self.case {
+            Some(HexCase::Lower) => format!("{:0width$x}", value, width = width),
+            _ => format!("{:0width$X}", value, width = width),
+        }
+    }
+
+    // Address without the $ like 808000, as used by the columns of the listing
+    pub fn address_digits(&self, address: u32) -> String {
+        self.digits(address, self.address_width)
+    }
+
+    // Address like $808000
+    pub fn address(&self, address: u32) -> String {
+        format!("${}", self.address_digits(address))
+    }
+
+    // Byte like $7E
+    pub fn byte(&self, value: u32) -> String {
+        format!("${}", self.digits(value, 2))
+    }
+
+    // Value with only the digits needed like $1F
+    pub fn value(&self, value: u32) -> String {
+        format!("${}", self.digits(value, 0))
+    }
+
+    pub fn bytes(&self, bytes: &[u8]) -> String {
+        bytes
+            .iter()
+            .map(|byte| self.digits(*byte as u32, 2))
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+}
@@ -1,22 +1,94 @@
 use zeal::lexer::Token;
 use zeal::parser::*;
 use zeal::system_definition::*;
-use zeal::pass::TreePass;
+use zeal::pass::{DiagnosticSink, TreePass};
 use zeal::symbol_table::SymbolTable;
 
 pub struct InstructionToStatementPass {
     system: &'static SystemDefinition,
-    pub error_messages: Vec<ErrorMessage>,
+    diagnostics: DiagnosticSink,
 }
 
 impl InstructionToStatementPass {
     pub fn new(system: &'static SystemDefinition) -> Self {
         InstructionToStatementPass {
             system: system,
-            error_messages: Vec::new(),
+            diagnostics: DiagnosticSink::new(),
         }
     }
 
+    // Every way the opcode can be written in the addressing mode along with the name of the mode,
+    // like direct page for lda dp
+    fn valid_instruction_forms(&self, opcode_name: &str, addressing_mode: &AddressingMode) -> Vec<(String, String)> {
+        let mut forms: Vec<(String, String)> = Vec::new();
+
+        for instruction in self.system
+            .instructions
+            .iter()
+            .filter(|instruction| instruction.name == opcode_name && &instruction.addressing == addressing_mode)
+        {
+            let operands = instruction
+                .arguments
+                .iter()
+                .map(|argument| match (&instruction.addressing, argument) {
+                    (_, &InstructionArgument::Register(register_name)) => register_name.to_string(),
+                    (&AddressingMode::Immediate, _) => String::from("imm"),
+                    (&AddressingMode::Relative, _) => String::from("label"),
+                    (&AddressingMode::BlockMove, _) => String::from("bank"),
+                    (_, &InstructionArgument::Number(size)) => (self.system.size_to_operand_name)(size).to_string(),
+                    (_, &InstructionArgument::Numbers(sizes)) => match sizes.first() {
+                        Some(&size) => (self.system.size_to_operand_name)(size).to_string(),
+                        None => String::new(),
+                    },
+                    (_, &InstructionArgument::NotStaticRegister(ref register_name)) => register_name.to_owned(),
+                })
+                .collect::<Vec<String>>();
+
+            let form = (
+                instruction_addressing_to_string(self.system, instruction),
+                format_instruction(opcode_name, &instruction.addressing, &operands),
+            );
+            if !forms.contains(&form) {
+                forms.push(form);
+            }
+        }
+
+        forms
+    }
+
+    // The error lists the addressing modes the opcode does support with an example of each,
+    // like 'lda' supports: immediate (lda #imm), direct page (lda dp), ...
+    fn add_unsupported_error(&mut self, error_message: &str, opcode_name: &str, offending_token: Token) {
+        let supported_modes = find_all_valid_modes(self.system, opcode_name)
+            .iter()
+            .flat_map(|addressing_mode| self.valid_instruction_forms(opcode_name, addressing_mode))
+            .map(|(mode_name, form)| format!("{} ({})", mode_name, form))
+            .collect::<Vec<String>>();
+
+        if supported_modes.is_empty() {
+            self.diagnostics.add_error(error_message, offending_token);
+        } else {
+            self.diagnostics.add_error(
+                &format!("{} '{}' supports: {}.", error_message, opcode_name, supported_modes.join(", ")),
+                offending_token,
+            );
+        }
+    }
+
+    // Values computed from an expression can still be wider than any operand, literals are checked by the parser
+    fn check_operand_sizes(&mut self, node: &ParseNode) -> bool {
+        for argument in node.expression.arguments() {
+            if let &ParseArgument::NumberLiteral(ref number) = argument {
+                if let Some(message) = oversized_operand_message(self.system, &number.to_string(), number.argument_size) {
+                    self.diagnostics.add_error(&message, node.start_token.clone());
+                    return false;
+                }
+            }
+        }
+
+        true
+    }
+
     fn find_suitable_instruction(
         &mut self,
         opcode_name: &str,
@@ -92,14 +164,26 @@ impl InstructionToStatementPass {
         return None;
     }
 
-    fn add_error_message(&mut self, error_message: &str, offending_token: Token) {
-        let new_message = ErrorMessage {
-            message: error_message.to_owned(),
-            token: offending_token,
-            severity: ErrorSeverity::Error,
-        };
-
-        self.error_messages.push(new_message);
+    // Block move operands are bank numbers and must fit in 8 bits, a bank written like $007E is 8-bit too
+    fn validate_bank_operand(&mut self, opcode_name: &str, argument: &ParseArgument, token: &Token) -> Option<ParseArgument> {
+        match argument {
+            &ParseArgument::NumberLiteral(number) if number.number > 0xFF => {
+                self.diagnostics.add_error(
+                    &format!(
+                        "Bank operand ${:X} of '{}' is not 8-bit, block moves take source and destination banks.",
+                        number.number, opcode_name
+                    ),
+                    token.clone(),
+                );
+                None
+            }
+            &ParseArgument::NumberLiteral(number) => {
+                let mut bank = number;
+                bank.argument_size = ArgumentSize::Word8;
+                Some(ParseArgument::NumberLiteral(bank))
+            }
+            _ => Some(argument.clone()),
+        }
     }
 
     fn add_to_argument_list_capture_register(
@@ -119,7 +203,7 @@ impl InstructionToStatementPass {
                 ));
                 return Some(result_register_name);
             }
-            &ParseArgument::Identifier(_) => {
+            &ParseArgument::Identifier(_) | &ParseArgument::Expression(_) => {
                 return None;
             }
         };
@@ -139,28 +223,29 @@ impl InstructionToStatementPass {
                     register_name.to_owned(),
                 ));
             }
-            &ParseArgument::Identifier(_) => {}
+            &ParseArgument::Identifier(_) | &ParseArgument::Expression(_) => {}
         };
     }
 }
 
 impl TreePass for InstructionToStatementPass {
-    fn has_errors(&self) -> bool {
-        return !self.error_messages.is_empty();
-    }
-
-    fn get_error_messages(&self) -> &Vec<ErrorMessage> {
-        &self.error_messages
+    fn name(&self) -> &'static str {
+        "instruction-to-statement"
     }
 
     fn do_pass(
         &mut self,
         parse_tree: Vec<ParseNode>,
         _symbol_table: &mut SymbolTable,
-    ) -> Vec<ParseNode> {
+    ) -> (Vec<ParseNode>, Vec<ErrorMessage>) {
         let mut new_tree: Vec<ParseNode> = Vec::new();
 
-        for node in parse_tree.iter() {
+        for node in parse_tree.into_iter() {
+            if !self.check_operand_sizes(&node) {
+                new_tree.push(node);
+                continue;
+            }
+
             match node.expression {
                 ParseExpression::ImpliedInstruction(ref opcode_name) => {
                     match self.find_suitable_instruction(
@@ -171,20 +256,22 @@ impl TreePass for InstructionToStatementPass {
                         Some(instruction) => {
                             new_tree.push(ParseNode {
                                 start_token: node.start_token.clone(),
+                                address: node.address,
                                 expression: ParseExpression::FinalInstruction(
                                     FinalInstruction::ImpliedInstruction(instruction),
                                 ),
                             });
                         }
                         None => {
-                            self.add_error_message(
+                            self.add_unsupported_error(
                                 &format!(
                                     "opcode '{}' does not support implied addressing mode.",
                                     opcode_name
                                 ),
+                                opcode_name,
                                 node.start_token.clone(),
                             );
-                            new_tree.push(node.clone());
+                            new_tree.push(node);
                         }
                     }
                 }
@@ -199,6 +286,7 @@ impl TreePass for InstructionToStatementPass {
                                 Some(instruction) => {
                                     new_tree.push(ParseNode {
                                         start_token: node.start_token.clone(),
+                                        address: node.address,
                                         expression: ParseExpression::FinalInstruction(
                                             FinalInstruction::SingleArgumentInstruction(
                                                 instruction,
@@ -208,17 +296,17 @@ impl TreePass for InstructionToStatementPass {
                                     });
                                 }
                                 None => {
-                                    self.add_error_message(&format!("opcode '{}' does not support immediate addressing mode of size {}-bit.", opcode_name, argument_size_to_bit_size(number.argument_size)), node.start_token.clone());
-                                    new_tree.push(node.clone());
+                                    self.add_unsupported_error(&format!("opcode '{}' does not support immediate addressing mode of size {}-bit.", opcode_name, argument_size_to_bit_size(number.argument_size)), opcode_name, node.start_token.clone());
+                                    new_tree.push(node);
                                 }
                             }
                         }
                         &ParseArgument::Register(ref register_name) => {
-                            self.add_error_message(&format!("immediate addressing mode does not support '{}' register argument.", register_name), node.start_token.clone());
-                            new_tree.push(node.clone());
+                            self.diagnostics.add_error(&format!("immediate addressing mode does not support '{}' register argument.", register_name), node.start_token.clone());
+                            new_tree.push(node);
                         }
-                        &ParseArgument::Identifier(_) => {
-                            new_tree.push(node.clone());
+                        &ParseArgument::Identifier(_) | &ParseArgument::Expression(_) => {
+                            new_tree.push(node);
                         }
                     }
                 }
@@ -233,6 +321,7 @@ impl TreePass for InstructionToStatementPass {
                                 Some(instruction) => {
                                     new_tree.push(ParseNode {
                                         start_token: node.start_token.clone(),
+                                        address: node.address,
                                         expression: ParseExpression::FinalInstruction(
                                             FinalInstruction::SingleArgumentInstruction(
                                                 instruction,
@@ -242,7 +331,7 @@ impl TreePass for InstructionToStatementPass {
                                     });
                                 }
                                 None => {
-                                    self.add_error_message(
+                                    self.add_unsupported_error(
                                         &format!(
                                             "opcode '{}' does not support {} addressing mode.",
                                             opcode_name,
@@ -250,24 +339,25 @@ impl TreePass for InstructionToStatementPass {
                                                 number.argument_size
                                             )
                                         ),
+                                        opcode_name,
                                         node.start_token.clone(),
                                     );
-                                    new_tree.push(node.clone());
+                                    new_tree.push(node);
                                 }
                             }
                         }
                         &ParseArgument::Register(ref register_name) => {
-                            self.add_error_message(
+                            self.diagnostics.add_error(
                                 &format!(
                                     "addressing mode does not support '{}' register argument.",
                                     register_name
                                 ),
                                 node.start_token.clone(),
                             );
-                            new_tree.push(node.clone());
+                            new_tree.push(node);
                         }
-                        &ParseArgument::Identifier(_) => {
-                            new_tree.push(node.clone());
+                        &ParseArgument::Identifier(_) | &ParseArgument::Expression(_) => {
+                            new_tree.push(node);
                         }
                     }
                 }
@@ -299,6 +389,7 @@ impl TreePass for InstructionToStatementPass {
                         Some(instruction) => {
                             new_tree.push(ParseNode {
                                 start_token: node.start_token.clone(),
+                                address: node.address,
                                 expression: ParseExpression::FinalInstruction(
                                     FinalInstruction::SingleArgumentInstruction(
                                         instruction,
@@ -309,17 +400,18 @@ impl TreePass for InstructionToStatementPass {
                         }
                         None => {
                             if result_register_name == "s" {
-                                self.add_error_message(
+                                self.add_unsupported_error(
                                     &format!(
                                         "opcode '{}' does not support stack relative mode.",
                                         opcode_name
                                     ),
+                                    opcode_name,
                                     node.start_token.clone(),
                                 );
                             } else {
-                                self.add_error_message(&format!("opcode '{}' does not support '{}' indexed addressing mode.", opcode_name, result_register_name), node.start_token.clone());
+                                self.add_unsupported_error(&format!("opcode '{}' does not support '{}' indexed addressing mode.", opcode_name, result_register_name), opcode_name, node.start_token.clone());
                             }
-                            new_tree.push(node.clone());
+                            new_tree.push(node);
                         }
                     }
                 }
@@ -334,6 +426,7 @@ impl TreePass for InstructionToStatementPass {
                                 Some(instruction) => {
                                     new_tree.push(ParseNode {
                                         start_token: node.start_token.clone(),
+                                        address: node.address,
                                         expression: ParseExpression::FinalInstruction(
                                             FinalInstruction::SingleArgumentInstruction(
                                                 instruction,
@@ -343,23 +436,23 @@ impl TreePass for InstructionToStatementPass {
                                     });
                                 }
                                 None => {
-                                    self.add_error_message(&format!("opcode '{}' does not support indirect addressing mode.", opcode_name), node.start_token.clone());
-                                    new_tree.push(node.clone());
+                                    self.add_unsupported_error(&format!("opcode '{}' does not support indirect addressing mode.", opcode_name), opcode_name, node.start_token.clone());
+                                    new_tree.push(node);
                                 }
                             }
                         }
                         &ParseArgument::Register(ref register_name) => {
-                            self.add_error_message(
+                            self.diagnostics.add_error(
                                 &format!(
                                     "addressing mode does not support '{}' register argument.",
                                     register_name
                                 ),
                                 node.start_token.clone(),
                             );
-                            new_tree.push(node.clone());
+                            new_tree.push(node);
                         }
-                        &ParseArgument::Identifier(_) => {
-                            new_tree.push(node.clone());
+                        &ParseArgument::Identifier(_) | &ParseArgument::Expression(_) => {
+                            new_tree.push(node);
                         }
                     }
                 }
@@ -374,6 +467,7 @@ impl TreePass for InstructionToStatementPass {
                                 Some(instruction) => {
                                     new_tree.push(ParseNode {
                                         start_token: node.start_token.clone(),
+                                        address: node.address,
                                         expression: ParseExpression::FinalInstruction(
                                             FinalInstruction::SingleArgumentInstruction(
                                                 instruction,
@@ -383,23 +477,23 @@ impl TreePass for InstructionToStatementPass {
                                     });
                                 }
                                 None => {
-                                    self.add_error_message(&format!("opcode '{}' does not support indirect long addressing mode.", opcode_name), node.start_token.clone());
-                                    new_tree.push(node.clone());
+                                    self.add_unsupported_error(&format!("opcode '{}' does not support indirect long addressing mode.", opcode_name), opcode_name, node.start_token.clone());
+                                    new_tree.push(node);
                                 }
                             }
                         }
                         &ParseArgument::Register(ref register_name) => {
-                            self.add_error_message(
+                            self.diagnostics.add_error(
                                 &format!(
                                     "addressing mode does not support '{}' register argument.",
                                     register_name
                                 ),
                                 node.start_token.clone(),
                             );
-                            new_tree.push(node.clone());
+                            new_tree.push(node);
                         }
-                        &ParseArgument::Identifier(_) => {
-                            new_tree.push(node.clone());
+                        &ParseArgument::Identifier(_) | &ParseArgument::Expression(_) => {
+                            new_tree.push(node);
                         }
                     }
                 }
@@ -431,6 +525,7 @@ impl TreePass for InstructionToStatementPass {
                         Some(instruction) => {
                             new_tree.push(ParseNode {
                                 start_token: node.start_token.clone(),
+                                address: node.address,
                                 expression: ParseExpression::FinalInstruction(
                                     FinalInstruction::SingleArgumentInstruction(
                                         instruction,
@@ -440,8 +535,8 @@ impl TreePass for InstructionToStatementPass {
                             });
                         }
                         None => {
-                            self.add_error_message(&format!("opcode '{}' does not support '{}' indexed indirect addressing mode.", opcode_name, result_register_name), node.start_token.clone());
-                            new_tree.push(node.clone());
+                            self.add_unsupported_error(&format!("opcode '{}' does not support '{}' indexed indirect addressing mode.", opcode_name, result_register_name), opcode_name, node.start_token.clone());
+                            new_tree.push(node);
                         }
                     }
                 }
@@ -473,6 +568,7 @@ impl TreePass for InstructionToStatementPass {
                         Some(instruction) => {
                             new_tree.push(ParseNode {
                                 start_token: node.start_token.clone(),
+                                address: node.address,
                                 expression: ParseExpression::FinalInstruction(
                                     FinalInstruction::SingleArgumentInstruction(
                                         instruction,
@@ -482,8 +578,8 @@ impl TreePass for InstructionToStatementPass {
                             });
                         }
                         None => {
-                            self.add_error_message(&format!("opcode '{}' does not support '{}' indirect indexed addressing mode.", opcode_name, result_register_name), node.start_token.clone());
-                            new_tree.push(node.clone());
+                            self.add_unsupported_error(&format!("opcode '{}' does not support '{}' indirect indexed addressing mode.", opcode_name, result_register_name), opcode_name, node.start_token.clone());
+                            new_tree.push(node);
                         }
                     }
                 }
@@ -515,6 +611,7 @@ impl TreePass for InstructionToStatementPass {
                         Some(instruction) => {
                             new_tree.push(ParseNode {
                                 start_token: node.start_token.clone(),
+                                address: node.address,
                                 expression: ParseExpression::FinalInstruction(
                                     FinalInstruction::SingleArgumentInstruction(
                                         instruction,
@@ -524,8 +621,8 @@ impl TreePass for InstructionToStatementPass {
                             });
                         }
                         None => {
-                            self.add_error_message(&format!("opcode '{}' does not support '{}' indirect indexed long addressing mode.", opcode_name, result_register_name), node.start_token.clone());
-                            new_tree.push(node.clone());
+                            self.add_unsupported_error(&format!("opcode '{}' does not support '{}' indirect indexed long addressing mode.", opcode_name, result_register_name), opcode_name, node.start_token.clone());
+                            new_tree.push(node);
                         }
                     }
                 }
@@ -534,6 +631,16 @@ impl TreePass for InstructionToStatementPass {
                     ref argument1,
                     ref argument2,
                 ) => {
+                    let source_bank = self.validate_bank_operand(opcode_name, &argument1, &node.start_token);
+                    let destination_bank = self.validate_bank_operand(opcode_name, &argument2, &node.start_token);
+                    let (argument1, argument2) = match (source_bank, destination_bank) {
+                        (Some(argument1), Some(argument2)) => (argument1, argument2),
+                        _ => {
+                            new_tree.push(node);
+                            continue;
+                        }
+                    };
+
                     let mut argument_list = Vec::new();
 
                     self.add_to_argument_list(&mut argument_list, &argument1);
@@ -547,24 +654,26 @@ impl TreePass for InstructionToStatementPass {
                         Some(instruction) => {
                             new_tree.push(ParseNode {
                                 start_token: node.start_token.clone(),
+                                address: node.address,
                                 expression: ParseExpression::FinalInstruction(
                                     FinalInstruction::TwoArgumentInstruction(
                                         instruction,
-                                        argument1.clone(),
-                                        argument2.clone(),
+                                        argument1,
+                                        argument2,
                                     ),
                                 ),
                             });
                         }
                         None => {
-                            self.add_error_message(
+                            self.add_unsupported_error(
                                 &format!(
                                     "opcode '{}' does not support block mode addressing mode.",
                                     opcode_name
                                 ),
+                                opcode_name,
                                 node.start_token.clone(),
                             );
-                            new_tree.push(node.clone());
+                            new_tree.push(node);
                         }
                     }
                 }
@@ -588,6 +697,7 @@ impl TreePass for InstructionToStatementPass {
                         Some(instruction) => {
                             new_tree.push(ParseNode {
                                 start_token: node.start_token.clone(),
+                                address: node.address,
                                 expression: ParseExpression::FinalInstruction(
                                     FinalInstruction::SingleArgumentInstruction(
                                         instruction,
@@ -597,17 +707,17 @@ impl TreePass for InstructionToStatementPass {
                             });
                         }
                         None => {
-                            self.add_error_message(&format!("opcode '{}' does not support stack relative indirect indexed addressing mode.", opcode_name), node.start_token.clone());
-                            new_tree.push(node.clone());
+                            self.add_unsupported_error(&format!("opcode '{}' does not support stack relative indirect indexed addressing mode.", opcode_name), opcode_name, node.start_token.clone());
+                            new_tree.push(node);
                         }
                     }
                 }
                 _ => {
-                    new_tree.push(node.clone());
+                    new_tree.push(node);
                 }
             };
         }
 
-        return new_tree;
+        return (new_tree, self.diagnostics.take_messages());
     }
 }
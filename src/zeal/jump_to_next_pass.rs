@@ -0,0 +1,86 @@
+use zeal::node_size::*;
+use zeal::parser::*;
+use zeal::pass::{DiagnosticSink, TreePass};
+use zeal::symbol_table::SymbolTable;
+use zeal::system_definition::*;
+
+// Warn about branches and jumps to the instruction right after them, usually left behind after an edit.
+// Runs after ResolveLabelPass so every target is a number.
+pub struct JumpToNextPass {
+    system: &'static SystemDefinition,
+    diagnostics: DiagnosticSink,
+}
+
+impl JumpToNextPass {
+    pub fn new(system: &'static SystemDefinition) -> Self {
+        JumpToNextPass {
+            system: system,
+            diagnostics: DiagnosticSink::new(),
+        }
+    }
+
+    fn has_addressing(&self, opcode_name: &str, addressing_mode: AddressingMode) -> bool {
+        self.system
+            .instructions
+            .iter()
+            .any(|instruction| instruction.name == opcode_name && instruction.addressing == addressing_mode)
+    }
+}
+
+impl TreePass for JumpToNextPass {
+    fn name(&self) -> &'static str {
+        "jump-to-next"
+    }
+
+    fn do_pass(
+        &mut self,
+        parse_tree: Vec<ParseNode>,
+        _symbol_table: &mut SymbolTable,
+    ) -> (Vec<ParseNode>, Vec<ErrorMessage>) {
+        let mut current_address: u32 = 0;
+
+        for node in parse_tree.iter() {
+            let next_address = current_address + node_size(self.system, node);
+
+            match node.expression {
+                ParseExpression::OriginStatement(ref number, _) => {
+                    current_address = number.number;
+                    continue;
+                }
+                ParseExpression::BankStatement(bank) => {
+                    current_address = address_in_bank(current_address, bank);
+                    continue;
+                }
+                ParseExpression::SingleArgumentInstruction(ref opcode_name, ParseArgument::NumberLiteral(ref number)) => {
+                    if self.has_addressing(opcode_name, AddressingMode::Relative) {
+                        // The offset of a relative branch is counted from the next instruction
+                        if number.number == 0 {
+                            self.diagnostics.add_warning(
+                                &format!("'{}' branches to the next instruction and has no effect.", opcode_name),
+                                node.start_token.clone(),
+                            );
+                        }
+                    } else if opcode_name == "jmp" || opcode_name == "jml" {
+                        let is_next_address = match number.argument_size {
+                            // Absolute jumps stay in the current bank
+                            ArgumentSize::Word16 => number.number & 0xFFFF == next_address & 0xFFFF,
+                            _ => number.number == next_address,
+                        };
+
+                        if is_next_address {
+                            self.diagnostics.add_warning(
+                                &format!("'{}' jumps to the next instruction and has no effect.", opcode_name),
+                                node.start_token.clone(),
+                            );
+                        }
+                    }
+                }
+                _ => {}
+            }
+
+            current_address = next_address;
+        }
+
+        return (parse_tree, self.diagnostics.take_messages());
+    }
+}
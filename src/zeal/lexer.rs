@@ -1,22 +1,86 @@
-use std::io::{Read, Result};
+use std::fmt;
+use std::io::{self, Read, Result};
 use std::fs::{File};
-use std::error::Error;
 use std::path::{Path, PathBuf};
+use std::rc::Rc;
 use zeal::system_definition::*;
 
+// How a number literal was written in the source
+#[derive(PartialEq, Copy, Clone)]
+pub enum NumberLiteralKind {
+    Decimal,
+    Hex,
+    Binary,
+}
+
+impl NumberLiteralKind {
+    // Hexadecimal and binary literals are as wide as their digits, so $0010 is 16-bit.
+    // Decimal literals are as wide as their value.
+    pub fn argument_size(&self, number: u32, digit_count: usize) -> ArgumentSize {
+        let bits_per_digit = match self {
+            &NumberLiteralKind::Decimal => return number_to_argument_size(number),
+            &NumberLiteralKind::Hex => 4,
+            &NumberLiteralKind::Binary => 1,
+        };
+
+        let bit_count = digit_count * bits_per_digit;
+
+        if bit_count > 24 {
+            ArgumentSize::Word32
+        } else if bit_count > 16 {
+            ArgumentSize::Word24
+        } else if bit_count > 8 {
+            ArgumentSize::Word16
+        } else {
+            ArgumentSize::Word8
+        }
+    }
+
+    pub fn has_explicit_size(&self) -> bool {
+        *self != NumberLiteralKind::Decimal
+    }
+}
+
 #[derive(PartialEq, Copy, Clone)]
 pub struct NumberLiteral {
     pub number: u32,
     pub argument_size: ArgumentSize,
+    pub kind: NumberLiteralKind,
+}
+
+// Written back in the base it was written in, hexadecimal and binary are padded to their size
+impl fmt::Display for NumberLiteral {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            NumberLiteralKind::Decimal => write!(formatter, "{}", self.number),
+            NumberLiteralKind::Hex => write!(
+                formatter,
+                "${:0width$X}",
+                self.number,
+                width = argument_size_to_byte_size(self.argument_size) as usize * 2
+            ),
+            NumberLiteralKind::Binary => write!(
+                formatter,
+                "%{:0width$b}",
+                self.number,
+                width = argument_size_to_bit_size(self.argument_size) as usize
+            ),
+        }
+    }
 }
 
 #[derive(Clone, PartialEq)]
 pub enum TokenType {
     Invalid(char),
+    // String literal that isn't closed or has a bad escape sequence, with the error to report
+    InvalidString(String),
     Identifier(String),
     Opcode(String),
+    // Opcode followed by a size suffix like lda.w, its operand is written with that size
+    SizedOpcode(String, ArgumentSize),
     NumberLiteral(NumberLiteral),
-    StringLiteral(String),
+    // Bytes of the string, UTF-8 except for the bytes written \x80 to \xFF
+    StringLiteral(Vec<u8>),
     Register(String),
     Comma,
     Immediate,
@@ -25,31 +89,101 @@ pub enum TokenType {
     LeftBracket,
     RightBracket,
     Colon,
+    CurrentAddress,
+    Plus,
+    Minus,
+    ShiftLeft,
+    ShiftRight,
+    BitwiseAnd,
+    BitwiseOr,
     EndOfFile,
     KeywordInclude,
     KeywordIncbin,
     KeywordOrigin,
     KeywordSnesMap,
+    KeywordStruct,
+    KeywordEndStruct,
+    KeywordDsb,
+    KeywordVersion,
+    KeywordEnum,
+    KeywordEndEnum,
+    KeywordNamespace,
+    KeywordEndNamespace,
+    KeywordExport,
+    KeywordFreeSpace,
+    KeywordAutoOrigin,
+    KeywordDb,
+    KeywordDw,
+    KeywordDl,
+    KeywordDbTable,
+    KeywordDwTable,
+    KeywordDs,
+    KeywordWidth(WidthRegister, ArgumentSize),
+    KeywordEndWidth,
+    KeywordDirectPageBase,
+    KeywordDataBank,
+    KeywordVectors,
+    KeywordEndVectors,
+    KeywordBank,
+    Dot,
+    Range,
+}
+
+// Location of an include statement that lead to a file being parsed
+#[derive(Clone)]
+pub struct IncludeLocation {
+    pub source_file: String,
+    pub line: u32,
 }
 
 #[derive(Clone)]
 pub struct Token {
     pub ttype: TokenType,
     pub line: u32,
+    // Columns start at 1 and count characters, not bytes, so 'é' is one column
     pub start_column: u32,
     pub end_column: u32,
-    pub source_file: String,
-    pub context_start: usize
+    // Shared by every token of the file
+    pub source_file: Rc<String>,
+    // Index of the first character of the line in the file, without its byte order mark
+    pub context_start: usize,
+    // Outermost include first, empty for the main input file
+    pub include_chain: Rc<Vec<IncludeLocation>>,
+}
+
+// Comment along with where it starts, kept for tools rewriting the source like the formatter
+pub struct Comment {
+    pub line: u32,
+    pub column: u32,
+    // Starts with the //
+    pub text: String,
 }
 
 pub struct Lexer {
     system: &'static SystemDefinition,
-    pub source_file: String,
+    pub source_file: Rc<String>,
     file_content: Vec<char>,
     current_char: usize,
     line: u32,
     column: u32,
-    line_start: usize
+    line_start: usize,
+    include_chain: Rc<Vec<IncludeLocation>>,
+    // Comments read so far when keep_comments() was called, they are skipped otherwise
+    comments: Option<Vec<Comment>>,
+}
+
+// Lines end with \n, \r\n or a lone \r
+pub fn is_line_terminator(current_char: char) -> bool {
+    current_char == '\n' || current_char == '\r'
+}
+
+// Editors on Windows start UTF-8 files with a byte order mark, it isn't part of the source
+pub fn strip_byte_order_mark(file_content: &str) -> &str {
+    if file_content.starts_with('\u{FEFF}') {
+        &file_content['\u{FEFF}'.len_utf8()..]
+    } else {
+        file_content
+    }
 }
 
 fn is_ascii_numeric(current_char: char) -> bool {
@@ -70,7 +204,27 @@ fn is_ascii_alphanumeric(current_char: char) -> bool {
         || (current_char >= 'a' && current_char <= 'z')
 }
 
-fn absolute_path(path: &Path) -> Result<PathBuf> {
+// Why a source file couldn't be lexed
+pub enum SourceFileError {
+    Io(io::Error),
+    // Byte offset of the first invalid UTF-8 sequence
+    InvalidUtf8(usize),
+}
+
+impl fmt::Display for SourceFileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &SourceFileError::Io(ref error) => write!(f, "{}", error),
+            &SourceFileError::InvalidUtf8(offset) => write!(
+                f,
+                "the file isn't valid UTF-8, the first invalid byte is at offset {} (${:X})",
+                offset, offset
+            ),
+        }
+    }
+}
+
+pub fn absolute_path(path: &Path) -> Result<PathBuf> {
     let path_buf = path.canonicalize()?;
 
     #[cfg(windows)]
@@ -84,53 +238,56 @@ fn absolute_path(path: &Path) -> Result<PathBuf> {
     Ok(path_buf)
 }
 
+// Source files are written relative to the directory of the input in reports
+// so they don't depend on where the project was built
+pub fn relative_source_path(source_file: &str, base_directory: &Path) -> String {
+    match Path::new(source_file).strip_prefix(base_directory) {
+        Ok(relative_path) => relative_path.to_string_lossy().replace('\\', "/"),
+        Err(_) => source_file.to_owned(),
+    }
+}
+
 impl Lexer {
-    // pub fn from_string(
-    //     system: &'static SystemDefinition,
-    //     file_content: &str,
-    // ) -> Self {
-    //     Lexer {
-    //         system: system,
-    //         file_content: file_content.chars().collect(),
-    //         current_char: 0,
-    //         source_file: String::from(""),
-    //         line: 1,
-    //         column: 1,
-    //         line_start: 0,
-    //     }
-    // }
-
-    pub fn from_file(system: &'static SystemDefinition, filename: &str) -> Self {
+    // Lex source code that isn't read from a file, `source_file` is only used to report errors
+    pub fn from_string(system: &'static SystemDefinition, source_file: &str, file_content: &str) -> Self {
+        Lexer {
+            system: system,
+            file_content: strip_byte_order_mark(file_content).chars().collect(),
+            current_char: 0,
+            source_file: Rc::new(source_file.to_owned()),
+            line: 1,
+            column: 1,
+            line_start: 0,
+            include_chain: Rc::new(Vec::new()),
+            comments: None,
+        }
+    }
+
+    pub fn from_file(system: &'static SystemDefinition, filename: &str) -> ::std::result::Result<Self, SourceFileError> {
         let input_path = Path::new(filename);
-        let path_display = input_path.display();
 
         let mut file = match File::open(input_path) {
-            Err(why) => panic!("Couldn't open {}: {}", path_display, why.description()),
+            Err(why) => return Err(SourceFileError::Io(why)),
             Ok(file) => file,
         };
 
-        let mut string_file_content = String::new();
-        match file.read_to_string(&mut string_file_content) {
-            Err(why) => panic!("Couldn't read {}: {}", path_display, why.description()),
+        let mut file_bytes = Vec::new();
+        if let Err(why) = file.read_to_end(&mut file_bytes) {
+            return Err(SourceFileError::Io(why));
+        }
+
+        let string_file_content = match String::from_utf8(file_bytes) {
+            Err(why) => return Err(SourceFileError::InvalidUtf8(why.utf8_error().valid_up_to())),
             Ok(result) => result,
         };
 
-        let absolute_path_buf = match absolute_path(input_path) {
-            Err(_) => None,
-            Ok(result) => {
-                Some(result)
-            }
+        // Paths that can't be made absolute, like on some network shares, are kept as written
+        let source_file = match absolute_path(input_path) {
+            Err(_) => filename.to_owned(),
+            Ok(result) => result.to_string_lossy().into_owned(),
         };
 
-        Lexer {
-            system: system,
-            file_content: string_file_content.chars().collect(),
-            current_char: 0,
-            source_file: absolute_path_buf.unwrap().to_str().unwrap().to_string(),
-            line: 1,
-            column: 1,
-            line_start: 0,
-        }
+        Ok(Lexer::from_string(system, &source_file, &string_file_content))
     }
 
     pub fn get_next_token(&mut self) -> Token {
@@ -143,9 +300,49 @@ impl Lexer {
         }
     }
 
+    pub fn line_count(&self) -> u32 {
+        // The \r of a \r\n isn't counted on its own
+        let newline_count = self.file_content
+            .iter()
+            .enumerate()
+            .filter(|&(index, &current_char)| {
+                current_char == '\n' || (current_char == '\r' && self.file_content.get(index + 1) != Some(&'\n'))
+            })
+            .count() as u32;
+
+        match self.file_content.last() {
+            Some(&last_char) if !is_line_terminator(last_char) => newline_count + 1,
+            _ => newline_count,
+        }
+    }
+
+    pub fn include_chain(&self) -> Rc<Vec<IncludeLocation>> {
+        self.include_chain.clone()
+    }
+
+    pub fn set_include_chain(&mut self, include_chain: Rc<Vec<IncludeLocation>>) {
+        self.include_chain = include_chain;
+    }
+
+    pub fn keep_comments(&mut self) {
+        self.comments = Some(Vec::new());
+    }
+
+    pub fn take_comments(&mut self) -> Vec<Comment> {
+        self.comments.take().unwrap_or_default()
+    }
+
+    // Text of a token as written in the source
+    pub fn token_text(&self, token: &Token) -> String {
+        let start = token.context_start + token.start_column as usize - 1;
+        let end = token.context_start + token.end_column as usize - 1;
+
+        self.file_content[start..end].iter().collect()
+    }
+
     pub fn reset(&mut self) {
         self.line = 1;
-        self.column = 0;
+        self.column = 1;
         self.current_char = 0;
         self.line_start = 0;
     }
@@ -155,6 +352,7 @@ impl Lexer {
         let backup_column = self.column;
         let backup_current_char = self.current_char;
         let backup_line_start = self.line_start;
+        let backup_comment_count = self.comments.as_ref().map(|comments| comments.len());
 
         for _i in 0..(times - 1) {
             self.get_next_token();
@@ -166,6 +364,10 @@ impl Lexer {
         self.column = backup_column;
         self.current_char = backup_current_char;
         self.line_start = backup_line_start;
+        // The comments are read again by the next get_next_token()
+        if let (Some(comment_count), Some(ref mut comments)) = (backup_comment_count, self.comments.as_mut()) {
+            comments.truncate(comment_count);
+        }
 
         return lookahead;
     }
@@ -205,6 +407,44 @@ impl Lexer {
             ':' => {
                 return self.new_simple_token(TokenType::Colon);
             }
+            '*' => {
+                return self.new_simple_token(TokenType::CurrentAddress);
+            }
+            '+' => {
+                return self.new_simple_token(TokenType::Plus);
+            }
+            '-' => {
+                return self.new_simple_token(TokenType::Minus);
+            }
+            '<' if self.peek_lookahead(1) == Some('<') => {
+                return self.new_two_char_token(TokenType::ShiftLeft);
+            }
+            '>' if self.peek_lookahead(1) == Some('>') => {
+                return self.new_two_char_token(TokenType::ShiftRight);
+            }
+            '&' => {
+                return self.new_simple_token(TokenType::BitwiseAnd);
+            }
+            '|' => {
+                return self.new_simple_token(TokenType::BitwiseOr);
+            }
+            '.' => match self.peek_lookahead(1) {
+                // Directives like .version
+                Some(next_char) if next_char.is_ascii_alphabetic() => {
+                    return self.parse_identifier_or_similar();
+                }
+                Some('.') => {
+                    let context_start = self.line_start;
+                    let start_column = self.column;
+                    self.consume();
+                    self.consume();
+                    let end_column = self.column;
+                    return self.new_token(TokenType::Range, start_column, end_column, context_start);
+                }
+                _ => {
+                    return self.new_simple_token(TokenType::Dot);
+                }
+            },
             _ => if is_ascii_numeric(current_char) {
                 return self.parse_number();
             } else {
@@ -215,7 +455,7 @@ impl Lexer {
 
     fn eat_whitespaces(&mut self) {
         while let Some(&current_char) = self.peek() {
-            if current_char == '\n' {
+            if is_line_terminator(current_char) {
                 self.do_end_of_line();
             } else if !current_char.is_whitespace() {
                 break;
@@ -225,33 +465,33 @@ impl Lexer {
         }
     }
 
+    // Comments can follow each other, indented or after blank lines
     fn eat_comment(&mut self) {
-        let mut is_done = false;
-        while !is_done {
-            match self.peek() {
-                Some(&first_char) => if first_char == '/' {
-                    match self.peek_lookahead(1) {
-                        Some(second_char) => if second_char == '/' {
-                            while let Some(&current_char) = self.peek() {
-                                if current_char == '\n' {
-                                    self.do_end_of_line();
-                                    break;
-                                } else {
-                                    self.consume();
-                                }
-                            }
-                        } else {
-                            is_done = true
-                        },
-                        None => is_done = true,
-                    }
+        while self.peek() == Some(&'/') && self.peek_lookahead(1) == Some('/') {
+            let line = self.line;
+            let column = self.column;
+            let mut text = String::new();
+
+            while let Some(&current_char) = self.peek() {
+                if is_line_terminator(current_char) {
+                    self.do_end_of_line();
+                    break;
                 } else {
-                    is_done = true
-                },
-                None => is_done = true,
-            };
+                    text.push(current_char);
+                    self.consume();
+                }
+            }
+
+            if let Some(ref mut comments) = self.comments {
+                comments.push(Comment {
+                    line: line,
+                    column: column,
+                    text: text,
+                });
+            }
+
+            self.eat_whitespaces();
         }
-        self.eat_whitespaces();
     }
 
     fn parse_identifier_or_similar(&mut self) -> Token {
@@ -265,7 +505,11 @@ impl Lexer {
             match self.peek() {
                 None => break,
                 Some(&current_char) => {
-                    if is_ascii_alphanumeric(current_char) || current_char == '_' {
+                    // Dots are used by qualified names like Struct.field and size suffixes like lda.w,
+                    // a name always follows them so start..end stays a range
+                    let is_qualifier_dot = current_char == '.'
+                        && self.peek_lookahead(1).map_or(false, |next_char| next_char.is_ascii_alphabetic() || next_char == '_');
+                    if is_ascii_alphanumeric(current_char) || current_char == '_' || is_qualifier_dot {
                         parsed_identifier.push(self.consume().unwrap())
                     } else {
                         break;
@@ -283,8 +527,9 @@ impl Lexer {
                     line: self.line,
                     start_column: start_column,
                     end_column: end_column,
-                    source_file: self.source_file.to_string(),
+                    source_file: self.source_file.clone(),
                     context_start: context_start,
+                    include_chain: self.include_chain.clone(),
                 };
             }
             None => if self.is_opcode(&parsed_identifier) {
@@ -293,8 +538,19 @@ impl Lexer {
                     line: self.line,
                     start_column: start_column,
                     end_column: end_column,
-                    source_file: self.source_file.to_string(),
+                    source_file: self.source_file.clone(),
+                    context_start: context_start,
+                    include_chain: self.include_chain.clone(),
+                };
+            } else if let Some((opcode_name, size)) = self.split_size_suffix(&parsed_identifier) {
+                return Token {
+                    ttype: TokenType::SizedOpcode(opcode_name, size),
+                    line: self.line,
+                    start_column: start_column,
+                    end_column: end_column,
+                    source_file: self.source_file.clone(),
                     context_start: context_start,
+                    include_chain: self.include_chain.clone(),
                 };
             } else if self.is_register(&parsed_identifier) {
                 return Token {
@@ -302,8 +558,9 @@ impl Lexer {
                     line: self.line,
                     start_column: start_column,
                     end_column: end_column,
-                    source_file: self.source_file.to_string(),
+                    source_file: self.source_file.clone(),
                     context_start: context_start,
+                    include_chain: self.include_chain.clone(),
                 };
             } else {
                 return Token {
@@ -311,57 +568,102 @@ impl Lexer {
                     line: self.line,
                     start_column: start_column,
                     end_column: end_column,
-                    source_file: self.source_file.to_string(),
+                    source_file: self.source_file.clone(),
                     context_start: context_start,
+                    include_chain: self.include_chain.clone(),
                 };
             },
         }
     }
 
+    // Byte of the escape sequence after the '\\', Err with the error message for an unknown one.
+    // The '\\' is never the last character of the line.
+    fn parse_escape_sequence(&mut self) -> ::std::result::Result<u8, String> {
+        match self.consume().unwrap() {
+            '"' => Ok(b'"'),
+            '\\' => Ok(b'\\'),
+            'n' => Ok(b'\n'),
+            't' => Ok(b'\t'),
+            '0' => Ok(b'\0'),
+            'x' => {
+                let mut value = 0;
+                for _ in 0..2 {
+                    match self.peek() {
+                        Some(&digit) if is_ascii_hex_digit(digit) => {
+                            self.consume();
+                            value = value * 16 + digit.to_digit(16).unwrap();
+                        }
+                        _ => return Err("Expected 2 hexadecimal digits after \\x in string literal.".to_owned()),
+                    }
+                }
+
+                // Kept as a single byte, like the values of a custom character set
+                Ok(value as u8)
+            }
+            escape_char => Err(format!(
+                "Unknown escape sequence '\\{}' in string literal, the escapes are \\\" \\\\ \\n \\t \\0 and \\xNN.",
+                escape_char
+            )),
+        }
+    }
+
+    // Strings end on the line they start, so a missing quote doesn't swallow the rest of the file.
+    // An error is reported at the opening quote or at the first bad escape sequence.
     fn parse_string_literal(&mut self) -> Token {
         let context_start = self.line_start;
         let start_column = self.column;
 
-        let mut parsed_string = String::new();
+        let mut parsed_string = Vec::new();
+        // Message along with the columns of the first bad escape sequence
+        let mut escape_error = None;
 
         // Eat first '"'
         self.consume();
 
         loop {
-            match self.peek() {
-                None => break,
-                Some(&current_char) => {
-                    if current_char != '"' {
-                        parsed_string.push(self.consume().unwrap())
-                    } else {
-                        break;
-                    }
+            match self.peek_lookahead(0) {
+                Some('"') => {
+                    self.consume();
+                    break;
                 }
-            }
-        }
-
-        let end_lookahead = self.peek_lookahead(0);
-        match end_lookahead {
-            Some(result) => {
-                if result == '"' {
+                // A '\\' ending the line is left to the unclosed string error
+                Some('\\') if !self.peek_lookahead(1).map_or(true, is_line_terminator) => {
+                    let escape_column = self.column;
                     self.consume();
 
-                    let end_column = self.column;
-
-                    return Token {
-                        ttype: TokenType::StringLiteral(parsed_string),
-                        line: self.line,
-                        start_column: start_column,
-                        end_column: end_column,
-                        source_file: self.source_file.to_string(),
-                        context_start: context_start,
-                    };
-                } else {
-                    self.token_invalid()
+                    match self.parse_escape_sequence() {
+                        Ok(escaped_byte) => parsed_string.push(escaped_byte),
+                        Err(message) => if escape_error.is_none() {
+                            escape_error = Some((message, escape_column, self.column));
+                        },
+                    }
+                }
+                Some(current_char) if !is_line_terminator(current_char) => {
+                    self.consume();
+                    let mut utf8_bytes = [0; 4];
+                    parsed_string.extend_from_slice(current_char.encode_utf8(&mut utf8_bytes).as_bytes());
+                }
+                _ => {
+                    return self.new_token(
+                        TokenType::InvalidString("String literal isn't closed on the line it starts.".to_owned()),
+                        start_column,
+                        start_column + 1,
+                        context_start,
+                    );
                 }
             }
+        }
+
+        match escape_error {
+            Some((message, escape_start_column, escape_end_column)) => self.new_token(
+                TokenType::InvalidString(message),
+                escape_start_column,
+                escape_end_column,
+                context_start,
+            ),
             None => {
-                self.token_invalid()
+                let end_column = self.column;
+                self.new_token(TokenType::StringLiteral(parsed_string), start_column, end_column, context_start)
             }
         }
     }
@@ -372,6 +674,33 @@ impl Lexer {
             "incbin" => Some(TokenType::KeywordIncbin),
             "origin" => Some(TokenType::KeywordOrigin),
             "snesmap" => Some(TokenType::KeywordSnesMap),
+            "struct" => Some(TokenType::KeywordStruct),
+            "endstruct" => Some(TokenType::KeywordEndStruct),
+            "dsb" => Some(TokenType::KeywordDsb),
+            ".version" => Some(TokenType::KeywordVersion),
+            "enum" => Some(TokenType::KeywordEnum),
+            "endenum" => Some(TokenType::KeywordEndEnum),
+            "namespace" => Some(TokenType::KeywordNamespace),
+            "endnamespace" => Some(TokenType::KeywordEndNamespace),
+            "export" => Some(TokenType::KeywordExport),
+            "freespace" => Some(TokenType::KeywordFreeSpace),
+            "autoorg" => Some(TokenType::KeywordAutoOrigin),
+            "db" => Some(TokenType::KeywordDb),
+            "dw" => Some(TokenType::KeywordDw),
+            "dl" => Some(TokenType::KeywordDl),
+            "dbtable" => Some(TokenType::KeywordDbTable),
+            "dwtable" => Some(TokenType::KeywordDwTable),
+            "ds" | "skip" => Some(TokenType::KeywordDs),
+            ".a8" => Some(TokenType::KeywordWidth(WidthRegister::Accumulator, ArgumentSize::Word8)),
+            ".a16" => Some(TokenType::KeywordWidth(WidthRegister::Accumulator, ArgumentSize::Word16)),
+            ".i8" => Some(TokenType::KeywordWidth(WidthRegister::Index, ArgumentSize::Word8)),
+            ".i16" => Some(TokenType::KeywordWidth(WidthRegister::Index, ArgumentSize::Word16)),
+            ".endw" => Some(TokenType::KeywordEndWidth),
+            "dpbase" => Some(TokenType::KeywordDirectPageBase),
+            "databank" => Some(TokenType::KeywordDataBank),
+            ".vectors" => Some(TokenType::KeywordVectors),
+            ".endvectors" => Some(TokenType::KeywordEndVectors),
+            ".bank" => Some(TokenType::KeywordBank),
             _ => None,
         }
     }
@@ -396,28 +725,52 @@ impl Lexer {
             }
         }
 
+        // Bank and address form like $A0:8000, read as the 24-bit address $A08000
+        if !parsed_number.is_empty() && parsed_number.len() <= 2 && self.peek() == Some(&':') {
+            let address_start = match self.peek_lookahead(1) {
+                Some('$') => 2,
+                _ => 1,
+            };
+
+            let mut parsed_address = String::new();
+            while let Some(current_char) = self.peek_lookahead(address_start + parsed_address.len()) {
+                if is_ascii_hex_digit(current_char) {
+                    parsed_address.push(current_char);
+                } else {
+                    break;
+                }
+            }
+
+            if !parsed_address.is_empty() && parsed_address.len() <= 4 {
+                for _ in 0..address_start + parsed_address.len() {
+                    self.consume();
+                }
+
+                parsed_number = format!("{:0>2}{:0>4}", parsed_number, parsed_address);
+            }
+        }
+
         let end_column = self.column;
 
+        // A lone '$' is the current address symbol
+        if parsed_number.is_empty() {
+            return self.new_token(
+                TokenType::CurrentAddress,
+                start_column,
+                end_column,
+                context_start,
+            );
+        }
+
         let result_number = match u32::from_str_radix(&parsed_number, 16) {
             Ok(result) => result,
             Err(_) => 0,
         };
 
-        let parsed_length = parsed_number.len();
-
-        let argument_size = if parsed_length > 6 {
-            ArgumentSize::Word32
-        } else if parsed_length > 4 {
-            ArgumentSize::Word24
-        } else if parsed_length > 2 {
-            ArgumentSize::Word16
-        } else {
-            ArgumentSize::Word8
-        };
-
         let number_literal = NumberLiteral {
             number: result_number,
-            argument_size: argument_size,
+            argument_size: NumberLiteralKind::Hex.argument_size(result_number, parsed_number.len()),
+            kind: NumberLiteralKind::Hex,
         };
 
         self.new_token(
@@ -455,21 +808,10 @@ impl Lexer {
             Err(_) => 0,
         };
 
-        let parsed_length = parsed_number.len();
-
-        let argument_size = if parsed_length > 24 {
-            ArgumentSize::Word32
-        } else if parsed_length > 16 {
-            ArgumentSize::Word24
-        } else if parsed_length > 8 {
-            ArgumentSize::Word16
-        } else {
-            ArgumentSize::Word8
-        };
-
         let number_literal = NumberLiteral {
             number: result_number,
-            argument_size: argument_size,
+            argument_size: NumberLiteralKind::Binary.argument_size(result_number, parsed_number.len()),
+            kind: NumberLiteralKind::Binary,
         };
 
         self.new_token(
@@ -505,11 +847,10 @@ impl Lexer {
             Err(_) => 0,
         };
 
-        let argument_size = number_to_argument_size(result_number);
-
         let number_literal = NumberLiteral {
             number: result_number,
-            argument_size: argument_size,
+            argument_size: NumberLiteralKind::Decimal.argument_size(result_number, parsed_number.len()),
+            kind: NumberLiteralKind::Decimal,
         };
 
         self.new_token(
@@ -530,6 +871,19 @@ impl Lexer {
         return false;
     }
 
+    // Opcode and size of an identifier like lda.w
+    fn split_size_suffix(&self, identifier: &str) -> Option<(String, ArgumentSize)> {
+        let dot_index = identifier.rfind('.')?;
+        let opcode_name = &identifier[..dot_index];
+        let size = size_suffix_to_argument_size(&identifier[dot_index + 1..])?;
+
+        if self.is_opcode(opcode_name) {
+            Some((opcode_name.to_owned(), size))
+        } else {
+            None
+        }
+    }
+
     fn is_register(&self, identifier: &str) -> bool {
         for &register in self.system.registers.iter() {
             if register == identifier {
@@ -540,11 +894,15 @@ impl Lexer {
         return false;
     }
 
+    // \r\n counts as a single line terminator
     fn do_end_of_line(&mut self) {
         self.line += 1;
         self.column = 0;
 
-        self.consume();
+        if self.consume() == Some('\r') && self.peek() == Some(&'\n') {
+            self.column = 0;
+            self.consume();
+        }
         self.line_start = self.current_char;
     }
 
@@ -588,6 +946,15 @@ impl Lexer {
         return self.new_token(ttype, start_column, end_column, context_start);
     }
 
+    fn new_two_char_token(&mut self, ttype: TokenType) -> Token {
+        let context_start = self.line_start;
+        let start_column = self.column;
+        self.consume();
+        self.consume();
+        let end_column = self.column;
+        return self.new_token(ttype, start_column, end_column, context_start);
+    }
+
     fn new_token(
         &mut self,
         ttype: TokenType,
@@ -600,8 +967,9 @@ impl Lexer {
             line: self.line,
             start_column: start_column,
             end_column: end_column,
-            source_file: self.source_file.to_string(),
+            source_file: self.source_file.clone(),
             context_start: context_start,
+            include_chain: self.include_chain.clone(),
         }
     }
 
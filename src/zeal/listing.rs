@@ -0,0 +1,72 @@
+use std::io::{Result, Write};
+use zeal::hex_style::HexStyle;
+use zeal::parser::*;
+
+// Bytes shown on a line, longer data is cut with '..'
+const LISTING_BYTES_PER_LINE: usize = 8;
+
+// Statement of the listing, its bytes are read back from the output once everything is written
+pub struct ListingLine {
+    pub address: Option<u32>,
+    pub file_offset: u32,
+    pub size: u32,
+    pub text: String,
+}
+
+fn format_data_arguments(elements: &[DataElement]) -> String {
+    elements
+        .iter()
+        .map(|element| element.argument.to_string())
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+// Source of the node as written in the listing, None for nodes that aren't listed
+pub fn listing_text(node: &ParseNode) -> Option<String> {
+    match node.expression {
+        ParseExpression::FinalInstruction(ref instruction) => Some(format!("    {}", instruction)),
+        ParseExpression::Label(ref name) => Some(format!("{}:", name)),
+        ParseExpression::OriginStatement(ref number, allow_rewind) => {
+            Some(format!("origin{} {}", if allow_rewind { "!" } else { "" }, number))
+        }
+        ParseExpression::DataBytes(ref arguments) => Some(format!("    db {}", format_data_arguments(arguments))),
+        ParseExpression::DataWords(ref arguments) => Some(format!("    dw {}", format_data_arguments(arguments))),
+        ParseExpression::DataLongs(ref arguments) => Some(format!("    dl {}", format_data_arguments(arguments))),
+        ParseExpression::IncBinStatement(ref path, 0, _, _) => Some(format!("    incbin \"{}\"", path)),
+        ParseExpression::IncBinStatement(ref path, offset, size, _) => {
+            Some(format!("    incbin \"{}\", {}, {}", path, offset, size))
+        }
+        ParseExpression::SkipBytes(size) => Some(format!("    ds {}", size)),
+        ParseExpression::BankStatement(bank) => Some(format!(".bank ${:02X}", bank)),
+        _ => None,
+    }
+}
+
+// One line per statement: SNES address, bytes in hexadecimal then the source, like
+// `008000  A9 01                    lda #$01`
+pub fn write_listing<W: Write>(writer: &mut W, lines: &[ListingLine], line_bytes: &[Vec<u8>], hex_style: &HexStyle) -> Result<()> {
+    writeln!(writer, "; Generated by zealc")?;
+
+    for (line, bytes) in lines.iter().zip(line_bytes.iter()) {
+        let address = match line.address {
+            Some(address) => hex_style.address_digits(address),
+            None => String::new(),
+        };
+
+        let mut hex_bytes = hex_style.bytes(&bytes[..bytes.len().min(LISTING_BYTES_PER_LINE)]);
+        if bytes.len() > LISTING_BYTES_PER_LINE {
+            hex_bytes.push_str(" ..");
+        }
+
+        writeln!(
+            writer,
+            "{:width$}  {:26} {}",
+            address,
+            hex_bytes,
+            line.text,
+            width = hex_style.address_width
+        )?;
+    }
+
+    Ok(())
+}
@@ -1,9 +1,34 @@
+pub mod assembler;
+pub mod ast_json;
+pub mod bank_check_pass;
+pub mod checksum;
 pub mod collect_label_pass;
+pub mod config_file;
+pub mod debug_file;
+pub mod disassembler;
+pub mod expression;
+pub mod freespace_pass;
+pub mod formatter;
+pub mod hex_format;
+pub mod hex_style;
 pub mod instruction_statement_pass;
+pub mod jump_to_next_pass;
 pub mod lexer;
+pub mod listing;
+pub mod node_size;
 pub mod output_writer;
 pub mod parser;
 pub mod pass;
+pub mod patch_format;
+pub mod peephole_pass;
+pub mod register_width_pass;
 pub mod resolve_label_pass;
+pub mod rom_usage;
+pub mod self_check;
+pub mod split_banks;
+pub mod symbol_file;
 pub mod system_definition;
-pub mod symbol_table;
\ No newline at end of file
+pub mod symbol_table;
+pub mod unused_label_pass;
+pub mod version_check_pass;
+pub mod xref;
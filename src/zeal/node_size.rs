@@ -0,0 +1,97 @@
+use zeal::parser::*;
+use zeal::system_definition::*;
+
+pub fn find_instruction_argument_size(
+    system: &SystemDefinition,
+    opcode_name: &str,
+    possible_addressings: &[AddressingMode],
+) -> Option<ArgumentSize> {
+    for instruction in system.instructions.iter() {
+        if instruction.name == opcode_name {
+            for addressing_mode in possible_addressings.iter() {
+                if &instruction.addressing == addressing_mode {
+                    for argument in instruction.arguments {
+                        match argument {
+                            &InstructionArgument::Number(argument_size) => {
+                                return Some(argument_size);
+                            }
+                            &InstructionArgument::Numbers(ref sizes) => if sizes.len() > 0 {
+                                return Some(sizes[0]);
+                            },
+                            _ => {}
+                        };
+                    }
+                }
+            }
+        }
+    }
+
+    return None;
+}
+
+// Size of an argument before labels are resolved, labels and expressions take the size of a label
+// unless the expression gives its own, like low(label)
+pub fn unresolved_argument_size(system: &SystemDefinition, argument: &ParseArgument) -> ArgumentSize {
+    match argument {
+        &ParseArgument::NumberLiteral(ref number) => number.argument_size,
+        &ParseArgument::Expression(ref expression) => expression.size_hint().unwrap_or(system.label_size),
+        _ => system.label_size,
+    }
+}
+
+fn argument_byte_size(system: &SystemDefinition, argument: &ParseArgument) -> u32 {
+    match argument {
+        &ParseArgument::Register(_) => 0,
+        _ => argument_size_to_byte_size(unresolved_argument_size(system, argument)),
+    }
+}
+
+// Number of bytes a node will take in the output, 0 for nodes that don't emit anything
+pub fn node_size(system: &SystemDefinition, node: &ParseNode) -> u32 {
+    match node.expression {
+        ParseExpression::ImpliedInstruction(_) => 1,
+        ParseExpression::SingleArgumentInstruction(ref opcode_name, ref argument) => match argument {
+            // Branches to a label use the relative argument size
+            &ParseArgument::Identifier(_) | &ParseArgument::Expression(_) => {
+                match find_instruction_argument_size(system, opcode_name, &[AddressingMode::Relative]) {
+                    Some(size) => 1 + argument_size_to_byte_size(size),
+                    None => 1 + argument_byte_size(system, argument),
+                }
+            }
+            _ => 1 + argument_byte_size(system, argument),
+        },
+        ParseExpression::ImmediateInstruction(_, ref argument)
+        | ParseExpression::IndirectInstruction(_, ref argument)
+        | ParseExpression::IndirectLongInstruction(_, ref argument) => 1 + argument_byte_size(system, argument),
+        ParseExpression::IndexedInstruction(_, ref argument1, ref argument2)
+        | ParseExpression::IndexedIndirectInstruction(_, ref argument1, ref argument2)
+        | ParseExpression::IndirectIndexedInstruction(_, ref argument1, ref argument2)
+        | ParseExpression::IndirectIndexedLongInstruction(_, ref argument1, ref argument2) => {
+            1 + argument_byte_size(system, argument1) + argument_byte_size(system, argument2)
+        }
+        // Banks are a byte each, even when written as $007E or as a symbol
+        ParseExpression::BlockMoveInstruction(..) => 3,
+        ParseExpression::StackRelativeIndirectIndexedInstruction(
+            _,
+            ref argument1,
+            ref argument2,
+            ref argument3,
+        ) => {
+            1 + argument_byte_size(system, argument1) + argument_byte_size(system, argument2)
+                + argument_byte_size(system, argument3)
+        }
+        ParseExpression::FinalInstruction(FinalInstruction::ImpliedInstruction(_)) => 1,
+        ParseExpression::FinalInstruction(FinalInstruction::SingleArgumentInstruction(_, ref argument)) => {
+            1 + argument_byte_size(system, argument)
+        }
+        ParseExpression::FinalInstruction(FinalInstruction::TwoArgumentInstruction(_, ref argument1, ref argument2)) => {
+            1 + argument_byte_size(system, argument1) + argument_byte_size(system, argument2)
+        }
+        ParseExpression::IncBinStatement(_, _, size, _) => size as u32,
+        ParseExpression::DataBytes(ref elements) => elements.len() as u32,
+        ParseExpression::DataWords(ref elements) => 2 * elements.len() as u32,
+        ParseExpression::DataLongs(ref elements) => 3 * elements.len() as u32,
+        ParseExpression::SkipBytes(size) => size,
+        _ => 0,
+    }
+}
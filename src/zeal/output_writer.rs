@@ -1,19 +1,165 @@
 extern crate byteorder;
 
 use self::byteorder::{BigEndian, LittleEndian, WriteBytesExt};
-use std::error::Error;
-use std::io::{BufReader, Read, Seek, SeekFrom, Write};
+use std::collections::HashMap;
+use std::io;
+use std::io::{BufReader, BufWriter, Cursor, Read, Seek, SeekFrom, Write};
+use std::fs;
 use std::fs::File;
 use std::fs::OpenOptions;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use zeal::checksum::*;
+use zeal::hex_format::*;
+use zeal::hex_style::HexStyle;
 use zeal::lexer::*;
+use zeal::listing::*;
+use zeal::node_size::node_size;
 use zeal::parser::*;
+use zeal::patch_format::*;
+use zeal::split_banks::*;
 use zeal::system_definition::*;
 
 pub struct OutputWriter {
     system: &'static SystemDefinition,
-    output: File,
+    output: OutputTarget,
     map_function: fn(u32) -> u32,
+    format: OutputFormat,
+    bytes_per_record: usize,
+    split_banks_directory: Option<PathBuf>,
+    keep_empty_banks: bool,
+    // Byte written between the emitted ranges and by ds, None in patching mode to keep the existing content
+    gap_fill: Option<u8>,
+    // Bytes written by each node, only kept when verifying against a golden file
+    node_ranges: Option<Vec<NodeRange>>,
+    // Statements of the listing format
+    listing_lines: Option<Vec<ListingLine>>,
+    // ROM the ips and bps patches are made against
+    source_rom: Option<PathBuf>,
+    hex_style: HexStyle,
+    pub error_messages: Vec<ErrorMessage>,
+    pub bytes_written: u64,
+    // Contiguous ranges of emitted bytes, used for the ROM usage report
+    pub emitted_ranges: Vec<EmittedRange>,
+    pub snes_map: Option<SnesMap>,
+    current_address: u32,
+    // Bytes of copier header before the ROM, added to every file offset
+    header_size: u32,
+    is_finalized: bool,
+}
+
+// Where the bytes go, a dry run only keeps track of the position and size of the output
+enum OutputTarget {
+    // Temporary file moved to the final path by finalize()
+    File {
+        file: File,
+        temp_path: PathBuf,
+        final_path: PathBuf,
+    },
+    Discard { position: u64, length: u64 },
+    // Kept in memory for the caller to take with take_bytes()
+    Memory(Cursor<Vec<u8>>),
+}
+
+impl OutputTarget {
+    fn truncate(&mut self) -> io::Result<()> {
+        match self {
+            &mut OutputTarget::File { ref mut file, .. } => {
+                file.set_len(0)?;
+                file.seek(SeekFrom::Start(0))?;
+            }
+            &mut OutputTarget::Discard {
+                ref mut position,
+                ref mut length,
+            } => {
+                *position = 0;
+                *length = 0;
+            }
+            &mut OutputTarget::Memory(ref mut cursor) => {
+                cursor.get_mut().clear();
+                cursor.set_position(0);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn file_name(&self) -> String {
+        match self {
+            &OutputTarget::File { ref final_path, .. } => final_path.file_name().unwrap().to_string_lossy().into_owned(),
+            &OutputTarget::Discard { .. } | &OutputTarget::Memory(_) => String::new(),
+        }
+    }
+}
+
+impl Read for OutputTarget {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        match self {
+            &mut OutputTarget::File { ref mut file, .. } => file.read(buffer),
+            // Nothing was kept, read back zeros up to the end of the output
+            &mut OutputTarget::Discard {
+                ref mut position,
+                length,
+            } => {
+                let size = (length.saturating_sub(*position) as usize).min(buffer.len());
+                for byte in buffer[..size].iter_mut() {
+                    *byte = 0;
+                }
+                *position += size as u64;
+                Ok(size)
+            }
+            &mut OutputTarget::Memory(ref mut cursor) => cursor.read(buffer),
+        }
+    }
+}
+
+impl Write for OutputTarget {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        match self {
+            &mut OutputTarget::File { ref mut file, .. } => file.write(buffer),
+            &mut OutputTarget::Discard {
+                ref mut position,
+                ref mut length,
+            } => {
+                *position += buffer.len() as u64;
+                *length = (*length).max(*position);
+                Ok(buffer.len())
+            }
+            &mut OutputTarget::Memory(ref mut cursor) => cursor.write(buffer),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            &mut OutputTarget::File { ref mut file, .. } => file.flush(),
+            &mut OutputTarget::Discard { .. } | &mut OutputTarget::Memory(_) => Ok(()),
+        }
+    }
+}
+
+impl Seek for OutputTarget {
+    fn seek(&mut self, seek_from: SeekFrom) -> io::Result<u64> {
+        match self {
+            &mut OutputTarget::File { ref mut file, .. } => file.seek(seek_from),
+            &mut OutputTarget::Discard {
+                ref mut position,
+                length,
+            } => {
+                let new_position = match seek_from {
+                    SeekFrom::Start(offset) => offset as i64,
+                    SeekFrom::Current(offset) => *position as i64 + offset,
+                    SeekFrom::End(offset) => length as i64 + offset,
+                };
+
+                if new_position < 0 {
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput, "seek before the start of the output"));
+                }
+
+                *position = new_position as u64;
+                Ok(*position)
+            }
+            &mut OutputTarget::Memory(ref mut cursor) => cursor.seek(seek_from),
+        }
+    }
 }
 
 fn map_default(value: u32) -> u32 {
@@ -28,60 +174,694 @@ fn map_snes_hirom(value: u32) -> u32 {
     value & 0x3FFFFF
 }
 
+// SA-1 with the default Super MMC setup: banks $00-$3F and $80-$BF are LoROM windows on the first and
+// second 2 MiB of the ROM and $C0-$FF map the whole ROM like HiROM. BW-RAM in $40-$4F isn't in the file.
+fn map_snes_sa1rom(value: u32) -> u32 {
+    let bank = (value >> 16) & 0xFF;
+
+    if bank <= 0x3F {
+        map_snes_lorom(value)
+    } else if bank >= 0x80 && bank <= 0xBF {
+        0x200000 | map_snes_lorom(value & 0x3FFFFF)
+    } else {
+        value & 0x3FFFFF
+    }
+}
+
+// The FastROM banks $C0-$FF mirror $40-$7F and $80-$BF mirrors $00-$3F, so both halves of the
+// bus land on the same file offsets
+fn map_snes_hirom_fast(value: u32) -> u32 {
+    value & 0x3FFFFF
+}
+
+// ExHiROM: $C0-$FF and the upper half of $80-$BF are the first 4 MiB of the file, $40-$7D and the
+// upper half of $00-$3F are the next 4 MiB. The header at $00FFC0 is at offset $40FFC0.
+fn map_snes_exhirom(value: u32) -> u32 {
+    let upper_rom = if value & 0x800000 == 0 { 0x400000 } else { 0 };
+
+    upper_rom | (value & 0x3FFFFF)
+}
+
+// Function giving the file offset of an address, without the copier header
+pub fn snes_map_function(snes_map: &Option<SnesMap>) -> fn(u32) -> u32 {
+    match snes_map {
+        &Some(SnesMap::LoRom) => map_snes_lorom,
+        &Some(SnesMap::HiRom) => map_snes_hirom,
+        &Some(SnesMap::HiRomFast) => map_snes_hirom_fast,
+        &Some(SnesMap::Sa1Rom) => map_snes_sa1rom,
+        &Some(SnesMap::ExHiRom) => map_snes_exhirom,
+        &None => map_default,
+    }
+}
+
+pub struct EmittedRange {
+    pub start_address: u32,
+    pub file_offset: u32,
+    pub size: u32,
+}
+
+// Bytes of the output file written by a node, tracked with OutputWriterOptions::track_nodes
+pub struct NodeRange {
+    pub file_offset: u32,
+    pub size: u32,
+    pub token: Token,
+}
+
 pub struct OutputWriterOptions {
-    pub create_new: bool
+    pub create_new: bool,
+    // Replace an existing output file, only new files are written otherwise
+    pub force: bool,
+    pub format: OutputFormat,
+    pub bytes_per_record: usize,
+    // Also write each bank of the image to its own file in this directory
+    pub split_banks_directory: Option<PathBuf>,
+    pub keep_empty_banks: bool,
+    // Byte used for the unwritten parts of a new output file
+    pub gap_fill: u8,
+    // Remember which node wrote each byte so verify() can point at the source of a mismatch
+    pub track_nodes: bool,
+    // The output starts with a copier header, also detected from the size of a patched file
+    pub smc_header: bool,
+    // ROM the ips and bps formats are made against, the output starts as a copy of it
+    pub source_rom: Option<PathBuf>,
+    // Hexadecimal of the listing and of the messages printing addresses
+    pub hex_style: HexStyle,
 }
 
 impl OutputWriterOptions {
     pub fn new() -> Self {
         OutputWriterOptions {
             create_new: true,
+            force: false,
+            format: OutputFormat::Binary,
+            bytes_per_record: 16,
+            split_banks_directory: None,
+            keep_empty_banks: false,
+            gap_fill: 0x00,
+            track_nodes: false,
+            smc_header: false,
+            source_rom: None,
+            hex_style: HexStyle::new(),
         }
     }
+
+    // Make sure the output file can be written: a patched file must already exist and an existing file
+    // is only replaced when forced
+    pub fn check_output_path(&self, file_path: &Path) -> Result<(), String> {
+        if file_path.is_dir() {
+            return Err(format!("Output path '{}' is a directory.", file_path.display()));
+        }
+
+        if !self.create_new {
+            if !file_path.is_file() {
+                return Err(format!(
+                    "Output file '{}' doesn't exist, patching mode needs an existing file to patch.",
+                    file_path.display()
+                ));
+            }
+        } else if file_path.exists() && !self.force {
+            return Err(format!(
+                "Output file '{}' already exists, use --force to overwrite it.",
+                file_path.display()
+            ));
+        } else if !file_path.exists() {
+            // A file name alone is in the current directory
+            let directory = match file_path.parent() {
+                Some(directory) if !directory.as_os_str().is_empty() => directory,
+                _ => Path::new("."),
+            };
+            if !directory.is_dir() {
+                return Err(format!(
+                    "Directory '{}' of the output file doesn't exist.",
+                    directory.display()
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Header that SMC and SWC copiers put before the ROM
+pub const COPIER_HEADER_SIZE: u32 = 0x200;
+
+const FILL_CHUNK_SIZE: usize = 0x10000;
+
+// Write `size` fill bytes a chunk at a time, a large ds doesn't need a buffer as large
+fn write_fill<W: Write>(output: &mut W, fill_byte: u8, size: usize) -> io::Result<()> {
+    let fill_buffer = vec![fill_byte; size.min(FILL_CHUNK_SIZE)];
+
+    let mut remaining = size;
+    while remaining > 0 {
+        let chunk_size = remaining.min(fill_buffer.len());
+        output.write_all(&fill_buffer[..chunk_size])?;
+        remaining -= chunk_size;
+    }
+
+    Ok(())
+}
+
+// ROMs are a multiple of 1 KiB so 512 extra bytes are a copier header
+pub fn has_copier_header(file_size: u64) -> bool {
+    file_size % 1024 == COPIER_HEADER_SIZE as u64
+}
+
+// Copy a ROM without its copier header, returns whether there was one to strip
+pub fn strip_copier_header(input_path: &Path, output_path: &Path) -> io::Result<bool> {
+    let bytes = fs::read(input_path)?;
+    let has_header = has_copier_header(bytes.len() as u64);
+    let rom_start = if has_header { COPIER_HEADER_SIZE as usize } else { 0 };
+
+    fs::write(output_path, &bytes[rom_start..])?;
+    Ok(has_header)
 }
 
 impl OutputWriter {
-    pub fn new(system: &'static SystemDefinition, file_path: &Path, output_options: &OutputWriterOptions) -> Self {
+    // Output goes to a temporary file next to the target until finalize() is called
+    pub fn new(system: &'static SystemDefinition, file_path: &Path, output_options: &OutputWriterOptions) -> io::Result<Self> {
+        // Checked here too, finalize() would otherwise replace any existing file, ROMs included
+        if let Err(message) = output_options.check_output_path(file_path) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+        }
+
+        let mut temp_file_name = match file_path.file_name() {
+            Some(file_name) => file_name.to_os_string(),
+            None => return Err(io::Error::new(io::ErrorKind::InvalidInput, "the output path has no file name")),
+        };
+        temp_file_name.push(".tmp");
+        let temp_path = file_path.with_file_name(temp_file_name);
+
+        // In patching mode, start from the content of the existing file which check_output_path() made sure is there
+        let base_file = if !output_options.create_new {
+            Some(file_path.to_path_buf())
+        } else if output_options.format.is_patch() {
+            output_options.source_rom.clone()
+        } else {
+            None
+        };
+
+        if let Some(ref base_file) = base_file {
+            fs::copy(base_file, &temp_path)?;
+        }
+
         let mut file_options = OpenOptions::new();
+        file_options.read(true);
         file_options.write(true);
-        file_options.create_new(output_options.create_new);
+        file_options.create(true);
+        // Only patching mode and the patch formats keep the content copied above
+        file_options.truncate(base_file.is_none());
 
-        let file = match file_options.open(file_path) {
-            Ok(file) => file,
-            Err(_) => File::create(file_path).unwrap(),
-        };
+        let file = file_options.open(&temp_path)?;
+        let file_size = file.metadata()?.len();
 
+        let mut output_writer = OutputWriter::with_target(
+            system,
+            OutputTarget::File {
+                file: file,
+                temp_path: temp_path,
+                final_path: file_path.to_path_buf(),
+            },
+            output_options,
+        );
+
+        if !output_options.create_new && has_copier_header(file_size) {
+            output_writer.header_size = COPIER_HEADER_SIZE;
+        }
+
+        Ok(output_writer)
+    }
+
+    // Lay out the output without creating any file, for a dry run
+    pub fn discarding(system: &'static SystemDefinition, output_options: &OutputWriterOptions) -> Self {
+        OutputWriter::with_target(system, OutputTarget::Discard { position: 0, length: 0 }, output_options)
+    }
+
+    // Keep the output in memory, take it with take_bytes() after finalize()
+    pub fn in_memory(system: &'static SystemDefinition, output_options: &OutputWriterOptions) -> Self {
+        OutputWriter::with_target(system, OutputTarget::Memory(Cursor::new(Vec::new())), output_options)
+    }
+
+    // Bytes of an in-memory output, empty for the other targets
+    pub fn take_bytes(&mut self) -> Vec<u8> {
+        match self.output {
+            OutputTarget::Memory(ref mut cursor) => std::mem::replace(cursor.get_mut(), Vec::new()),
+            _ => Vec::new(),
+        }
+    }
+
+    fn with_target(system: &'static SystemDefinition, output: OutputTarget, output_options: &OutputWriterOptions) -> Self {
         OutputWriter {
             system: system,
-            output: file,
-            map_function: map_default
+            output: output,
+            map_function: map_default,
+            format: output_options.format,
+            bytes_per_record: output_options.bytes_per_record,
+            split_banks_directory: output_options.split_banks_directory.clone(),
+            keep_empty_banks: output_options.keep_empty_banks,
+            gap_fill: if output_options.create_new && !output_options.format.is_patch() {
+                Some(output_options.gap_fill)
+            } else {
+                None
+            },
+            node_ranges: if output_options.track_nodes {
+                Some(Vec::new())
+            } else {
+                None
+            },
+            listing_lines: if output_options.format == OutputFormat::Listing {
+                Some(Vec::new())
+            } else {
+                None
+            },
+            source_rom: output_options.source_rom.clone(),
+            hex_style: output_options.hex_style,
+            error_messages: Vec::new(),
+            bytes_written: 0,
+            emitted_ranges: Vec::new(),
+            snes_map: None,
+            current_address: 0,
+            header_size: if output_options.smc_header {
+                COPIER_HEADER_SIZE
+            } else {
+                0
+            },
+            is_finalized: false,
         }
     }
 
+    // Move the temporary file to the final output path
+    pub fn finalize(&mut self) -> io::Result<()> {
+        if let Some(fill_byte) = self.gap_fill {
+            self.fill_gaps(fill_byte)?;
+        }
+
+        if let Some(directory) = self.split_banks_directory.clone() {
+            let bank_size = self.bank_size();
+            let mut used_banks = Vec::new();
+            for range in self.emitted_ranges.iter() {
+                for bank in range.file_offset / bank_size..(range.file_offset + range.size - 1) / bank_size + 1 {
+                    if !used_banks.contains(&bank) {
+                        used_banks.push(bank);
+                    }
+                }
+            }
+            used_banks.sort();
+
+            write_split_banks(&mut self.output, bank_size, &used_banks, &directory, self.keep_empty_banks)?;
+        }
+
+        match self.format {
+            OutputFormat::Binary => {}
+            OutputFormat::IntelHex | OutputFormat::SRecord => self.convert_to_text_format()?,
+            OutputFormat::Ips | OutputFormat::Bps => self.convert_to_patch_format()?,
+            OutputFormat::Listing => self.convert_to_listing()?,
+        }
+
+        if let OutputTarget::File {
+            ref file,
+            ref temp_path,
+            ref final_path,
+        } = self.output
+        {
+            file.sync_all()?;
+            fs::rename(temp_path, final_path)?;
+        }
+        self.is_finalized = true;
+
+        Ok(())
+    }
+
     pub fn write(&mut self, parse_tree: &Vec<ParseNode>) {
         for node in parse_tree.iter() {
+            let node_position = self.output.stream_position().unwrap();
+
             match node.expression {
                 ParseExpression::FinalInstruction(ref final_instruction) => {
+                    let start_position = self.output.stream_position().unwrap();
                     self.handle_final_instruction(final_instruction);
+                    let size = self.output.stream_position().unwrap() - start_position;
+                    self.record_emitted(start_position as u32, size as u32, &node.start_token);
+                }
+                ParseExpression::IncBinStatement(ref filename, offset, size, ref checksum) => {
+                    let start_position = self.output.stream_position().unwrap();
+                    self.do_incbin(&filename, offset, size, checksum, &node.start_token);
+                    let size = self.output.stream_position().unwrap() - start_position;
+                    self.record_emitted(start_position as u32, size as u32, &node.start_token);
                 }
-                ParseExpression::IncBinStatement(ref filename, _) => {
-                    self.do_incbin(&filename);
+                ParseExpression::DataBytes(ref elements)
+                | ParseExpression::DataWords(ref elements)
+                | ParseExpression::DataLongs(ref elements) => {
+                    let start_position = self.output.stream_position().unwrap();
+                    for element in elements.iter() {
+                        if let ParseArgument::NumberLiteral(ref number) = element.argument {
+                            self.write_number_literal(number);
+                        }
+                    }
+                    let size = self.output.stream_position().unwrap() - start_position;
+                    self.record_emitted(start_position as u32, size as u32, &node.start_token);
                 }
-                ParseExpression::OriginStatement(ref number) => {
-                    let physical_address = (self.map_function)(number.number);
+                ParseExpression::SkipBytes(size) => match self.gap_fill {
+                    Some(fill_byte) => {
+                        let start_position = self.output.stream_position().unwrap();
+                        write_fill(&mut self.output, fill_byte, size as usize).unwrap();
+                        self.record_emitted(start_position as u32, size, &node.start_token);
+                    }
+                    // Patch mode keeps the bytes of the ROM
+                    None => {
+                        self.output.seek(SeekFrom::Current(size as i64)).unwrap();
+                        self.current_address += size;
+                    }
+                },
+                ParseExpression::OriginStatement(ref number, _) => {
+                    // A file offset given by mistake would be written at the wrong place
+                    if let Some(ref snes_map) = self.snes_map.clone() {
+                        if !snes_map.is_rom_address(number.number) {
+                            self.add_error_message(
+                                &format!(
+                                    "Origin {} isn't mapped to the ROM, origin takes a SNES address like {}.",
+                                    self.hex_style.address(number.number),
+                                    snes_map.example_address()
+                                ),
+                                node.start_token.clone(),
+                            );
+                        }
+                    }
+
+                    self.current_address = number.number;
+                    let physical_address = self.file_offset(number.number);
                     match self.output.seek(SeekFrom::Start(physical_address as u64)) {
                         _=> {}
                     }
                 }
+                // Only the addresses change, the bytes keep going at the same place in the file
+                ParseExpression::BankStatement(bank) => {
+                    self.current_address = address_in_bank(self.current_address, bank);
+                }
+                ParseExpression::InterruptVectors { ref fields } => {
+                    self.write_interrupt_vectors(fields, &node.start_token);
+                }
                 ParseExpression::SnesMapStatement(ref map_mode) => {
-                    match map_mode {
-                        &SnesMap::LoRom => self.map_function = map_snes_lorom,
-                        &SnesMap::HiRom => self.map_function = map_snes_hirom,
-                    };
+                    self.snes_map = Some(map_mode.clone());
+                    self.map_function = snes_map_function(&self.snes_map);
                 }
                 _ => {}
             };
+
+            if let (Some(ref mut listing_lines), Some(text)) = (self.listing_lines.as_mut(), listing_text(node)) {
+                listing_lines.push(ListingLine {
+                    address: node.address,
+                    file_offset: node_position as u32,
+                    size: node_size(self.system, node),
+                    text: text,
+                });
+            }
+        }
+    }
+
+    // Vectors are written in place then the output continues where it was
+    fn write_interrupt_vectors(&mut self, fields: &HashMap<String, DataElement>, node_token: &Token) {
+        let saved_position = self.output.stream_position().unwrap();
+        let saved_address = self.current_address;
+
+        for &(vector_name, vector_address) in INTERRUPT_VECTORS.iter() {
+            if let Some(&DataElement {
+                ref token,
+                argument: ParseArgument::NumberLiteral(ref handler),
+            }) = fields.get(vector_name)
+            {
+                if !self.is_reachable_from_bank_zero(handler.number) {
+                    self.add_error_message(
+                        &format!(
+                            "Handler {} of {} can't be reached from bank $00, vectors only hold a 16-bit address.",
+                            self.hex_style.address(handler.number),
+                            vector_name
+                        ),
+                        token.clone(),
+                    );
+                    continue;
+                }
+
+                let file_offset = self.file_offset(vector_address);
+                self.output.seek(SeekFrom::Start(file_offset as u64)).unwrap();
+
+                // Handlers are run from bank $00, only their 16-bit address is stored
+                self.write_number_literal(&NumberLiteral {
+                    number: handler.number & 0xFFFF,
+                    argument_size: ArgumentSize::Word16,
+                    kind: handler.kind,
+                });
+
+                self.current_address = vector_address;
+                self.record_emitted(file_offset, 2, node_token);
+
+                // Each vector gets its own line since they are written away from the statement
+                let handler_text = format!("    {} {}", vector_name, self.hex_style.address(handler.number));
+                if let Some(ref mut listing_lines) = self.listing_lines {
+                    listing_lines.push(ListingLine {
+                        address: Some(vector_address),
+                        file_offset: file_offset,
+                        size: 2,
+                        text: handler_text,
+                    });
+                }
+            }
+        }
+
+        self.output.seek(SeekFrom::Start(saved_position)).unwrap();
+        self.current_address = saved_address;
+    }
+
+    // Bank $00, or bank $80 where the map makes it a mirror of bank $00, like $80:8000-$FFFF in LoROM and HiROM
+    fn is_reachable_from_bank_zero(&self, address: u32) -> bool {
+        match address >> 16 {
+            0x00 => true,
+            0x80 => self.snes_map.is_some() && (self.map_function)(address) == (self.map_function)(address & 0xFFFF),
+            _ => false,
+        }
+    }
+
+    fn file_offset(&self, address: u32) -> u32 {
+        self.header_size + (self.map_function)(address)
+    }
+
+    fn record_emitted(&mut self, file_offset: u32, size: u32, node_token: &Token) {
+        if size == 0 {
+            return;
+        }
+
+        if let Some(ref mut node_ranges) = self.node_ranges {
+            node_ranges.push(NodeRange {
+                file_offset: file_offset,
+                size: size,
+                token: node_token.clone(),
+            });
+        }
+
+        self.bytes_written += size as u64;
+
+        let current_address = self.current_address;
+        let is_contiguous = match self.emitted_ranges.last() {
+            Some(range) => {
+                range.start_address + range.size == current_address && range.file_offset + range.size == file_offset
+            }
+            None => false,
+        };
+
+        if is_contiguous {
+            self.emitted_ranges.last_mut().unwrap().size += size;
+        } else {
+            self.emitted_ranges.push(EmittedRange {
+                start_address: current_address,
+                file_offset: file_offset,
+                size: size,
+            });
+        }
+
+        self.current_address += size;
+    }
+
+    // Empty unless the nodes are tracked
+    pub fn node_ranges(&self) -> &[NodeRange] {
+        match self.node_ranges {
+            Some(ref node_ranges) => node_ranges,
+            None => &[],
+        }
+    }
+
+    // Compare the emitted bytes with a golden file instead of finalizing the output.
+    // Each node with different bytes is reported as an error, up to max_mismatches of them.
+    pub fn verify(&mut self, golden_path: &Path, max_mismatches: usize) -> io::Result<()> {
+        let mut golden_file = File::open(golden_path)?;
+        let golden_size = golden_file.metadata()?.len();
+        let node_ranges = self.node_ranges.take().unwrap_or(Vec::new());
+        let mut mismatch_count = 0;
+
+        for node_range in node_ranges.iter() {
+            let mut actual = vec![0; node_range.size as usize];
+            self.output.seek(SeekFrom::Start(node_range.file_offset as u64))?;
+            self.output.read_exact(&mut actual)?;
+
+            // Bytes past the end of the golden file are missing and always differ
+            let golden_end = golden_size.min(node_range.file_offset as u64 + node_range.size as u64);
+            let mut expected = vec![0; golden_end.saturating_sub(node_range.file_offset as u64) as usize];
+            golden_file.seek(SeekFrom::Start(node_range.file_offset as u64))?;
+            golden_file.read_exact(&mut expected)?;
+
+            let first_difference = match (0..actual.len()).position(|index| expected.get(index) != Some(&actual[index])) {
+                Some(index) => index,
+                None => continue,
+            };
+
+            mismatch_count += 1;
+            if mismatch_count > max_mismatches {
+                continue;
+            }
+
+            // Only show a few bytes from the first difference for large nodes like incbin
+            let shown_range = first_difference..actual.len().min(first_difference + 8);
+            let expected_text = if shown_range.start < expected.len() {
+                self.hex_style.bytes(&expected[shown_range.start..shown_range.end.min(expected.len())])
+            } else {
+                String::from("nothing (past the end of the file)")
+            };
+
+            let message = format!(
+                "Output differs from '{}' at offset {}: expected {} but assembled {}.",
+                golden_path.display(),
+                self.hex_style.address(node_range.file_offset + first_difference as u32),
+                expected_text,
+                self.hex_style.bytes(&actual[shown_range])
+            );
+            self.add_error_message(&message, node_range.token.clone());
+        }
+
+        if mismatch_count > max_mismatches {
+            let last_token = node_ranges.last().unwrap().token.clone();
+            self.add_error_message(
+                &format!("{} more nodes differ from '{}'.", mismatch_count - max_mismatches, golden_path.display()),
+                last_token,
+            );
+        }
+
+        Ok(())
+    }
+
+    // Size of a bank in the output file for the current snesmap
+    fn bank_size(&self) -> u32 {
+        match self.snes_map {
+            Some(SnesMap::LoRom) | Some(SnesMap::Sa1Rom) => 0x8000,
+            _ => 0x10000,
+        }
+    }
+
+    // Emitted ranges as (start, end) file offsets sorted by offset, overlapping or adjacent ranges are merged
+    fn merged_file_ranges(&self) -> Vec<(u32, u32)> {
+        let mut file_ranges = self.emitted_ranges
+            .iter()
+            .map(|range| (range.file_offset, range.file_offset + range.size))
+            .collect::<Vec<(u32, u32)>>();
+        file_ranges.sort();
+
+        let mut merged_ranges: Vec<(u32, u32)> = Vec::new();
+        for (start, end) in file_ranges {
+            match merged_ranges.last_mut() {
+                Some(last) if start <= last.1 => last.1 = last.1.max(end),
+                _ => merged_ranges.push((start, end)),
+            }
+        }
+
+        merged_ranges
+    }
+
+    // Write the fill byte everywhere nothing was emitted up to the end of the last range,
+    // so the gaps don't depend on how the file system handles seeking past the end of a file
+    fn fill_gaps(&mut self, fill_byte: u8) -> io::Result<()> {
+        let mut gap_start = 0;
+
+        for (start, end) in self.merged_file_ranges() {
+            self.output.seek(SeekFrom::Start(gap_start as u64))?;
+            write_fill(&mut self.output, fill_byte, (start - gap_start) as usize)?;
+
+            gap_start = end;
+        }
+
+        Ok(())
+    }
+
+    // Runs of emitted bytes by file offset
+    fn read_data_runs(&mut self) -> io::Result<Vec<DataRun>> {
+        let mut runs = Vec::new();
+        for (start, end) in self.merged_file_ranges() {
+            let mut bytes = vec![0; (end - start) as usize];
+            self.output.seek(SeekFrom::Start(start as u64))?;
+            self.output.read_exact(&mut bytes)?;
+
+            runs.push(DataRun {
+                offset: start,
+                bytes: bytes,
+            });
+        }
+
+        Ok(runs)
+    }
+
+    // Replace the binary image in the temporary file by its text encoding
+    fn convert_to_text_format(&mut self) -> io::Result<()> {
+        let runs = self.read_data_runs()?;
+
+        let header = self.output.file_name();
+        self.output.truncate()?;
+
+        let mut writer = BufWriter::new(&mut self.output);
+        match self.format {
+            OutputFormat::IntelHex => write_intel_hex(&mut writer, &runs, self.bytes_per_record)?,
+            OutputFormat::SRecord => {
+                write_srecord(&mut writer, &header, &runs, self.bytes_per_record)?
+            }
+            _ => {}
+        }
+        writer.flush()
+    }
+
+    // Replace the patched copy of the source ROM in the temporary file by the patch
+    fn convert_to_patch_format(&mut self) -> io::Result<()> {
+        let source = match self.source_rom {
+            Some(ref source_rom) => fs::read(source_rom)?,
+            None => Vec::new(),
+        };
+
+        let mut target = Vec::new();
+        self.output.seek(SeekFrom::Start(0))?;
+        self.output.read_to_end(&mut target)?;
+
+        self.output.truncate()?;
+
+        let mut writer = BufWriter::new(&mut self.output);
+        match self.format {
+            OutputFormat::Ips => IpsPatchWriter::new(&source).write(&mut writer, &target)?,
+            OutputFormat::Bps => BpsPatchWriter::new(&source).write(&mut writer, &target)?,
+            _ => {}
         }
+        writer.flush()
+    }
+
+    // Replace the binary image in the temporary file by the listing of its statements
+    fn convert_to_listing(&mut self) -> io::Result<()> {
+        let lines = self.listing_lines.take().unwrap_or(Vec::new());
+
+        let mut line_bytes = Vec::new();
+        for line in lines.iter() {
+            let mut bytes = vec![0; line.size as usize];
+            self.output.seek(SeekFrom::Start(line.file_offset as u64))?;
+            self.output.read_exact(&mut bytes)?;
+            line_bytes.push(bytes);
+        }
+
+        self.output.truncate()?;
+
+        let mut writer = BufWriter::new(&mut self.output);
+        write_listing(&mut writer, &lines, &line_bytes, &self.hex_style)?;
+        writer.flush()
     }
 
     fn handle_final_instruction(&mut self, final_instruction: &FinalInstruction) {
@@ -100,6 +880,13 @@ impl OutputWriter {
             &FinalInstruction::TwoArgumentInstruction(instruction, ref argument1, ref argument2) => {
                 self.output.write_u8(instruction.opcode).unwrap();
 
+                // Block moves are written as 'source, destination' but encoded as destination then source bank
+                let (argument1, argument2) = if instruction.addressing == AddressingMode::BlockMove {
+                    (argument2, argument1)
+                } else {
+                    (argument1, argument2)
+                };
+
                 match argument1 {
                     &ParseArgument::NumberLiteral(ref number) => self.write_number_literal(&number),
                     _ => {}
@@ -141,20 +928,219 @@ impl OutputWriter {
         }
     }
 
-    fn do_incbin(&mut self, filename: &str) {
-        let input_path = Path::new(filename);
-        let path_display = input_path.display();
-
-        let file = match File::open(input_path) {
-            Err(why) => panic!("Couldn't open {}: {}", path_display, why.description()),
+    fn do_incbin(&mut self, filename: &str, offset: u64, size: u64, expected_checksum: &Option<Checksum>, incbin_token: &Token) {
+        let mut file = match File::open(Path::new(filename)) {
             Ok(file) => file,
+            Err(why) => {
+                self.add_error_message(&format!("Couldn't open '{}': {}.", filename, why), incbin_token.clone());
+                return;
+            }
+        };
+
+        if let Err(why) = file.seek(SeekFrom::Start(offset)) {
+            self.add_error_message(&format!("Couldn't seek to {} in '{}': {}.", offset, filename, why), incbin_token.clone());
+            return;
+        }
+
+        // Streamed so large files are never fully loaded in memory
+        let mut reader = ChecksumReader::new(BufReader::new(file).take(size), expected_checksum);
+        if let Err(why) = io::copy(&mut reader, &mut self.output) {
+            self.add_error_message(&format!("Couldn't copy '{}' to the output: {}.", filename, why), incbin_token.clone());
+            return;
+        }
+
+        if let (Some(actual), &Some(ref expected)) = (reader.finish(), expected_checksum) {
+            if &actual != expected {
+                self.add_error_message(
+                    &format!(
+                        "'{}' does not match its checksum, expected {} but found {}.",
+                        filename, expected, actual
+                    ),
+                    incbin_token.clone(),
+                );
+            }
+        }
+    }
+
+    fn add_error_message(&mut self, error_message: &str, offending_token: Token) {
+        let new_message = ErrorMessage {
+            message: error_message.to_owned(),
+            token: offending_token,
+            severity: ErrorSeverity::Error,
         };
 
-        let mut buf_reader = BufReader::new(file);
-        let mut file_content: Vec<u8> = Vec::new();
+        self.error_messages.push(new_message);
+    }
+}
+
+impl Drop for OutputWriter {
+    // Don't leave a partial output behind when assembly failed
+    fn drop(&mut self) {
+        if let OutputTarget::File { ref temp_path, .. } = self.output {
+            if !self.is_finalized {
+                let _ = fs::remove_file(temp_path);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+    use snes_cpu::SNES_CPU;
+    use std::process;
+    use zeal::output_writer::*;
+    use zeal::parser::Parser;
+
+    // Empty directory of the system temporary directory, unique to the test and the process
+    fn temp_directory(test_name: &str) -> PathBuf {
+        let directory = env::temp_dir().join(format!("zealc_{}_{}", test_name, process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    #[test]
+    fn check_output_path_refuses_existing_file_without_force() {
+        let directory = temp_directory("existing_without_force");
+        let output_path = directory.join("out.sfc");
+        fs::write(&output_path, b"previous").unwrap();
+
+        let options = OutputWriterOptions::new();
+        assert!(options.check_output_path(&output_path).is_err());
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn check_output_path_accepts_existing_file_with_force() {
+        let directory = temp_directory("existing_with_force");
+        let output_path = directory.join("out.sfc");
+        fs::write(&output_path, b"previous").unwrap();
+
+        let mut options = OutputWriterOptions::new();
+        options.force = true;
+        assert!(options.check_output_path(&output_path).is_ok());
+        assert!(options.check_output_path(&directory.join("new.sfc")).is_ok());
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn check_output_path_refuses_missing_directory() {
+        let directory = temp_directory("missing_directory");
+        let output_path = directory.join("missing").join("out.sfc");
+
+        let mut options = OutputWriterOptions::new();
+        assert!(options.check_output_path(&output_path).is_err());
+        options.force = true;
+        assert!(options.check_output_path(&output_path).is_err());
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    // Write $AA $BB at file offset 2 to `output_path`
+    fn write_two_bytes(output_path: &Path, options: &OutputWriterOptions) {
+        let (parse_tree, error_messages) = Parser::parse_from_str(&SNES_CPU, "origin $0002\n    db $AA, $BB\n");
+        assert!(error_messages.is_empty());
+
+        let mut output_writer = OutputWriter::new(&SNES_CPU, output_path, options).unwrap();
+        output_writer.write(&parse_tree);
+        output_writer.finalize().unwrap();
+    }
+
+    #[test]
+    fn existing_output_is_kept_without_force() {
+        let directory = temp_directory("output_refused");
+        let output_path = directory.join("out.sfc");
+        fs::write(&output_path, b"previous").unwrap();
+
+        let options = OutputWriterOptions::new();
+        assert!(options.check_output_path(&output_path).is_err());
+        assert_eq!(fs::read(&output_path).unwrap(), b"previous");
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn existing_rom_is_kept_without_force() {
+        let directory = temp_directory("rom_refused");
+        let output_path = directory.join("game.sfc");
+        let rom = vec![0xEA; 0x8000];
+        fs::write(&output_path, &rom).unwrap();
+
+        // Refused even when the caller didn't check the path first
+        let options = OutputWriterOptions::new();
+        assert!(options.check_output_path(&output_path).is_err());
+        assert!(OutputWriter::new(&SNES_CPU, &output_path, &options).is_err());
+        assert_eq!(fs::read(&output_path).unwrap(), rom);
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn existing_output_is_replaced_with_force() {
+        let directory = temp_directory("output_forced");
+        let output_path = directory.join("out.sfc");
+        fs::write(&output_path, b"previous").unwrap();
+
+        let mut options = OutputWriterOptions::new();
+        options.force = true;
+        assert!(options.check_output_path(&output_path).is_ok());
+        write_two_bytes(&output_path, &options);
+
+        assert_eq!(fs::read(&output_path).unwrap(), [0x00, 0x00, 0xAA, 0xBB]);
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn patched_output_keeps_the_other_bytes() {
+        let directory = temp_directory("output_patched");
+        let output_path = directory.join("out.sfc");
+        fs::write(&output_path, b"previous").unwrap();
+
+        let mut options = OutputWriterOptions::new();
+        options.create_new = false;
+        assert!(options.check_output_path(&output_path).is_ok());
+        write_two_bytes(&output_path, &options);
+
+        assert_eq!(fs::read(&output_path).unwrap(), b"pr\xAA\xBBious");
+
+        // Patching needs a file to patch
+        assert!(options.check_output_path(&directory.join("missing.sfc")).is_err());
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn large_incbin_is_copied_whole_and_in_part() {
+        let directory = temp_directory("large_incbin");
+        let data_path = directory.join("large.bin");
+        let data: Vec<u8> = (0..3 * 1024 * 1024).map(|index| (index % 251) as u8).collect();
+        fs::write(&data_path, &data).unwrap();
+
+        let source = format!(
+            "origin $0000\n    incbin \"{0}\"\n    incbin \"{0}\", $12345, $100000\n    incbin \"{0}\", $2FFFF0\n",
+            data_path.display()
+        );
+        let (parse_tree, error_messages) = Parser::parse_from_str(&SNES_CPU, &source);
+        assert!(error_messages.is_empty());
+
+        let output_path = directory.join("out.sfc");
+        let options = OutputWriterOptions::new();
+        let mut output_writer = OutputWriter::new(&SNES_CPU, &output_path, &options).unwrap();
+        output_writer.write(&parse_tree);
+        output_writer.finalize().unwrap();
 
-        buf_reader.read_to_end(&mut file_content).unwrap();
+        let output = fs::read(&output_path).unwrap();
+        assert_eq!(output.len(), data.len() + 0x100000 + 0x10);
+        assert!(output[..data.len()] == data[..]);
+        assert!(output[data.len()..data.len() + 0x100000] == data[0x12345..0x112345]);
+        assert!(output[data.len() + 0x100000..] == data[0x2FFFF0..]);
 
-        self.output.write(&file_content).unwrap();
+        fs::remove_dir_all(&directory).unwrap();
     }
 }
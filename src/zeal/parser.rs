@@ -1,13 +1,24 @@
+use std::collections::HashMap;
+use std::fmt;
 use std::fs::{metadata};
 use std::path::{Path, PathBuf};
+use std::mem;
+use std::rc::Rc;
+use zeal::checksum::*;
+use zeal::expression::*;
 use zeal::lexer::*;
+use zeal::node_size::find_instruction_argument_size;
 use zeal::system_definition::*;
 
+// Largest ds, a size that fits in 24 bits
+const MAX_SKIP_SIZE: i64 = 0xFFFFFF;
+
 #[derive(Clone)]
 pub enum ParseArgument {
     NumberLiteral(NumberLiteral),
     Register(String),
-    Identifier(String)
+    Identifier(String),
+    Expression(Expr),
 }
 
 #[derive(Clone)]
@@ -21,6 +32,150 @@ pub enum FinalInstruction {
 pub enum SnesMap {
     LoRom,
     HiRom,
+    // HiROM running from the FastROM banks $80-$FF
+    HiRomFast,
+    // SA-1 cartridge with the default Super MMC banks
+    Sa1Rom,
+    // HiROM of up to 8 MiB, the first 4 MiB of the file are in $C0-$FF and the rest in $40-$7D
+    ExHiRom,
+}
+
+// Origins and labels are SNES addresses, the output writer maps them to file offsets
+impl SnesMap {
+    // Name used by the snesmap statement, like hirom-fast
+    pub fn from_name(name: &str) -> Option<SnesMap> {
+        match name {
+            "lorom" => Some(SnesMap::LoRom),
+            "hirom" => Some(SnesMap::HiRom),
+            "hirom-fast" => Some(SnesMap::HiRomFast),
+            "sa1rom" => Some(SnesMap::Sa1Rom),
+            "exhirom" => Some(SnesMap::ExHiRom),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            &SnesMap::LoRom => "lorom",
+            &SnesMap::HiRom => "hirom",
+            &SnesMap::HiRomFast => "hirom-fast",
+            &SnesMap::Sa1Rom => "sa1rom",
+            &SnesMap::ExHiRom => "exhirom",
+        }
+    }
+
+    // Whether the ROM is visible at this address of the SNES bus
+    pub fn is_rom_address(&self, address: u32) -> bool {
+        let bank = address >> 16;
+        let offset = address & 0xFFFF;
+
+        match self {
+            _ if bank > 0xFF || bank == 0x7E || bank == 0x7F => false,
+            &SnesMap::LoRom => offset >= 0x8000,
+            &SnesMap::HiRom | &SnesMap::ExHiRom => (bank >= 0x40 && bank <= 0x7D) || bank >= 0xC0 || offset >= 0x8000,
+            &SnesMap::HiRomFast => bank >= 0xC0 || (bank >= 0x80 && offset >= 0x8000),
+            // $40-$4F is BW-RAM
+            &SnesMap::Sa1Rom => bank >= 0xC0 || ((bank <= 0x3F || (bank >= 0x80 && bank <= 0xBF)) && offset >= 0x8000),
+        }
+    }
+
+    pub fn example_address(&self) -> &'static str {
+        match self {
+            &SnesMap::LoRom => "$808000",
+            &SnesMap::HiRom | &SnesMap::HiRomFast | &SnesMap::ExHiRom => "$C00000",
+            &SnesMap::Sa1Rom => "$008000",
+        }
+    }
+}
+
+// Interrupt vectors of the 65816 in bank $00, the CPU always resets in emulation mode
+pub static INTERRUPT_VECTORS: &'static [(&'static str, u32)] = &[
+    ("native_cop", 0x00FFE4),
+    ("native_brk", 0x00FFE6),
+    ("native_abort", 0x00FFE8),
+    ("native_nmi", 0x00FFEA),
+    ("native_irq", 0x00FFEE),
+    ("emu_cop", 0x00FFF4),
+    ("emu_abort", 0x00FFF8),
+    ("emu_nmi", 0x00FFFA),
+    ("emu_reset", 0x00FFFC),
+    ("emu_irq", 0x00FFFE),
+];
+
+pub fn interrupt_vector_address(vector_name: &str) -> Option<u32> {
+    INTERRUPT_VECTORS
+        .iter()
+        .find(|&&(name, _)| name == vector_name)
+        .map(|&(_, address)| address)
+}
+
+#[derive(Clone)]
+pub enum FreeSpaceRegion {
+    // Every address of the bank usable by the current snesmap
+    Bank(u32),
+    // Start and end address, inclusive
+    Range(u32, u32),
+}
+
+// Address of an origin using symbols like origin CODE_BANK:$8000 or origin BASE + $100,
+// evaluated by CollectLabelPass with the symbols defined before it
+#[derive(Clone)]
+pub struct OriginAddress {
+    // Bank of the bank:address form
+    pub bank: Option<Expr>,
+    pub address: Expr,
+}
+
+impl fmt::Display for OriginAddress {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match self.bank {
+            Some(ref bank) => write!(formatter, "{}:{}", bank, self.address),
+            None => write!(formatter, "{}", self.address),
+        }
+    }
+}
+
+fn format_signed_hex(value: i64) -> String {
+    if value < 0 {
+        format!("-${:X}", -value)
+    } else {
+        format!("${:X}", value)
+    }
+}
+
+// SNES address of an origin, the bank:address form takes an 8-bit bank and a 16-bit address
+pub fn origin_address_value(bank: Option<i64>, address: i64) -> Result<u32, String> {
+    match bank {
+        Some(bank) if bank < 0 || bank > 0xFF => Err(format!(
+            "Bank {} of the origin isn't between $00 and $FF.",
+            format_signed_hex(bank)
+        )),
+        Some(_) if address < 0 || address > 0xFFFF => Err(format!(
+            "Address {} of the origin isn't between $0000 and $FFFF, the bank goes before the colon.",
+            format_signed_hex(address)
+        )),
+        Some(bank) => Ok(((bank << 16) | address) as u32),
+        None if address < 0 || address > 0xFFFFFF => Err(format!(
+            "origin {} isn't a 24-bit SNES address.",
+            format_signed_hex(address)
+        )),
+        None => Ok(address as u32),
+    }
+}
+
+// Named field reserved with dsb inside a struct or enum block
+#[derive(Clone)]
+pub struct StorageField {
+    pub token: Token,
+    pub name: String,
+    pub size: Expr,
+}
+
+// Element of a db or dw statement, or handler of a vector, with its first token so errors point at it
+#[derive(Clone)]
+pub struct DataElement {
+    pub token: Token,
+    pub argument: ParseArgument,
 }
 
 #[derive(Clone)]
@@ -38,23 +193,346 @@ pub enum ParseExpression {
     StackRelativeIndirectIndexedInstruction(String, ParseArgument, ParseArgument, ParseArgument),
     FinalInstruction(FinalInstruction),
     Label(String),
-    OriginStatement(NumberLiteral),
+    // True for origin! and the origins placed by FreeSpacePass, which can go back without a warning
+    OriginStatement(NumberLiteral, bool),
+    // Origin whose address uses symbols, replaced by an OriginStatement in CollectLabelPass
+    OriginExpression(OriginAddress, bool),
     SnesMapStatement(SnesMap),
-    IncBinStatement(String, u64),
+    // Path, offset in the file and size of the included bytes, which the checksum covers
+    IncBinStatement(String, u64, u64, Option<Checksum>),
+    StructDefinition(String, Vec<StorageField>),
+    VersionRequirement { major: u32, minor: u32 },
+    ExportLabel(String),
+    FreeSpace(FreeSpaceRegion),
+    // Optional number of bytes to reserve for the block
+    AutoOrigin(Option<u32>),
+    NamespaceStart(String),
+    NamespaceEnd,
+    EnumDefinition {
+        start_address: u32,
+        end_address: Option<u32>,
+        fields: Vec<StorageField>,
+    },
+    // One byte per argument, string literals are already expanded to their bytes
+    DataBytes(Vec<DataElement>),
+    // One 16-bit word per argument
+    DataWords(Vec<DataElement>),
+    // One 24-bit long address per argument, like a table of pointers to other banks
+    DataLongs(Vec<DataElement>),
+    // Bytes skipped by ds, left as they are in patch mode and filled otherwise
+    SkipBytes(u32),
+    // Value of the direct page register D assumed by the following code
+    DirectPageBase(u32),
+    // Value of the data bank register B assumed by the following code
+    DataBank(u32),
+    // Handler of each interrupt vector by vector name, like native_nmi
+    InterruptVectors { fields: HashMap<String, DataElement> },
+    // Bank byte of the addresses of the following code, the file offset doesn't change
+    BankStatement(u8),
+}
+
+impl fmt::Display for ParseArgument {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &ParseArgument::NumberLiteral(ref number) => write!(formatter, "{}", number),
+            &ParseArgument::Register(ref name) | &ParseArgument::Identifier(ref name) => write!(formatter, "{}", name),
+            &ParseArgument::Expression(ref expression) => write!(formatter, "{}", expression),
+        }
+    }
+}
+
+impl ParseArgument {
+    // Labels used by the argument
+    pub fn identifiers(&self) -> Vec<&str> {
+        match self {
+            &ParseArgument::Identifier(ref identifier) => vec![identifier],
+            &ParseArgument::Expression(ref expression) => expression.identifiers(),
+            _ => vec![],
+        }
+    }
+}
+
+// Address moved to another bank by .bank, like $008123 to $818123
+pub fn address_in_bank(address: u32, bank: u8) -> u32 {
+    ((bank as u32) << 16) | (address & 0xFFFF)
+}
+
+// Instruction written back with the syntax of its addressing mode, like lda ($10),y
+pub fn format_instruction(opcode_name: &str, addressing_mode: &AddressingMode, operands: &[String]) -> String {
+    let operand = |index: usize| operands.get(index).map(|operand| operand.as_str()).unwrap_or("?");
+
+    match addressing_mode {
+        &AddressingMode::Implied => opcode_name.to_string(),
+        &AddressingMode::Immediate => format!("{} #{}", opcode_name, operand(0)),
+        &AddressingMode::Relative | &AddressingMode::SingleArgument => format!("{} {}", opcode_name, operand(0)),
+        &AddressingMode::Indexed | &AddressingMode::BlockMove => {
+            format!("{} {},{}", opcode_name, operand(0), operand(1))
+        }
+        &AddressingMode::Indirect => format!("{} ({})", opcode_name, operand(0)),
+        &AddressingMode::IndirectLong => format!("{} [{}]", opcode_name, operand(0)),
+        &AddressingMode::IndexedIndirect => format!("{} ({},{})", opcode_name, operand(0), operand(1)),
+        &AddressingMode::IndirectIndexed => format!("{} ({}),{}", opcode_name, operand(0), operand(1)),
+        &AddressingMode::IndirectIndexedLong => format!("{} [{}],{}", opcode_name, operand(0), operand(1)),
+        &AddressingMode::StackRelativeIndirectIndexed => {
+            format!("{} ({},{}),{}", opcode_name, operand(0), operand(1), operand(2))
+        }
+    }
+}
+
+fn format_arguments(arguments: &[&ParseArgument]) -> Vec<String> {
+    arguments.iter().map(|argument| argument.to_string()).collect()
+}
+
+// Registers fixed by the instruction aren't kept as arguments, they come back from the instruction info
+impl fmt::Display for FinalInstruction {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        let (info, arguments) = match self {
+            &FinalInstruction::ImpliedInstruction(info) => (info, vec![]),
+            &FinalInstruction::SingleArgumentInstruction(info, ref argument) => (info, vec![argument]),
+            &FinalInstruction::TwoArgumentInstruction(info, ref argument1, ref argument2) => {
+                (info, vec![argument1, argument2])
+            }
+        };
+
+        let mut arguments = arguments.into_iter();
+        let operands = info.arguments
+            .iter()
+            .map(|argument| match argument {
+                &InstructionArgument::Register(register_name) => register_name.to_string(),
+                _ => arguments.next().map(|argument| argument.to_string()).unwrap_or_default(),
+            })
+            .collect::<Vec<String>>();
+
+        write!(formatter, "{}", format_instruction(info.name, &info.addressing, &operands))
+    }
+}
+
+impl fmt::Display for ParseExpression {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        let children = match self {
+            &ParseExpression::ImpliedInstruction(ref opcode_name) => opcode_name.to_string(),
+            &ParseExpression::ImmediateInstruction(ref opcode_name, ref argument) => {
+                format_instruction(opcode_name, &AddressingMode::Immediate, &format_arguments(&[argument]))
+            }
+            &ParseExpression::SingleArgumentInstruction(ref opcode_name, ref argument) => {
+                format_instruction(opcode_name, &AddressingMode::SingleArgument, &format_arguments(&[argument]))
+            }
+            &ParseExpression::IndexedInstruction(ref opcode_name, ref argument1, ref argument2) => format_instruction(
+                opcode_name,
+                &AddressingMode::Indexed,
+                &format_arguments(&[argument1, argument2]),
+            ),
+            &ParseExpression::IndirectInstruction(ref opcode_name, ref argument) => {
+                format_instruction(opcode_name, &AddressingMode::Indirect, &format_arguments(&[argument]))
+            }
+            &ParseExpression::IndirectLongInstruction(ref opcode_name, ref argument) => {
+                format_instruction(opcode_name, &AddressingMode::IndirectLong, &format_arguments(&[argument]))
+            }
+            &ParseExpression::IndexedIndirectInstruction(ref opcode_name, ref argument1, ref argument2) => {
+                format_instruction(
+                    opcode_name,
+                    &AddressingMode::IndexedIndirect,
+                    &format_arguments(&[argument1, argument2]),
+                )
+            }
+            &ParseExpression::IndirectIndexedInstruction(ref opcode_name, ref argument1, ref argument2) => {
+                format_instruction(
+                    opcode_name,
+                    &AddressingMode::IndirectIndexed,
+                    &format_arguments(&[argument1, argument2]),
+                )
+            }
+            &ParseExpression::IndirectIndexedLongInstruction(ref opcode_name, ref argument1, ref argument2) => {
+                format_instruction(
+                    opcode_name,
+                    &AddressingMode::IndirectIndexedLong,
+                    &format_arguments(&[argument1, argument2]),
+                )
+            }
+            &ParseExpression::BlockMoveInstruction(ref opcode_name, ref argument1, ref argument2) => format_instruction(
+                opcode_name,
+                &AddressingMode::BlockMove,
+                &format_arguments(&[argument1, argument2]),
+            ),
+            &ParseExpression::StackRelativeIndirectIndexedInstruction(
+                ref opcode_name,
+                ref argument1,
+                ref argument2,
+                ref argument3,
+            ) => format_instruction(
+                opcode_name,
+                &AddressingMode::StackRelativeIndirectIndexed,
+                &format_arguments(&[argument1, argument2, argument3]),
+            ),
+            &ParseExpression::FinalInstruction(ref instruction) => instruction.to_string(),
+            &ParseExpression::Label(ref name)
+            | &ParseExpression::ExportLabel(ref name)
+            | &ParseExpression::NamespaceStart(ref name) => name.to_string(),
+            &ParseExpression::OriginStatement(ref number, _) => number.to_string(),
+            &ParseExpression::OriginExpression(ref origin_address, _) => origin_address.to_string(),
+            &ParseExpression::SnesMapStatement(ref map_mode) => map_mode.name().to_string(),
+            &ParseExpression::IncBinStatement(ref path, 0, size, _) => format!("\"{}\", {} bytes", path, size),
+            &ParseExpression::IncBinStatement(ref path, offset, size, _) => {
+                format!("\"{}\", {} bytes from {}", path, size, offset)
+            }
+            &ParseExpression::StructDefinition(ref name, ref fields) => {
+                let fields = fields
+                    .iter()
+                    .map(|field| format!("{} {}", field.name, field.size))
+                    .collect::<Vec<String>>();
+                format!("{}, {}", name, fields.join(", "))
+            }
+            &ParseExpression::VersionRequirement { major, minor } => format!("{}.{}", major, minor),
+            &ParseExpression::FreeSpace(FreeSpaceRegion::Bank(bank)) => format!("bank ${:02X}", bank),
+            &ParseExpression::FreeSpace(FreeSpaceRegion::Range(start_address, end_address)) => {
+                format!("${:06X}-${:06X}", start_address, end_address)
+            }
+            &ParseExpression::AutoOrigin(reserved_size) => match reserved_size {
+                Some(reserved_size) => format!("{} bytes", reserved_size),
+                None => String::new(),
+            },
+            &ParseExpression::NamespaceEnd => String::new(),
+            &ParseExpression::EnumDefinition {
+                start_address,
+                ref fields,
+                ..
+            } => {
+                let fields = fields
+                    .iter()
+                    .map(|field| format!("{} {}", field.name, field.size))
+                    .collect::<Vec<String>>();
+                format!("${:06X}, {}", start_address, fields.join(", "))
+            }
+            &ParseExpression::DataBytes(ref elements)
+            | &ParseExpression::DataWords(ref elements)
+            | &ParseExpression::DataLongs(ref elements) => {
+                format_arguments(&elements.iter().map(|element| &element.argument).collect::<Vec<&ParseArgument>>()).join(", ")
+            }
+            &ParseExpression::DirectPageBase(value) => format!("${:04X}", value),
+            &ParseExpression::DataBank(value) => format!("${:02X}", value),
+            &ParseExpression::BankStatement(bank) => format!("${:02X}", bank),
+            &ParseExpression::SkipBytes(size) => size.to_string(),
+            &ParseExpression::InterruptVectors { ref fields } => {
+                let mut fields = fields
+                    .iter()
+                    .map(|(name, handler)| format!("{} {}", name, handler.argument))
+                    .collect::<Vec<String>>();
+                fields.sort();
+                fields.join(", ")
+            }
+        };
+
+        write!(formatter, "{}({})", self.kind_name(), children)
+    }
+}
+
+// Like file(line,column) FinalInstruction(lda #$01) at $008000
+impl fmt::Display for ParseNode {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            formatter,
+            "{}({},{}) {}",
+            self.start_token.source_file,
+            self.start_token.line,
+            self.start_token.start_column,
+            self.expression
+        )?;
+
+        if let Some(address) = self.address {
+            write!(formatter, " at ${:06X}", address)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl ParseExpression {
+    // Arguments of the instructions and data statements
+    pub fn arguments(&self) -> Vec<&ParseArgument> {
+        match self {
+            &ParseExpression::ImmediateInstruction(_, ref argument)
+            | &ParseExpression::SingleArgumentInstruction(_, ref argument)
+            | &ParseExpression::IndirectInstruction(_, ref argument)
+            | &ParseExpression::IndirectLongInstruction(_, ref argument) => vec![argument],
+            &ParseExpression::IndexedInstruction(_, ref argument1, ref argument2)
+            | &ParseExpression::IndexedIndirectInstruction(_, ref argument1, ref argument2)
+            | &ParseExpression::IndirectIndexedInstruction(_, ref argument1, ref argument2)
+            | &ParseExpression::IndirectIndexedLongInstruction(_, ref argument1, ref argument2)
+            | &ParseExpression::BlockMoveInstruction(_, ref argument1, ref argument2) => vec![argument1, argument2],
+            &ParseExpression::StackRelativeIndirectIndexedInstruction(_, ref argument1, ref argument2, ref argument3) => {
+                vec![argument1, argument2, argument3]
+            }
+            &ParseExpression::FinalInstruction(FinalInstruction::SingleArgumentInstruction(_, ref argument)) => {
+                vec![argument]
+            }
+            &ParseExpression::FinalInstruction(FinalInstruction::TwoArgumentInstruction(_, ref argument1, ref argument2)) => {
+                vec![argument1, argument2]
+            }
+            &ParseExpression::DataBytes(ref elements)
+            | &ParseExpression::DataWords(ref elements)
+            | &ParseExpression::DataLongs(ref elements) => {
+                elements.iter().map(|element| &element.argument).collect()
+            }
+            &ParseExpression::InterruptVectors { ref fields } => fields.values().map(|handler| &handler.argument).collect(),
+            _ => vec![],
+        }
+    }
+
+    // Name of the variant, used when printing the parse tree
+    pub fn kind_name(&self) -> &'static str {
+        match self {
+            &ParseExpression::ImpliedInstruction(..) => "ImpliedInstruction",
+            &ParseExpression::ImmediateInstruction(..) => "ImmediateInstruction",
+            &ParseExpression::SingleArgumentInstruction(..) => "SingleArgumentInstruction",
+            &ParseExpression::IndexedInstruction(..) => "IndexedInstruction",
+            &ParseExpression::IndirectInstruction(..) => "IndirectInstruction",
+            &ParseExpression::IndirectLongInstruction(..) => "IndirectLongInstruction",
+            &ParseExpression::IndexedIndirectInstruction(..) => "IndexedIndirectInstruction",
+            &ParseExpression::IndirectIndexedInstruction(..) => "IndirectIndexedInstruction",
+            &ParseExpression::IndirectIndexedLongInstruction(..) => "IndirectIndexedLongInstruction",
+            &ParseExpression::BlockMoveInstruction(..) => "BlockMoveInstruction",
+            &ParseExpression::StackRelativeIndirectIndexedInstruction(..) => "StackRelativeIndirectIndexedInstruction",
+            &ParseExpression::FinalInstruction(..) => "FinalInstruction",
+            &ParseExpression::Label(..) => "Label",
+            &ParseExpression::OriginStatement(..) => "OriginStatement",
+            &ParseExpression::OriginExpression(..) => "OriginExpression",
+            &ParseExpression::SnesMapStatement(..) => "SnesMapStatement",
+            &ParseExpression::IncBinStatement(..) => "IncBinStatement",
+            &ParseExpression::StructDefinition(..) => "StructDefinition",
+            &ParseExpression::VersionRequirement { .. } => "VersionRequirement",
+            &ParseExpression::ExportLabel(..) => "ExportLabel",
+            &ParseExpression::FreeSpace(..) => "FreeSpace",
+            &ParseExpression::AutoOrigin(..) => "AutoOrigin",
+            &ParseExpression::NamespaceStart(..) => "NamespaceStart",
+            &ParseExpression::NamespaceEnd => "NamespaceEnd",
+            &ParseExpression::EnumDefinition { .. } => "EnumDefinition",
+            &ParseExpression::DataBytes(..) => "DataBytes",
+            &ParseExpression::DataWords(..) => "DataWords",
+            &ParseExpression::DataLongs(..) => "DataLongs",
+            &ParseExpression::SkipBytes(..) => "SkipBytes",
+            &ParseExpression::DirectPageBase(..) => "DirectPageBase",
+            &ParseExpression::DataBank(..) => "DataBank",
+            &ParseExpression::BankStatement(..) => "BankStatement",
+            &ParseExpression::InterruptVectors { .. } => "InterruptVectors",
+        }
+    }
 }
 
 #[derive(Clone)]
 pub struct ParseNode {
     pub start_token: Token,
+    // Address of the statement, known once ResolveLabelPass is done
+    pub address: Option<u32>,
     pub expression: ParseExpression,
 }
 
-#[derive(PartialEq)]
+#[derive(PartialEq, Clone, Copy)]
 pub enum ErrorSeverity {
     Error,
     Warning,
 }
 
+#[derive(Clone)]
 pub struct ErrorMessage {
     pub message: String,
     pub token: Token,
@@ -64,8 +542,91 @@ pub struct ErrorMessage {
 pub struct Parser {
     system: &'static SystemDefinition,
     lexers: Vec<Lexer>,
-    current_lexer: i32,
+    // Index in lexers for each canonical file path, a file is only read once
+    lexer_indices: HashMap<PathBuf, usize>,
+    include_stack: Vec<usize>,
+    // Open .a8, .a16, .i8 and .i16 blocks, the innermost block of each register sets its width
+    width_blocks: Vec<(WidthRegister, ArgumentSize, Token)>,
+    // Opcode of an instruction needing an argument that was the last statement before a file started or ended
+    boundary_instruction: Option<(String, Token)>,
+    // Opcode of the instruction being parsed along with its size suffix, like the .w of lda.w
+    current_opcode: Option<(String, Option<ArgumentSize>)>,
+    // Operands sized by the value of a decimal literal or by the size of labels are errors
+    strict_sizes: bool,
     pub error_messages: Vec<ErrorMessage>,
+    // Number of source lines read, counting every time a file is parsed
+    pub lines_processed: u32,
+}
+
+// Tokens that can only be found at the start of a statement
+fn is_statement_keyword(ttype: &TokenType) -> bool {
+    match ttype {
+        &TokenType::KeywordInclude
+        | &TokenType::KeywordIncbin
+        | &TokenType::KeywordOrigin
+        | &TokenType::KeywordSnesMap
+        | &TokenType::KeywordStruct
+        | &TokenType::KeywordEndStruct
+        | &TokenType::KeywordDsb
+        | &TokenType::KeywordEnum
+        | &TokenType::KeywordEndEnum
+        | &TokenType::KeywordNamespace
+        | &TokenType::KeywordEndNamespace
+        | &TokenType::KeywordExport
+        | &TokenType::KeywordFreeSpace
+        | &TokenType::KeywordAutoOrigin
+        | &TokenType::KeywordDb
+        | &TokenType::KeywordDw
+        | &TokenType::KeywordDl
+        | &TokenType::KeywordDbTable
+        | &TokenType::KeywordDwTable
+        | &TokenType::KeywordDs
+        | &TokenType::KeywordWidth(_, _)
+        | &TokenType::KeywordEndWidth
+        | &TokenType::KeywordDirectPageBase
+        | &TokenType::KeywordDataBank
+        | &TokenType::KeywordVectors
+        | &TokenType::KeywordBank
+        | &TokenType::KeywordEndVectors
+        | &TokenType::KeywordVersion => true,
+        _ => false,
+    }
+}
+
+// Binary operators of each precedence level of expressions
+fn bitwise_or_operator(ttype: &TokenType) -> Option<BinaryOperator> {
+    match ttype {
+        &TokenType::BitwiseOr => Some(BinaryOperator::BitwiseOr),
+        _ => None,
+    }
+}
+
+fn bitwise_and_operator(ttype: &TokenType) -> Option<BinaryOperator> {
+    match ttype {
+        &TokenType::BitwiseAnd => Some(BinaryOperator::BitwiseAnd),
+        _ => None,
+    }
+}
+
+fn shift_operator(ttype: &TokenType) -> Option<BinaryOperator> {
+    match ttype {
+        &TokenType::ShiftLeft => Some(BinaryOperator::ShiftLeft),
+        &TokenType::ShiftRight => Some(BinaryOperator::ShiftRight),
+        _ => None,
+    }
+}
+
+fn additive_operator(ttype: &TokenType) -> Option<BinaryOperator> {
+    match ttype {
+        &TokenType::Plus => Some(BinaryOperator::Add),
+        &TokenType::Minus => Some(BinaryOperator::Subtract),
+        _ => None,
+    }
+}
+
+// Include and incbin paths can use \ as separator like on Windows, / works on every system
+fn portable_path(filename: &str) -> String {
+    filename.replace('\\', "/")
 }
 
 enum ParseResult<T> {
@@ -80,54 +641,168 @@ impl Parser {
         Parser {
             system: system,
             lexers: Vec::new(),
+            lexer_indices: HashMap::new(),
+            include_stack: Vec::new(),
+            width_blocks: Vec::new(),
+            boundary_instruction: None,
+            current_opcode: None,
+            strict_sizes: false,
             error_messages: Vec::new(),
-            current_lexer: -1,
+            lines_processed: 0,
+        }
+    }
+
+    // Parse source code held in memory, includes are relative to the current directory
+    pub fn parse_from_str(system: &'static SystemDefinition, source: &str) -> (Vec<ParseNode>, Vec<ErrorMessage>) {
+        let mut parser = Parser::new(system);
+
+        parser.set_current_input_string("<input>", source);
+
+        let parse_tree = parser.parse_tree();
+
+        (parse_tree, parser.error_messages)
+    }
+
+    // Parse a whole string as an expression, like the value of --define. Errors are returned without a position
+    // since the text doesn't come from a file.
+    pub fn expression_from_str(system: &'static SystemDefinition, text: &str) -> Result<Expr, String> {
+        let mut parser = Parser::new(system);
+
+        parser.set_current_input_string("<expression>", text);
+
+        let expression = parser.parse_expression();
+        if let Some(error_message) = parser.error_messages.first() {
+            return Err(error_message.message.clone());
+        }
+
+        let lookahead = parser.lookahead(1);
+        match expression {
+            ParseResult::Some(expression) => if lookahead.ttype == TokenType::EndOfFile {
+                Ok(expression)
+            } else {
+                Err(format!("Unexpected text at column {} after the expression.", lookahead.start_column))
+            },
+            _ => Err("Expected a number literal or label.".to_owned()),
         }
     }
 
+    // Make the file the current input. Parsing resumes in the including file once it is done.
+    // A file that can't be read is reported as an error at its first line.
     pub fn set_current_input_file(&mut self, filename: &str) {
-        for index in 0..self.lexers.len() {
-            if self.lexers[index].source_file == filename {
-                self.current_lexer = index as i32;
+        if let Err(why) = self.push_input_file(filename, Rc::new(Vec::new())) {
+            self.set_current_input_string(filename, "");
+            let token = self.lookahead(1);
+            self.add_error_message(&format!("Couldn't read '{}': {}.", filename, why), token);
+        }
+    }
+
+    // Source that isn't read from a file, like stdin. Files it includes are relative to the current directory.
+    pub fn set_current_input_string(&mut self, source_name: &str, source: &str) {
+        self.lexers.push(Lexer::from_string(self.system, source_name, source));
+        self.include_stack.push(self.lexers.len() - 1);
+    }
+
+    fn push_input_file(&mut self, filename: &str, include_chain: Rc<Vec<IncludeLocation>>) -> Result<(), SourceFileError> {
+        let lexer_index = match self.find_lexer(filename) {
+            Some(index) => {
                 self.lexers[index].reset();
-                return;
+                index
             }
-        }
+            None => {
+                let lexer = Lexer::from_file(self.system, filename)?;
+                self.lexer_indices.insert(PathBuf::from(lexer.source_file.as_str()), self.lexers.len());
+                self.lexers.push(lexer);
+                self.lexers.len() - 1
+            }
+        };
+
+        self.lexers[lexer_index].set_include_chain(include_chain);
+        self.include_stack.push(lexer_index);
+
+        Ok(())
+    }
 
-        self.lexers.push(Lexer::from_file(self.system, filename));
-        self.current_lexer = (self.lexers.len() - 1) as i32;
+    // Operands have to give their size: hexadecimal or binary literals, a size suffix like lda.w,
+    // low(), high(), bank() or a .a16 block for immediates
+    pub fn set_strict_sizes(&mut self, strict_sizes: bool) {
+        self.strict_sizes = strict_sizes;
     }
 
     pub fn has_errors(&self) -> bool {
         return !self.error_messages.is_empty();
     }
 
+    // Number of different files that were parsed, including the main input file
+    pub fn file_count(&self) -> usize {
+        self.lexers.len()
+    }
+
+    // The end of a file always ends the statement in it, an instruction can't take its argument from
+    // the file including it or from the file it includes.
     pub fn parse_tree(&mut self) -> Vec<ParseNode> {
         let mut parsed_tree = Vec::new();
 
         loop {
+            let statement_token = self.lookahead(1);
+            let lexer_index = self.include_stack.last().cloned();
+
             match self.parse() {
                 ParseResult::Some(node) => parsed_tree.push(node),
-                ParseResult::None => continue,
-                ParseResult::Error => continue,
+                ParseResult::None => {}
+                ParseResult::Error => {}
+                // Like 'lda #' on the last line, the end of file is read again by the next parse()
+                ParseResult::Done if statement_token.ttype != TokenType::EndOfFile => {
+                    self.add_error_message(
+                        "Unexpected end of file in this statement, statements must end in the file they start in.",
+                        statement_token,
+                    );
+                    continue;
+                }
                 ParseResult::Done => {
-                    self.current_lexer -= 1;
-                    if self.current_lexer < 0 {
-                        break
+                    self.lines_processed += self.lexer().unwrap().line_count();
+                    self.include_stack.pop();
+                    if self.include_stack.is_empty() {
+                        self.check_width_blocks_closed();
+                        break;
                     }
                 }
             }
+
+            self.boundary_instruction = if self.include_stack.last().cloned() != lexer_index {
+                match parsed_tree.last() {
+                    Some(&ParseNode {
+                        expression: ParseExpression::ImpliedInstruction(ref opcode_name),
+                        ref start_token,
+                        ..
+                    }) if !find_all_valid_modes(self.system, opcode_name).contains(&AddressingMode::Implied) =>
+                    {
+                        Some((opcode_name.clone(), start_token.clone()))
+                    }
+                    _ => None,
+                }
+            } else {
+                None
+            };
         }
 
         return parsed_tree;
     }
 
-    // root : (cpuInstruction | label | origin_statement | snesmap_statement | incbin_statement | include_statement)*;
+    // root : (cpuInstruction | label | data_statement | origin_statement | autoorg_statement | freespace_statement | snesmap_statement | incbin_statement | include_statement | struct_statement | enum_statement | namespace_start | namespace_end | export_statement | version_statement | width_block_start | width_block_end | dpbase_statement | databank_statement | vectors_statement | bank_statement)*;
     fn parse(&mut self) -> ParseResult<ParseNode> {
+        let boundary_instruction = self.boundary_instruction.take();
         let token = self.get_next_token();
         match token.ttype {
             TokenType::EndOfFile => return ParseResult::Done,
-            TokenType::Opcode(ref opcode_name) => self.parse_cpu_instruction(&token, opcode_name),
+            TokenType::Opcode(ref name) | TokenType::Register(ref name) | TokenType::SizedOpcode(ref name, _)
+                if self.lookahead(1).ttype == TokenType::Colon =>
+            {
+                self.get_next_token(); // Eat colon
+                self.add_reserved_name_error(token.clone(), name, "a label");
+                return ParseResult::Error;
+            }
+            TokenType::Opcode(ref opcode_name) => self.parse_instruction(&token, opcode_name, None),
+            TokenType::SizedOpcode(ref opcode_name, size) => self.parse_instruction(&token, opcode_name, Some(size)),
             TokenType::Identifier(ref label_name) => {
                 self.parse_label(&token, label_name)
             }
@@ -140,13 +815,86 @@ impl Parser {
             TokenType::KeywordOrigin => {
                 self.parse_origin_statement(&token)
             }
+            TokenType::KeywordDb | TokenType::KeywordDw | TokenType::KeywordDl => {
+                self.parse_data_statement(&token)
+            }
+            TokenType::KeywordDbTable | TokenType::KeywordDwTable => {
+                self.parse_table_statement(&token)
+            }
+            TokenType::KeywordDs => {
+                self.parse_skip_statement(&token)
+            }
             TokenType::KeywordSnesMap => {
                 self.parse_snesmap_statement(&token)
             }
+            TokenType::KeywordStruct => {
+                self.parse_struct(&token)
+            }
+            TokenType::KeywordEnum => {
+                self.parse_enum(&token)
+            }
+            TokenType::KeywordVersion => {
+                self.parse_version_statement(&token)
+            }
+            TokenType::KeywordFreeSpace => {
+                self.parse_freespace_statement(&token)
+            }
+            TokenType::KeywordAutoOrigin => {
+                self.parse_autoorg_statement(&token)
+            }
+            TokenType::KeywordExport => {
+                self.parse_export_statement(&token)
+            }
+            TokenType::KeywordNamespace => {
+                self.parse_namespace_start(&token)
+            }
+            TokenType::KeywordEndNamespace => {
+                ParseResult::Some(ParseNode {
+                    start_token: token.clone(),
+                    address: None,
+                    expression: ParseExpression::NamespaceEnd,
+                })
+            }
+            TokenType::KeywordWidth(register, size) => {
+                self.width_blocks.push((register, size, token.clone()));
+                ParseResult::None
+            }
+            TokenType::KeywordEndWidth => {
+                self.parse_width_block_end(&token)
+            }
+            TokenType::KeywordDirectPageBase | TokenType::KeywordDataBank => {
+                self.parse_register_assumption(&token)
+            }
+            TokenType::KeywordBank => {
+                self.parse_bank_statement(&token)
+            }
+            TokenType::KeywordVectors => {
+                self.parse_vectors(&token)
+            }
+            TokenType::InvalidString(ref message) => {
+                self.add_error_message(message, token.clone());
+                return ParseResult::Error;
+            }
             TokenType::Invalid(invalid_token) => {
                 self.add_invalid_token_message(invalid_token, token);
                 return ParseResult::Error;
             }
+            TokenType::NumberLiteral(_) | TokenType::Immediate | TokenType::LeftParen | TokenType::LeftBracket
+                if boundary_instruction.is_some() =>
+            {
+                let (opcode_name, opcode_token) = boundary_instruction.unwrap();
+                self.add_error_message(
+                    &format!(
+                        "This can't be the argument of '{}' at {}({}), an instruction ends with the file it is in.",
+                        opcode_name,
+                        opcode_token.source_file,
+                        opcode_token.line
+                    ),
+                    token.clone(),
+                );
+                self.skip_rest_of_line(token.line);
+                return ParseResult::Error;
+            }
             _ => {
                 self.add_error_message("unexpected token found.", token);
                 return ParseResult::Error;
@@ -154,6 +902,95 @@ impl Parser {
         }
     }
 
+    fn parse_instruction(
+        &mut self,
+        opcode_token: &Token,
+        opcode_name: &str,
+        size_suffix: Option<ArgumentSize>,
+    ) -> ParseResult<ParseNode> {
+        self.current_opcode = Some((opcode_name.to_owned(), size_suffix));
+        let result = self.parse_cpu_instruction(opcode_token, opcode_name);
+        self.current_opcode = None;
+
+        match result {
+            ParseResult::Some(ParseNode { expression: ParseExpression::ImpliedInstruction(_), .. }) if size_suffix.is_some() => {
+                self.add_error_message(
+                    &format!("'{}' has a size suffix but no operand to give the size of.", opcode_name),
+                    opcode_token.clone(),
+                );
+                ParseResult::Error
+            }
+            ParseResult::Some(_) if size_suffix.is_some() && self.is_branch(opcode_name) => {
+                self.add_error_message(
+                    &format!(
+                        "'{}' is a branch, the size of its offset is set by the opcode so it can't have a size suffix.",
+                        opcode_name
+                    ),
+                    opcode_token.clone(),
+                );
+                ParseResult::Error
+            }
+            _ => result,
+        }
+    }
+
+    // Branches like bra and brl, their offset is relative to the next instruction
+    fn is_branch(&self, opcode_name: &str) -> bool {
+        find_instruction_argument_size(self.system, opcode_name, &[AddressingMode::Relative]).is_some()
+    }
+
+    // Size given by the suffix of the opcode being parsed, like lda.w
+    fn opcode_size(&self) -> Option<ArgumentSize> {
+        match self.current_opcode {
+            Some((_, size_suffix)) => size_suffix,
+            None => None,
+        }
+    }
+
+    // In strict mode an operand sized by the value of a decimal literal or by the size of labels is an error.
+    // Branches are sized by the instruction.
+    fn check_explicit_size(&mut self, argument: &ParseArgument, argument_token: &Token) {
+        let opcode_name = match self.current_opcode {
+            Some((ref opcode_name, _)) => opcode_name.clone(),
+            None => return,
+        };
+
+        if self.is_branch(&opcode_name) {
+            return;
+        }
+
+        let message = match argument {
+            &ParseArgument::NumberLiteral(ref number) if !number.kind.has_explicit_size() => format!(
+                "{} is {}-bit because of its value, write it in hexadecimal with as many digits as its size \
+                 or use {}.b, {}.w or {}.l.",
+                number,
+                argument_size_to_bit_size(number.argument_size),
+                opcode_name,
+                opcode_name,
+                opcode_name
+            ),
+            &ParseArgument::Identifier(_) => format!(
+                "'{}' is {}-bit because it is a label, use {}.b, {}.w or {}.l to give its size.",
+                argument,
+                argument_size_to_bit_size(self.system.label_size),
+                opcode_name,
+                opcode_name,
+                opcode_name
+            ),
+            &ParseArgument::Expression(ref expression) if expression.size_hint().is_none() => format!(
+                "'{}' is {}-bit because it uses a label, use {}.b, {}.w or {}.l to give its size.",
+                argument,
+                argument_size_to_bit_size(self.system.label_size),
+                opcode_name,
+                opcode_name,
+                opcode_name
+            ),
+            _ => return,
+        };
+
+        self.add_error_message(&message, argument_token.clone());
+    }
+
     // cpuInstruction : OPCODE #Implied
     //    | OPCODE '#' argument #Immediate
     //    | OPCODE argument #SingleArgument
@@ -196,6 +1033,7 @@ impl Parser {
                                     ParseResult::Some(second_result) => {
                                         return ParseResult::Some(ParseNode {
                                             start_token: opcode_token.clone(),
+                                            address: None,
                                             expression: ParseExpression::IndexedInstruction(
                                                 opcode_name.to_string(),
                                                 result,
@@ -216,12 +1054,24 @@ impl Parser {
                                     ParseResult::Done => return ParseResult::Done,
                                 }
                             }
-                            TokenType::NumberLiteral(_) => {
+                            // Same operands as the first one, like mvn $7E, bank(Data)
+                            TokenType::NumberLiteral(_) | TokenType::Identifier(_) | TokenType::CurrentAddress | TokenType::Minus => {
                                 let second_argument = self.parse_argument();
                                 match second_argument {
+                                    // A negative constant would be written in two's complement, $FF for -1
+                                    ParseResult::Some(_)
+                                        if lookahead.ttype == TokenType::Minus || second_lookahead.ttype == TokenType::Minus =>
+                                    {
+                                        self.add_error_message(
+                                            &format!("Bank operands of '{}' can't be negative, block moves take source and destination banks.", opcode_name),
+                                            opcode_token.clone(),
+                                        );
+                                        return ParseResult::Error;
+                                    }
                                     ParseResult::Some(second_result) => {
                                         return ParseResult::Some(ParseNode {
                                             start_token: opcode_token.clone(),
+                                            address: None,
                                             expression: ParseExpression::BlockMoveInstruction(
                                                 opcode_name.to_string(),
                                                 result,
@@ -255,6 +1105,7 @@ impl Parser {
 
                     return ParseResult::Some(ParseNode {
                         start_token: opcode_token.clone(),
+                        address: None,
                         expression: ParseExpression::SingleArgumentInstruction(
                             opcode_name.to_string(),
                             result,
@@ -264,6 +1115,7 @@ impl Parser {
                 ParseResult::None | ParseResult::Done => {
                     return ParseResult::Some(ParseNode {
                         start_token: opcode_token.clone(),
+                        address: None,
                         expression: ParseExpression::ImpliedInstruction(opcode_name.to_string()),
                     });
                 }
@@ -281,12 +1133,28 @@ impl Parser {
     ) -> ParseResult<ParseNode> {
         self.get_next_token();
 
-        let argument = self.parse_argument();
+        // Like lda #and, the opcode would be taken as the next instruction
+        let lookahead = self.lookahead(1);
+        if let TokenType::Opcode(ref name) = lookahead.ttype {
+            self.get_next_token(); // Eat opcode
+            self.add_reserved_name_error(lookahead.clone(), name, "a label");
+            return ParseResult::Error;
+        }
+
+        // A size suffix wins over the .a16 or .i8 block
+        let width = match (self.opcode_size(), self.system.immediate_width_register(opcode_name)) {
+            (Some(size), _) => Some(size),
+            (None, Some(register)) => self.current_width(register),
+            (None, None) => None,
+        };
+
+        let argument = self.parse_sized_argument(width);
 
         match argument {
             ParseResult::Some(result) => {
                 return ParseResult::Some(ParseNode {
                     start_token: opcode_token.clone(),
+                    address: None,
                     expression: ParseExpression::ImmediateInstruction(
                         opcode_name.to_string(),
                         result,
@@ -336,6 +1204,7 @@ impl Parser {
                             ParseResult::Some(second_result) => {
                                 return ParseResult::Some(ParseNode {
                                     start_token: opcode_token.clone(),
+                                    address: None,
                                     expression: ParseExpression::IndirectIndexedInstruction(
                                         opcode_name.to_string(),
                                         result,
@@ -356,6 +1225,7 @@ impl Parser {
                     } else {
                         return ParseResult::Some(ParseNode {
                             start_token: opcode_token.clone(),
+                            address: None,
                             expression: ParseExpression::IndirectInstruction(
                                 opcode_name.to_string(),
                                 result,
@@ -383,6 +1253,7 @@ impl Parser {
                                         ParseResult::Some(third_result) => {
                                             return ParseResult::Some(ParseNode {
                                                 start_token: opcode_token.clone(),
+                                                address: None,
                                                 expression: ParseExpression::StackRelativeIndirectIndexedInstruction(
                                                     opcode_name.to_string(),
                                                     result,
@@ -404,6 +1275,7 @@ impl Parser {
                                 } else {
                                     return ParseResult::Some(ParseNode {
                                         start_token: opcode_token.clone(),
+                                        address: None,
                                         expression: ParseExpression::IndexedIndirectInstruction(
                                             opcode_name.to_string(),
                                             result,
@@ -477,6 +1349,7 @@ impl Parser {
                             ParseResult::Some(second_result) => {
                                 return ParseResult::Some(ParseNode {
                                     start_token: opcode_token.clone(),
+                                    address: None,
                                     expression: ParseExpression::IndirectIndexedLongInstruction(
                                         opcode_name.to_string(),
                                         result,
@@ -497,6 +1370,7 @@ impl Parser {
                     } else {
                         return ParseResult::Some(ParseNode {
                             start_token: opcode_token.clone(),
+                            address: None,
                             expression: ParseExpression::IndirectLongInstruction(
                                 opcode_name.to_string(),
                                 result,
@@ -525,31 +1399,61 @@ impl Parser {
         };
     }
 
-    // argument : NUMBER_LITERAL
-    //          | REGISTER
-    //          | IDENTIFIER
+    // argument : REGISTER
+    //          | expression
     //          ;
     fn parse_argument(&mut self) -> ParseResult<ParseArgument> {
+        let size_suffix = self.opcode_size();
+        self.parse_sized_argument(size_suffix)
+    }
+
+    // Argument given the width of a register, a literal like $01 is then written with the register width
+    fn parse_sized_argument(&mut self, width: Option<ArgumentSize>) -> ParseResult<ParseArgument> {
         let lookahead = self.lookahead(1);
         match lookahead.ttype {
-            TokenType::NumberLiteral(number_literal) => {
-                self.get_next_token(); // Eat tokenNumberLiteral
-                ParseResult::Some(ParseArgument::NumberLiteral(number_literal))
+            // Label on the next line
+            TokenType::Identifier(_) | TokenType::Register(_) if self.lookahead(2).ttype == TokenType::Colon => {
+                ParseResult::None
             }
             TokenType::Register(register_name) => {
                 self.get_next_token(); // Eat register token
                 ParseResult::Some(ParseArgument::Register(register_name))
             }
-            TokenType::Identifier(identifier) => {
-                let second_lookahead = self.lookahead(2);
-                if second_lookahead.ttype == TokenType::Colon {
-                    return ParseResult::None
-                } else {
-                    self.get_next_token(); // Eat identifier token
-                    ParseResult::Some(ParseArgument::Identifier(identifier))
+            TokenType::NumberLiteral(_)
+            | TokenType::Identifier(_)
+            | TokenType::CurrentAddress
+            | TokenType::Minus
+            | TokenType::LeftParen => {
+                match self.parse_expression() {
+                    ParseResult::Some(expression) => {
+                        // Point at the literal itself, the passes only know the token of the instruction
+                        if let Expr::Number(ref number) = expression {
+                            if let Some(message) =
+                                oversized_operand_message(self.system, &number.to_string(), number.argument_size)
+                            {
+                                self.add_error_message(&message, lookahead.clone());
+                            }
+                        }
+
+                        let expression = match width {
+                            Some(size) => Expr::Sized(size, Box::new(expression)),
+                            None => expression,
+                        };
+
+                        let argument = self.expression_to_argument(expression);
+                        if self.strict_sizes && width.is_none() {
+                            self.check_explicit_size(&argument, &lookahead);
+                        }
+                        ParseResult::Some(argument)
+                    }
+                    ParseResult::None => ParseResult::None,
+                    ParseResult::Error => ParseResult::Error,
+                    ParseResult::Done => ParseResult::Done,
                 }
             }
-            TokenType::Opcode(_) => ParseResult::None,
+            // Start of the next statement
+            TokenType::Opcode(_) | TokenType::SizedOpcode(_, _) => ParseResult::None,
+            ref ttype if is_statement_keyword(ttype) => ParseResult::None,
             TokenType::Invalid(invalid_token) => {
                 self.get_next_token(); // Eat token
                 self.add_invalid_token_message(invalid_token, lookahead);
@@ -567,63 +1471,842 @@ impl Parser {
         }
     }
 
-    // label : IDENTIFIER ':'
-    fn parse_label(&mut self, label_token: &Token, label_name: &str) -> ParseResult<ParseNode> {
-        let lookahead = self.lookahead(1);
+    // expression : bitwise_and ('|' bitwise_and)*
+    // Operators are left associative, from the loosest to the tightest: | & << >> + - *
+    fn parse_expression(&mut self) -> ParseResult<Expr> {
+        self.parse_binary_operators(bitwise_or_operator, Parser::parse_bitwise_and)
+    }
 
-        if lookahead.ttype == TokenType::Colon {
-            self.get_next_token(); // Eat colon
-            return ParseResult::Some(ParseNode {
-                    start_token: label_token.clone(),
-                    expression: ParseExpression::Label(label_name.to_string()),
-                });
-        } else {
-            self.add_error_message(&"Expected a colon after this identifier.", label_token.clone());
-            return ParseResult::Error;
-        }
+    // bitwise_and : shift ('&' shift)*
+    fn parse_bitwise_and(&mut self) -> ParseResult<Expr> {
+        self.parse_binary_operators(bitwise_and_operator, Parser::parse_shift)
     }
 
-    // origin_statement: 'origin' NUMBER_LITERAL
-    fn parse_origin_statement(&mut self, origin_token: &Token) -> ParseResult<ParseNode> {
-        let lookahead = self.lookahead(1);
+    // shift : additive (('<<' | '>>') additive)*
+    fn parse_shift(&mut self) -> ParseResult<Expr> {
+        self.parse_binary_operators(shift_operator, Parser::parse_additive)
+    }
 
-        match lookahead.ttype {
-            TokenType::NumberLiteral(number) => {
-                self.get_next_token(); // Eat literal
-                return ParseResult::Some(ParseNode {
-                    start_token: origin_token.clone(),
-                    expression: ParseExpression::OriginStatement(number),
-                });
-            }
-            TokenType::Invalid(invalid_token) => {
-                self.get_next_token(); // Eat token
-                self.add_invalid_token_message(invalid_token, lookahead);
-                ParseResult::Error
-            }
+    // additive : term (('+' | '-') term)*
+    fn parse_additive(&mut self) -> ParseResult<Expr> {
+        self.parse_binary_operators(additive_operator, Parser::parse_term)
+    }
+
+    // Operands parsed by `parse_operand` separated by the operators given by `operator_of`
+    fn parse_binary_operators(
+        &mut self,
+        operator_of: fn(&TokenType) -> Option<BinaryOperator>,
+        parse_operand: fn(&mut Parser) -> ParseResult<Expr>,
+    ) -> ParseResult<Expr> {
+        let mut expression = match parse_operand(self) {
+            ParseResult::Some(result) => result,
+            ParseResult::None => return ParseResult::None,
+            ParseResult::Error => return ParseResult::Error,
+            ParseResult::Done => return ParseResult::Done,
+        };
+
+        loop {
+            let lookahead = self.lookahead(1);
+            let operator = match operator_of(&lookahead.ttype) {
+                Some(operator) => operator,
+                None => break,
+            };
+
+            self.get_next_token(); // Eat operator
+
+            match parse_operand(self) {
+                ParseResult::Some(right) => {
+                    expression = Expr::Binary(operator, Box::new(expression), Box::new(right));
+                }
+                ParseResult::Error => return ParseResult::Error,
+                ParseResult::None | ParseResult::Done => {
+                    self.add_error_message(
+                        "A number literal or label was expected after this operator.",
+                        lookahead,
+                    );
+                    return ParseResult::Error;
+                }
+            }
+        }
+
+        ParseResult::Some(expression)
+    }
+
+    // term : primary ('*' primary)*
+    fn parse_term(&mut self) -> ParseResult<Expr> {
+        let mut expression = match self.parse_primary() {
+            ParseResult::Some(result) => result,
+            ParseResult::None => return ParseResult::None,
+            ParseResult::Error => return ParseResult::Error,
+            ParseResult::Done => return ParseResult::Done,
+        };
+
+        loop {
+            // After a value, '*' is the multiplication operator instead of the current address
+            let lookahead = self.lookahead(1);
+            if lookahead.ttype != TokenType::CurrentAddress {
+                break;
+            }
+
+            self.get_next_token(); // Eat operator
+
+            match self.parse_primary() {
+                ParseResult::Some(right) => {
+                    expression = Expr::Binary(
+                        BinaryOperator::Multiply,
+                        Box::new(expression),
+                        Box::new(right),
+                    );
+                }
+                ParseResult::Error => return ParseResult::Error,
+                ParseResult::None | ParseResult::Done => {
+                    self.add_error_message(
+                        "A number literal or label was expected after this operator.",
+                        lookahead,
+                    );
+                    return ParseResult::Error;
+                }
+            }
+        }
+
+        ParseResult::Some(expression)
+    }
+
+    // primary : NUMBER_LITERAL
+    //         | IDENTIFIER
+    //         | '*'
+    //         | '-' primary
+    //         | '(' expression ')'
+    //         | call
+    //         ;
+    // An operand starting with a parenthesis is indirect, only immediates and later operands can start with a group
+    fn parse_primary(&mut self) -> ParseResult<Expr> {
+        let lookahead = self.lookahead(1);
+        match lookahead.ttype {
+            TokenType::LeftParen => {
+                self.get_next_token(); // Eat left parenthesis
+
+                let expression = match self.parse_expression() {
+                    ParseResult::Some(expression) => expression,
+                    ParseResult::Error => return ParseResult::Error,
+                    ParseResult::None | ParseResult::Done => {
+                        self.add_error_message("An expression was expected inside these parentheses.", lookahead);
+                        return ParseResult::Error;
+                    }
+                };
+
+                if self.lookahead(1).ttype != TokenType::RightParen {
+                    self.add_error_message("no closing parenthesis found.", lookahead);
+                    return ParseResult::Error;
+                }
+                self.get_next_token(); // Eat right parenthesis
+
+                ParseResult::Some(expression)
+            }
+            TokenType::NumberLiteral(number_literal) => {
+                self.get_next_token(); // Eat number literal
+                ParseResult::Some(Expr::Number(number_literal))
+            }
+            // Like db "unclosed
+            TokenType::InvalidString(ref message) => {
+                self.get_next_token(); // Eat string literal
+                self.add_error_message(message, lookahead.clone());
+                ParseResult::Error
+            }
+            TokenType::Identifier(ref identifier) => {
+                self.get_next_token(); // Eat identifier
+
+                match BuiltinFunction::from_name(identifier) {
+                    Some(function) if self.lookahead(1).ttype == TokenType::LeftParen => {
+                        self.parse_call(function, lookahead.clone())
+                    }
+                    _ => ParseResult::Some(Expr::Identifier(identifier.clone())),
+                }
+            }
+            TokenType::CurrentAddress => {
+                self.get_next_token(); // Eat current address symbol
+                ParseResult::Some(Expr::CurrentAddress)
+            }
+            // An operand is required here so this isn't the next instruction, like db and
+            TokenType::Opcode(ref name) => {
+                self.get_next_token(); // Eat opcode
+                self.add_reserved_name_error(lookahead.clone(), name, "a label");
+                ParseResult::Error
+            }
+            TokenType::Minus => {
+                self.get_next_token(); // Eat minus sign
+
+                match self.parse_primary() {
+                    ParseResult::Some(operand) => ParseResult::Some(Expr::Negate(Box::new(operand))),
+                    ParseResult::Error => ParseResult::Error,
+                    ParseResult::None | ParseResult::Done => {
+                        self.add_error_message(
+                            "A number literal or label was expected after this minus sign.",
+                            lookahead,
+                        );
+                        ParseResult::Error
+                    }
+                }
+            }
+            TokenType::Invalid(invalid_token) => {
+                self.get_next_token(); // Eat token
+                self.add_invalid_token_message(invalid_token, lookahead);
+                ParseResult::Error
+            }
+            TokenType::EndOfFile => ParseResult::Done,
+            _ => ParseResult::None,
+        }
+    }
+
+    // call : ('low' | 'high' | 'bank') '(' expression ')'
+    fn parse_call(&mut self, function: BuiltinFunction, function_token: Token) -> ParseResult<Expr> {
+        self.get_next_token(); // Eat left parenthesis
+
+        let argument = match self.parse_expression() {
+            ParseResult::Some(argument) => argument,
+            ParseResult::Error => return ParseResult::Error,
+            ParseResult::None | ParseResult::Done => {
+                self.add_error_message("An expression was expected inside this function call.", function_token);
+                return ParseResult::Error;
+            }
+        };
+
+        if self.lookahead(1).ttype != TokenType::RightParen {
+            self.add_error_message("no closing parenthesis found.", function_token);
+            return ParseResult::Error;
+        }
+        self.get_next_token(); // Eat right parenthesis
+
+        ParseResult::Some(Expr::Call(function, Box::new(argument)))
+    }
+
+    // Keep simple arguments in their simple form so the passes can size them without evaluating
+    fn expression_to_argument(&self, expression: Expr) -> ParseArgument {
+        match expression {
+            Expr::Number(number) => ParseArgument::NumberLiteral(number),
+            Expr::Identifier(identifier) => ParseArgument::Identifier(identifier),
+            _ => match expression.fold_constant() {
+                Some(number) => ParseArgument::NumberLiteral(number),
+                None => ParseArgument::Expression(expression),
+            },
+        }
+    }
+
+    // Register names and opcodes are never lexed as identifiers, a label named like one could never be referenced
+    fn add_reserved_name_error(&mut self, name_token: Token, name: &str, usage: &str) {
+        let (kind, reserved_names) = match name_token.ttype {
+            TokenType::Opcode(_) | TokenType::SizedOpcode(_, _) => ("an opcode", "mnemonics"),
+            _ => ("a register name", "register names"),
+        };
+
+        self.add_error_message(
+            &format!("'{}' is {} and can't be used as {}, {} are reserved.", name, kind, usage, reserved_names),
+            name_token,
+        );
+    }
+
+    // Dots only separate a namespace or a struct from the names inside it, Pair.x refers to field x of Pair
+    fn add_dotted_name_error(&mut self, name_token: Token, name: &str, usage: &str) {
+        self.add_error_message(
+            &format!("'{}' can't be used as {}, dots only separate a namespace or struct name from the name inside it.", name, usage),
+            name_token,
+        );
+    }
+
+    // label : IDENTIFIER ':'
+    fn parse_label(&mut self, label_token: &Token, label_name: &str) -> ParseResult<ParseNode> {
+        let lookahead = self.lookahead(1);
+
+        if lookahead.ttype == TokenType::Colon && label_name.contains('.') {
+            self.get_next_token(); // Eat colon
+            self.add_dotted_name_error(label_token.clone(), label_name, "a label");
+            return ParseResult::Error;
+        } else if lookahead.ttype == TokenType::Colon {
+            self.get_next_token(); // Eat colon
+            return ParseResult::Some(ParseNode {
+                    start_token: label_token.clone(),
+                    address: None,
+                    expression: ParseExpression::Label(label_name.to_string()),
+                });
+        } else if let Some(opcode_name) = suggest_similar_opcode(label_name, self.system) {
+            self.add_error_message(
+                &format!("Unknown opcode '{}', did you mean '{}'?", label_name, opcode_name),
+                label_token.clone(),
+            );
+
+            // The rest of the line is the operand of the misspelled instruction
+            self.skip_rest_of_line(label_token.line);
+
+            return ParseResult::Error;
+        } else {
+            self.add_error_message(&"Expected a colon after this identifier.", label_token.clone());
+            return ParseResult::Error;
+        }
+    }
+
+    // data_statement : ('db' | 'dw' | 'dl') data_element (',' data_element)*
+    // data_element : STRING_LITERAL
+    //              | expression
+    //              ;
+    // String literals are only allowed in db, they are expanded to their bytes
+    fn parse_data_statement(&mut self, data_token: &Token) -> ParseResult<ParseNode> {
+        let is_bytes = data_token.ttype == TokenType::KeywordDb;
+        let mut elements = Vec::new();
+
+        loop {
+            let lookahead = self.lookahead(1);
+
+            match lookahead.ttype {
+                TokenType::StringLiteral(ref text) => {
+                    self.get_next_token(); // Eat string literal
+
+                    if !is_bytes {
+                        self.add_error_message(&"String literals can only be used in db.", lookahead.clone());
+                        return ParseResult::Error;
+                    }
+
+                    for &byte in text.iter() {
+                        elements.push(DataElement {
+                            token: lookahead.clone(),
+                            argument: ParseArgument::NumberLiteral(NumberLiteral {
+                                number: byte as u32,
+                                argument_size: ArgumentSize::Word8,
+                                kind: NumberLiteralKind::Hex,
+                            }),
+                        });
+                    }
+                }
+                _ => match self.parse_expression() {
+                    // Expressions are kept as is so negative values are checked against the size of the data
+                    ParseResult::Some(expression) => elements.push(DataElement {
+                        token: lookahead.clone(),
+                        argument: match expression {
+                            Expr::Number(number) => ParseArgument::NumberLiteral(number),
+                            Expr::Identifier(identifier) => ParseArgument::Identifier(identifier),
+                            expression => ParseArgument::Expression(expression),
+                        },
+                    }),
+                    ParseResult::Error => return ParseResult::Error,
+                    ParseResult::None | ParseResult::Done => {
+                        self.add_error_message(&"Expected a number literal, label or string literal in data statement.", lookahead);
+                        return ParseResult::Error;
+                    }
+                },
+            }
+
+            if self.lookahead(1).ttype != TokenType::Comma {
+                break;
+            }
+
+            self.get_next_token(); // Eat comma
+        }
+
+        return ParseResult::Some(ParseNode {
+            start_token: data_token.clone(),
+            address: None,
+            expression: match data_token.ttype {
+                TokenType::KeywordDb => ParseExpression::DataBytes(elements),
+                TokenType::KeywordDw => ParseExpression::DataWords(elements),
+                _ => ParseExpression::DataLongs(elements),
+            },
+        });
+    }
+
+    // table_statement : ('dbtable' | 'dwtable') expression (',' expression)+
+    // Each entry is written minus the base, like a dw entry - base for every entry
+    fn parse_table_statement(&mut self, table_token: &Token) -> ParseResult<ParseNode> {
+        let base = match self.parse_expression() {
+            ParseResult::Some(base) => base,
+            ParseResult::Error => return ParseResult::Error,
+            ParseResult::None | ParseResult::Done => {
+                self.add_error_message(&"Expected the base address of the table.", table_token.clone());
+                return ParseResult::Error;
+            }
+        };
+
+        let mut elements = Vec::new();
+
+        while self.lookahead(1).ttype == TokenType::Comma {
+            self.get_next_token(); // Eat comma
+
+            let lookahead = self.lookahead(1);
+            match self.parse_expression() {
+                ParseResult::Some(entry) => elements.push(DataElement {
+                    token: lookahead.clone(),
+                    argument: ParseArgument::Expression(Expr::Binary(
+                        BinaryOperator::Subtract,
+                        Box::new(entry),
+                        Box::new(base.clone()),
+                    )),
+                }),
+                ParseResult::Error => return ParseResult::Error,
+                ParseResult::None | ParseResult::Done => {
+                    self.add_error_message(&"Expected a number literal or label as table entry.", lookahead);
+                    return ParseResult::Error;
+                }
+            }
+        }
+
+        if elements.is_empty() {
+            self.add_error_message(&"A table needs at least one entry after its base.", table_token.clone());
+            return ParseResult::Error;
+        }
+
+        return ParseResult::Some(ParseNode {
+            start_token: table_token.clone(),
+            address: None,
+            expression: if table_token.ttype == TokenType::KeywordDbTable {
+                ParseExpression::DataBytes(elements)
+            } else {
+                ParseExpression::DataWords(elements)
+            },
+        });
+    }
+
+    // skip_statement : ('ds' | 'skip') expression
+    // The size is needed before labels are collected so it can only use number literals
+    fn parse_skip_statement(&mut self, skip_token: &Token) -> ParseResult<ParseNode> {
+        let lookahead = self.lookahead(1);
+
+        let size = match self.parse_expression() {
+            ParseResult::Some(expression) => expression.constant_value(),
+            ParseResult::Error => return ParseResult::Error,
+            ParseResult::None | ParseResult::Done => {
+                self.add_error_message(&"Expected the number of bytes to skip after ds.", skip_token.clone());
+                return ParseResult::Error;
+            }
+        };
+
+        match size {
+            Some(size) if size >= 0 && size <= MAX_SKIP_SIZE => ParseResult::Some(ParseNode {
+                start_token: skip_token.clone(),
+                address: None,
+                expression: ParseExpression::SkipBytes(size as u32),
+            }),
+            Some(size) => {
+                self.add_error_message(&format!("ds can't skip {} bytes, it skips 0 to ${:X} bytes.", size, MAX_SKIP_SIZE), lookahead);
+                ParseResult::Error
+            }
+            None => {
+                self.add_error_message(&"The size of ds can only use number literals.", lookahead);
+                ParseResult::Error
+            }
+        }
+    }
+
+    // origin_statement: 'origin' '!'? (origin_address | 'auto')
+    fn parse_origin_statement(&mut self, origin_token: &Token) -> ParseResult<ParseNode> {
+        // origin! can go back before the current address without a warning
+        let allow_rewind = self.lookahead(1).ttype == TokenType::Invalid('!');
+        if allow_rewind {
+            self.get_next_token(); // Eat !
+        }
+
+        let lookahead = self.lookahead(1);
+
+        match lookahead.ttype {
+            TokenType::Identifier(ref identifier) if identifier == "auto" && allow_rewind => {
+                self.add_error_message(&"origin! can't be used with auto.", origin_token.clone());
+                ParseResult::Error
+            }
+            TokenType::Identifier(ref identifier) if identifier == "auto" => {
+                self.get_next_token(); // Eat auto
+                return ParseResult::Some(ParseNode {
+                    start_token: origin_token.clone(),
+                    address: None,
+                    expression: ParseExpression::AutoOrigin(None),
+                });
+            }
+            // A name on the next line is a label, not the address
+            TokenType::NumberLiteral(_) | TokenType::Identifier(_) | TokenType::CurrentAddress | TokenType::Minus
+                if lookahead.line == origin_token.line =>
+            {
+                self.parse_origin_address(origin_token, allow_rewind)
+            }
+            TokenType::Invalid(invalid_token) => {
+                self.get_next_token(); // Eat token
+                self.add_invalid_token_message(invalid_token, lookahead);
+                ParseResult::Error
+            }
+            TokenType::EndOfFile => ParseResult::Done,
+            _ => {
+                self.add_error_message(&"Expected an address or auto after origin keyword.", origin_token.clone());
+                ParseResult::Error
+            }
+        }
+    }
+
+    // origin_address : expression (':' expression)?
+    fn parse_origin_address(&mut self, origin_token: &Token, allow_rewind: bool) -> ParseResult<ParseNode> {
+        let first_expression = match self.parse_expression() {
+            ParseResult::Some(expression) => expression,
+            ParseResult::Error => return ParseResult::Error,
+            ParseResult::None | ParseResult::Done => {
+                self.add_error_message(&"Expected an address or auto after origin keyword.", origin_token.clone());
+                return ParseResult::Error;
+            }
+        };
+
+        // Bank and address like CODE_BANK:$8000, $81:8000 is already a single literal
+        let (bank, address) = if self.lookahead(1).ttype == TokenType::Colon {
+            let colon_token = self.get_next_token(); // Eat colon
+
+            match self.parse_expression() {
+                ParseResult::Some(address) => (Some(first_expression), address),
+                ParseResult::Error => return ParseResult::Error,
+                ParseResult::None | ParseResult::Done => {
+                    self.add_error_message(&"An address was expected after the bank of the origin.", colon_token);
+                    return ParseResult::Error;
+                }
+            }
+        } else {
+            (None, first_expression)
+        };
+
+        let bank_value = match bank {
+            Some(ref bank) => bank.constant_value().map(Some),
+            None => Some(None),
+        };
+
+        // Origins made of number literals are computed now so every pass sees their address
+        let expression = match (bank_value, address.constant_value()) {
+            (Some(bank_value), Some(address_value)) => match origin_address_value(bank_value, address_value) {
+                Ok(value) => {
+                    let number = match address {
+                        Expr::Number(number) if bank.is_none() => number,
+                        _ => NumberLiteral {
+                            number: value,
+                            argument_size: ArgumentSize::Word24,
+                            kind: NumberLiteralKind::Hex,
+                        },
+                    };
+                    ParseExpression::OriginStatement(number, allow_rewind)
+                }
+                Err(message) => {
+                    self.add_error_message(&message, origin_token.clone());
+                    return ParseResult::Error;
+                }
+            },
+            _ => ParseExpression::OriginExpression(
+                OriginAddress {
+                    bank: bank,
+                    address: address,
+                },
+                allow_rewind,
+            ),
+        };
+
+        ParseResult::Some(ParseNode {
+            start_token: origin_token.clone(),
+            address: None,
+            expression: expression,
+        })
+    }
+
+    // dpbase_statement : 'dpbase' NUMBER_LITERAL
+    // databank_statement : 'databank' NUMBER_LITERAL
+    fn parse_register_assumption(&mut self, keyword_token: &Token) -> ParseResult<ParseNode> {
+        let (keyword, max_value) = match keyword_token.ttype {
+            TokenType::KeywordDirectPageBase => ("dpbase", 0xFFFF),
+            _ => ("databank", 0xFF),
+        };
+
+        let lookahead = self.lookahead(1);
+
+        match lookahead.ttype {
+            TokenType::NumberLiteral(number) => {
+                self.get_next_token(); // Eat literal
+
+                if number.number > max_value {
+                    self.add_error_message(
+                        &format!("{} takes a value between $00 and ${:X}.", keyword, max_value),
+                        lookahead.clone(),
+                    );
+                    return ParseResult::Error;
+                }
+
+                ParseResult::Some(ParseNode {
+                    start_token: keyword_token.clone(),
+                    address: None,
+                    expression: match keyword_token.ttype {
+                        TokenType::KeywordDirectPageBase => ParseExpression::DirectPageBase(number.number),
+                        _ => ParseExpression::DataBank(number.number),
+                    },
+                })
+            }
+            TokenType::Invalid(invalid_token) => {
+                self.get_next_token(); // Eat token
+                self.add_invalid_token_message(invalid_token, lookahead);
+                ParseResult::Error
+            }
+            TokenType::EndOfFile => ParseResult::Done,
+            _ => {
+                self.add_error_message(&format!("Expected a number literal after {}.", keyword), keyword_token.clone());
+                ParseResult::Error
+            }
+        }
+    }
+
+    // bank_statement : '.bank' NUMBER_LITERAL
+    fn parse_bank_statement(&mut self, bank_token: &Token) -> ParseResult<ParseNode> {
+        let lookahead = self.lookahead(1);
+
+        match lookahead.ttype {
+            TokenType::NumberLiteral(number) => {
+                self.get_next_token(); // Eat literal
+
+                if number.number > 0xFF {
+                    self.add_error_message(&".bank takes a bank between $00 and $FF.", lookahead.clone());
+                    return ParseResult::Error;
+                }
+
+                ParseResult::Some(ParseNode {
+                    start_token: bank_token.clone(),
+                    address: None,
+                    expression: ParseExpression::BankStatement(number.number as u8),
+                })
+            }
+            TokenType::Invalid(invalid_token) => {
+                self.get_next_token(); // Eat token
+                self.add_invalid_token_message(invalid_token, lookahead);
+                ParseResult::Error
+            }
+            TokenType::EndOfFile => ParseResult::Done,
+            _ => {
+                self.add_error_message(&"Expected a number literal after .bank.", bank_token.clone());
+                ParseResult::Error
+            }
+        }
+    }
+
+    // autoorg_statement : 'autoorg' NUMBER_LITERAL?
+    fn parse_autoorg_statement(&mut self, autoorg_token: &Token) -> ParseResult<ParseNode> {
+        let mut reserved_size = None;
+
+        if let TokenType::NumberLiteral(number) = self.lookahead(1).ttype {
+            self.get_next_token(); // Eat literal
+            reserved_size = Some(number.number);
+        }
+
+        return ParseResult::Some(ParseNode {
+            start_token: autoorg_token.clone(),
+            address: None,
+            expression: ParseExpression::AutoOrigin(reserved_size),
+        });
+    }
+
+    // freespace_statement : 'freespace' NUMBER_LITERAL ('-' NUMBER_LITERAL)?
+    // A single number is a bank, otherwise it is an inclusive address range like $A0:8000-$A0:FFFF
+    fn parse_freespace_statement(&mut self, freespace_token: &Token) -> ParseResult<ParseNode> {
+        let start_address = match self.parse_freespace_address(freespace_token) {
+            ParseResult::Some(address) => address,
+            ParseResult::None => return ParseResult::None,
+            ParseResult::Error => return ParseResult::Error,
+            ParseResult::Done => return ParseResult::Done,
+        };
+
+        if self.lookahead(1).ttype != TokenType::Minus {
+            if start_address > 0xFF {
+                self.add_error_message(&"Expected '-' followed by the end address of the freespace region.", freespace_token.clone());
+                return ParseResult::Error;
+            }
+
+            return ParseResult::Some(ParseNode {
+                start_token: freespace_token.clone(),
+                address: None,
+                expression: ParseExpression::FreeSpace(FreeSpaceRegion::Bank(start_address)),
+            });
+        }
+
+        self.get_next_token(); // Eat minus
+
+        let end_address = match self.parse_freespace_address(freespace_token) {
+            ParseResult::Some(address) => address,
+            ParseResult::None => return ParseResult::None,
+            ParseResult::Error => return ParseResult::Error,
+            ParseResult::Done => return ParseResult::Done,
+        };
+
+        if end_address < start_address {
+            self.add_error_message(&"The end address of the freespace region is before its start address.", freespace_token.clone());
+            return ParseResult::Error;
+        }
+
+        return ParseResult::Some(ParseNode {
+            start_token: freespace_token.clone(),
+            address: None,
+            expression: ParseExpression::FreeSpace(FreeSpaceRegion::Range(start_address, end_address)),
+        });
+    }
+
+    fn parse_freespace_address(&mut self, freespace_token: &Token) -> ParseResult<u32> {
+        let lookahead = self.lookahead(1);
+
+        match lookahead.ttype {
+            TokenType::NumberLiteral(number) => {
+                self.get_next_token(); // Eat literal
+                ParseResult::Some(number.number)
+            }
+            TokenType::Invalid(invalid_token) => {
+                self.get_next_token(); // Eat token
+                self.add_invalid_token_message(invalid_token, lookahead);
+                ParseResult::Error
+            }
             TokenType::EndOfFile => ParseResult::Done,
             _ => {
-                self.add_error_message(&"Expected a number literal after origin keyword.", origin_token.clone());
+                self.add_error_message(&"Expected a bank or an address range like $A0:8000-$A0:FFFF after freespace keyword.", freespace_token.clone());
                 ParseResult::Error
             }
         }
     }
 
-    // snesmap_statement: 'snesmap' ('lorom'|'hirom')
-    fn parse_snesmap_statement(&mut self, origin_token: &Token) -> ParseResult<ParseNode> {
+    // export_statement : 'export' IDENTIFIER
+    fn parse_export_statement(&mut self, export_token: &Token) -> ParseResult<ParseNode> {
+        let lookahead = self.lookahead(1);
+
+        match lookahead.ttype {
+            TokenType::Identifier(ref identifier) => {
+                self.get_next_token(); // Eat identifier
+                return ParseResult::Some(ParseNode {
+                    start_token: lookahead.clone(),
+                    address: None,
+                    expression: ParseExpression::ExportLabel(identifier.to_owned()),
+                });
+            }
+            TokenType::Invalid(invalid_token) => {
+                self.get_next_token(); // Eat token
+                self.add_invalid_token_message(invalid_token, lookahead);
+                ParseResult::Error
+            }
+            TokenType::EndOfFile => ParseResult::Done,
+            _ => {
+                self.add_error_message(&"Expected a label name after export keyword.", export_token.clone());
+                ParseResult::Error
+            }
+        }
+    }
+
+    // namespace_start : 'namespace' IDENTIFIER
+    // namespace_end : 'endnamespace'
+    fn parse_namespace_start(&mut self, namespace_token: &Token) -> ParseResult<ParseNode> {
         let lookahead = self.lookahead(1);
 
         match lookahead.ttype {
+            TokenType::Identifier(ref identifier) if !identifier.contains('.') => {
+                self.get_next_token(); // Eat identifier
+                return ParseResult::Some(ParseNode {
+                    start_token: namespace_token.clone(),
+                    address: None,
+                    expression: ParseExpression::NamespaceStart(identifier.to_owned()),
+                });
+            }
+            TokenType::Invalid(invalid_token) => {
+                self.get_next_token(); // Eat token
+                self.add_invalid_token_message(invalid_token, lookahead);
+                ParseResult::Error
+            }
+            TokenType::EndOfFile => ParseResult::Done,
+            _ => {
+                self.add_error_message(&"Expected a name without dots after namespace keyword.", namespace_token.clone());
+                ParseResult::Error
+            }
+        }
+    }
+
+    // width_block_start : ('.a8' | '.a16' | '.i8' | '.i16')
+    // width_block_end : '.endw'
+    fn parse_width_block_end(&mut self, end_token: &Token) -> ParseResult<ParseNode> {
+        match self.width_blocks.pop() {
+            Some(_) => ParseResult::None,
+            None => {
+                self.add_error_message(&".endw without a matching .a8, .a16, .i8 or .i16.", end_token.clone());
+                ParseResult::Error
+            }
+        }
+    }
+
+    fn current_width(&self, register: WidthRegister) -> Option<ArgumentSize> {
+        self.width_blocks
+            .iter()
+            .rev()
+            .find(|&&(block_register, _, _)| block_register == register)
+            .map(|&(_, size, _)| size)
+    }
+
+    fn check_width_blocks_closed(&mut self) {
+        let width_blocks = mem::replace(&mut self.width_blocks, Vec::new());
+
+        for (_, _, start_token) in width_blocks.into_iter() {
+            self.add_error_message(&"Width block isn't closed by .endw.", start_token);
+        }
+    }
+
+    // version_statement : '.version' NUMBER_LITERAL ('.' NUMBER_LITERAL)?
+    fn parse_version_statement(&mut self, version_token: &Token) -> ParseResult<ParseNode> {
+        let lookahead = self.lookahead(1);
+
+        let major = match lookahead.ttype {
+            TokenType::NumberLiteral(number) => {
+                self.get_next_token(); // Eat literal
+                number.number
+            }
+            TokenType::Invalid(invalid_token) => {
+                self.get_next_token(); // Eat token
+                self.add_invalid_token_message(invalid_token, lookahead);
+                return ParseResult::Error;
+            }
+            TokenType::EndOfFile => return ParseResult::Done,
+            _ => {
+                self.add_error_message(&"Expected a version number like 1.0 after .version.", version_token.clone());
+                return ParseResult::Error;
+            }
+        };
+
+        let mut minor = 0;
+        if self.lookahead(1).ttype == TokenType::Dot {
+            self.get_next_token(); // Eat dot
+
+            let lookahead = self.lookahead(1);
+            match lookahead.ttype {
+                TokenType::NumberLiteral(number) => {
+                    self.get_next_token(); // Eat literal
+                    minor = number.number;
+                }
+                _ => {
+                    self.add_error_message(&"Expected a minor version number after the dot.", version_token.clone());
+                    return ParseResult::Error;
+                }
+            }
+        }
+
+        return ParseResult::Some(ParseNode {
+            start_token: version_token.clone(),
+            address: None,
+            expression: ParseExpression::VersionRequirement {
+                major: major,
+                minor: minor,
+            },
+        });
+    }
+
+    // snesmap_statement: 'snesmap' ('lorom'|'hirom'|'hirom-fast'|'sa1rom'|'exhirom')
+    fn parse_snesmap_statement(&mut self, origin_token: &Token) -> ParseResult<ParseNode> {
+        let lookahead = self.lookahead(1);
+
+        match lookahead.ttype.clone() {
             TokenType::Identifier(identifier) => {
                 self.get_next_token(); // Eat literal
-                match self.identifier_to_snesmap(&identifier) {
+                let identifier = self.parse_dashed_suffix(identifier, &lookahead);
+                match SnesMap::from_name(&identifier) {
                     Some(snes_map) => {
                         return ParseResult::Some(ParseNode {
                             start_token: origin_token.clone(),
+                            address: None,
                             expression: ParseExpression::SnesMapStatement(snes_map),
                         });
                     }
                     None => {
-                        self.add_error_message(&"Expected lorom or hirom as argument to snesmap.", origin_token.clone());
+                        self.add_error_message(&"Expected lorom, hirom, hirom-fast, sa1rom or exhirom as argument to snesmap.", origin_token.clone());
                         ParseResult::Error
                     }
                 }
@@ -635,38 +2318,389 @@ impl Parser {
             }
             TokenType::EndOfFile => ParseResult::Done,
             _ => {
-                self.add_error_message(&"Expected lorom or hirom as argument to snesmap.", origin_token.clone());
+                self.add_error_message(&"Expected lorom, hirom, hirom-fast, sa1rom or exhirom as argument to snesmap.", origin_token.clone());
                 ParseResult::Error
             }
         }
     }
 
+    // struct_statement : 'struct' IDENTIFIER storage_field* 'endstruct'
+    fn parse_struct(&mut self, struct_token: &Token) -> ParseResult<ParseNode> {
+        let name_token = self.get_next_token();
+        let struct_name = match name_token.ttype {
+            TokenType::Identifier(ref identifier) => identifier.to_owned(),
+            _ => {
+                self.add_error_message(&"Expected a name after struct keyword.", name_token);
+                return ParseResult::Error;
+            }
+        };
+
+        let fields = match self.parse_storage_fields(
+            struct_token,
+            &format!("struct '{}'", struct_name),
+            TokenType::KeywordEndStruct,
+            "endstruct",
+        ) {
+            ParseResult::Some(fields) => fields,
+            ParseResult::Done => return ParseResult::Done,
+            ParseResult::None | ParseResult::Error => return ParseResult::Error,
+        };
+
+        let mut has_errors = false;
+        if struct_name.contains('.') {
+            self.add_dotted_name_error(name_token, &struct_name, "a struct name");
+            has_errors = true;
+        }
+
+        for field in fields.iter() {
+            if field.name == "size" {
+                self.add_error_message(&format!("Field name 'size' is reserved for the size of struct '{}'.", struct_name), field.token.clone());
+                has_errors = true;
+            }
+        }
+
+        if has_errors {
+            return ParseResult::Error;
+        }
+
+        ParseResult::Some(ParseNode {
+            start_token: struct_token.clone(),
+            address: None,
+            expression: ParseExpression::StructDefinition(struct_name, fields),
+        })
+    }
+
+    // enum_statement : 'enum' NUMBER_LITERAL ('..' NUMBER_LITERAL)? storage_field* 'endenum'
+    fn parse_enum(&mut self, enum_token: &Token) -> ParseResult<ParseNode> {
+        let lookahead = self.lookahead(1);
+        let start_address = match lookahead.ttype {
+            TokenType::NumberLiteral(number) => {
+                self.get_next_token(); // Eat literal
+                number.number
+            }
+            TokenType::Invalid(invalid_token) => {
+                self.get_next_token(); // Eat token
+                self.add_invalid_token_message(invalid_token, lookahead);
+                return ParseResult::Error;
+            }
+            TokenType::EndOfFile => return ParseResult::Done,
+            _ => {
+                self.add_error_message(&"Expected a start address after enum keyword.", enum_token.clone());
+                return ParseResult::Error;
+            }
+        };
+
+        let mut end_address = None;
+        if self.lookahead(1).ttype == TokenType::Range {
+            self.get_next_token(); // Eat range
+
+            let lookahead = self.lookahead(1);
+            match lookahead.ttype {
+                TokenType::NumberLiteral(number) => {
+                    self.get_next_token(); // Eat literal
+                    end_address = Some(number.number);
+                }
+                _ => {
+                    self.add_error_message(&"Expected an end address after '..'.", enum_token.clone());
+                    return ParseResult::Error;
+                }
+            }
+        }
+
+        let fields = match self.parse_storage_fields(
+            enum_token,
+            &format!("enum ${:X}", start_address),
+            TokenType::KeywordEndEnum,
+            "endenum",
+        ) {
+            ParseResult::Some(fields) => fields,
+            ParseResult::Done => return ParseResult::Done,
+            ParseResult::None | ParseResult::Error => return ParseResult::Error,
+        };
+
+        // Enum fields are labels, unlike struct fields they aren't qualified by a name
+        let mut has_errors = false;
+        for field in fields.iter() {
+            match field.token.ttype {
+                TokenType::Opcode(_) | TokenType::Register(_) => {
+                    self.add_reserved_name_error(field.token.clone(), &field.name, "an enum field");
+                    has_errors = true;
+                }
+                _ => {}
+            }
+        }
+
+        if has_errors {
+            return ParseResult::Error;
+        }
+
+        ParseResult::Some(ParseNode {
+            start_token: enum_token.clone(),
+            address: None,
+            expression: ParseExpression::EnumDefinition {
+                start_address: start_address,
+                end_address: end_address,
+                fields: fields,
+            },
+        })
+    }
+
+    // storage_field : IDENTIFIER ':' ('dsb' expression | '.b' | '.w' | '.l')
+    fn parse_storage_fields(
+        &mut self,
+        block_token: &Token,
+        block_description: &str,
+        end_keyword: TokenType,
+        end_keyword_name: &str,
+    ) -> ParseResult<Vec<StorageField>> {
+        let mut fields: Vec<StorageField> = Vec::new();
+        let mut has_errors = false;
+
+        loop {
+            let token = self.get_next_token();
+            match token.ttype {
+                ref ttype if ttype == &end_keyword => break,
+                TokenType::EndOfFile => {
+                    self.add_error_message(&format!("{} is missing its {}.", block_description, end_keyword_name), block_token.clone());
+                    return ParseResult::Done;
+                }
+                // Register names and opcodes are allowed since struct fields are qualified by the struct name,
+                // parse_enum() rejects them
+                TokenType::Identifier(ref field_name) | TokenType::Register(ref field_name) | TokenType::Opcode(ref field_name)
+                    if self.lookahead(1).ttype == TokenType::Colon =>
+                {
+                    self.get_next_token(); // Eat colon
+
+                    let dsb_token = self.get_next_token();
+                    let size = match dsb_token.ttype {
+                        TokenType::KeywordDsb => self.parse_expression(),
+                        // Like x: .w, a field as wide as a byte, a word or a long address
+                        TokenType::Identifier(ref size_name) if size_name.starts_with('.') => {
+                            match size_suffix_to_argument_size(&size_name[1..]) {
+                                Some(size) => {
+                                    let byte_size = argument_size_to_byte_size(size);
+                                    ParseResult::Some(Expr::Number(NumberLiteral {
+                                        number: byte_size,
+                                        argument_size: number_to_argument_size(byte_size),
+                                        kind: NumberLiteralKind::Decimal,
+                                    }))
+                                }
+                                None => {
+                                    self.add_error_message(
+                                        &format!("Unknown field size '{}', use .b, .w or .l or dsb followed by a size.", size_name),
+                                        dsb_token.clone(),
+                                    );
+                                    has_errors = true;
+                                    continue;
+                                }
+                            }
+                        }
+                        _ => {
+                            self.add_error_message(&"Expected dsb or a size like .w after the field name.", dsb_token);
+                            has_errors = true;
+                            continue;
+                        }
+                    };
+
+                    let size = match size {
+                        ParseResult::Some(expression) => expression,
+                        ParseResult::Done => {
+                            self.add_error_message(&format!("{} is missing its {}.", block_description, end_keyword_name), block_token.clone());
+                            return ParseResult::Done;
+                        }
+                        ParseResult::None => {
+                            self.add_error_message(&"Expected the size of the field after dsb.", dsb_token);
+                            has_errors = true;
+                            continue;
+                        }
+                        ParseResult::Error => {
+                            has_errors = true;
+                            continue;
+                        }
+                    };
+
+                    if field_name.contains('.') {
+                        self.add_dotted_name_error(token.clone(), field_name, "a field name");
+                        has_errors = true;
+                        continue;
+                    }
+
+                    let duplicate_token = match fields.iter().find(|field| &field.name == field_name) {
+                        Some(field) => Some(field.token.clone()),
+                        None => None,
+                    };
+
+                    if let Some(first_token) = duplicate_token {
+                        self.add_error_message(
+                            &format!(
+                                "Duplicate field '{}' in {}, first defined at {}({},{}).",
+                                field_name,
+                                block_description,
+                                first_token.source_file,
+                                first_token.line,
+                                first_token.start_column
+                            ),
+                            token.clone(),
+                        );
+                        has_errors = true;
+                        continue;
+                    }
+
+                    fields.push(StorageField {
+                        token: token.clone(),
+                        name: field_name.to_owned(),
+                        size: size,
+                    });
+                }
+                TokenType::Invalid(invalid_token) => {
+                    self.add_invalid_token_message(invalid_token, token);
+                    has_errors = true;
+                }
+                _ => {
+                    self.add_error_message(&format!("Expected a field or {} in {}.", end_keyword_name, block_description), token);
+                    has_errors = true;
+                }
+            }
+        }
+
+        if has_errors {
+            return ParseResult::Error;
+        }
+
+        ParseResult::Some(fields)
+    }
+
+    // vectors_statement : '.vectors' (IDENTIFIER (IDENTIFIER | NUMBER_LITERAL))* '.endvectors'
+    fn parse_vectors(&mut self, vectors_token: &Token) -> ParseResult<ParseNode> {
+        let mut fields: HashMap<String, DataElement> = HashMap::new();
+        let mut has_errors = false;
+
+        loop {
+            let token = self.get_next_token();
+            match token.ttype {
+                TokenType::KeywordEndVectors => break,
+                TokenType::EndOfFile => {
+                    self.add_error_message(&".vectors is missing its .endvectors.", vectors_token.clone());
+                    return ParseResult::Done;
+                }
+                TokenType::Identifier(ref vector_name) => {
+                    let handler = match self.lookahead(1).ttype {
+                        TokenType::Identifier(ref label_name) => ParseArgument::Identifier(label_name.to_owned()),
+                        TokenType::NumberLiteral(number) => ParseArgument::NumberLiteral(number),
+                        _ => {
+                            self.add_error_message(
+                                &format!("Expected the label or address of the handler after {}.", vector_name),
+                                token.clone(),
+                            );
+                            has_errors = true;
+                            continue;
+                        }
+                    };
+                    self.get_next_token(); // Eat handler
+
+                    if vector_name == "native_reset" {
+                        self.add_error_message(&"There is no native_reset vector, the CPU always resets in emulation mode so use emu_reset.", token.clone());
+                        has_errors = true;
+                    } else if interrupt_vector_address(vector_name).is_none() {
+                        let vector_names = INTERRUPT_VECTORS
+                            .iter()
+                            .map(|&(name, _)| name)
+                            .collect::<Vec<&str>>();
+                        self.add_error_message(
+                            &format!("Unknown interrupt vector '{}', expected one of {}.", vector_name, vector_names.join(", ")),
+                            token.clone(),
+                        );
+                        has_errors = true;
+                    } else if fields.contains_key(vector_name) {
+                        self.add_error_message(&format!("Interrupt vector '{}' is already set.", vector_name), token.clone());
+                        has_errors = true;
+                    } else {
+                        fields.insert(
+                            vector_name.to_owned(),
+                            DataElement {
+                                token: token.clone(),
+                                argument: handler,
+                            },
+                        );
+                    }
+                }
+                TokenType::Invalid(invalid_token) => {
+                    self.add_invalid_token_message(invalid_token, token);
+                    has_errors = true;
+                }
+                _ => {
+                    self.add_error_message(&"Expected an interrupt vector name or .endvectors in .vectors.", token);
+                    has_errors = true;
+                }
+            }
+        }
+
+        if has_errors {
+            return ParseResult::Error;
+        }
+
+        ParseResult::Some(ParseNode {
+            start_token: vectors_token.clone(),
+            address: None,
+            expression: ParseExpression::InterruptVectors { fields: fields },
+        })
+    }
+
     // include_statement : 'include' STRING_LITERAL
     fn parse_include(&mut self, origin_token: &Token) -> ParseResult<ParseNode> {
         let lookahead = self.lookahead(1);
 
         match lookahead.ttype {
             TokenType::StringLiteral(filename) => {
+                let filename = String::from_utf8_lossy(&filename).into_owned();
                 let source_filename = self.lexer().unwrap().source_file.to_string();
                 let source_file_path = Path::new(&source_filename);
                 let mut include_path = PathBuf::new();
                 include_path.push(source_file_path.parent().unwrap());
-                include_path.push(&filename);
+                include_path.push(portable_path(&filename));
 
                 match metadata(&include_path) {
                     Ok(_) => {
                         self.get_next_token(); // eat string literal
-                        self.set_current_input_file(include_path.to_str().unwrap()); // Make the current lexer the included file
 
-                        ParseResult::None
+                        if self.is_being_parsed(include_path.to_str().unwrap()) {
+                            self.add_error_message(&format!("File '{}' is already being included, recursive include found.", filename), origin_token.clone());
+                            return ParseResult::Error;
+                        }
+
+                        let mut include_chain = (*self.lexer().unwrap().include_chain()).clone();
+                        include_chain.push(IncludeLocation {
+                            source_file: source_filename.to_owned(),
+                            line: origin_token.line,
+                        });
+
+                        // Make the current lexer the included file
+                        match self.push_input_file(&include_path.to_string_lossy(), Rc::new(include_chain)) {
+                            Ok(()) => ParseResult::None,
+                            Err(why) => {
+                                self.add_error_message(
+                                    &format!("Couldn't read file '{}' for include statement: {}.", filename, why),
+                                    origin_token.clone(),
+                                );
+                                ParseResult::Error
+                            }
+                        }
                     }
-                    _ => {
+                    Err(why) => {
                         self.get_next_token(); // eat string literal
-                        self.add_error_message(&format!("Couldn't open file '{}' for include statement", filename), origin_token.clone());
+                        self.add_error_message(
+                            &format!("Couldn't open file '{}' for include statement: {}.", filename, why),
+                            origin_token.clone(),
+                        );
                         ParseResult::Error
                     }
                 }
             }
+            TokenType::InvalidString(ref message) => {
+                self.get_next_token(); // Eat string literal
+                self.add_error_message(message, lookahead.clone());
+                ParseResult::Error
+            }
             TokenType::Invalid(invalid_token) => {
                 self.get_next_token(); // Eat token
                 self.add_invalid_token_message(invalid_token, lookahead);
@@ -680,25 +2714,69 @@ impl Parser {
         }
     }
 
-    // incbin_statement : 'incbin' STRING_LITERAL
+    // incbin_statement : 'incbin' STRING_LITERAL (',' NUMBER_LITERAL (',' NUMBER_LITERAL)?)? incbin_checksum?
     fn parse_incbin(&mut self, origin_token: &Token) -> ParseResult<ParseNode> {
         let lookahead = self.lookahead(1);
 
         match lookahead.ttype {
             TokenType::StringLiteral(filename) => {
+                let filename = String::from_utf8_lossy(&filename).into_owned();
                 let source_filename = self.lexer().unwrap().source_file.to_string();
                 let source_file_path = Path::new(&source_filename);
                 let mut incbin_path = PathBuf::new();
                 incbin_path.push(source_file_path.parent().unwrap());
-                incbin_path.push(&filename);
+                incbin_path.push(portable_path(&filename));
 
                 match metadata(&incbin_path) {
                     Ok(file_metadata) => {
                         self.get_next_token(); // eat string literal
                         let file_size = file_metadata.len();
+
+                        // Optional offset and length of the part of the file to include
+                        let offset = match self.parse_incbin_number("offset") {
+                            ParseResult::Some(offset) if offset > file_size => {
+                                self.add_error_message(
+                                    &format!("Offset {} is past the end of '{}', which is {} bytes.", offset, filename, file_size),
+                                    origin_token.clone(),
+                                );
+                                return ParseResult::Error;
+                            }
+                            ParseResult::Some(offset) => offset,
+                            ParseResult::None | ParseResult::Done => 0,
+                            ParseResult::Error => return ParseResult::Error,
+                        };
+
+                        let size = match self.parse_incbin_number("length") {
+                            ParseResult::Some(length) if offset + length > file_size => {
+                                self.add_error_message(
+                                    &format!(
+                                        "{} bytes from offset {} go past the end of '{}', which is {} bytes.",
+                                        length, offset, filename, file_size
+                                    ),
+                                    origin_token.clone(),
+                                );
+                                return ParseResult::Error;
+                            }
+                            ParseResult::Some(length) => length,
+                            ParseResult::None | ParseResult::Done => file_size - offset,
+                            ParseResult::Error => return ParseResult::Error,
+                        };
+
+                        let checksum = match self.parse_incbin_checksum() {
+                            ParseResult::Some(checksum) => Some(checksum),
+                            ParseResult::None | ParseResult::Done => None,
+                            ParseResult::Error => return ParseResult::Error,
+                        };
+
                         return ParseResult::Some(ParseNode {
                             start_token: origin_token.clone(),
-                            expression: ParseExpression::IncBinStatement(incbin_path.to_str().unwrap().to_string(), file_size),
+                            address: None,
+                            expression: ParseExpression::IncBinStatement(
+                                incbin_path.to_str().unwrap().to_string(),
+                                offset,
+                                size,
+                                checksum,
+                            ),
                         });
                     }
                     _ => {
@@ -708,6 +2786,11 @@ impl Parser {
                     }
                 }
             }
+            TokenType::InvalidString(ref message) => {
+                self.get_next_token(); // Eat string literal
+                self.add_error_message(message, lookahead.clone());
+                ParseResult::Error
+            }
             TokenType::Invalid(invalid_token) => {
                 self.get_next_token(); // Eat token
                 self.add_invalid_token_message(invalid_token, lookahead);
@@ -721,13 +2804,94 @@ impl Parser {
         }
     }
 
-    fn identifier_to_snesmap(&self, identifier: &str) -> Option<SnesMap> {
-        if identifier == "lorom" {
-            Some(SnesMap::LoRom)
-        } else if identifier == "hirom" {
-            Some(SnesMap::HiRom)
-        } else {
-            None
+    // incbin_checksum : 'crc32' NUMBER_LITERAL
+    //                 | 'md5' STRING_LITERAL
+    //                 ;
+    // ',' NUMBER_LITERAL, the offset or the length of an incbin
+    fn parse_incbin_number(&mut self, name: &str) -> ParseResult<u64> {
+        if self.lookahead(1).ttype != TokenType::Comma {
+            return ParseResult::None;
+        }
+
+        self.get_next_token(); // Eat comma
+        let number_token = self.get_next_token();
+
+        match number_token.ttype {
+            TokenType::NumberLiteral(number) => ParseResult::Some(number.number as u64),
+            _ => {
+                self.add_error_message(&format!("Expected a number literal for the {} of incbin.", name), number_token);
+                ParseResult::Error
+            }
+        }
+    }
+
+    fn parse_incbin_checksum(&mut self) -> ParseResult<Checksum> {
+        let lookahead = self.lookahead(1);
+
+        let checksum_kind = match lookahead.ttype {
+            TokenType::Identifier(ref identifier) if identifier == "crc32" || identifier == "md5" => {
+                identifier.to_owned()
+            }
+            _ => return ParseResult::None,
+        };
+
+        if self.lookahead(2).ttype == TokenType::Colon {
+            return ParseResult::None; // A label on the next line
+        }
+
+        self.get_next_token(); // Eat checksum kind
+        let value_token = self.get_next_token();
+
+        match (checksum_kind.as_ref(), value_token.ttype.clone()) {
+            ("crc32", TokenType::NumberLiteral(number)) => ParseResult::Some(Checksum::Crc32(number.number)),
+            ("md5", TokenType::StringLiteral(digest_text)) => match parse_md5_digest(&String::from_utf8_lossy(&digest_text)) {
+                Some(digest) => ParseResult::Some(Checksum::Md5(digest)),
+                None => {
+                    self.add_error_message(&"Expected 32 hexadecimal digits for the MD5 checksum.", value_token);
+                    ParseResult::Error
+                }
+            },
+            ("crc32", _) => {
+                self.add_error_message(&"Expected a number literal after crc32.", value_token);
+                ParseResult::Error
+            }
+            _ => {
+                self.add_error_message(&"Expected a string literal after md5.", value_token);
+                ParseResult::Error
+            }
+        }
+    }
+
+    // Names like hirom-fast are lexed as an identifier, a minus and an identifier, they are joined back
+    // when written without spaces
+    fn parse_dashed_suffix(&mut self, identifier: String, identifier_token: &Token) -> String {
+        let minus_token = self.lookahead(1);
+        if minus_token.ttype != TokenType::Minus || minus_token.line != identifier_token.line
+            || minus_token.start_column != identifier_token.end_column
+        {
+            return identifier;
+        }
+
+        let suffix_token = self.lookahead(2);
+        match suffix_token.ttype {
+            TokenType::Identifier(ref suffix)
+                if suffix_token.line == minus_token.line && suffix_token.start_column == minus_token.end_column =>
+            {
+                self.get_next_token(); // Eat minus
+                self.get_next_token(); // Eat suffix
+                format!("{}-{}", identifier, suffix)
+            }
+            _ => identifier,
+        }
+    }
+
+    fn skip_rest_of_line(&mut self, line: u32) {
+        loop {
+            let lookahead = self.lookahead(1);
+            if lookahead.ttype == TokenType::EndOfFile || lookahead.line != line {
+                break;
+            }
+            self.get_next_token();
         }
     }
 
@@ -740,10 +2904,25 @@ impl Parser {
     }
 
     fn lexer(&mut self) -> Option<&mut Lexer> {
-        if self.current_lexer >= 0 && self.current_lexer < (self.lexers.len() as i32) {
-            Some(&mut self.lexers[self.current_lexer as usize])
-        } else {
-            None
+        match self.include_stack.last() {
+            Some(&index) => Some(&mut self.lexers[index]),
+            None => None,
+        }
+    }
+
+    fn find_lexer(&self, filename: &str) -> Option<usize> {
+        let absolute_filename = match absolute_path(Path::new(filename)) {
+            Ok(result) => result,
+            Err(_) => return None,
+        };
+
+        self.lexer_indices.get(&absolute_filename).cloned()
+    }
+
+    fn is_being_parsed(&self, filename: &str) -> bool {
+        match self.find_lexer(filename) {
+            Some(index) => self.include_stack.contains(&index),
+            None => false,
         }
     }
 
@@ -1,8 +1,156 @@
-use zeal::parser::{ErrorMessage, ParseNode};
+use std::mem;
+use std::time::{Duration, Instant};
+use zeal::lexer::Token;
+use zeal::parser::{ErrorMessage, ErrorSeverity, ParseNode};
 use zeal::symbol_table::SymbolTable;
 
+// Errors and warnings collected by a pass while it runs, returned by do_pass() with take_messages()
+pub struct DiagnosticSink {
+    messages: Vec<ErrorMessage>,
+}
+
+impl DiagnosticSink {
+    pub fn new() -> Self {
+        DiagnosticSink {
+            messages: Vec::new(),
+        }
+    }
+
+    fn add_message(&mut self, message: &str, offending_token: Token, severity: ErrorSeverity) {
+        self.messages.push(ErrorMessage {
+            message: message.to_owned(),
+            token: offending_token,
+            severity: severity,
+        });
+    }
+
+    pub fn add_error(&mut self, error_message: &str, offending_token: Token) {
+        self.add_message(error_message, offending_token, ErrorSeverity::Error);
+    }
+
+    pub fn add_warning(&mut self, warning_message: &str, offending_token: Token) {
+        self.add_message(warning_message, offending_token, ErrorSeverity::Warning);
+    }
+
+    // Warnings alone don't count
+    pub fn has_errors(&self) -> bool {
+        self.messages
+            .iter()
+            .any(|error_message| error_message.severity == ErrorSeverity::Error)
+    }
+
+    pub fn take_messages(&mut self) -> Vec<ErrorMessage> {
+        mem::replace(&mut self.messages, Vec::new())
+    }
+}
+
 pub trait TreePass {
-    fn has_errors(&self) -> bool;
-    fn get_error_messages(&self) -> &Vec<ErrorMessage>;
-    fn do_pass(&mut self, Vec<ParseNode>, &mut SymbolTable) -> Vec<ParseNode>;
+    // Short name used to tag the messages of the pass, like resolve-labels
+    fn name(&self) -> &'static str;
+    // The new tree along with the errors and warnings of the pass, warnings alone don't stop the pipeline
+    fn do_pass(&mut self, Vec<ParseNode>, &mut SymbolTable) -> (Vec<ParseNode>, Vec<ErrorMessage>);
+}
+
+// Warnings alone don't count, the assembly only fails on errors
+pub fn has_error_severity(messages: &[ErrorMessage]) -> bool {
+    messages
+        .iter()
+        .any(|error_message| error_message.severity == ErrorSeverity::Error)
+}
+
+// A message along with the name of the pass that reported it
+pub struct PassMessage {
+    pub pass_name: &'static str,
+    pub error_message: ErrorMessage,
+}
+
+// Passes run in order on the parse tree, stopping after the first pass reporting an error.
+// Library users can add their own passes between the built-in ones with insert_before() and insert_after().
+pub struct PassPipeline {
+    passes: Vec<Box<dyn TreePass>>,
+    pub messages: Vec<PassMessage>,
+    // Time taken by each pass that ran, in order
+    pub timings: Vec<(&'static str, Duration)>,
+}
+
+impl PassPipeline {
+    pub fn new() -> Self {
+        PassPipeline {
+            passes: Vec::new(),
+            messages: Vec::new(),
+            timings: Vec::new(),
+        }
+    }
+
+    pub fn add_pass(&mut self, pass: Box<dyn TreePass>) {
+        self.passes.push(pass);
+    }
+
+    // Names of the passes in the order they run
+    pub fn pass_names(&self) -> Vec<&'static str> {
+        self.passes.iter().map(|pass| pass.name()).collect()
+    }
+
+    fn pass_index(&self, pass_name: &str) -> Result<usize, String> {
+        match self.passes.iter().position(|pass| pass.name() == pass_name) {
+            Some(index) => Ok(index),
+            None => Err(format!(
+                "No pass named '{}' in the pipeline, the passes are: {}.",
+                pass_name,
+                self.pass_names().join(", ")
+            )),
+        }
+    }
+
+    // Run the pass right before the pass named `pass_name`, like resolve-labels
+    pub fn insert_before(&mut self, pass_name: &str, pass: Box<dyn TreePass>) -> Result<(), String> {
+        let index = self.pass_index(pass_name)?;
+        self.passes.insert(index, pass);
+        Ok(())
+    }
+
+    // Run the pass right after the pass named `pass_name`
+    pub fn insert_after(&mut self, pass_name: &str, pass: Box<dyn TreePass>) -> Result<(), String> {
+        let index = self.pass_index(pass_name)?;
+        self.passes.insert(index + 1, pass);
+        Ok(())
+    }
+
+    pub fn run(&mut self, parse_tree: Vec<ParseNode>, symbol_table: &mut SymbolTable) -> Vec<ParseNode> {
+        let mut parse_tree = parse_tree;
+
+        for pass in self.passes.iter_mut() {
+            let start_time = Instant::now();
+            let (new_tree, error_messages) = pass.do_pass(parse_tree, symbol_table);
+            parse_tree = new_tree;
+            self.timings.push((pass.name(), start_time.elapsed()));
+
+            let has_errors = has_error_severity(&error_messages);
+            for error_message in error_messages.into_iter() {
+                self.messages.push(PassMessage {
+                    pass_name: pass.name(),
+                    error_message: error_message,
+                });
+            }
+
+            if has_errors {
+                break;
+            }
+        }
+
+        parse_tree
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.messages
+            .iter()
+            .any(|message| message.error_message.severity == ErrorSeverity::Error)
+    }
+
+    pub fn error_messages(&self) -> Vec<ErrorMessage> {
+        self.messages
+            .iter()
+            .map(|message| message.error_message.clone())
+            .collect()
+    }
 }
@@ -0,0 +1,151 @@
+extern crate byteorder;
+
+use self::byteorder::{BigEndian, LittleEndian, WriteBytesExt};
+use std::io::{self, Write};
+use zeal::checksum::Crc32;
+
+// IPS offsets are 24-bit and a record can't start at the offset spelling 'EOF'
+const IPS_MAX_OFFSET: usize = 0xFFFFFF;
+const IPS_EOF_OFFSET: usize = 0x454F46;
+const IPS_MAX_RECORD_SIZE: usize = 0xFFFF;
+
+// BPS actions, stored in the low 2 bits of the action number
+const BPS_SOURCE_READ: usize = 0;
+const BPS_TARGET_READ: usize = 1;
+
+fn is_unchanged(source: &[u8], target: &[u8], offset: usize) -> bool {
+    offset < source.len() && source[offset] == target[offset]
+}
+
+// (start, end) of the runs of bytes of the target that differ from the source,
+// bytes past the end of the source are always part of a run
+fn changed_runs(source: &[u8], target: &[u8]) -> Vec<(usize, usize)> {
+    let mut runs = Vec::new();
+    let mut offset = 0;
+
+    while offset < target.len() {
+        if is_unchanged(source, target, offset) {
+            offset += 1;
+            continue;
+        }
+
+        let start = offset;
+        while offset < target.len() && !is_unchanged(source, target, offset) {
+            offset += 1;
+        }
+        runs.push((start, offset));
+    }
+
+    runs
+}
+
+// Patch in the IPS format, made of the bytes that differ from the source ROM
+pub struct IpsPatchWriter<'a> {
+    source: &'a [u8],
+}
+
+impl<'a> IpsPatchWriter<'a> {
+    pub fn new(source: &'a [u8]) -> Self {
+        IpsPatchWriter { source: source }
+    }
+
+    pub fn write<W: Write>(&self, writer: &mut W, target: &[u8]) -> io::Result<()> {
+        writer.write_all(b"PATCH")?;
+
+        for (start, end) in changed_runs(self.source, target) {
+            let mut offset = start;
+
+            while offset < end {
+                // Including the byte before doesn't change the result
+                if offset == IPS_EOF_OFFSET {
+                    offset -= 1;
+                }
+
+                if offset > IPS_MAX_OFFSET {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("IPS patches can't change bytes past offset ${:06X}", IPS_MAX_OFFSET),
+                    ));
+                }
+
+                let size = (end - offset).min(IPS_MAX_RECORD_SIZE);
+                writer.write_u24::<BigEndian>(offset as u32)?;
+                writer.write_u16::<BigEndian>(size as u16)?;
+                writer.write_all(&target[offset..offset + size])?;
+
+                offset += size;
+            }
+        }
+
+        writer.write_all(b"EOF")
+    }
+}
+
+// Patch in the BPS format, which also checks the CRC32 of the source and of the result
+pub struct BpsPatchWriter<'a> {
+    source: &'a [u8],
+}
+
+// Variable length number where each byte holds 7 bits, the last byte has bit 7 set
+fn write_bps_number(patch: &mut Vec<u8>, number: usize) {
+    let mut number = number;
+
+    loop {
+        let low_bits = (number & 0x7F) as u8;
+        number >>= 7;
+
+        if number == 0 {
+            patch.push(0x80 | low_bits);
+            break;
+        }
+
+        patch.push(low_bits);
+        number -= 1;
+    }
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc32 = Crc32::new();
+    crc32.update(data);
+    crc32.finish()
+}
+
+impl<'a> BpsPatchWriter<'a> {
+    pub fn new(source: &'a [u8]) -> Self {
+        BpsPatchWriter { source: source }
+    }
+
+    pub fn write<W: Write>(&self, writer: &mut W, target: &[u8]) -> io::Result<()> {
+        let mut patch = Vec::new();
+        patch.extend_from_slice(b"BPS1");
+        write_bps_number(&mut patch, self.source.len());
+        write_bps_number(&mut patch, target.len());
+        // No metadata
+        write_bps_number(&mut patch, 0);
+
+        // Unchanged bytes are read from the same offset of the source, the others are stored in the patch
+        let mut offset = 0;
+        while offset < target.len() {
+            let start = offset;
+            let is_source_read = is_unchanged(self.source, target, offset);
+
+            while offset < target.len() && is_unchanged(self.source, target, offset) == is_source_read {
+                offset += 1;
+            }
+
+            let action = if is_source_read { BPS_SOURCE_READ } else { BPS_TARGET_READ };
+            write_bps_number(&mut patch, ((offset - start - 1) << 2) | action);
+
+            if !is_source_read {
+                patch.extend_from_slice(&target[start..offset]);
+            }
+        }
+
+        patch.write_u32::<LittleEndian>(crc32(self.source))?;
+        patch.write_u32::<LittleEndian>(crc32(target))?;
+        let patch_crc32 = crc32(&patch);
+        patch.write_u32::<LittleEndian>(patch_crc32)?;
+
+        writer.write_all(&patch)
+    }
+}
@@ -0,0 +1,124 @@
+use zeal::parser::*;
+use zeal::pass::{DiagnosticSink, TreePass};
+use zeal::symbol_table::SymbolTable;
+use zeal::system_definition::ArgumentSize;
+
+// What to do with two instructions following each other
+enum Rewrite {
+    KeepBoth,
+    DropFirst,
+    DropSecond,
+}
+
+// Flag of the processor status register changed by a flag instruction. cli and sei aren't listed,
+// a cli right before a sei lets a pending IRQ run between them.
+fn flag_of(opcode_name: &str) -> Option<&'static str> {
+    match opcode_name {
+        "clc" | "sec" => Some("carry"),
+        "cld" | "sed" => Some("decimal"),
+        "clv" => Some("overflow"),
+        _ => None,
+    }
+}
+
+// Long addresses in banks $7E and $7F are always WRAM, where writing a value back has no effect.
+// Other addresses can be I/O registers depending on the bank registers.
+fn is_wram_long_address(argument: &ParseArgument) -> bool {
+    match argument {
+        &ParseArgument::NumberLiteral(ref number) => {
+            number.argument_size == ArgumentSize::Word24 && (number.number >> 16 == 0x7E || number.number >> 16 == 0x7F)
+        }
+        _ => false,
+    }
+}
+
+fn same_number(first: &ParseArgument, second: &ParseArgument) -> bool {
+    match (first, second) {
+        (&ParseArgument::NumberLiteral(ref first), &ParseArgument::NumberLiteral(ref second)) => {
+            first.number == second.number && first.argument_size == second.argument_size
+        }
+        _ => false,
+    }
+}
+
+fn rewrite(first: &ParseExpression, second: &ParseExpression) -> Rewrite {
+    match (first, second) {
+        // clc followed by sec or clc: only the last one matters
+        (&ParseExpression::ImpliedInstruction(ref first_opcode), &ParseExpression::ImpliedInstruction(ref second_opcode)) => {
+            match (flag_of(first_opcode), flag_of(second_opcode)) {
+                (Some(first_flag), Some(second_flag)) if first_flag == second_flag => Rewrite::DropFirst,
+                _ => Rewrite::KeepBoth,
+            }
+        }
+        // lda #$01 followed by lda #$02: the second load replaces the register and the N and Z flags
+        (&ParseExpression::ImmediateInstruction(ref first_opcode, _), &ParseExpression::ImmediateInstruction(ref second_opcode, _)) => {
+            match first_opcode.as_str() {
+                "lda" | "ldx" | "ldy" if first_opcode == second_opcode => Rewrite::DropFirst,
+                _ => Rewrite::KeepBoth,
+            }
+        }
+        // lda $7E0010 followed by sta $7E0010: the store writes back the value just loaded and doesn't change the flags
+        (
+            &ParseExpression::SingleArgumentInstruction(ref first_opcode, ref first_argument),
+            &ParseExpression::SingleArgumentInstruction(ref second_opcode, ref second_argument),
+        ) => {
+            if first_opcode == "lda" && second_opcode == "sta" && is_wram_long_address(first_argument)
+                && same_number(first_argument, second_argument)
+            {
+                Rewrite::DropSecond
+            } else {
+                Rewrite::KeepBoth
+            }
+        }
+        _ => Rewrite::KeepBoth,
+    }
+}
+
+// Remove instructions made redundant by the instruction next to them, enabled with --optimize.
+// Only instructions right after each other are rewritten, a label in between keeps both since code can jump there.
+// Runs before CollectLabelPass so the addresses are computed from the shorter code, code using
+// its own address like `bra * + 3` across a removed instruction has to be written with labels instead.
+pub struct PeepholePass {
+    diagnostics: DiagnosticSink,
+}
+
+impl PeepholePass {
+    pub fn new() -> Self {
+        PeepholePass {
+            diagnostics: DiagnosticSink::new(),
+        }
+    }
+}
+
+impl TreePass for PeepholePass {
+    fn name(&self) -> &'static str {
+        "peephole"
+    }
+
+    fn do_pass(
+        &mut self,
+        parse_tree: Vec<ParseNode>,
+        _symbol_table: &mut SymbolTable,
+    ) -> (Vec<ParseNode>, Vec<ErrorMessage>) {
+        let mut new_tree: Vec<ParseNode> = Vec::new();
+
+        for node in parse_tree.into_iter() {
+            let action = match new_tree.last() {
+                Some(previous) => rewrite(&previous.expression, &node.expression),
+                None => Rewrite::KeepBoth,
+            };
+
+            match action {
+                Rewrite::KeepBoth => new_tree.push(node),
+                // The instruction before the removed one can be rewritten with the new one too, like clc sec clc
+                Rewrite::DropFirst => {
+                    new_tree.pop();
+                    new_tree.push(node);
+                }
+                Rewrite::DropSecond => {}
+            }
+        }
+
+        return (new_tree, self.diagnostics.take_messages());
+    }
+}
@@ -0,0 +1,173 @@
+use zeal::lexer::Token;
+use zeal::parser::*;
+use zeal::pass::{DiagnosticSink, TreePass};
+use zeal::symbol_table::SymbolTable;
+use zeal::system_definition::*;
+
+// Bits of the processor status register set by sep and cleared by rep
+const ACCUMULATOR_FLAG: u32 = 0x20;
+const INDEX_FLAG: u32 = 0x10;
+
+// Width of a register along with the rep or sep instruction that set it
+struct KnownWidth {
+    size: ArgumentSize,
+    instruction_text: String,
+    token: Token,
+}
+
+// Last rep instruction, to tell which flags it didn't clear
+struct LastRep {
+    flags: u32,
+    instruction_text: String,
+    token: Token,
+}
+
+// Warn about immediate values whose size doesn't match the width of the register set by the last rep or sep,
+// like lda #$01 after rep #$20. Widths are forgotten at labels since code can jump there from anywhere.
+// Runs after ResolveLabelPass so every immediate has its final size.
+pub struct RegisterWidthPass {
+    system: &'static SystemDefinition,
+    diagnostics: DiagnosticSink,
+    accumulator: Option<KnownWidth>,
+    index: Option<KnownWidth>,
+    last_rep: Option<LastRep>,
+    // Warn about a 16-bit immediate after a rep that cleared the flag of the other register, like rep #$10 then lda #$1234
+    warn_rep_flags: bool,
+}
+
+impl RegisterWidthPass {
+    pub fn new(system: &'static SystemDefinition) -> Self {
+        RegisterWidthPass {
+            system: system,
+            diagnostics: DiagnosticSink::new(),
+            accumulator: None,
+            index: None,
+            last_rep: None,
+            warn_rep_flags: true,
+        }
+    }
+
+    pub fn set_warn_rep_flags(&mut self, warn_rep_flags: bool) {
+        self.warn_rep_flags = warn_rep_flags;
+    }
+
+    fn forget_widths(&mut self) {
+        self.accumulator = None;
+        self.index = None;
+        self.last_rep = None;
+    }
+
+    fn set_widths(&mut self, node: &ParseNode, instruction_text: String, flags: u32, size: ArgumentSize) {
+        if flags & ACCUMULATOR_FLAG != 0 {
+            self.accumulator = Some(KnownWidth {
+                size: size,
+                instruction_text: instruction_text.clone(),
+                token: node.start_token.clone(),
+            });
+        }
+
+        if flags & INDEX_FLAG != 0 {
+            self.index = Some(KnownWidth {
+                size: size,
+                instruction_text: instruction_text,
+                token: node.start_token.clone(),
+            });
+        }
+    }
+
+    fn check_immediate(&mut self, node: &ParseNode, register: WidthRegister, immediate_size: ArgumentSize) {
+        let (known_width, register_name) = match register {
+            WidthRegister::Accumulator => (&self.accumulator, "accumulator"),
+            WidthRegister::Index => (&self.index, "index registers"),
+        };
+
+        if known_width.is_none() && immediate_size == ArgumentSize::Word16 && self.warn_rep_flags {
+            let (flag, flag_name) = match register {
+                WidthRegister::Accumulator => (ACCUMULATOR_FLAG, "M"),
+                WidthRegister::Index => (INDEX_FLAG, "X"),
+            };
+
+            if let Some(ref last_rep) = self.last_rep {
+                if last_rep.flags & flag == 0 {
+                    self.diagnostics.add_warning(
+                        &format!(
+                            "Immediate value is 16-bit but '{}' at {}({}) didn't clear the {} flag, the {} {} still 8-bit. Use rep #${:02X} to make {} 16-bit.",
+                            last_rep.instruction_text,
+                            last_rep.token.source_file,
+                            last_rep.token.line,
+                            flag_name,
+                            register_name,
+                            if register == WidthRegister::Accumulator { "is" } else { "are" },
+                            flag,
+                            if register == WidthRegister::Accumulator { "it" } else { "them" }
+                        ),
+                        node.start_token.clone(),
+                    );
+                }
+            }
+        }
+
+        if let &Some(ref known_width) = known_width {
+            if known_width.size != immediate_size {
+                self.diagnostics.add_warning(
+                    &format!(
+                        "Immediate value is {}-bit but the {} {} {}-bit since '{}' at {}({}).",
+                        argument_size_to_bit_size(immediate_size),
+                        register_name,
+                        if register == WidthRegister::Accumulator { "is" } else { "are" },
+                        argument_size_to_bit_size(known_width.size),
+                        known_width.instruction_text,
+                        known_width.token.source_file,
+                        known_width.token.line
+                    ),
+                    node.start_token.clone(),
+                );
+            }
+        }
+    }
+}
+
+impl TreePass for RegisterWidthPass {
+    fn name(&self) -> &'static str {
+        "register-width"
+    }
+
+    fn do_pass(
+        &mut self,
+        parse_tree: Vec<ParseNode>,
+        _symbol_table: &mut SymbolTable,
+    ) -> (Vec<ParseNode>, Vec<ErrorMessage>) {
+        for node in parse_tree.iter() {
+            match node.expression {
+                ParseExpression::Label(_) | ParseExpression::OriginStatement(..) => self.forget_widths(),
+                ParseExpression::ImmediateInstruction(ref opcode_name, ParseArgument::NumberLiteral(ref number)) => {
+                    let instruction_text =
+                        format_instruction(opcode_name, &AddressingMode::Immediate, &[number.to_string()]);
+
+                    match opcode_name.as_str() {
+                        // Cleared flags make the registers 16-bit
+                        "rep" => {
+                            self.last_rep = Some(LastRep {
+                                flags: number.number,
+                                instruction_text: instruction_text.clone(),
+                                token: node.start_token.clone(),
+                            });
+                            self.set_widths(node, instruction_text, number.number, ArgumentSize::Word16);
+                        }
+                        "sep" => self.set_widths(node, instruction_text, number.number, ArgumentSize::Word8),
+                        _ => if let Some(register) = self.system.immediate_width_register(opcode_name) {
+                            self.check_immediate(node, register, number.argument_size);
+                        },
+                    }
+                }
+                // The status register comes from somewhere the pass can't follow
+                ParseExpression::ImpliedInstruction(ref opcode_name) if opcode_name == "plp" || opcode_name == "xce" => {
+                    self.forget_widths()
+                }
+                _ => {}
+            }
+        }
+
+        return (parse_tree, self.diagnostics.take_messages());
+    }
+}
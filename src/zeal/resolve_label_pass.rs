@@ -1,30 +1,184 @@
+use std::collections::HashMap;
+use zeal::expression::*;
 use zeal::lexer::*;
+use zeal::node_size::{node_size, unresolved_argument_size};
 use zeal::parser::*;
 use zeal::system_definition::*;
-use zeal::pass::TreePass;
+use zeal::pass::{DiagnosticSink, TreePass};
 use zeal::symbol_table::*;
 
 pub struct ResolveLabelPass {
     system: &'static SystemDefinition,
-    pub error_messages: Vec<ErrorMessage>,
+    diagnostics: DiagnosticSink,
 }
 
 impl ResolveLabelPass {
     pub fn new(system: &'static SystemDefinition) -> Self {
         ResolveLabelPass {
             system: system,
-            error_messages: Vec::new(),
+            diagnostics: DiagnosticSink::new(),
         }
     }
 
-    fn add_error_message(&mut self, error_message: &str, offending_token: Token) {
-        let new_message = ErrorMessage {
-            message: error_message.to_owned(),
-            token: offending_token,
-            severity: ErrorSeverity::Error,
+    fn evaluate_signed_argument(
+        &mut self,
+        argument: &ParseArgument,
+        symbol_table: &SymbolTable,
+        statement_address: u32,
+        offending_token: &Token,
+    ) -> Option<i64> {
+        let result = match argument {
+            &ParseArgument::Identifier(ref identifier) => {
+                Expr::Identifier(identifier.to_owned()).evaluate(symbol_table, statement_address)
+            }
+            &ParseArgument::Expression(ref expression) => {
+                expression.evaluate(symbol_table, statement_address)
+            }
+            &ParseArgument::NumberLiteral(ref number) => Ok(number.number as i64),
+            &ParseArgument::Register(_) => return None,
         };
 
-        self.error_messages.push(new_message);
+        match result {
+            Ok(value) => Some(value),
+            Err(message) => {
+                self.diagnostics.add_error(&message, offending_token.clone());
+                None
+            }
+        }
+    }
+
+    fn evaluate_argument(
+        &mut self,
+        argument: &ParseArgument,
+        symbol_table: &SymbolTable,
+        statement_address: u32,
+        offending_token: &Token,
+    ) -> Option<u32> {
+        let value = self.evaluate_signed_argument(argument, symbol_table, statement_address, offending_token)?;
+
+        if value < 0 || value > (u32::max_value() as i64) {
+            self.diagnostics.add_error(
+                &format!("Expression evaluates to {} which is not a valid address.", value),
+                offending_token.clone(),
+            );
+            None
+        } else {
+            Some(value as u32)
+        }
+    }
+
+    // Evaluate a value stored in the given size, negative values are written in two's complement
+    fn resolve_sized_argument(
+        &mut self,
+        argument: &ParseArgument,
+        argument_size: ArgumentSize,
+        symbol_table: &SymbolTable,
+        statement_address: u32,
+        offending_token: &Token,
+    ) -> Option<ParseArgument> {
+        let value = self.evaluate_signed_argument(argument, symbol_table, statement_address, offending_token)?;
+
+        match signed_value_to_argument_size(value, argument_size) {
+            Some(number) => Some(ParseArgument::NumberLiteral(NumberLiteral {
+                number: number,
+                argument_size: argument_size,
+                kind: NumberLiteralKind::Hex,
+            })),
+            None => {
+                self.diagnostics.add_error(
+                    &format!("Value {} doesn't fit in {} bits.", value, argument_size_to_bit_size(argument_size)),
+                    offending_token.clone(),
+                );
+                None
+            }
+        }
+    }
+
+    // A label too wide for its immediate is usually missing low(), high() or bank(), the value isn't truncated
+    // like a number literal so this points at the functions instead of only giving the value
+    fn check_immediate_label(
+        &mut self,
+        argument: &ParseArgument,
+        argument_size: ArgumentSize,
+        symbol_table: &SymbolTable,
+        statement_address: u32,
+        offending_token: &Token,
+    ) -> bool {
+        let label_name = match argument {
+            &ParseArgument::Identifier(ref identifier) => identifier,
+            &ParseArgument::Expression(Expr::Identifier(ref identifier)) => identifier,
+            &ParseArgument::Expression(Expr::Sized(_, ref operand)) => match **operand {
+                Expr::Identifier(ref identifier) => identifier,
+                _ => return true,
+            },
+            _ => return true,
+        };
+
+        // Undefined labels are reported when resolving the argument
+        let value = match Expr::Identifier(label_name.to_owned()).evaluate(symbol_table, statement_address) {
+            Ok(value) => value,
+            Err(_) => return true,
+        };
+
+        if signed_value_to_argument_size(value, argument_size).is_some() {
+            return true;
+        }
+
+        let bit_size = argument_size_to_bit_size(argument_size);
+        self.diagnostics.add_error(
+            &format!(
+                "Label '{}' is ${:X} which doesn't fit in {} {}-bit immediate and would be truncated to ${:0width$X}, \
+                 use low({}), high({}) or bank({}) to pick a byte.",
+                label_name,
+                value,
+                if bit_size == 8 { "an" } else { "a" },
+                bit_size,
+                truncate_to_argument_size(value, argument_size),
+                label_name,
+                label_name,
+                label_name,
+                width = argument_size_to_byte_size(argument_size) as usize * 2
+            ),
+            offending_token.clone(),
+        );
+        false
+    }
+
+    // Errors point at the element instead of the db or dw
+    fn resolve_data_elements(
+        &mut self,
+        elements: &[DataElement],
+        argument_size: ArgumentSize,
+        symbol_table: &SymbolTable,
+        statement_address: u32,
+    ) -> Vec<DataElement> {
+        elements
+            .iter()
+            .filter_map(|element| {
+                self.resolve_sized_argument(&element.argument, argument_size, symbol_table, statement_address, &element.token)
+                    .map(|argument| DataElement {
+                        token: element.token.clone(),
+                        argument: argument,
+                    })
+            })
+            .collect()
+    }
+
+    fn resolve_label_argument(
+        &mut self,
+        argument: &ParseArgument,
+        symbol_table: &SymbolTable,
+        statement_address: u32,
+        offending_token: &Token,
+    ) -> Option<ParseArgument> {
+        match self.evaluate_argument(argument, symbol_table, statement_address, offending_token) {
+            Some(address) => Some(ParseArgument::NumberLiteral(NumberLiteral {
+                number: address,
+                kind: NumberLiteralKind::Hex,
+                argument_size: unresolved_argument_size(self.system, argument),
+            })),
+            None => None,
+        }
     }
 
     fn find_instruction_argument_size(
@@ -71,145 +225,168 @@ impl ResolveLabelPass {
 }
 
 impl TreePass for ResolveLabelPass {
-    fn has_errors(&self) -> bool {
-        return !self.error_messages.is_empty();
-    }
-
-    fn get_error_messages(&self) -> &Vec<ErrorMessage> {
-        &self.error_messages
+    fn name(&self) -> &'static str {
+        "resolve-labels"
     }
 
     fn do_pass(
         &mut self,
         parse_tree: Vec<ParseNode>,
         symbol_table: &mut SymbolTable,
-    ) -> Vec<ParseNode> {
+    ) -> (Vec<ParseNode>, Vec<ErrorMessage>) {
         let mut new_tree: Vec<ParseNode> = Vec::new();
 
         let mut current_address: u32 = 0;
 
-        for node in parse_tree.iter() {
+        for mut node in parse_tree.into_iter() {
+            let statement_address = current_address;
+            node.address = Some(statement_address);
+
+            for argument in node.expression.arguments() {
+                for identifier in argument.identifiers() {
+                    symbol_table.add_reference(identifier, &node.start_token);
+                }
+            }
+
             match node.expression {
                 ParseExpression::ImpliedInstruction(_) => {
-                    new_tree.push(node.clone());
+                    new_tree.push(node);
                     current_address += 1;
                 }
                 ParseExpression::ImmediateInstruction(ref opcode_name, ref argument) => {
                     current_address += 1;
 
                     match argument {
-                        &ParseArgument::Identifier(ref identifier) => {
-                            if symbol_table.has_label(identifier) {
-                                let argument_size = self.system.label_size;
-
-                                let number = NumberLiteral {
-                                    number: symbol_table.address_for(identifier),
-                                    argument_size: argument_size,
-                                };
-
-                                current_address += argument_size_to_byte_size(argument_size);
+                        &ParseArgument::Identifier(_) | &ParseArgument::Expression(_) => {
+                            current_address += argument_size_to_byte_size(unresolved_argument_size(self.system, argument));
+
+                            let argument_size = unresolved_argument_size(self.system, argument);
+                            if !self.check_immediate_label(
+                                argument,
+                                argument_size,
+                                symbol_table,
+                                statement_address,
+                                &node.start_token,
+                            ) {
+                                new_tree.push(node);
+                                continue;
+                            }
 
-                                new_tree.push(ParseNode {
-                                    start_token: node.start_token.clone(),
-                                    expression: ParseExpression::ImmediateInstruction(
-                                        opcode_name.to_owned(),
-                                        ParseArgument::NumberLiteral(number),
-                                    ),
-                                });
-                            } else {
-                                self.add_error_message(
-                                    &format!("Label '{}' not found.", identifier),
-                                    node.start_token.clone(),
-                                );
-                                new_tree.push(node.clone());
+                            match self.resolve_sized_argument(
+                                argument,
+                                argument_size,
+                                symbol_table,
+                                statement_address,
+                                &node.start_token,
+                            ) {
+                                Some(resolved_argument) => {
+                                    new_tree.push(ParseNode {
+                                        start_token: node.start_token.clone(),
+                                        address: node.address,
+                                        expression: ParseExpression::ImmediateInstruction(
+                                            opcode_name.to_owned(),
+                                            resolved_argument,
+                                        ),
+                                    });
+                                }
+                                None => new_tree.push(node),
                             }
                         }
                         &ParseArgument::NumberLiteral(ref number) => {
                             current_address += argument_size_to_byte_size(number.argument_size);
-                            new_tree.push(node.clone());
+                            new_tree.push(node);
                         }
                         _ => {
-                            new_tree.push(node.clone());
+                            new_tree.push(node);
                         }
-                    }
+                    };
                 }
                 ParseExpression::SingleArgumentInstruction(ref opcode_name, ref argument) => {
                     current_address += 1;
 
                     match argument {
-                        &ParseArgument::Identifier(ref identifier) => {
-                            if symbol_table.has_label(identifier) {
-                                let argument_size = match self.find_instruction_argument_size(
-                                    opcode_name,
-                                    &[AddressingMode::Relative],
-                                ) {
-                                    Some(size) => size,
-                                    None => self.system.label_size,
-                                };
+                        &ParseArgument::Identifier(_) | &ParseArgument::Expression(_) => {
+                            let argument_size = match self.find_instruction_argument_size(
+                                opcode_name,
+                                &[AddressingMode::Relative],
+                            ) {
+                                Some(size) => size,
+                                None => unresolved_argument_size(self.system, argument),
+                            };
 
-                                let mut address = 0;
-
-                                if self.is_branching_instruction(opcode_name) {
-                                    match argument_size {
-                                        ArgumentSize::Word8 => {
-                                            let temp_address:i64 = (symbol_table.address_for(identifier) as i64) - ((current_address + argument_size_to_byte_size(argument_size)) as i64);
-                                            if temp_address > (i8::max_value() as i64)
-                                                || temp_address < (i8::min_value() as i64)
-                                            {
-                                                println!(
-                                                    "address: {}, current_address: {}",
-                                                    symbol_table.address_for(identifier),
-                                                    current_address
-                                                );
-                                                self.add_error_message(&format!("Branch label '{0}' is too far away. Consider reducing the distance of the label.", identifier), node.start_token.clone());
-                                            } else {
-                                                address = (temp_address as u32) & 0xFF;
-                                            }
-                                        }
-                                        ArgumentSize::Word16 => {
-                                            let temp_address:i64 = (symbol_table.address_for(identifier) as i64) - ((current_address + argument_size_to_byte_size(argument_size)) as i64);
-                                            if temp_address > (i16::max_value() as i64)
-                                                || temp_address < (i16::min_value() as i64)
-                                            {
-                                                self.add_error_message(&format!("Branch label '{0}' is too far away. Consider reducing the distance of the label.", identifier), node.start_token.clone());
-                                            } else {
-                                                address = (temp_address as u32) & 0xFFFF;
-                                            }
-                                        }
-                                        _ => {}
-                                    };
-                                } else {
-                                    address = symbol_table.address_for(identifier);
+                            current_address += argument_size_to_byte_size(argument_size);
+
+                            let target = match self.evaluate_argument(
+                                argument,
+                                symbol_table,
+                                statement_address,
+                                &node.start_token,
+                            ) {
+                                Some(target) => target,
+                                None => {
+                                    new_tree.push(node);
+                                    continue;
                                 }
+                            };
 
-                                let number = NumberLiteral {
-                                    number: address,
-                                    argument_size: argument_size,
-                                };
+                            let branch_target = match argument {
+                                &ParseArgument::Identifier(ref identifier) => {
+                                    format!("label '{}'", identifier)
+                                }
+                                _ => format!("target ${:X}", target),
+                            };
 
-                                current_address += argument_size_to_byte_size(argument_size);
+                            let mut address = 0;
 
-                                new_tree.push(ParseNode {
-                                    start_token: node.start_token.clone(),
-                                    expression: ParseExpression::SingleArgumentInstruction(
-                                        opcode_name.to_owned(),
-                                        ParseArgument::NumberLiteral(number),
-                                    ),
-                                });
+                            if self.is_branching_instruction(opcode_name) {
+                                let temp_address: i64 = (target as i64) - (current_address as i64);
+
+                                match argument_size {
+                                    ArgumentSize::Word8 => {
+                                        if temp_address > (i8::max_value() as i64)
+                                            || temp_address < (i8::min_value() as i64)
+                                        {
+                                            self.diagnostics.add_error(&format!("Branch {} is too far away. Consider reducing the distance of the label.", branch_target), node.start_token.clone());
+                                        } else {
+                                            address = (temp_address as u32) & 0xFF;
+                                        }
+                                    }
+                                    ArgumentSize::Word16 => {
+                                        if temp_address > (i16::max_value() as i64)
+                                            || temp_address < (i16::min_value() as i64)
+                                        {
+                                            self.diagnostics.add_error(&format!("Branch {} is too far away. Consider reducing the distance of the label.", branch_target), node.start_token.clone());
+                                        } else {
+                                            address = (temp_address as u32) & 0xFFFF;
+                                        }
+                                    }
+                                    _ => {}
+                                };
                             } else {
-                                self.add_error_message(
-                                    &format!("Label '{}' not found.", identifier),
-                                    node.start_token.clone(),
-                                );
-                                new_tree.push(node.clone());
+                                address = target;
                             }
+
+                            let number = NumberLiteral {
+                                number: address,
+                                kind: NumberLiteralKind::Hex,
+                                argument_size: argument_size,
+                            };
+
+                            new_tree.push(ParseNode {
+                                start_token: node.start_token.clone(),
+                                address: node.address,
+                                expression: ParseExpression::SingleArgumentInstruction(
+                                    opcode_name.to_owned(),
+                                    ParseArgument::NumberLiteral(number),
+                                ),
+                            });
                         }
                         &ParseArgument::NumberLiteral(ref number) => {
                             current_address += argument_size_to_byte_size(number.argument_size);
-                            new_tree.push(node.clone());
+                            new_tree.push(node);
                         }
                         _ => {
-                            new_tree.push(node.clone());
+                            new_tree.push(node);
                         }
                     }
                 }
@@ -221,120 +398,107 @@ impl TreePass for ResolveLabelPass {
                     current_address += 1;
 
                     match argument1 {
-                        &ParseArgument::Identifier(ref identifier) => {
-                            if symbol_table.has_label(identifier) {
-                                let argument_size = self.system.label_size;
-
-                                let number = NumberLiteral {
-                                    number: symbol_table.address_for(identifier),
-                                    argument_size: argument_size,
-                                };
-
-                                current_address += argument_size_to_byte_size(argument_size);
-
-                                new_tree.push(ParseNode {
-                                    start_token: node.start_token.clone(),
-                                    expression: ParseExpression::IndexedInstruction(
-                                        opcode_name.to_owned(),
-                                        ParseArgument::NumberLiteral(number),
-                                        argument2.clone(),
-                                    ),
-                                });
-                            } else {
-                                self.add_error_message(
-                                    &format!("Label '{}' not found.", identifier),
-                                    node.start_token.clone(),
-                                );
-                                new_tree.push(node.clone());
+                        &ParseArgument::Identifier(_) | &ParseArgument::Expression(_) => {
+                            current_address += argument_size_to_byte_size(unresolved_argument_size(self.system, argument1));
+
+                            match self.resolve_label_argument(
+                                argument1,
+                                symbol_table,
+                                statement_address,
+                                &node.start_token,
+                            ) {
+                                Some(resolved_argument) => {
+                                    new_tree.push(ParseNode {
+                                        start_token: node.start_token.clone(),
+                                        address: node.address,
+                                        expression: ParseExpression::IndexedInstruction(
+                                            opcode_name.to_owned(),
+                                            resolved_argument,
+                                            argument2.clone(),
+                                        ),
+                                    });
+                                }
+                                None => new_tree.push(node),
                             }
                         }
                         &ParseArgument::NumberLiteral(ref number) => {
                             current_address += argument_size_to_byte_size(number.argument_size);
-                            new_tree.push(node.clone());
+                            new_tree.push(node);
                         }
                         _ => {
-                            new_tree.push(node.clone());
+                            new_tree.push(node);
                         }
                     };
                 }
                 ParseExpression::IndirectInstruction(ref opcode_name, ref argument) => {
-                    new_tree.push(node.clone());
                     current_address += 1;
 
                     match argument {
-                        &ParseArgument::Identifier(ref identifier) => {
-                            if symbol_table.has_label(identifier) {
-                                let argument_size = self.system.label_size;
-
-                                let number = NumberLiteral {
-                                    number: symbol_table.address_for(identifier),
-                                    argument_size: argument_size,
-                                };
-
-                                current_address += argument_size_to_byte_size(argument_size);
-
-                                new_tree.push(ParseNode {
-                                    start_token: node.start_token.clone(),
-                                    expression: ParseExpression::IndirectInstruction(
-                                        opcode_name.to_owned(),
-                                        ParseArgument::NumberLiteral(number),
-                                    ),
-                                });
-                            } else {
-                                self.add_error_message(
-                                    &format!("Label '{}' not found.", identifier),
-                                    node.start_token.clone(),
-                                );
-                                new_tree.push(node.clone());
+                        &ParseArgument::Identifier(_) | &ParseArgument::Expression(_) => {
+                            current_address += argument_size_to_byte_size(unresolved_argument_size(self.system, argument));
+
+                            match self.resolve_label_argument(
+                                argument,
+                                symbol_table,
+                                statement_address,
+                                &node.start_token,
+                            ) {
+                                Some(resolved_argument) => {
+                                    new_tree.push(ParseNode {
+                                        start_token: node.start_token.clone(),
+                                        address: node.address,
+                                        expression: ParseExpression::IndirectInstruction(
+                                            opcode_name.to_owned(),
+                                            resolved_argument,
+                                        ),
+                                    });
+                                }
+                                None => new_tree.push(node),
                             }
                         }
                         &ParseArgument::NumberLiteral(ref number) => {
                             current_address += argument_size_to_byte_size(number.argument_size);
-                            new_tree.push(node.clone());
+                            new_tree.push(node);
                         }
                         _ => {
-                            new_tree.push(node.clone());
+                            new_tree.push(node);
                         }
                     };
                 }
                 ParseExpression::IndirectLongInstruction(ref opcode_name, ref argument) => {
-                    new_tree.push(node.clone());
+                    current_address += 1;
 
                     match argument {
-                        &ParseArgument::Identifier(ref identifier) => {
-                            if symbol_table.has_label(identifier) {
-                                let argument_size = self.system.label_size;
-
-                                let number = NumberLiteral {
-                                    number: symbol_table.address_for(identifier),
-                                    argument_size: argument_size,
-                                };
-
-                                current_address += argument_size_to_byte_size(argument_size);
-
-                                new_tree.push(ParseNode {
-                                    start_token: node.start_token.clone(),
-                                    expression: ParseExpression::IndirectLongInstruction(
-                                        opcode_name.to_owned(),
-                                        ParseArgument::NumberLiteral(number),
-                                    ),
-                                });
-                            } else {
-                                self.add_error_message(
-                                    &format!("Label '{}' not found.", identifier),
-                                    node.start_token.clone(),
-                                );
-                                new_tree.push(node.clone());
+                        &ParseArgument::Identifier(_) | &ParseArgument::Expression(_) => {
+                            current_address += argument_size_to_byte_size(unresolved_argument_size(self.system, argument));
+
+                            match self.resolve_label_argument(
+                                argument,
+                                symbol_table,
+                                statement_address,
+                                &node.start_token,
+                            ) {
+                                Some(resolved_argument) => {
+                                    new_tree.push(ParseNode {
+                                        start_token: node.start_token.clone(),
+                                        address: node.address,
+                                        expression: ParseExpression::IndirectLongInstruction(
+                                            opcode_name.to_owned(),
+                                            resolved_argument,
+                                        ),
+                                    });
+                                }
+                                None => new_tree.push(node),
                             }
                         }
                         &ParseArgument::NumberLiteral(ref number) => {
                             current_address += argument_size_to_byte_size(number.argument_size);
-                            new_tree.push(node.clone());
+                            new_tree.push(node);
                         }
                         _ => {
-                            new_tree.push(node.clone());
+                            new_tree.push(node);
                         }
-                    }
+                    };
                 }
                 ParseExpression::IndexedIndirectInstruction(
                     ref opcode_name,
@@ -344,39 +508,35 @@ impl TreePass for ResolveLabelPass {
                     current_address += 1;
 
                     match argument1 {
-                        &ParseArgument::Identifier(ref identifier) => {
-                            if symbol_table.has_label(identifier) {
-                                let argument_size = self.system.label_size;
-
-                                let number = NumberLiteral {
-                                    number: symbol_table.address_for(identifier),
-                                    argument_size: argument_size,
-                                };
-
-                                current_address += argument_size_to_byte_size(argument_size);
-
-                                new_tree.push(ParseNode {
-                                    start_token: node.start_token.clone(),
-                                    expression: ParseExpression::IndexedIndirectInstruction(
-                                        opcode_name.to_owned(),
-                                        ParseArgument::NumberLiteral(number),
-                                        argument2.clone(),
-                                    ),
-                                });
-                            } else {
-                                self.add_error_message(
-                                    &format!("Label '{}' not found.", identifier),
-                                    node.start_token.clone(),
-                                );
-                                new_tree.push(node.clone());
+                        &ParseArgument::Identifier(_) | &ParseArgument::Expression(_) => {
+                            current_address += argument_size_to_byte_size(unresolved_argument_size(self.system, argument1));
+
+                            match self.resolve_label_argument(
+                                argument1,
+                                symbol_table,
+                                statement_address,
+                                &node.start_token,
+                            ) {
+                                Some(resolved_argument) => {
+                                    new_tree.push(ParseNode {
+                                        start_token: node.start_token.clone(),
+                                        address: node.address,
+                                        expression: ParseExpression::IndexedIndirectInstruction(
+                                            opcode_name.to_owned(),
+                                            resolved_argument,
+                                            argument2.clone(),
+                                        ),
+                                    });
+                                }
+                                None => new_tree.push(node),
                             }
                         }
                         &ParseArgument::NumberLiteral(ref number) => {
                             current_address += argument_size_to_byte_size(number.argument_size);
-                            new_tree.push(node.clone());
+                            new_tree.push(node);
                         }
                         _ => {
-                            new_tree.push(node.clone());
+                            new_tree.push(node);
                         }
                     };
                 }
@@ -388,39 +548,35 @@ impl TreePass for ResolveLabelPass {
                     current_address += 1;
 
                     match argument1 {
-                        &ParseArgument::Identifier(ref identifier) => {
-                            if symbol_table.has_label(identifier) {
-                                let argument_size = self.system.label_size;
-
-                                let number = NumberLiteral {
-                                    number: symbol_table.address_for(identifier),
-                                    argument_size: argument_size,
-                                };
-
-                                current_address += argument_size_to_byte_size(argument_size);
-
-                                new_tree.push(ParseNode {
-                                    start_token: node.start_token.clone(),
-                                    expression: ParseExpression::IndirectIndexedInstruction(
-                                        opcode_name.to_owned(),
-                                        ParseArgument::NumberLiteral(number),
-                                        argument2.clone(),
-                                    ),
-                                });
-                            } else {
-                                self.add_error_message(
-                                    &format!("Label '{}' not found.", identifier),
-                                    node.start_token.clone(),
-                                );
-                                new_tree.push(node.clone());
+                        &ParseArgument::Identifier(_) | &ParseArgument::Expression(_) => {
+                            current_address += argument_size_to_byte_size(unresolved_argument_size(self.system, argument1));
+
+                            match self.resolve_label_argument(
+                                argument1,
+                                symbol_table,
+                                statement_address,
+                                &node.start_token,
+                            ) {
+                                Some(resolved_argument) => {
+                                    new_tree.push(ParseNode {
+                                        start_token: node.start_token.clone(),
+                                        address: node.address,
+                                        expression: ParseExpression::IndirectIndexedInstruction(
+                                            opcode_name.to_owned(),
+                                            resolved_argument,
+                                            argument2.clone(),
+                                        ),
+                                    });
+                                }
+                                None => new_tree.push(node),
                             }
                         }
                         &ParseArgument::NumberLiteral(ref number) => {
                             current_address += argument_size_to_byte_size(number.argument_size);
-                            new_tree.push(node.clone());
+                            new_tree.push(node);
                         }
                         _ => {
-                            new_tree.push(node.clone());
+                            new_tree.push(node);
                         }
                     };
                 }
@@ -432,59 +588,57 @@ impl TreePass for ResolveLabelPass {
                     current_address += 1;
 
                     match argument1 {
-                        &ParseArgument::Identifier(ref identifier) => {
-                            if symbol_table.has_label(identifier) {
-                                let argument_size = self.system.label_size;
-
-                                let number = NumberLiteral {
-                                    number: symbol_table.address_for(identifier),
-                                    argument_size: argument_size,
-                                };
-
-                                current_address += argument_size_to_byte_size(argument_size);
-
-                                new_tree.push(ParseNode {
-                                    start_token: node.start_token.clone(),
-                                    expression: ParseExpression::IndirectIndexedLongInstruction(
-                                        opcode_name.to_owned(),
-                                        ParseArgument::NumberLiteral(number),
-                                        argument2.clone(),
-                                    ),
-                                });
-                            } else {
-                                self.add_error_message(
-                                    &format!("Label '{}' not found.", identifier),
-                                    node.start_token.clone(),
-                                );
-                                new_tree.push(node.clone());
+                        &ParseArgument::Identifier(_) | &ParseArgument::Expression(_) => {
+                            current_address += argument_size_to_byte_size(unresolved_argument_size(self.system, argument1));
+
+                            match self.resolve_label_argument(
+                                argument1,
+                                symbol_table,
+                                statement_address,
+                                &node.start_token,
+                            ) {
+                                Some(resolved_argument) => {
+                                    new_tree.push(ParseNode {
+                                        start_token: node.start_token.clone(),
+                                        address: node.address,
+                                        expression: ParseExpression::IndirectIndexedLongInstruction(
+                                            opcode_name.to_owned(),
+                                            resolved_argument,
+                                            argument2.clone(),
+                                        ),
+                                    });
+                                }
+                                None => new_tree.push(node),
                             }
                         }
                         &ParseArgument::NumberLiteral(ref number) => {
                             current_address += argument_size_to_byte_size(number.argument_size);
-                            new_tree.push(node.clone());
+                            new_tree.push(node);
                         }
                         _ => {
-                            new_tree.push(node.clone());
+                            new_tree.push(node);
                         }
                     };
                 }
-                ParseExpression::BlockMoveInstruction(_, ref argument1, ref argument2) => {
-                    new_tree.push(node.clone());
-                    current_address += 1;
-
-                    match argument1 {
-                        &ParseArgument::NumberLiteral(ref number) => {
-                            current_address += argument_size_to_byte_size(number.argument_size);
-                        }
-                        _ => {}
-                    };
-
-                    match argument2 {
-                        &ParseArgument::NumberLiteral(ref number) => {
-                            current_address += argument_size_to_byte_size(number.argument_size);
-                        }
-                        _ => {}
-                    };
+                ParseExpression::BlockMoveInstruction(ref opcode_name, ref argument1, ref argument2) => {
+                    current_address += node_size(self.system, &node);
+
+                    // Banks given by symbols or expressions, like bank(Data), are replaced by their value
+                    let source_bank = self.resolve_label_argument(argument1, symbol_table, statement_address, &node.start_token);
+                    let destination_bank =
+                        self.resolve_label_argument(argument2, symbol_table, statement_address, &node.start_token);
+
+                    if let (Some(source_bank), Some(destination_bank)) = (source_bank, destination_bank) {
+                        new_tree.push(ParseNode {
+                            start_token: node.start_token.clone(),
+                            address: node.address,
+                            expression: ParseExpression::BlockMoveInstruction(
+                                opcode_name.to_owned(),
+                                source_bank,
+                                destination_bank,
+                            ),
+                        });
+                    }
                 }
                 ParseExpression::StackRelativeIndirectIndexedInstruction(
                     ref opcode_name,
@@ -495,58 +649,125 @@ impl TreePass for ResolveLabelPass {
                     current_address += 1;
 
                     match argument1 {
-                        &ParseArgument::Identifier(ref identifier) => {
-                            if symbol_table.has_label(identifier) {
-                                let argument_size = self.system.label_size;
-
-                                let number = NumberLiteral {
-                                    number: symbol_table.address_for(identifier),
-                                    argument_size: argument_size,
-                                };
-
-                                current_address += argument_size_to_byte_size(argument_size);
-
-                                new_tree.push(ParseNode {
-                                    start_token: node.start_token.clone(),
-                                    expression:
-                                        ParseExpression::StackRelativeIndirectIndexedInstruction(
+                        &ParseArgument::Identifier(_) | &ParseArgument::Expression(_) => {
+                            current_address += argument_size_to_byte_size(unresolved_argument_size(self.system, argument1));
+
+                            match self.resolve_label_argument(
+                                argument1,
+                                symbol_table,
+                                statement_address,
+                                &node.start_token,
+                            ) {
+                                Some(resolved_argument) => {
+                                    new_tree.push(ParseNode {
+                                        start_token: node.start_token.clone(),
+                                        address: node.address,
+                                        expression: ParseExpression::StackRelativeIndirectIndexedInstruction(
                                             opcode_name.to_owned(),
-                                            ParseArgument::NumberLiteral(number),
+                                            resolved_argument,
                                             argument2.clone(),
                                             argument3.clone(),
                                         ),
-                                });
-                            } else {
-                                self.add_error_message(
-                                    &format!("Label '{}' not found.", identifier),
-                                    node.start_token.clone(),
-                                );
-                                new_tree.push(node.clone());
+                                    });
+                                }
+                                None => new_tree.push(node),
                             }
                         }
                         &ParseArgument::NumberLiteral(ref number) => {
                             current_address += argument_size_to_byte_size(number.argument_size);
-                            new_tree.push(node.clone());
+                            new_tree.push(node);
                         }
                         _ => {
-                            new_tree.push(node.clone());
+                            new_tree.push(node);
                         }
                     };
                 }
-                ParseExpression::OriginStatement(ref number) => {
+                ParseExpression::OriginStatement(ref number, _) => {
                     current_address = number.number;
-                    new_tree.push(node.clone());
+                    node.address = Some(current_address);
+                    new_tree.push(node);
+                }
+                ParseExpression::BankStatement(bank) => {
+                    current_address = address_in_bank(current_address, bank);
+                    node.address = Some(current_address);
+                    new_tree.push(node);
+                }
+                ParseExpression::IncBinStatement(_, _, size, _) => {
+                    current_address += size as u32;
+                    new_tree.push(node);
+                }
+                ParseExpression::SkipBytes(size) => {
+                    current_address += size;
+                    new_tree.push(node);
+                }
+                ParseExpression::DataBytes(ref elements) => {
+                    current_address += elements.len() as u32;
+
+                    let resolved_elements =
+                        self.resolve_data_elements(elements, ArgumentSize::Word8, symbol_table, statement_address);
+                    new_tree.push(ParseNode {
+                        start_token: node.start_token.clone(),
+                        address: node.address,
+                        expression: ParseExpression::DataBytes(resolved_elements),
+                    });
+                }
+                ParseExpression::DataWords(ref elements) => {
+                    current_address += 2 * elements.len() as u32;
+
+                    let resolved_elements =
+                        self.resolve_data_elements(elements, ArgumentSize::Word16, symbol_table, statement_address);
+                    new_tree.push(ParseNode {
+                        start_token: node.start_token.clone(),
+                        address: node.address,
+                        expression: ParseExpression::DataWords(resolved_elements),
+                    });
+                }
+                ParseExpression::DataLongs(ref elements) => {
+                    current_address += 3 * elements.len() as u32;
+
+                    let resolved_elements =
+                        self.resolve_data_elements(elements, ArgumentSize::Word24, symbol_table, statement_address);
+                    new_tree.push(ParseNode {
+                        start_token: node.start_token.clone(),
+                        address: node.address,
+                        expression: ParseExpression::DataLongs(resolved_elements),
+                    });
+                }
+                ParseExpression::InterruptVectors { ref fields } => {
+                    let mut resolved_fields = HashMap::new();
+
+                    for (vector_name, handler) in fields.iter() {
+                        if let Some(resolved_handler) =
+                            self.resolve_label_argument(&handler.argument, symbol_table, statement_address, &handler.token)
+                        {
+                            resolved_fields.insert(
+                                vector_name.to_owned(),
+                                DataElement {
+                                    token: handler.token.clone(),
+                                    argument: resolved_handler,
+                                },
+                            );
+                        }
+                    }
+
+                    new_tree.push(ParseNode {
+                        start_token: node.start_token.clone(),
+                        address: node.address,
+                        expression: ParseExpression::InterruptVectors { fields: resolved_fields },
+                    });
+                }
+                ParseExpression::NamespaceStart(ref namespace) => {
+                    symbol_table.push_namespace(namespace);
                 }
-                ParseExpression::IncBinStatement(_, file_size) => {
-                    current_address += file_size as u32;
-                    new_tree.push(node.clone());
+                ParseExpression::NamespaceEnd => {
+                    symbol_table.pop_namespace();
                 }
                 _ => {
-                    new_tree.push(node.clone());
+                    new_tree.push(node);
                 }
             }
         }
 
-        return new_tree;
+        return (new_tree, self.diagnostics.take_messages());
     }
 }
@@ -0,0 +1,171 @@
+use std::fmt::Write;
+use zeal::output_writer::EmittedRange;
+use zeal::parser::*;
+
+pub struct RegionUsage {
+    pub start_address: u32,
+    pub end_address: u32,
+    pub used: u32,
+}
+
+impl RegionUsage {
+    pub fn size(&self) -> u32 {
+        self.end_address - self.start_address + 1
+    }
+
+    pub fn remaining(&self) -> u32 {
+        self.size().saturating_sub(self.used)
+    }
+}
+
+// How full the ROM is: emitted ranges, declared freespace regions and banks
+pub struct RomUsage {
+    pub ranges: Vec<(u32, u32)>,
+    pub freespace: Vec<RegionUsage>,
+    pub banks: Vec<RegionUsage>,
+}
+
+// Number of bytes of a range that are inside a region
+fn overlap(start_address: u32, size: u32, region_start: u32, region_end: u32) -> u32 {
+    let end_address = start_address + size - 1;
+    if end_address < region_start || start_address > region_end {
+        return 0;
+    }
+
+    end_address.min(region_end) - start_address.max(region_start) + 1
+}
+
+impl RomUsage {
+    pub fn new(emitted_ranges: &[EmittedRange], parse_tree: &Vec<ParseNode>, snes_map: &Option<SnesMap>) -> Self {
+        let ranges = emitted_ranges
+            .iter()
+            .map(|range| (range.start_address, range.size))
+            .collect::<Vec<(u32, u32)>>();
+
+        let mut freespace = Vec::new();
+        for node in parse_tree.iter() {
+            if let ParseExpression::FreeSpace(FreeSpaceRegion::Range(start_address, end_address)) = node.expression {
+                freespace.push(RegionUsage {
+                    start_address: start_address,
+                    end_address: end_address,
+                    used: ranges
+                        .iter()
+                        .map(|&(range_start, size)| overlap(range_start, size, start_address, end_address))
+                        .sum(),
+                });
+            }
+        }
+
+        // Only the upper half of each bank is ROM in LoROM and the LoROM banks of SA-1
+        let bank_start = match snes_map {
+            &Some(SnesMap::LoRom) | &Some(SnesMap::Sa1Rom) => 0x8000,
+            _ => 0x0000,
+        };
+
+        let mut banks: Vec<RegionUsage> = Vec::new();
+        for &(start_address, size) in ranges.iter() {
+            let first_bank = start_address >> 16;
+            let last_bank = (start_address + size - 1) >> 16;
+
+            for bank in first_bank..last_bank + 1 {
+                let bank_region = ((bank << 16) | bank_start, (bank << 16) | 0xFFFF);
+                let used = overlap(start_address, size, bank_region.0, bank_region.1);
+
+                match banks.iter().position(|usage| usage.start_address == bank_region.0) {
+                    Some(index) => banks[index].used += used,
+                    None => banks.push(RegionUsage {
+                        start_address: bank_region.0,
+                        end_address: bank_region.1,
+                        used: used,
+                    }),
+                }
+            }
+        }
+        banks.sort_by_key(|usage| usage.start_address);
+
+        RomUsage {
+            ranges: ranges,
+            freespace: freespace,
+            banks: banks,
+        }
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+
+        writeln!(text, "Emitted ranges:").unwrap();
+        for &(start_address, size) in self.ranges.iter() {
+            writeln!(text, "  ${:06X}-${:06X} {:>8} bytes", start_address, start_address + size - 1, size).unwrap();
+        }
+
+        if !self.freespace.is_empty() {
+            writeln!(text, "Freespace:").unwrap();
+            for usage in self.freespace.iter() {
+                writeln!(
+                    text,
+                    "  ${:06X}-${:06X} {:>8} used {:>8} remaining",
+                    usage.start_address,
+                    usage.end_address,
+                    usage.used,
+                    usage.remaining()
+                ).unwrap();
+            }
+        }
+
+        writeln!(text, "Banks:").unwrap();
+        for usage in self.banks.iter() {
+            writeln!(
+                text,
+                "  ${:02X} {:>8} used {:>8} remaining",
+                usage.start_address >> 16,
+                usage.used,
+                usage.remaining()
+            ).unwrap();
+        }
+
+        text
+    }
+
+    pub fn to_json(&self) -> String {
+        let ranges = self.ranges
+            .iter()
+            .map(|&(start_address, size)| {
+                format!("{{\"start\":{},\"end\":{},\"size\":{}}}", start_address, start_address + size - 1, size)
+            })
+            .collect::<Vec<String>>();
+
+        let freespace = self.freespace
+            .iter()
+            .map(|usage| {
+                format!(
+                    "{{\"start\":{},\"end\":{},\"size\":{},\"used\":{},\"remaining\":{}}}",
+                    usage.start_address,
+                    usage.end_address,
+                    usage.size(),
+                    usage.used,
+                    usage.remaining()
+                )
+            })
+            .collect::<Vec<String>>();
+
+        let banks = self.banks
+            .iter()
+            .map(|usage| {
+                format!(
+                    "{{\"bank\":{},\"size\":{},\"used\":{},\"remaining\":{}}}",
+                    usage.start_address >> 16,
+                    usage.size(),
+                    usage.used,
+                    usage.remaining()
+                )
+            })
+            .collect::<Vec<String>>();
+
+        format!(
+            "{{\"ranges\":[{}],\"freespace\":[{}],\"banks\":[{}]}}",
+            ranges.join(","),
+            freespace.join(","),
+            banks.join(",")
+        )
+    }
+}
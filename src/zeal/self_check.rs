@@ -0,0 +1,148 @@
+use zeal::assembler::*;
+use zeal::disassembler::Disassembler;
+use zeal::parser::{format_instruction, SnesMap};
+use zeal::system_definition::*;
+
+// Address the instructions are assembled at, mapped to the start of a LoROM file
+const CHECK_ORIGIN: u32 = 0x8000;
+
+// Operand of each size, different bytes so a swapped or truncated operand shows
+fn sample_value(size: ArgumentSize) -> String {
+    match size {
+        ArgumentSize::Word8 => "$12".to_owned(),
+        ArgumentSize::Word16 => "$1234".to_owned(),
+        ArgumentSize::Word24 => "$123456".to_owned(),
+        ArgumentSize::Word32 => "$12345678".to_owned(),
+    }
+}
+
+// Instructions like brk are listed without and with their signature byte, the bytes are read back
+// as the one with the most arguments
+fn is_read_back_as_other(system: &SystemDefinition, instruction: &InstructionInfo) -> bool {
+    system
+        .instructions
+        .iter()
+        .any(|other| other.opcode == instruction.opcode && other.arguments.len() > instruction.arguments.len())
+}
+
+// Source lines checked for an instruction, one for each size of its immediate value.
+// Each one is the instruction line along with the line before it setting the register width, if any.
+fn sample_sources(system: &SystemDefinition, instruction: &InstructionInfo) -> Vec<(Option<&'static str>, String)> {
+    let immediate_sizes = instruction.arguments.iter().find_map(|argument| match argument {
+        &InstructionArgument::Numbers(sizes) => Some(sizes),
+        _ => None,
+    });
+
+    let width_prefixes: Vec<(Option<&'static str>, Option<ArgumentSize>)> = match immediate_sizes {
+        Some(sizes) if system.immediate_width_register(instruction.name).is_some() => sizes
+            .iter()
+            .map(|&size| {
+                let prefix = if size == ArgumentSize::Word8 { "sep #$30" } else { "rep #$30" };
+                (Some(prefix), Some(size))
+            })
+            .collect(),
+        Some(sizes) => vec![(None, sizes.first().cloned())],
+        None => vec![(None, None)],
+    };
+
+    width_prefixes
+        .into_iter()
+        .map(|(prefix, immediate_size)| {
+            let operands: Vec<String> = instruction
+                .arguments
+                .iter()
+                .map(|argument| match argument {
+                    // Past the instruction so it doesn't jump to the next one
+                    _ if instruction.addressing == AddressingMode::Relative => "* + 5".to_owned(),
+                    &InstructionArgument::Number(size) => sample_value(size),
+                    &InstructionArgument::Numbers(_) => sample_value(immediate_size.unwrap()),
+                    &InstructionArgument::Register(name) => name.to_owned(),
+                    &InstructionArgument::NotStaticRegister(ref name) => name.to_owned(),
+                })
+                .collect();
+
+            (prefix, format_instruction(instruction.name, &instruction.addressing, &operands))
+        })
+        .collect()
+}
+
+// Assemble every instruction of the system on its own, disassemble the bytes and check that the same
+// instruction and operands come back. Returns one message for each instruction that didn't, so a
+// mistake in the instruction table like two instructions sharing an opcode is found without a ROM.
+pub fn self_check(system: &'static SystemDefinition) -> (usize, Vec<String>) {
+    let mut options = AssembleOptions::new();
+    options.warn_jump_to_next = false;
+    options.warn_unused_labels = false;
+
+    let mut checked_count = 0;
+    let mut failures = Vec::new();
+
+    for instruction in system.instructions.iter() {
+        if is_read_back_as_other(system, instruction) {
+            continue;
+        }
+
+        for (prefix, line) in sample_sources(system, instruction) {
+            checked_count += 1;
+
+            let mut source = format!("snesmap lorom\norigin ${:X}\n", CHECK_ORIGIN);
+            if let Some(prefix) = prefix {
+                source.push_str(&format!("    {}\n", prefix));
+            }
+            source.push_str(&format!("    {}\n", line));
+
+            let result = assemble_string(&source, system, &options);
+            if result.has_errors() {
+                let messages: Vec<String> = result
+                    .error_messages
+                    .iter()
+                    .map(|error_message| error_message.message.clone())
+                    .collect();
+                failures.push(format!("'{}' doesn't assemble: {}", line, messages.join(" ")));
+                continue;
+            }
+
+            let prefix_size = if prefix.is_some() { 2 } else { 0 };
+            match result.output.get(prefix_size) {
+                Some(&opcode) if opcode == instruction.opcode => {}
+                Some(&opcode) => {
+                    failures.push(format!(
+                        "'{}' assembles to opcode ${:02X} instead of ${:02X}.",
+                        line, opcode, instruction.opcode
+                    ));
+                    continue;
+                }
+                None => {
+                    failures.push(format!("'{}' assembles to nothing.", line));
+                    continue;
+                }
+            }
+
+            let mut disassembler = Disassembler::new(system, Some(SnesMap::LoRom));
+            let disassembled_lines = disassembler.disassemble_lines(&result.output, CHECK_ORIGIN);
+            match disassembled_lines.last() {
+                Some(disassembled_line) if disassembled_line == &line => {}
+                Some(disassembled_line) => {
+                    failures.push(format!("'{}' is disassembled as '{}'.", line, disassembled_line))
+                }
+                None => failures.push(format!("'{}' isn't disassembled.", line)),
+            }
+        }
+    }
+
+    (checked_count, failures)
+}
+
+#[cfg(test)]
+mod tests {
+    use snes_cpu::SNES_CPU;
+    use zeal::self_check::self_check;
+
+    #[test]
+    fn snes_cpu_instructions_come_back_unchanged() {
+        let (checked_count, failures) = self_check(&SNES_CPU);
+
+        assert!(checked_count > 0);
+        assert!(failures.is_empty(), "Self check failures:\n{}", failures.join("\n"));
+    }
+}
@@ -0,0 +1,52 @@
+use std::fs;
+use std::fs::File;
+use std::io::{BufWriter, Read, Result, Seek, SeekFrom, Write};
+use std::path::Path;
+
+// Write each bank of the ROM image to its own file, like bank_00.bin, along with a manifest.txt
+// listing the files written. Empty banks up to the last used one are zero-filled when keep_empty is set.
+pub fn write_split_banks<R: Read + Seek>(
+    image: &mut R,
+    bank_size: u32,
+    used_banks: &[u32],
+    directory: &Path,
+    keep_empty: bool,
+) -> Result<()> {
+    fs::create_dir_all(directory)?;
+
+    let banks: Vec<u32> = if keep_empty {
+        match used_banks.iter().max() {
+            Some(&last_bank) => (0..last_bank + 1).collect(),
+            None => Vec::new(),
+        }
+    } else {
+        used_banks.to_vec()
+    };
+
+    let mut manifest = BufWriter::new(File::create(directory.join("manifest.txt"))?);
+    writeln!(manifest, "; Generated by zealc")?;
+    writeln!(manifest, "; file bank offset size")?;
+
+    for bank in banks {
+        let file_name = format!("bank_{:02x}.bin", bank);
+        let file_offset = bank * bank_size;
+
+        // Banks past the end of the image or only partially written are padded with zeros
+        let mut bytes = vec![0; bank_size as usize];
+        if used_banks.contains(&bank) {
+            image.seek(SeekFrom::Start(file_offset as u64))?;
+            let mut read_size = 0;
+            while read_size < bytes.len() {
+                match image.read(&mut bytes[read_size..])? {
+                    0 => break,
+                    size => read_size += size,
+                }
+            }
+        }
+
+        File::create(directory.join(&file_name))?.write_all(&bytes)?;
+        writeln!(manifest, "{} ${:02X} ${:06X} {}", file_name, bank, file_offset, bank_size)?;
+    }
+
+    manifest.flush()
+}
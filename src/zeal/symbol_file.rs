@@ -0,0 +1,106 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Result, Write};
+use std::path::Path;
+use zeal::hex_style::{HexCase, HexStyle};
+
+// WLA DX symbol files are split in sections like [labels], the files of SymbolTable::export_to_file() have none
+pub fn is_wla_symbol_file(path: &Path) -> Result<bool> {
+    for line in BufReader::new(File::open(path)?).lines() {
+        if line?.trim_start().starts_with('[') {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+// Read the [labels] section of a WLA DX symbol file.
+// Each label is written as 'BB:AAAA name' with the bank and address in hexadecimal.
+pub fn read_wla_symbols(path: &Path) -> ::std::result::Result<Vec<(String, u32)>, String> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(why) => return Err(format!("Couldn't open '{}': {}", path.display(), why)),
+    };
+
+    let mut symbols = Vec::new();
+    let mut in_labels_section = false;
+
+    for (index, line) in BufReader::new(file).lines().enumerate() {
+        let line = match line {
+            Ok(line) => line,
+            Err(why) => return Err(format!("Couldn't read '{}': {}", path.display(), why)),
+        };
+
+        let line = match line.find(';') {
+            Some(comment_start) => &line[..comment_start],
+            None => &line[..],
+        }.trim();
+
+        if line.is_empty() {
+            continue;
+        }
+
+        if line.starts_with('[') {
+            in_labels_section = line == "[labels]";
+            continue;
+        }
+
+        if !in_labels_section {
+            continue;
+        }
+
+        match parse_wla_label(line) {
+            Some(symbol) => symbols.push(symbol),
+            None => {
+                return Err(format!(
+                    "{}({}): Expected a label like '00:8000 name' but found '{}'.",
+                    path.display(),
+                    index + 1,
+                    line
+                ))
+            }
+        }
+    }
+
+    Ok(symbols)
+}
+
+fn parse_wla_label(line: &str) -> Option<(String, u32)> {
+    let mut parts = line.split_whitespace();
+    let address = parts.next()?;
+    let name = parts.next()?;
+
+    if parts.next().is_some() {
+        return None;
+    }
+
+    let mut address_parts = address.split(':');
+    let bank = u32::from_str_radix(address_parts.next()?, 16).ok()?;
+    let offset = u32::from_str_radix(address_parts.next()?, 16).ok()?;
+
+    if address_parts.next().is_some() || bank > 0xFF || offset > 0xFFFF {
+        return None;
+    }
+
+    Some((name.to_owned(), (bank << 16) | offset))
+}
+
+// Addresses are always written as bank:offset like 80:8000, only the case follows `hex_style`
+pub fn write_wla_symbols(path: &Path, symbols: &[(String, u32)], hex_style: &HexStyle) -> Result<()> {
+    let mut output = BufWriter::new(File::create(path)?);
+    let hex_style = hex_style.with_default_case(HexCase::Lower);
+
+    writeln!(output, "; Generated by zealc")?;
+    writeln!(output, "[labels]")?;
+    for &(ref name, address) in symbols.iter() {
+        writeln!(
+            output,
+            "{}:{} {}",
+            hex_style.digits((address >> 16) & 0xFF, 2),
+            hex_style.digits(address & 0xFFFF, 4),
+            name
+        )?;
+    }
+
+    Ok(())
+}
@@ -1,28 +1,247 @@
-use std::collections::HashMap;
+extern crate rustc_hash;
 
+use self::rustc_hash::{FxHashMap, FxHashSet};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use zeal::hex_style::HexStyle;
+use zeal::lexer::Token;
+
+#[derive(Clone)]
+struct Symbol {
+    address: u32,
+    // Label statement defining the symbol, None for struct and enum fields and imported labels
+    definition: Option<Token>,
+}
+
+impl Symbol {
+    fn new(address: u32, definition: Option<Token>) -> Self {
+        Symbol {
+            address: address,
+            definition: definition,
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct SymbolTable {
-    label_map: HashMap<String, u32>,
+    label_map: FxHashMap<String, Symbol>,
+    namespace_stack: Vec<String>,
+    imported_labels: FxHashSet<String>,
+    exported_labels: Vec<String>,
+    // Statements using each label, by qualified name
+    references: FxHashMap<String, Vec<Token>>,
 }
 
 impl SymbolTable {
     pub fn new() -> Self {
         SymbolTable {
-            label_map: HashMap::new(),
+            label_map: FxHashMap::default(),
+            namespace_stack: Vec::new(),
+            imported_labels: FxHashSet::default(),
+            exported_labels: Vec::new(),
+            references: FxHashMap::default(),
+        }
+    }
+
+    pub fn label_count(&self) -> usize {
+        self.label_map.len()
+    }
+
+    // Labels coming from a symbol file, they are always in the global namespace
+    pub fn add_imported_label(&mut self, label_name: &str, address: u32) {
+        self.label_map.insert(label_name.to_owned(), Symbol::new(address, None));
+        self.imported_labels.insert(label_name.to_owned());
+    }
+
+    // Add the labels of a file written by export_to_file(), they are never reported as unused
+    pub fn import_from_file(&mut self, path: &Path) -> io::Result<()> {
+        let file = File::open(path)?;
+
+        for (index, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with(';') {
+                continue;
+            }
+
+            let mut parts = line.split_whitespace();
+            let symbol = match (parts.next(), parts.next(), parts.next()) {
+                (Some(name), Some(address), None) if address.starts_with('$') => {
+                    match u32::from_str_radix(&address[1..], 16) {
+                        Ok(address) => Some((name, address)),
+                        Err(_) => None,
+                    }
+                }
+                _ => None,
+            };
+
+            match symbol {
+                Some((name, address)) => self.add_imported_label(name, address),
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("line {}: Expected a symbol like 'name $008000' but found '{}'.", index + 1, line),
+                    ))
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Write the exported labels as 'NAME $ADDRESS' lines, for another run to import them
+    pub fn export_to_file(&self, path: &Path, hex_style: &HexStyle) -> io::Result<()> {
+        let mut output = BufWriter::new(File::create(path)?);
+
+        writeln!(output, "; Generated by zealc")?;
+        for (name, address) in self.exported_symbols() {
+            writeln!(output, "{} {}", name, hex_style.address(address))?;
+        }
+
+        output.flush()
+    }
+
+    pub fn is_imported(&self, qualified_name: &str) -> bool {
+        self.imported_labels.contains(qualified_name)
+    }
+
+    pub fn export_label(&mut self, qualified_name: &str) {
+        if !self.exported_labels.iter().any(|label| label == qualified_name) {
+            self.exported_labels.push(qualified_name.to_owned());
+        }
+    }
+
+    pub fn exported_symbols(&self) -> Vec<(String, u32)> {
+        self.exported_labels
+            .iter()
+            .filter_map(|label| match self.label_map.get(label) {
+                Some(symbol) => Some((label.to_owned(), symbol.address)),
+                None => None,
+            })
+            .collect()
+    }
+
+    // Every label with its address sorted by name
+    pub fn labels(&self) -> Vec<(String, u32)> {
+        let mut labels = self.label_map
+            .iter()
+            .map(|(label, symbol)| (label.to_owned(), symbol.address))
+            .collect::<Vec<(String, u32)>>();
+        labels.sort();
+
+        labels
+    }
+
+    // Labels defined by a label statement that no statement uses, in source order.
+    // Exported labels are used by other programs so they are left out.
+    pub fn unused_labels(&self) -> Vec<(String, Token)> {
+        let mut unused_labels = self.label_map
+            .iter()
+            .filter(|&(label, _)| self.references_to(label).is_empty() && !self.exported_labels.contains(label))
+            .filter_map(|(label, symbol)| match symbol.definition {
+                Some(ref token) => Some((label.to_owned(), token.clone())),
+                None => None,
+            })
+            .collect::<Vec<(String, Token)>>();
+        unused_labels.sort_by(|&(_, ref left), &(_, ref right)| {
+            (&left.source_file, left.line, left.start_column).cmp(&(&right.source_file, right.line, right.start_column))
+        });
+
+        unused_labels
+    }
+
+    pub fn push_namespace(&mut self, namespace: &str) {
+        self.namespace_stack.push(namespace.to_owned());
+    }
+
+    pub fn pop_namespace(&mut self) -> Option<String> {
+        self.namespace_stack.pop()
+    }
+
+    // Name of the label prefixed by the current namespaces, like player.init
+    pub fn qualified_name(&self, label_name: &str) -> String {
+        let mut qualified_name = String::new();
+        for namespace in self.namespace_stack.iter() {
+            qualified_name.push_str(namespace);
+            qualified_name.push('.');
         }
+        qualified_name.push_str(label_name);
+
+        qualified_name
     }
 
     pub fn add_or_update_label(&mut self, label_name: &str, address: u32) {
-        self.label_map.insert(label_name.to_owned(), address);
+        let qualified_name = self.qualified_name(label_name);
+        self.label_map.insert(qualified_name, Symbol::new(address, None));
+    }
+
+    // Label from a label statement, the token is kept to report where it is defined
+    pub fn add_label_definition(&mut self, label_name: &str, address: u32, token: &Token) {
+        let qualified_name = self.qualified_name(label_name);
+        self.label_map.insert(qualified_name, Symbol::new(address, Some(token.clone())));
+    }
+
+    // Only looks in the current namespace, used to find duplicate definitions
+    pub fn has_label_in_namespace(&self, label_name: &str) -> bool {
+        self.label_map.contains_key(&self.qualified_name(label_name))
     }
 
+    // Looking up the address doesn't count as a use, add_reference() records the statements using the label
     pub fn address_for(&self, label_name: &str) -> u32 {
-        match self.label_map.get(label_name) {
-            Some(&address) => address,
+        match self.find_label(label_name) {
+            Some(qualified_name) => self.label_map[&qualified_name].address,
             None => 0,
         }
     }
 
+    pub fn reference_count(&self, qualified_name: &str) -> u32 {
+        self.references_to(qualified_name).len() as u32
+    }
+
     pub fn has_label(&self, label_name: &str) -> bool {
-        self.label_map.contains_key(label_name)
+        self.find_label(label_name).is_some()
+    }
+
+    // Remember that the statement starting at `token` uses the label, unknown labels are ignored.
+    // A statement using the same label more than once is only counted once.
+    pub fn add_reference(&mut self, label_name: &str, token: &Token) {
+        if let Some(qualified_name) = self.find_label(label_name) {
+            let references = self.references.entry(qualified_name).or_insert_with(Vec::new);
+
+            let is_recorded = references.iter().any(|recorded_token| {
+                recorded_token.source_file == token.source_file && recorded_token.line == token.line
+                    && recorded_token.start_column == token.start_column
+            });
+
+            if !is_recorded {
+                references.push(token.clone());
+            }
+        }
+    }
+
+    pub fn references_to(&self, qualified_name: &str) -> &[Token] {
+        match self.references.get(qualified_name) {
+            Some(references) => references,
+            None => &[],
+        }
+    }
+
+    // Search from the innermost namespace outward to the global scope, returns the qualified name found
+    fn find_label(&self, label_name: &str) -> Option<String> {
+        for depth in (0..self.namespace_stack.len() + 1).rev() {
+            let mut qualified_name = self.namespace_stack[..depth].join(".");
+            if depth > 0 {
+                qualified_name.push('.');
+            }
+            qualified_name.push_str(label_name);
+
+            if self.label_map.contains_key(&qualified_name) {
+                return Some(qualified_name);
+            }
+        }
+
+        None
     }
 }
@@ -14,7 +14,7 @@ pub enum InstructionArgument {
     NotStaticRegister(String),
 }
 
-#[derive(PartialEq)]
+#[derive(PartialEq, Clone, Copy)]
 pub enum AddressingMode {
     Implied,
     Immediate,
@@ -30,6 +30,13 @@ pub enum AddressingMode {
     StackRelativeIndirectIndexed,
 }
 
+// Registers whose width sets the size of an immediate value
+#[derive(PartialEq, Copy, Clone)]
+pub enum WidthRegister {
+    Accumulator,
+    Index,
+}
+
 pub struct InstructionInfo {
     pub name: &'static str,
     pub addressing: AddressingMode,
@@ -39,12 +46,150 @@ pub struct InstructionInfo {
 
 pub struct SystemDefinition {
     pub short_name: &'static str,
+    // Other names accepted by --cpu
+    pub aliases: &'static [&'static str],
     pub name: &'static str,
     pub is_big_endian: bool,
     pub label_size: ArgumentSize,
+    // Widest operand any instruction takes
+    pub max_argument_size: ArgumentSize,
     pub registers: &'static [&'static str],
     pub size_to_addressing_mode: fn(ArgumentSize) -> &'static str,
+    // Short operand name used when listing instruction forms, like dp for a byte operand
+    pub size_to_operand_name: fn(ArgumentSize) -> &'static str,
     pub instructions: &'static [InstructionInfo],
+    // Instructions whose immediate value has the width of the accumulator or of the index registers
+    pub accumulator_immediates: &'static [&'static str],
+    pub index_immediates: &'static [&'static str],
+}
+
+// From the simplest to the most complex, the order used when listing the modes of an opcode
+const ADDRESSING_MODES: [AddressingMode; 12] = [
+    AddressingMode::Implied,
+    AddressingMode::Immediate,
+    AddressingMode::Relative,
+    AddressingMode::SingleArgument,
+    AddressingMode::Indexed,
+    AddressingMode::Indirect,
+    AddressingMode::IndirectLong,
+    AddressingMode::IndexedIndirect,
+    AddressingMode::IndirectIndexed,
+    AddressingMode::IndirectIndexedLong,
+    AddressingMode::BlockMove,
+    AddressingMode::StackRelativeIndirectIndexed,
+];
+
+// Every addressing mode supported by the opcode, empty for an unknown opcode
+pub fn find_all_valid_modes(system: &SystemDefinition, opcode_name: &str) -> Vec<AddressingMode> {
+    ADDRESSING_MODES
+        .iter()
+        .filter(|addressing_mode| {
+            system
+                .instructions
+                .iter()
+                .any(|instruction| instruction.name == opcode_name && &instruction.addressing == *addressing_mode)
+        })
+        .cloned()
+        .collect()
+}
+
+// Levenshtein distance where swapping two characters next to each other counts as one edit, so jrs is 1 edit from jsr
+fn edit_distance(first: &str, second: &str) -> usize {
+    let first_chars: Vec<char> = first.chars().collect();
+    let second_chars: Vec<char> = second.chars().collect();
+
+    // distances[i][j] is the distance between the first i characters of `first` and the first j of `second`
+    let mut distances = vec![vec![0; second_chars.len() + 1]; first_chars.len() + 1];
+    for i in 0..first_chars.len() + 1 {
+        distances[i][0] = i;
+    }
+    for j in 0..second_chars.len() + 1 {
+        distances[0][j] = j;
+    }
+
+    for i in 1..first_chars.len() + 1 {
+        for j in 1..second_chars.len() + 1 {
+            let replace_cost = if first_chars[i - 1] == second_chars[j - 1] { 0 } else { 1 };
+            let mut distance = (distances[i - 1][j - 1] + replace_cost)
+                .min(distances[i - 1][j] + 1)
+                .min(distances[i][j - 1] + 1);
+
+            if i > 1 && j > 1 && first_chars[i - 1] == second_chars[j - 2] && first_chars[i - 2] == second_chars[j - 1] {
+                distance = distance.min(distances[i - 2][j - 2] + 1);
+            }
+
+            distances[i][j] = distance;
+        }
+    }
+
+    distances[first_chars.len()][second_chars.len()]
+}
+
+fn common_prefix_length(first: &str, second: &str) -> usize {
+    first
+        .chars()
+        .zip(second.chars())
+        .take_while(|&(first_char, second_char)| first_char == second_char)
+        .count()
+}
+
+// Closest instruction name to a misspelled opcode like lsa, None when nothing is within 2 edits.
+// Between names as close, the one starting like the misspelled name wins, so lsa suggests lsr rather than lda.
+// Opcodes are lowercase so LDA suggests lda. Names shorter than 3 characters are 2 edits away from most opcodes.
+pub fn suggest_similar_opcode(name: &str, system: &SystemDefinition) -> Option<&'static str> {
+    if name.chars().count() < 3 {
+        return None;
+    }
+
+    let lowercase_name = name.to_lowercase();
+    let mut best_match: Option<(&'static str, usize, usize)> = None;
+
+    for instruction in system.instructions.iter() {
+        let distance = edit_distance(&lowercase_name, instruction.name);
+        if distance > 2 {
+            continue;
+        }
+
+        let prefix_length = common_prefix_length(&lowercase_name, instruction.name);
+        match best_match {
+            Some((_, best_distance, best_prefix_length))
+                if best_distance < distance || (best_distance == distance && best_prefix_length >= prefix_length) => {}
+            _ => best_match = Some((instruction.name, distance, prefix_length)),
+        }
+    }
+
+    best_match.map(|(instruction_name, _, _)| instruction_name)
+}
+
+pub fn addressing_mode_to_string(addressing_mode: &AddressingMode) -> &'static str {
+    match addressing_mode {
+        &AddressingMode::Implied => "implied",
+        &AddressingMode::Immediate => "immediate",
+        &AddressingMode::Relative => "relative",
+        &AddressingMode::SingleArgument => "single argument",
+        &AddressingMode::Indexed => "indexed",
+        &AddressingMode::Indirect => "indirect",
+        &AddressingMode::IndirectLong => "indirect long",
+        &AddressingMode::IndexedIndirect => "indexed indirect",
+        &AddressingMode::IndirectIndexed => "indirect indexed",
+        &AddressingMode::IndirectIndexedLong => "indirect indexed long",
+        &AddressingMode::BlockMove => "block move",
+        &AddressingMode::StackRelativeIndirectIndexed => "stack relative indirect indexed",
+    }
+}
+
+// Single argument instructions are named by the system from their argument size, like direct page,
+// and indexing by the stack pointer is stack relative
+pub fn instruction_addressing_to_string(system: &SystemDefinition, instruction: &InstructionInfo) -> String {
+    match (&instruction.addressing, instruction.arguments.first()) {
+        (&AddressingMode::SingleArgument, Some(&InstructionArgument::Number(size))) => {
+            (system.size_to_addressing_mode)(size).to_string()
+        }
+        (&AddressingMode::Indexed, _) if instruction.arguments.get(1) == Some(&InstructionArgument::Register("s")) => {
+            String::from("stack relative")
+        }
+        (addressing_mode, _) => addressing_mode_to_string(addressing_mode).to_string(),
+    }
 }
 
 pub fn argument_size_to_bit_size(size: ArgumentSize) -> i32 {
@@ -65,6 +210,69 @@ pub fn argument_size_to_byte_size(size: ArgumentSize) -> u32 {
     }
 }
 
+// Size given by a suffix like the w of lda.w: b for a byte, w for a word and l for a long address
+pub fn size_suffix_to_argument_size(suffix: &str) -> Option<ArgumentSize> {
+    match suffix {
+        "b" => Some(ArgumentSize::Word8),
+        "w" => Some(ArgumentSize::Word16),
+        "l" => Some(ArgumentSize::Word24),
+        _ => None,
+    }
+}
+
+// Smallest size holding a negative number in two's complement
+pub fn negative_number_to_argument_size(number: i64) -> Option<ArgumentSize> {
+    if number >= i8::min_value() as i64 {
+        Some(ArgumentSize::Word8)
+    } else if number >= i16::min_value() as i64 {
+        Some(ArgumentSize::Word16)
+    } else if number >= -0x800000 {
+        Some(ArgumentSize::Word24)
+    } else if number >= i32::min_value() as i64 {
+        Some(ArgumentSize::Word32)
+    } else {
+        None
+    }
+}
+
+// Keep only the bits that fit in the argument size, used for two's complement values
+pub fn truncate_to_argument_size(number: i64, size: ArgumentSize) -> u32 {
+    match size {
+        ArgumentSize::Word8 => (number as u32) & 0xFF,
+        ArgumentSize::Word16 => (number as u32) & 0xFFFF,
+        ArgumentSize::Word24 => (number as u32) & 0xFFFFFF,
+        ArgumentSize::Word32 => number as u32,
+    }
+}
+
+// Value as stored in the argument size, negative values are stored in two's complement.
+// None if the value doesn't fit, the valid range of a byte is -128 to 255.
+pub fn signed_value_to_argument_size(value: i64, size: ArgumentSize) -> Option<u32> {
+    let bit_count = argument_size_to_bit_size(size) as u32;
+    let min_value = -(1i64 << (bit_count - 1));
+    let max_value = (1i64 << bit_count) - 1;
+
+    if value < min_value || value > max_value {
+        None
+    } else {
+        Some(truncate_to_argument_size(value, size))
+    }
+}
+
+// Error for a number wider than any operand of the system, like a 32-bit literal on the 65816
+pub fn oversized_operand_message(system: &SystemDefinition, number_text: &str, size: ArgumentSize) -> Option<String> {
+    if argument_size_to_byte_size(size) > argument_size_to_byte_size(system.max_argument_size) {
+        Some(format!(
+            "Value {} is {}-bit but operands are at most {}-bit.",
+            number_text,
+            argument_size_to_bit_size(size),
+            argument_size_to_bit_size(system.max_argument_size)
+        ))
+    } else {
+        None
+    }
+}
+
 pub fn number_to_argument_size(number: u32) -> ArgumentSize {
     if number > 16777215 {
         ArgumentSize::Word32
@@ -76,3 +284,111 @@ pub fn number_to_argument_size(number: u32) -> ArgumentSize {
         ArgumentSize::Word8
     }
 }
+
+fn is_number_argument(argument: &InstructionArgument) -> bool {
+    match argument {
+        &InstructionArgument::Number(_) | &InstructionArgument::Numbers(_) => true,
+        _ => false,
+    }
+}
+
+fn is_register_argument(argument: &InstructionArgument) -> bool {
+    match argument {
+        &InstructionArgument::Register(_) | &InstructionArgument::NotStaticRegister(_) => true,
+        _ => false,
+    }
+}
+
+// Number of numeric and register arguments expected by each addressing mode
+fn expected_argument_counts(addressing_mode: &AddressingMode) -> (usize, usize) {
+    match addressing_mode {
+        &AddressingMode::Implied => (0, 0),
+        &AddressingMode::Immediate
+        | &AddressingMode::Relative
+        | &AddressingMode::SingleArgument
+        | &AddressingMode::Indirect
+        | &AddressingMode::IndirectLong => (1, 0),
+        &AddressingMode::Indexed
+        | &AddressingMode::IndexedIndirect
+        | &AddressingMode::IndirectIndexed
+        | &AddressingMode::IndirectIndexedLong => (1, 1),
+        &AddressingMode::BlockMove => (2, 0),
+        &AddressingMode::StackRelativeIndirectIndexed => (1, 2),
+    }
+}
+
+// Instructions like brk and wdm whose signature byte can be left out are listed twice for the same opcode,
+// without operand and with a byte operand
+fn is_optional_operand_form(first: &InstructionInfo, second: &InstructionInfo) -> bool {
+    first.name == second.name && match (&first.addressing, &second.addressing) {
+        (&AddressingMode::Implied, &AddressingMode::SingleArgument)
+        | (&AddressingMode::SingleArgument, &AddressingMode::Implied) => true,
+        _ => false,
+    }
+}
+
+impl SystemDefinition {
+    pub fn immediate_width_register(&self, opcode_name: &str) -> Option<WidthRegister> {
+        if self.accumulator_immediates.contains(&opcode_name) {
+            Some(WidthRegister::Accumulator)
+        } else if self.index_immediates.contains(&opcode_name) {
+            Some(WidthRegister::Index)
+        } else {
+            None
+        }
+    }
+
+    // Look for mistakes in the instruction table, returns a description of each issue found
+    pub fn validate(&self) -> Vec<String> {
+        let mut issues = Vec::new();
+
+        for (index, instruction) in self.instructions.iter().enumerate() {
+            let addressing_name = addressing_mode_to_string(&instruction.addressing);
+
+            for other in self.instructions[..index].iter() {
+                if other.opcode == instruction.opcode && !is_optional_operand_form(other, instruction) {
+                    issues.push(format!(
+                        "Opcode ${:02X} is used by both '{}' ({}) and '{}' ({}).",
+                        instruction.opcode,
+                        other.name,
+                        addressing_mode_to_string(&other.addressing),
+                        instruction.name,
+                        addressing_name
+                    ));
+                }
+
+                if other.name == instruction.name && other.addressing == instruction.addressing
+                    && other.arguments == instruction.arguments
+                {
+                    issues.push(format!(
+                        "'{}' ({}) is defined for both opcode ${:02X} and ${:02X}.",
+                        instruction.name, addressing_name, other.opcode, instruction.opcode
+                    ));
+                }
+            }
+
+            let number_count = instruction.arguments.iter().filter(|argument| is_number_argument(argument)).count();
+            let register_count = instruction.arguments.iter().filter(|argument| is_register_argument(argument)).count();
+
+            if (number_count, register_count) != expected_argument_counts(&instruction.addressing) {
+                issues.push(format!(
+                    "'{}' (${:02X}) has arguments that don't match the {} addressing mode.",
+                    instruction.name, instruction.opcode, addressing_name
+                ));
+            }
+
+            for argument in instruction.arguments.iter() {
+                if let &InstructionArgument::Register(register_name) = argument {
+                    if !self.registers.contains(&register_name) {
+                        issues.push(format!(
+                            "'{}' (${:02X}) uses the unknown register '{}'.",
+                            instruction.name, instruction.opcode, register_name
+                        ));
+                    }
+                }
+            }
+        }
+
+        issues
+    }
+}
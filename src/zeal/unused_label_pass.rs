@@ -0,0 +1,36 @@
+use zeal::parser::*;
+use zeal::pass::{DiagnosticSink, TreePass};
+use zeal::symbol_table::SymbolTable;
+
+// Warn about labels never used by a statement, usually left behind by a removed routine or misspelled.
+// Runs after ResolveLabelPass which looks up the address of every label used.
+pub struct UnusedLabelPass {
+    diagnostics: DiagnosticSink,
+}
+
+impl UnusedLabelPass {
+    pub fn new() -> Self {
+        UnusedLabelPass {
+            diagnostics: DiagnosticSink::new(),
+        }
+    }
+}
+
+impl TreePass for UnusedLabelPass {
+    fn name(&self) -> &'static str {
+        "unused-labels"
+    }
+
+    fn do_pass(
+        &mut self,
+        parse_tree: Vec<ParseNode>,
+        symbol_table: &mut SymbolTable,
+    ) -> (Vec<ParseNode>, Vec<ErrorMessage>) {
+        for (label_name, definition_token) in symbol_table.unused_labels().into_iter() {
+            self.diagnostics
+                .add_warning(&format!("Label '{}' is never used.", label_name), definition_token);
+        }
+
+        return (parse_tree, self.diagnostics.take_messages());
+    }
+}
@@ -0,0 +1,69 @@
+use zeal::parser::*;
+use zeal::pass::{DiagnosticSink, TreePass};
+use zeal::symbol_table::SymbolTable;
+
+const fn parse_version_number(text: &str) -> u32 {
+    let bytes = text.as_bytes();
+    let mut result = 0;
+    let mut index = 0;
+
+    while index < bytes.len() {
+        result = result * 10 + (bytes[index] - b'0') as u32;
+        index += 1;
+    }
+
+    result
+}
+
+// Version of the assembler as (major, minor), taken from Cargo.toml
+pub const ASSEMBLER_VERSION: (u32, u32) = (
+    parse_version_number(env!("CARGO_PKG_VERSION_MAJOR")),
+    parse_version_number(env!("CARGO_PKG_VERSION_MINOR")),
+);
+
+pub struct VersionCheckPass {
+    diagnostics: DiagnosticSink,
+}
+
+impl VersionCheckPass {
+    pub fn new() -> Self {
+        VersionCheckPass {
+            diagnostics: DiagnosticSink::new(),
+        }
+    }
+}
+
+impl TreePass for VersionCheckPass {
+    fn name(&self) -> &'static str {
+        "version-check"
+    }
+
+    fn do_pass(
+        &mut self,
+        parse_tree: Vec<ParseNode>,
+        _symbol_table: &mut SymbolTable,
+    ) -> (Vec<ParseNode>, Vec<ErrorMessage>) {
+        let mut new_tree: Vec<ParseNode> = Vec::new();
+
+        for node in parse_tree.into_iter() {
+            match node.expression {
+                ParseExpression::VersionRequirement { major, minor } => {
+                    if (major, minor) > ASSEMBLER_VERSION {
+                        self.diagnostics.add_error(
+                            &format!(
+                                "This file requires zealc {}.{} or newer but this is zealc {}.{}.",
+                                major, minor, ASSEMBLER_VERSION.0, ASSEMBLER_VERSION.1
+                            ),
+                            node.start_token.clone(),
+                        );
+                    }
+                }
+                _ => {
+                    new_tree.push(node);
+                }
+            }
+        }
+
+        return (new_tree, self.diagnostics.take_messages());
+    }
+}
@@ -0,0 +1,20 @@
+use std::fmt::Write;
+use std::path::Path;
+use zeal::hex_style::HexStyle;
+use zeal::lexer::relative_source_path;
+use zeal::symbol_table::SymbolTable;
+
+// Every label sorted by name with its address, followed by the file:line of each statement using it
+pub fn xref_to_text(symbol_table: &SymbolTable, base_directory: &Path, hex_style: &HexStyle) -> String {
+    let mut text = String::new();
+
+    for (label, address) in symbol_table.labels() {
+        writeln!(text, "{} {}", label, hex_style.address(address)).unwrap();
+
+        for token in symbol_table.references_to(&label).iter() {
+            writeln!(text, "    {}:{}", relative_source_path(&token.source_file, base_directory), token.line).unwrap();
+        }
+    }
+
+    text
+}
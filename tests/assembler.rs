@@ -0,0 +1,18 @@
+extern crate zealc;
+
+use std::path::Path;
+use zealc::snes_cpu::SNES_CPU;
+use zealc::zeal::assembler::*;
+
+// The symbol table returned by assemble_file() holds the address of each label of the file
+#[test]
+fn assemble_file_returns_label_addresses() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("testfiles/snescpu/label.zc");
+    let result = assemble_file(&path, &SNES_CPU, &AssembleOptions::new());
+
+    assert!(!result.has_errors());
+    assert!(!result.output.is_empty());
+    assert!(result.symbol_table.has_label("function"));
+    assert_eq!(result.symbol_table.address_for("function"), 0xC0AAFF);
+    assert_eq!(result.symbol_table.address_for("loop"), 0xC0021A);
+}
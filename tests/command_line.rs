@@ -0,0 +1,31 @@
+use std::process::{Command, Output};
+
+// Run zealc without a .zealrc of the current directory
+fn run(arguments: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_zealc"))
+        .arg("--no-config")
+        .args(arguments)
+        .output()
+        .unwrap()
+}
+
+fn messages(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned() + &String::from_utf8_lossy(&output.stderr)
+}
+
+#[test]
+fn unknown_cpu_is_an_error() {
+    let output = run(&["--cpu", "bogus", "--check", "testfiles/snescpu/instructions.zc"]);
+
+    assert!(!output.status.success());
+    assert!(messages(&output).contains("Unknown CPU 'bogus'"), "Unexpected messages: {}", messages(&output));
+}
+
+#[test]
+fn missing_input_is_reported_without_panicking() {
+    let output = run(&["--check", "nothere.zc"]);
+
+    assert!(!output.status.success());
+    assert!(messages(&output).contains("Couldn't read 'nothere.zc'"), "Unexpected messages: {}", messages(&output));
+    assert!(!messages(&output).contains("panicked"), "Unexpected messages: {}", messages(&output));
+}
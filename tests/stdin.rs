@@ -0,0 +1,34 @@
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+// Run zealc with the source piped to its standard input, a .zealrc of the current directory is left out
+fn run_with_stdin(arguments: &[&str], source: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_zealc"))
+        .arg("--no-config")
+        .args(arguments)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    child.stdin.take().unwrap().write_all(source.as_bytes()).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn source_is_read_from_stdin() {
+    let output = run_with_stdin(&["-o", "-", "-"], "origin $0000\n    lda #$12\n    rts\n");
+
+    assert!(output.status.success());
+    assert_eq!(output.stdout, [0xA9, 0x12, 0x60]);
+}
+
+#[test]
+fn errors_of_stdin_are_reported_at_stdin() {
+    let output = run_with_stdin(&["--check", "-"], "origin $0000\n    bogus\n");
+
+    assert!(!output.status.success());
+    let messages = String::from_utf8_lossy(&output.stdout).into_owned() + &String::from_utf8_lossy(&output.stderr);
+    assert!(messages.contains("<stdin>(2,5)"), "Unexpected messages: {}", messages);
+}